@@ -40,12 +40,16 @@ impl Display for Operation {
 }
 
 /// The transfer type requested by the client
-/// For now, only basic is supported.
-/// If no transfer type is specified, the server will assume basic, but if the client specify that it only accepts a different transfer type, the server will return an error.
-#[derive(Deserialize, Serialize, PartialEq, Debug)]
+/// Basic is always supported, multipart-basic only when the storage can be uploaded to in several parts, and tus and chunked only for the resumable and chunked uploads in proxy mode.
+/// If no transfer type is specified, the server will assume basic, but if the client specify that it only accepts unsupported transfer types, the server will return an error.
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy)]
 #[serde(rename_all = "camelCase")]
 pub enum Transfer {
     Basic,
+    #[serde(rename = "multipart-basic")]
+    Multipart,
+    Tus,
+    Chunked,
     Unknown,
 }
 
@@ -60,6 +64,9 @@ where
                 .into_iter()
                 .map(|transfer| match transfer.as_str() {
                     "basic" => Transfer::Basic,
+                    "multipart-basic" => Transfer::Multipart,
+                    "tus" => Transfer::Tus,
+                    "chunked" => Transfer::Chunked,
                     _ => Transfer::Unknown,
                 })
                 .collect();
@@ -96,6 +103,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_deserialize_transfer_multipart() {
+        let json = "{\"transfers\":[\"multipart-basic\",\"basic\"]}";
+        let transfer: TestHoldingTransfers = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            transfer,
+            TestHoldingTransfers {
+                transfers: Some(vec![Transfer::Multipart, Transfer::Basic])
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&transfer).unwrap(),
+            "{\"transfers\":[\"multipart-basic\",\"basic\"]}"
+        );
+    }
+
+    #[test]
+    fn test_deserialize_transfer_tus() {
+        let json = "{\"transfers\":[\"tus\",\"basic\"]}";
+        let transfer: TestHoldingTransfers = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            transfer,
+            TestHoldingTransfers {
+                transfers: Some(vec![Transfer::Tus, Transfer::Basic])
+            }
+        );
+        assert_eq!(serde_json::to_string(&transfer).unwrap(), json);
+    }
+
+    #[test]
+    fn test_deserialize_transfer_chunked() {
+        let json = "{\"transfers\":[\"chunked\",\"basic\"]}";
+        let transfer: TestHoldingTransfers = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            transfer,
+            TestHoldingTransfers {
+                transfers: Some(vec![Transfer::Chunked, Transfer::Basic])
+            }
+        );
+        assert_eq!(serde_json::to_string(&transfer).unwrap(), json);
+    }
+
     #[test]
     fn test_deserialize_transfer_unknown() {
         let json = "{\"transfers\":[\"foo\"]}";
@@ -7,6 +7,8 @@ pub struct RepoTokenPayload {
     repo: String,
     pub user: String,
     operation: String,
+    // Patterns of the refs that can be written, every ref if unset
+    refs: Option<Vec<String>>,
 }
 
 impl RepoTokenPayload {
@@ -23,10 +25,17 @@ impl RepoTokenPayload {
             ));
         }
 
+        // Optional, separated by spaces as a ref name can not contain one
+        let refs = token
+            .get_claim("refs")
+            .ok()
+            .map(|refs| refs.split_whitespace().map(String::from).collect());
+
         Ok(RepoTokenPayload {
             repo,
             user,
             operation,
+            refs,
         })
     }
 
@@ -37,6 +46,32 @@ impl RepoTokenPayload {
     pub fn has_write_access(&self) -> bool {
         self.operation == "upload"
     }
+
+    /// Whether the ref can be written, i.e. matches one of the patterns of the token, if it has
+    /// some. In a pattern, `*` matches any sequence of characters.
+    pub fn has_ref_access(&self, ref_name: Option<&str>) -> bool {
+        match (&self.refs, ref_name) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(refs), Some(ref_name)) => refs
+                .iter()
+                .any(|pattern| matches_pattern(pattern, ref_name)),
+        }
+    }
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| matches_pattern(rest, &name[i..]))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -46,8 +81,14 @@ impl RepoTokenPayload {
             repo: repo.to_string(),
             user: String::from("John Doe"),
             operation: operation.to_string(),
+            refs: None,
         }
     }
+
+    pub fn with_refs(mut self, refs: &[&str]) -> RepoTokenPayload {
+        self.refs = Some(refs.iter().map(|r| r.to_string()).collect());
+        self
+    }
 }
 
 #[cfg(test)]
@@ -134,6 +175,7 @@ mod tests {
             repo: "my-repo".to_string(),
             user: "John Doe".to_string(),
             operation: "download".to_string(),
+            refs: None,
         };
         assert!(payload.has_access("my-repo"));
     }
@@ -144,6 +186,7 @@ mod tests {
             repo: "my-repo".to_string(),
             user: "John Doe".to_string(),
             operation: "download".to_string(),
+            refs: None,
         };
         assert!(!payload.has_access("another-repo"));
     }
@@ -154,6 +197,7 @@ mod tests {
             repo: "my-repo".to_string(),
             user: "John Doe".to_string(),
             operation: "upload".to_string(),
+            refs: None,
         };
         assert!(payload.has_write_access());
     }
@@ -164,7 +208,47 @@ mod tests {
             repo: "my-repo".to_string(),
             user: "John Doe".to_string(),
             operation: "download".to_string(),
+            refs: None,
         };
         assert!(!payload.has_write_access());
     }
+
+    #[test]
+    fn test_new_repo_token_payload_with_refs() {
+        let token = Jwt::new_for_test(
+            vec![
+                ("repo".to_string(), "my-repo".to_string()),
+                ("user".to_string(), "John Doe".to_string()),
+                ("operation".to_string(), "upload".to_string()),
+                (
+                    "refs".to_string(),
+                    "refs/heads/main refs/heads/feature/*".to_string(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let payload = super::RepoTokenPayload::new(&token).unwrap();
+        assert!(payload.has_ref_access(Some("refs/heads/main")));
+        assert!(payload.has_ref_access(Some("refs/heads/feature/a/b")));
+        assert!(!payload.has_ref_access(Some("refs/heads/release")));
+        assert!(!payload.has_ref_access(Some("refs/heads/main2")));
+        assert!(!payload.has_ref_access(None));
+    }
+
+    #[test]
+    fn test_has_ref_access_without_refs() {
+        let payload = super::RepoTokenPayload::new_for_test("my-repo", "upload");
+        assert!(payload.has_ref_access(Some("refs/heads/release")));
+        assert!(payload.has_ref_access(None));
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(super::matches_pattern("refs/*/main", "refs/heads/main"));
+        assert!(super::matches_pattern("*", "refs/tags/v1"));
+        assert!(super::matches_pattern("refs/tags/v*.*", "refs/tags/v1.2"));
+        assert!(!super::matches_pattern("refs/tags/v*.*", "refs/tags/v12"));
+        assert!(!super::matches_pattern("refs/heads/main", "refs/heads/mai"));
+    }
 }
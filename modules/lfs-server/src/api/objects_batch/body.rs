@@ -10,13 +10,13 @@ use crate::api::{
 #[derive(Deserialize, PartialEq, Debug)]
 pub struct ObjectIdentity {
     pub oid: String,
-    pub size: u32,
+    pub size: u64,
 }
 
-/// A reference to a git pointer. Not used yet. Might be specified in the body
-#[derive(Deserialize)]
+/// A reference to a git pointer, the ref the objects are pushed to or fetched from
+#[derive(Deserialize, PartialEq, Debug)]
 pub struct Ref {
-    // name
+    pub name: String,
 }
 
 /// The body of the objects/batch request.
@@ -28,6 +28,8 @@ pub struct ObjectsBatchRequestPayload {
     pub objects: Vec<ObjectIdentity>,
     #[serde(deserialize_with = "from_hash_algo_string")]
     pub hash_algo: HashAlgorithm,
+    #[serde(rename = "ref", default)]
+    pub ref_: Option<Ref>,
 }
 
 impl ObjectsBatchRequestPayload {
@@ -44,6 +46,7 @@ impl ObjectsBatchRequestPayload {
     ///     transfers: Some(vec![Transfer::Basic]),
     ///     objects: vec![],
     ///     hash_algo: HashAlgorithm::Sha256,
+    ///     ref_: None,
     /// };
     /// payload.assert_transfer_accepted(Transfer::Basic).unwrap();
     /// ```
@@ -75,6 +78,38 @@ impl ObjectsBatchRequestPayload {
         ))
     }
 
+    /// Pick the transfer used for the batch: the first of the supported transfers, in the order of preference of the server, that the client accepts. If no transfer type is specified, only basic is accepted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lfs_info_server::api::{enums::*,objects_batch::body::ObjectsBatchRequestPayload};
+    /// let payload = ObjectsBatchRequestPayload {
+    ///     operation: Operation::Upload,
+    ///     transfers: Some(vec![Transfer::Basic, Transfer::Multipart]),
+    ///     objects: vec![],
+    ///     hash_algo: HashAlgorithm::Sha256,
+    ///     ref_: None,
+    /// };
+    /// let supported = [Transfer::Multipart, Transfer::Basic];
+    /// assert_eq!(payload.negotiate_transfer(&supported).unwrap(), Transfer::Multipart);
+    /// ```
+    ///
+    pub fn negotiate_transfer(
+        &self,
+        supported: &[Transfer],
+    ) -> Result<Transfer, (StatusCode, String)> {
+        let accepted = self.transfers.as_deref().unwrap_or(&[Transfer::Basic]);
+        supported
+            .iter()
+            .find(|transfer| accepted.contains(transfer))
+            .copied()
+            .ok_or((
+                StatusCode::NOT_IMPLEMENTED,
+                String::from("None of the requested transfers is supported"),
+            ))
+    }
+
     /// Verify that the hash algo is sha256, as it's the only one supported.
     ///
     /// # Examples
@@ -86,6 +121,7 @@ impl ObjectsBatchRequestPayload {
     ///    transfers: Some(vec![Transfer::Basic]),
     ///    objects: vec![],
     ///    hash_algo: HashAlgorithm::Sha256,
+    ///    ref_: None,
     /// };
     /// payload.assert_hash_algo(HashAlgorithm::Sha256).unwrap();
     /// ```
@@ -117,6 +153,40 @@ impl ObjectsBatchRequestPayload {
             Ok(())
         }
     }
+
+    /// Verify that the batch does not hold more objects than the server accepts, if it limits them.
+    pub fn assert_objects_count(
+        &self,
+        max_objects: Option<usize>,
+    ) -> Result<(), (StatusCode, String)> {
+        match max_objects {
+            Some(max_objects) if self.objects.len() > max_objects => Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("A batch holds at most {} objects", max_objects),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Verify that the token allows to upload to the ref of the request, if it restricts the refs.
+    pub fn assert_ref_access(
+        &self,
+        jwt_payload: &RepoTokenPayload,
+    ) -> Result<(), (StatusCode, String)> {
+        let ref_name = self.ref_.as_ref().map(|r| r.name.as_str());
+        match (&self.operation, ref_name) {
+            (Operation::Download, _) => Ok(()),
+            _ if jwt_payload.has_ref_access(ref_name) => Ok(()),
+            (Operation::Upload, Some(ref_name)) => Err((
+                StatusCode::FORBIDDEN,
+                format!("You can not upload to {}", ref_name),
+            )),
+            (Operation::Upload, None) => Err((
+                StatusCode::FORBIDDEN,
+                String::from("The ref of the upload is required"),
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -126,7 +196,7 @@ mod test {
     use crate::api::{
         enums::{HashAlgorithm, Operation, Transfer},
         jwt::RepoTokenPayload,
-        objects_batch::body::ObjectsBatchRequestPayload,
+        objects_batch::body::{ObjectIdentity, ObjectsBatchRequestPayload, Ref},
     };
 
     #[test]
@@ -136,6 +206,7 @@ mod test {
             transfers: None,
             objects: vec![],
             hash_algo: HashAlgorithm::Sha256,
+            ref_: None,
         };
         payload.assert_transfer_accepted(Transfer::Basic).unwrap();
     }
@@ -147,6 +218,7 @@ mod test {
             transfers: Some(vec![Transfer::Unknown, Transfer::Basic]),
             objects: vec![],
             hash_algo: HashAlgorithm::Sha256,
+            ref_: None,
         };
         payload.assert_transfer_accepted(Transfer::Basic).unwrap();
     }
@@ -158,6 +230,7 @@ mod test {
             transfers: Some(vec![Transfer::Unknown]),
             objects: vec![],
             hash_algo: HashAlgorithm::Sha256,
+            ref_: None,
         };
         let (status, _) = payload
             .assert_transfer_accepted(Transfer::Basic)
@@ -165,6 +238,36 @@ mod test {
         assert_eq!(status, StatusCode::NOT_IMPLEMENTED)
     }
 
+    #[test]
+    fn test_negotiate_transfer() {
+        let mut payload = ObjectsBatchRequestPayload {
+            operation: Operation::Upload,
+            transfers: None,
+            objects: vec![],
+            hash_algo: HashAlgorithm::Sha256,
+            ref_: None,
+        };
+        let supported = [Transfer::Multipart, Transfer::Basic];
+        assert_eq!(
+            payload.negotiate_transfer(&supported).unwrap(),
+            Transfer::Basic
+        );
+
+        payload.transfers = Some(vec![Transfer::Basic, Transfer::Multipart]);
+        assert_eq!(
+            payload.negotiate_transfer(&supported).unwrap(),
+            Transfer::Multipart
+        );
+        assert_eq!(
+            payload.negotiate_transfer(&[Transfer::Basic]).unwrap(),
+            Transfer::Basic
+        );
+
+        payload.transfers = Some(vec![Transfer::Multipart]);
+        let (status, _) = payload.negotiate_transfer(&[Transfer::Basic]).unwrap_err();
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+    }
+
     #[test]
     fn test_assert_hash_algo_unknown_algo() {
         let payload = ObjectsBatchRequestPayload {
@@ -172,6 +275,7 @@ mod test {
             transfers: Some(vec![Transfer::Basic]),
             objects: vec![],
             hash_algo: HashAlgorithm::Unknown,
+            ref_: None,
         };
         let (status, _) = payload.assert_hash_algo(HashAlgorithm::Sha256).unwrap_err();
         assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY)
@@ -184,6 +288,7 @@ mod test {
             transfers: Some(vec![Transfer::Basic]),
             objects: vec![],
             hash_algo: HashAlgorithm::Unknown,
+            ref_: None,
         };
         let jwt_payload = RepoTokenPayload::new_for_test("foo", "upload");
         payload
@@ -198,6 +303,7 @@ mod test {
             transfers: Some(vec![Transfer::Basic]),
             objects: vec![],
             hash_algo: HashAlgorithm::Unknown,
+            ref_: None,
         };
         let jwt_payload = RepoTokenPayload::new_for_test("foo", "download");
         let (status, _) = payload
@@ -226,10 +332,31 @@ mod test {
                     },
                 ],
                 hash_algo: HashAlgorithm::Sha256,
+                ref_: None,
             }
         );
     }
 
+    #[test]
+    fn deserialize_object_larger_than_4_gib() {
+        let serialized =
+            "{\"operation\":\"upload\",\"transfers\":[\"basic\"],\"objects\":[{\"oid\":\"oid1\",\"size\":4294967297}],\"hash_algo\":\"sha256\"}";
+        let deserialized: ObjectsBatchRequestPayload = serde_json::from_str(serialized).unwrap();
+        assert_eq!(deserialized.objects[0].size, 4_294_967_297);
+    }
+
+    #[test]
+    fn deserialize_ref() {
+        let serialized = "{\"operation\":\"upload\",\"ref\":{\"name\":\"refs/heads/main\"},\"objects\":[],\"hash_algo\":\"sha256\"}";
+        let deserialized: ObjectsBatchRequestPayload = serde_json::from_str(serialized).unwrap();
+        assert_eq!(
+            deserialized.ref_,
+            Some(Ref {
+                name: String::from("refs/heads/main")
+            })
+        );
+    }
+
     #[test]
     #[should_panic]
     fn deserialize_unknown_operation() {
@@ -248,6 +375,7 @@ mod test {
                 transfers: Some(vec![Transfer::Unknown]),
                 objects: vec![],
                 hash_algo: HashAlgorithm::Sha256,
+                ref_: None,
             }
         );
     }
@@ -263,6 +391,7 @@ mod test {
                 transfers: Some(vec![Transfer::Basic]),
                 objects: vec![],
                 hash_algo: HashAlgorithm::Unknown,
+                ref_: None,
             }
         );
     }
@@ -278,6 +407,7 @@ mod test {
                 transfers: None,
                 objects: vec![],
                 hash_algo: HashAlgorithm::Sha256,
+                ref_: None,
             }
         );
     }
@@ -289,6 +419,7 @@ mod test {
             transfers: Some(vec![Transfer::Basic]),
             objects: vec![],
             hash_algo: HashAlgorithm::Unknown,
+            ref_: None,
         };
         let jwt_payload = RepoTokenPayload::new_for_test("foo", "download");
         payload
@@ -303,6 +434,7 @@ mod test {
             transfers: Some(vec![Transfer::Basic]),
             objects: vec![],
             hash_algo: HashAlgorithm::Unknown,
+            ref_: None,
         };
         let jwt_payload = RepoTokenPayload::new_for_test("foo", "upload");
         payload
@@ -317,6 +449,7 @@ mod test {
             transfers: Some(vec![Transfer::Basic]),
             objects: vec![],
             hash_algo: HashAlgorithm::Unknown,
+            ref_: None,
         };
         let jwt_payload = RepoTokenPayload::new_for_test("foo", "download");
         let (status, msg) = payload
@@ -325,4 +458,45 @@ mod test {
         assert_eq!(status, StatusCode::FORBIDDEN);
         assert_eq!(msg, "You only have read access to this repository");
     }
+
+    #[test]
+    fn test_assert_ref_access() {
+        let mut payload = ObjectsBatchRequestPayload {
+            operation: Operation::Upload,
+            transfers: None,
+            objects: vec![],
+            hash_algo: HashAlgorithm::Sha256,
+            ref_: Some(Ref {
+                name: String::from("refs/heads/main"),
+            }),
+        };
+        let unrestricted = RepoTokenPayload::new_for_test("foo", "upload");
+        payload.assert_ref_access(&unrestricted).unwrap();
+        let restricted = unrestricted.with_refs(&["refs/heads/feature/*"]);
+        let (status, msg) = payload.assert_ref_access(&restricted).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(msg, "You can not upload to refs/heads/main");
+
+        payload.ref_ = None;
+        let (status, msg) = payload.assert_ref_access(&restricted).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(msg, "The ref of the upload is required");
+
+        // Downloads are not restricted
+        payload.operation = Operation::Download;
+        payload.assert_ref_access(&restricted).unwrap();
+    }
+
+    #[test]
+    fn test_assert_objects_count() {
+        let payload = ObjectsBatchRequestPayload::new_upload_default(vec![
+            ObjectIdentity::new("oid1", 1),
+            ObjectIdentity::new("oid2", 2),
+        ]);
+        payload.assert_objects_count(None).unwrap();
+        payload.assert_objects_count(Some(2)).unwrap();
+        let (status, msg) = payload.assert_objects_count(Some(1)).unwrap_err();
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(msg, "A batch holds at most 1 objects");
+    }
 }
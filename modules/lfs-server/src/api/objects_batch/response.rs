@@ -1,18 +1,83 @@
 use serde::Serialize;
 
 use super::super::enums::{HashAlgorithm, Transfer};
+use crate::traits::file_storage::ObjectGone;
+
+/// Reason why no action is available for an object, sent as the code of its error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectErrorCode {
+    /// The object does not exist on the server
+    NotFound,
+    /// The object conflicts with the state of the storage
+    Conflict,
+    /// The object was stored, but is no longer served, such as a quarantined one
+    Gone,
+    /// The object is refused by the storage, such as a size it does not accept
+    Validation,
+    /// The storage has no room left for the object
+    InsufficientStorage,
+    /// The storage failed to handle the object
+    Internal,
+}
+
+impl ObjectErrorCode {
+    pub fn code(self) -> u16 {
+        match self {
+            ObjectErrorCode::NotFound => 404,
+            ObjectErrorCode::Conflict => 409,
+            ObjectErrorCode::Gone => 410,
+            ObjectErrorCode::Validation => 422,
+            ObjectErrorCode::InsufficientStorage => 507,
+            ObjectErrorCode::Internal => 500,
+        }
+    }
+
+    /// Classify an error of the storage or of the link signer. The storages report the errors
+    /// worth telling apart as io errors, classified by their kind, or by the error they wrap.
+    pub fn classify(error: &(dyn std::error::Error + 'static)) -> ObjectErrorCode {
+        match error.downcast_ref::<std::io::Error>() {
+            Some(error) => match error.kind() {
+                std::io::ErrorKind::NotFound => match error.get_ref() {
+                    Some(inner) if inner.is::<ObjectGone>() => ObjectErrorCode::Gone,
+                    _ => ObjectErrorCode::NotFound,
+                },
+                std::io::ErrorKind::AlreadyExists => ObjectErrorCode::Conflict,
+                std::io::ErrorKind::InvalidInput
+                | std::io::ErrorKind::InvalidData
+                | std::io::ErrorKind::FileTooLarge => ObjectErrorCode::Validation,
+                std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded => {
+                    ObjectErrorCode::InsufficientStorage
+                }
+                _ => error
+                    .get_ref()
+                    .map_or(ObjectErrorCode::Internal, |inner| Self::classify(inner)),
+            },
+            None => ObjectErrorCode::Internal,
+        }
+    }
+}
 
 /// Error details about an object
 #[derive(Serialize, Debug)]
 pub struct ObjectError {
+    code: u16,
     message: String,
 }
 
+impl ObjectError {
+    pub fn new(code: ObjectErrorCode, message: String) -> ObjectError {
+        ObjectError {
+            code: code.code(),
+            message,
+        }
+    }
+}
+
 /// Object identification and error details, when object action is not available
 #[derive(Serialize, Debug)]
 pub struct ObjectWithError {
     oid: String,
-    size: u32,
+    size: u64,
     error: ObjectError,
 }
 
@@ -44,6 +109,15 @@ impl ObjectAction {
     }
 }
 
+/// An action uploading one part of an object, the size bytes starting at pos
+#[derive(Serialize, Debug)]
+pub struct PartAction {
+    #[serde(flatten)]
+    pub action: ObjectAction,
+    pub pos: u64,
+    pub size: u64,
+}
+
 /// For download operation, the api returns how to download the object
 #[derive(Serialize, Debug)]
 pub struct DownloadActions {
@@ -58,12 +132,20 @@ pub struct UploadActions {
     verify: Option<ObjectAction>,
 }
 
-/// According to the operation, the api returns either download or upload+verify actions
+/// For upload operation with the multipart-basic transfer, the api returns how to upload each part of the object, and how to complete the upload once they are all uploaded
+#[derive(Serialize, Debug)]
+pub struct MultipartUploadActions {
+    parts: Vec<PartAction>,
+    verify: ObjectAction,
+}
+
+/// According to the operation, the api returns either download, upload+verify or parts+verify actions
 #[derive(Serialize, Debug)]
 #[serde(untagged)]
 pub enum ObjectActions {
     Download(DownloadActions),
     Upload(UploadActions),
+    MultipartUpload(MultipartUploadActions),
 }
 
 impl ObjectActions {
@@ -73,13 +155,16 @@ impl ObjectActions {
     pub fn upload(upload: ObjectAction, verify: Option<ObjectAction>) -> ObjectActions {
         ObjectActions::Upload(UploadActions { upload, verify })
     }
+    pub fn multipart_upload(parts: Vec<PartAction>, verify: ObjectAction) -> ObjectActions {
+        ObjectActions::MultipartUpload(MultipartUploadActions { parts, verify })
+    }
 }
 
 /// The object identification and the actions specifications
 #[derive(Serialize, Debug)]
 pub struct ObjectWithAvailableActions {
     oid: String,
-    size: u32,
+    size: u64,
     actions: ObjectActions,
 }
 
@@ -93,7 +178,7 @@ pub enum Object {
 }
 
 impl Object {
-    fn success(oid: &str, size: u32, actions: ObjectActions) -> Object {
+    fn success(oid: &str, size: u64, actions: ObjectActions) -> Object {
         Object::ObjectWithAvailableActions(ObjectWithAvailableActions {
             oid: oid.to_string(),
             size,
@@ -102,39 +187,55 @@ impl Object {
     }
 
     /// Initialize with a not found error
-    pub fn not_found(oid: &str, size: u32) -> Object {
+    pub fn not_found(oid: &str, size: u64) -> Object {
         Object::ObjectWithError(ObjectWithError {
             oid: oid.to_string(),
             size,
-            error: ObjectError {
-                message: String::from("Not found"),
-            },
+            error: ObjectError::new(ObjectErrorCode::NotFound, String::from("Not found")),
+        })
+    }
+
+    /// Initialize with a gone error, for an object that is no longer served
+    pub fn gone(oid: &str, size: u64) -> Object {
+        Object::ObjectWithError(ObjectWithError {
+            oid: oid.to_string(),
+            size,
+            error: ObjectError::new(ObjectErrorCode::Gone, String::from("Gone")),
         })
     }
 
     /// Initialize with an upload and optionally a verify action
     pub fn upload(
         oid: &str,
-        size: u32,
+        size: u64,
         upload: ObjectAction,
         verify: Option<ObjectAction>,
     ) -> Object {
         Object::success(oid, size, ObjectActions::upload(upload, verify))
     }
 
+    /// Initialize with the actions uploading each part, and the verify action completing the upload
+    pub fn multipart_upload(
+        oid: &str,
+        size: u64,
+        parts: Vec<PartAction>,
+        verify: ObjectAction,
+    ) -> Object {
+        Object::success(oid, size, ObjectActions::multipart_upload(parts, verify))
+    }
+
     /// Initialize with a download action
-    pub fn download(oid: &str, size: u32, download: ObjectAction) -> Object {
+    pub fn download(oid: &str, size: u64, download: ObjectAction) -> Object {
         Object::success(oid, size, ObjectActions::download(download))
     }
 
-    /// Initialize with a custom error
-    pub fn error(oid: &str, size: u32, error: Box<dyn std::error::Error>) -> Object {
+    /// Initialize with an error of the storage, its code depending on the error
+    pub fn error(oid: &str, size: u64, error: Box<dyn std::error::Error>) -> Object {
+        let code = ObjectErrorCode::classify(error.as_ref());
         Object::ObjectWithError(ObjectWithError {
             oid: oid.to_string(),
             size,
-            error: ObjectError {
-                message: error.to_string(),
-            },
+            error: ObjectError::new(code, error.to_string()),
         })
     }
 }
@@ -158,12 +259,17 @@ impl ObjectsBatchSuccessResponse {
     ///   Object::download("oid1", 1, ObjectAction::new("href1".to_string(), None, 1)),
     ///   Object::upload("oid2", 2, ObjectAction::new("href2".to_string(), None, 2), None),
     ///   Object::not_found("oid3", 3),
-    ///   Object::error("oid4", 4, Box::new(std::io::Error::new(std::io::ErrorKind::Other, "test"))),
+    ///   Object::error("oid4", 4, Box::new(std::io::Error::other("test"))),
     /// ]);
     /// ```
     pub fn basic_sha256(objects: Vec<Object>) -> ObjectsBatchSuccessResponse {
+        Self::sha256(Transfer::Basic, objects)
+    }
+
+    /// Initialize with the given transfer type and sha256 hash algorithm
+    pub fn sha256(transfer: Transfer, objects: Vec<Object>) -> ObjectsBatchSuccessResponse {
         ObjectsBatchSuccessResponse {
-            transfer: Transfer::Basic,
+            transfer,
             objects,
             hash_algo: HashAlgorithm::Sha256,
         }
@@ -177,7 +283,7 @@ mod tests {
     #[test]
     fn test_objects_batch_success_response() {
         let (href1, href2) = ("href1".to_string(), "href2".to_string());
-        let e = Box::new(std::io::Error::new(std::io::ErrorKind::Other, "test"));
+        let e = Box::new(std::io::Error::other("test"));
         let res = ObjectsBatchSuccessResponse::basic_sha256(vec![
             Object::download("oid1", 1, ObjectAction::new(href1, None, 1)),
             Object::upload("oid2", 2, ObjectAction::new(href2, None, 2), None),
@@ -187,7 +293,100 @@ mod tests {
         let json = serde_json::to_string(&res).unwrap();
         assert_eq!(
             json,
-            r#"{"transfer":"basic","objects":[{"oid":"oid1","size":1,"actions":{"download":{"href":"href1","expires_in":1}}},{"oid":"oid2","size":2,"actions":{"upload":{"href":"href2","expires_in":2}}},{"oid":"oid3","size":3,"error":{"message":"Not found"}},{"oid":"oid4","size":4,"error":{"message":"test"}}],"hash_algo":"sha256"}"#
+            r#"{"transfer":"basic","objects":[{"oid":"oid1","size":1,"actions":{"download":{"href":"href1","expires_in":1}}},{"oid":"oid2","size":2,"actions":{"upload":{"href":"href2","expires_in":2}}},{"oid":"oid3","size":3,"error":{"code":404,"message":"Not found"}},{"oid":"oid4","size":4,"error":{"code":500,"message":"test"}}],"hash_algo":"sha256"}"#
+        );
+    }
+
+    #[test]
+    fn test_object_larger_than_4_gib() {
+        let href = "href".to_string();
+        let res = ObjectsBatchSuccessResponse::basic_sha256(vec![Object::download(
+            "oid1",
+            4_294_967_296,
+            ObjectAction::new(href, None, 1),
+        )]);
+        let json = serde_json::to_string(&res).unwrap();
+        assert!(json.contains(r#""size":4294967296"#));
+    }
+
+    #[test]
+    fn test_multipart_response() {
+        let part = |pos, size| PartAction {
+            action: ObjectAction::new(format!("part{}", pos), None, 1),
+            pos,
+            size,
+        };
+        let verify = ObjectAction::new("verify".to_string(), Some("token"), 1);
+        let res = ObjectsBatchSuccessResponse::sha256(
+            Transfer::Multipart,
+            vec![Object::multipart_upload(
+                "oid1",
+                3,
+                vec![part(0, 2), part(2, 1)],
+                verify,
+            )],
+        );
+        let json = serde_json::to_string(&res).unwrap();
+        assert_eq!(
+            json,
+            r#"{"transfer":"multipart-basic","objects":[{"oid":"oid1","size":3,"actions":{"parts":[{"href":"part0","expires_in":1,"pos":0,"size":2},{"href":"part2","expires_in":1,"pos":2,"size":1}],"verify":{"href":"verify","header":{"Authorization":"token"},"expires_in":1}}}],"hash_algo":"sha256"}"#
+        );
+    }
+
+    #[test]
+    fn test_classify_gone_object() {
+        let error = ObjectGone::io_error("Quarantined");
+        assert_eq!(ObjectErrorCode::classify(&error), ObjectErrorCode::Gone);
+        assert_eq!(ObjectErrorCode::Gone.code(), 410);
+    }
+
+    #[test]
+    fn test_classify_io_errors() {
+        let classify = |kind| ObjectErrorCode::classify(&std::io::Error::new(kind, "error"));
+        assert_eq!(
+            classify(std::io::ErrorKind::NotFound),
+            ObjectErrorCode::NotFound
+        );
+        assert_eq!(
+            classify(std::io::ErrorKind::AlreadyExists),
+            ObjectErrorCode::Conflict
+        );
+        assert_eq!(
+            classify(std::io::ErrorKind::InvalidData),
+            ObjectErrorCode::Validation
+        );
+        assert_eq!(
+            classify(std::io::ErrorKind::StorageFull),
+            ObjectErrorCode::InsufficientStorage
+        );
+        assert_eq!(
+            classify(std::io::ErrorKind::Other),
+            ObjectErrorCode::Internal
+        );
+
+        // The wrapped errors are classified
+        let full = std::io::Error::new(std::io::ErrorKind::StorageFull, "Full");
+        let wrapped = std::io::Error::other(full);
+        assert_eq!(
+            ObjectErrorCode::classify(&wrapped),
+            ObjectErrorCode::InsufficientStorage
+        );
+
+        // Other errors are internal
+        let other = "Not an io error".parse::<u64>().unwrap_err();
+        assert_eq!(ObjectErrorCode::classify(&other), ObjectErrorCode::Internal);
+    }
+
+    #[test]
+    fn test_error_code() {
+        let error = Box::new(std::io::Error::new(
+            std::io::ErrorKind::FileTooLarge,
+            "Too large",
+        ));
+        let json = serde_json::to_string(&Object::error("oid", 1, error)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"oid":"oid","size":1,"error":{"code":422,"message":"Too large"}}"#
         );
     }
 }
@@ -0,0 +1,190 @@
+use axum::http::{header, HeaderMap};
+
+/// An inclusive range of bytes inside an object, as requested with a `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// The requested range starts past the end of the object.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RangeNotSatisfiable;
+
+impl ByteRange {
+    /// Number of bytes covered by the range.
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value of the `Content-Range` header of a partial response.
+    pub fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, size)
+    }
+
+    /// Value of the `Range` header to request this range from an upstream server.
+    pub fn header_value(&self) -> String {
+        format!("bytes={}-{}", self.start, self.end)
+    }
+
+    /// Parse a single `bytes=` range against an object of the given size.
+    ///
+    /// Returns `Ok(None)` when the header shall be ignored and the full object served (unknown
+    /// unit, multiple ranges or malformed value), and an error when the range can't be satisfied.
+    pub fn parse(value: &str, size: u64) -> Result<Option<ByteRange>, RangeNotSatisfiable> {
+        let Some(spec) = value.trim().strip_prefix("bytes=") else {
+            return Ok(None);
+        };
+        if spec.contains(',') {
+            return Ok(None);
+        }
+        let Some((start, end)) = spec.trim().split_once('-') else {
+            return Ok(None);
+        };
+
+        let range = match (start.trim(), end.trim()) {
+            ("", "") => return Ok(None),
+            // Suffix range: the last n bytes
+            ("", suffix) => {
+                let Ok(suffix) = suffix.parse::<u64>() else {
+                    return Ok(None);
+                };
+                if suffix == 0 || size == 0 {
+                    return Err(RangeNotSatisfiable);
+                }
+                ByteRange {
+                    start: size.saturating_sub(suffix),
+                    end: size - 1,
+                }
+            }
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return Ok(None);
+                };
+                let end = match end {
+                    "" => u64::MAX,
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end,
+                        _ => return Ok(None),
+                    },
+                };
+                if start >= size {
+                    return Err(RangeNotSatisfiable);
+                }
+                ByteRange {
+                    start,
+                    end: end.min(size - 1),
+                }
+            }
+        };
+        Ok(Some(range))
+    }
+
+    /// Read the range requested in the headers, if any, and if it still applies.
+    ///
+    /// Objects are content addressed, so the only validator is the quoted oid used as `ETag`.
+    /// An `If-Range` with any other value (including a date) falls back to the full object.
+    pub fn from_headers(
+        headers: &HeaderMap,
+        etag: &str,
+        size: u64,
+    ) -> Result<Option<ByteRange>, RangeNotSatisfiable> {
+        let Some(range) = headers.get(header::RANGE) else {
+            return Ok(None);
+        };
+        if let Some(if_range) = headers.get(header::IF_RANGE) {
+            if if_range.as_bytes() != etag.as_bytes() {
+                return Ok(None);
+            }
+        }
+        match range.to_str() {
+            Ok(range) => ByteRange::parse(range, size),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> Option<ByteRange> {
+        Some(ByteRange { start, end })
+    }
+
+    #[test]
+    fn test_parse_bounded() {
+        assert_eq!(ByteRange::parse("bytes=0-9", 100).unwrap(), range(0, 9));
+        assert_eq!(ByteRange::parse("bytes=10-10", 100).unwrap(), range(10, 10));
+        assert_eq!(
+            ByteRange::parse("bytes=90-200", 100).unwrap(),
+            range(90, 99)
+        );
+    }
+
+    #[test]
+    fn test_parse_open_ended() {
+        assert_eq!(ByteRange::parse("bytes=90-", 100).unwrap(), range(90, 99));
+    }
+
+    #[test]
+    fn test_parse_suffix() {
+        assert_eq!(ByteRange::parse("bytes=-10", 100).unwrap(), range(90, 99));
+        assert_eq!(ByteRange::parse("bytes=-500", 100).unwrap(), range(0, 99));
+    }
+
+    #[test]
+    fn test_parse_ignored() {
+        assert_eq!(ByteRange::parse("items=0-9", 100).unwrap(), None);
+        assert_eq!(ByteRange::parse("bytes=0-9,20-29", 100).unwrap(), None);
+        assert_eq!(ByteRange::parse("bytes=9-0", 100).unwrap(), None);
+        assert_eq!(ByteRange::parse("bytes=a-b", 100).unwrap(), None);
+        assert_eq!(ByteRange::parse("bytes=-", 100).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_not_satisfiable() {
+        assert_eq!(
+            ByteRange::parse("bytes=100-", 100).unwrap_err(),
+            RangeNotSatisfiable
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=-0", 100).unwrap_err(),
+            RangeNotSatisfiable
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=0-", 0).unwrap_err(),
+            RangeNotSatisfiable
+        );
+    }
+
+    #[test]
+    fn test_from_headers_if_range() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, "bytes=0-9".parse().unwrap());
+        assert_eq!(
+            ByteRange::from_headers(&headers, "\"oid\"", 100).unwrap(),
+            range(0, 9)
+        );
+
+        headers.insert(header::IF_RANGE, "\"oid\"".parse().unwrap());
+        assert_eq!(
+            ByteRange::from_headers(&headers, "\"oid\"", 100).unwrap(),
+            range(0, 9)
+        );
+
+        headers.insert(header::IF_RANGE, "\"other\"".parse().unwrap());
+        assert_eq!(
+            ByteRange::from_headers(&headers, "\"oid\"", 100).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_content_range() {
+        let range = ByteRange { start: 10, end: 19 };
+        assert_eq!(range.length(), 10);
+        assert_eq!(range.content_range(100), "bytes 10-19/100");
+        assert_eq!(range.header_value(), "bytes=10-19");
+    }
+}
@@ -0,0 +1,45 @@
+use lfs_info_server::server::{
+    config::ServerConfig, injected_services::get_content_addressed_storage,
+};
+use std::env;
+
+/**
+ * Grant a repository access to objects of the global area of the content addressed layout,
+ * without uploading them, run with `grant-objects <storage> <repo> <oid>...`, e.g.
+ * `grant-objects sbs group/repo 2cf24dba5fb0...`.
+ *
+ * The storage is configured with the same environment variables as the server in proxy mode,
+ * including GLOBAL_OBJECTS_REPO. The command fails when an object could not be granted, such as
+ * one missing from the global area.
+ */
+#[tokio::main]
+async fn main() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let usage = "Usage: grant-objects <storage> <repo> <oid>...";
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 3 {
+        panic!("{}", usage);
+    }
+    let (storage, repo, oids) = (&args[0], &args[1], &args[2..]);
+
+    // The layout is only available in proxy mode
+    let config = ServerConfig::default()
+        .parse_args(vec![String::from("proxy"), storage.clone()])
+        .parse_env();
+    let layout = get_content_addressed_storage(&config);
+
+    let mut failed = 0;
+    for oid in oids {
+        match layout.grant(repo, oid).await {
+            Ok(()) => println!("Granted {} to {}", oid, repo),
+            Err(e) => {
+                eprintln!("Failed to grant {} to {}: {}", oid, repo, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,26 @@
+use lfs_info_server::{
+    server::config::ServerConfig, services::fs::local_file_storage::LocalFileStorage,
+};
+
+/**
+ * Move the objects of a local file storage to the layout given by FS_SHARD_DEPTH, along with
+ * their content types. It must be run while the server is stopped.
+ *
+ * The following environment variables are required:
+ *   - FS_ROOT_PATH
+ *   - FS_SHARD_DEPTH
+ */
+fn main() {
+    let config = ServerConfig::default().parse_env();
+    if config.fs_shard_depth.is_none() {
+        panic!("Missing environment variable: FS_SHARD_DEPTH");
+    }
+    let storage = LocalFileStorage::from_config(config.get_local_file_storage_config());
+    match storage.migrate_layout() {
+        Ok(moved) => println!("Moved {} files", moved),
+        Err(e) => {
+            eprintln!("Migration failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
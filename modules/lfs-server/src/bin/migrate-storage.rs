@@ -0,0 +1,67 @@
+use lfs_info_server::{
+    server::{config::ServerConfig, injected_services::get_migration_storage},
+    services::storage_migration::StorageMigration,
+};
+use std::env;
+
+// Prefix of the environment variables configuring the destination storage
+const DESTINATION_PREFIX: &str = "DESTINATION_";
+const MIGRATION_CONCURRENCY_KEY: &str = "MIGRATION_CONCURRENCY";
+
+/**
+ * Copy every object of a file storage to another one, run with
+ * `migrate-storage <source storage> <destination storage>`, e.g. `migrate-storage fs sbs`.
+ *
+ * The source is configured with the same environment variables as the server, and the
+ * destination with the same ones prefixed by DESTINATION_, e.g. DESTINATION_SBS_BUCKET_NAME.
+ * MIGRATION_CONCURRENCY sets the number of objects copied at once, 8 by default.
+ */
+#[tokio::main]
+async fn main() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        panic!("Usage: migrate-storage <source storage> <destination storage>");
+    }
+    let implementations: Vec<_> = args
+        .iter()
+        .map(
+            |name| match ServerConfig::parse_file_storage_implementation(name) {
+                Some(implementation) => implementation,
+                None => panic!("Invalid file storage: {}", name),
+            },
+        )
+        .collect();
+
+    let source_config = ServerConfig::default().parse_env();
+    let destination_config = ServerConfig::default()
+        .parse_vars(|key| env::var(format!("{}{}", DESTINATION_PREFIX, key)).ok());
+    let concurrency = env::var(MIGRATION_CONCURRENCY_KEY)
+        .ok()
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or(8);
+
+    let migration = StorageMigration::new(
+        get_migration_storage(&source_config, &implementations[0]),
+        get_migration_storage(&destination_config, &implementations[1]),
+        concurrency,
+    );
+    match migration.run().await {
+        Ok(report) => {
+            println!(
+                "Copied {} objects, skipped {} already copied, {} failed",
+                report.copied,
+                report.skipped,
+                report.failed.len()
+            );
+            if !report.failed.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Migration failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
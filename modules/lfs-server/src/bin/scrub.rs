@@ -0,0 +1,66 @@
+use lfs_info_server::{
+    server::{config::ServerConfig, injected_services::get_scrub_storage},
+    services::scrubber::Scrubber,
+};
+use std::env;
+
+const SCRUB_CONCURRENCY_KEY: &str = "SCRUB_CONCURRENCY";
+
+/**
+ * Check the objects of a file storage against their oid, run with
+ * `scrub <storage> [--repo <repo>] [--quarantine]`, e.g. `scrub fs --quarantine`.
+ *
+ * The storage is configured with the same environment variables as the server. The report is
+ * written as JSON on the standard output, and the command fails when it has findings.
+ * SCRUB_CONCURRENCY sets the number of objects checked at once, 8 by default.
+ */
+#[tokio::main]
+async fn main() {
+    let _ = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .try_init();
+
+    let usage = "Usage: scrub <storage> [--repo <repo>] [--quarantine]";
+    let args: Vec<String> = env::args().skip(1).collect();
+    let implementation = match args.first() {
+        Some(name) => match ServerConfig::parse_file_storage_implementation(name) {
+            Some(implementation) => implementation,
+            None => panic!("Invalid file storage: {}", name),
+        },
+        None => panic!("{}", usage),
+    };
+    let mut repo = None;
+    let mut quarantine = false;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--repo" => repo = Some(options.next().expect(usage).clone()),
+            "--quarantine" => quarantine = true,
+            _ => panic!("{}", usage),
+        }
+    }
+
+    let config = ServerConfig::default().parse_env();
+    let concurrency = env::var(SCRUB_CONCURRENCY_KEY)
+        .ok()
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or(8);
+    let scrubber = Scrubber::new(
+        get_scrub_storage(&config, &implementation),
+        concurrency,
+        quarantine,
+        config.global_objects_repo.clone(),
+    );
+    match scrubber.run(repo.as_deref()).await {
+        Ok(report) => {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            if !report.findings.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Scrub failed: {}", e);
+            std::process::exit(2);
+        }
+    }
+}
@@ -25,6 +25,7 @@ struct ErrorBuilder<'a> {
     message: &'a Option<String>,
 }
 
+#[allow(clippy::result_large_err)]
 impl<'a> ErrorBuilder<'a> {
     fn new(status: &'a StatusCode, message: &'a Option<String>) -> Self {
         Self { status, message }
@@ -67,15 +68,16 @@ pub async fn handle_and_filter_error_details<B>(
     let resp = next.run(req).await;
     let status = resp.status();
 
-    if status.is_success() || status == StatusCode::CONFLICT {
+    // A range not satisfiable keeps its Content-Range header, telling the size of the object
+    if status.is_success()
+        || status == StatusCode::CONFLICT
+        || status == StatusCode::RANGE_NOT_SATISFIABLE
+    {
         return Ok(resp);
     }
 
     let inner_error_message = match resp.into_body().data().await {
-        Some(Ok(data)) => match String::from_utf8(data.to_vec()) {
-            Ok(s) => Some(s),
-            Err(_) => None,
-        },
+        Some(Ok(data)) => String::from_utf8(data.to_vec()).ok(),
         _ => None,
     };
     let error_message = match &inner_error_message {
@@ -115,9 +117,59 @@ pub async fn handle_and_filter_error_details<B>(
         StatusCode::METHOD_NOT_ALLOWED => {
             error_builder.error("Method not allowed, try GET or POST")
         }
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => error_builder.with_message("Bad Content-Type header"),
+        // Required by the tus protocol of the resumable uploads
+        StatusCode::PRECONDITION_FAILED => error_builder.with_message("Unsupported tus version"),
 
         // Any other error is an internal server error
         _ => error_builder
             .custom_status_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{header, HeaderMap},
+        middleware,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    fn send(status: StatusCode) -> Response {
+        let app = Router::new()
+            .route(
+                "/",
+                get(move || async move {
+                    let mut headers = HeaderMap::new();
+                    headers.insert(header::CONTENT_RANGE, "bytes */50".parse().unwrap());
+                    (status, headers, "Inner error")
+                }),
+            )
+            .layer(middleware::from_fn(handle_and_filter_error_details));
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        crate::aw!(app.oneshot(request)).unwrap()
+    }
+
+    #[test]
+    fn test_range_not_satisfiable_passes_through() {
+        let response = send(StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            response.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes */50"
+        );
+    }
+
+    #[test]
+    fn test_unknown_error_is_hidden() {
+        let response = send(StatusCode::BAD_GATEWAY);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.headers().get(header::CONTENT_RANGE).is_none());
+        let body = crate::aw!(response.into_body().data()).unwrap().unwrap();
+        assert_eq!(body, "{\"message\":\"Internal server error\"}");
+    }
+}
@@ -422,6 +422,7 @@ mod tests {
                 decoded: Some(DecodedTokenMock {
                     operation: Operation::Upload,
                     repo: String::from("a/b/c"),
+                    refs: None,
                 }),
                 ..MockConfig::default()
             }))
@@ -491,6 +492,7 @@ mod tests {
                 decoded: Some(DecodedTokenMock {
                     operation: Operation::Upload,
                     repo: String::from("a/b/c"),
+                    refs: None,
                 }),
                 locks_enabled: true,
                 ..MockConfig::default()
@@ -543,6 +545,7 @@ mod tests {
                 decoded: Some(DecodedTokenMock {
                     operation: Operation::Download,
                     repo: String::from("a/b/c"),
+                    refs: None,
                 }),
                 locks_enabled: true,
                 ..MockConfig::default()
@@ -731,6 +734,7 @@ mod tests {
                 decoded: Some(DecodedTokenMock {
                     operation: Operation::Upload,
                     repo: String::from("a/b/c"),
+                    refs: None,
                 }),
                 locks_enabled: true,
                 ..MockConfig::default()
@@ -0,0 +1,114 @@
+use axum::{
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+
+// Media type of the payloads of the LFS API
+pub const LFS_MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+// Check that the client accepts the LFS media type, and sends its payloads with it
+// Implements the requirements defined at https://github.com/git-lfs/git-lfs/blob/main/docs/api/batch.md#requests
+pub async fn check_lfs_media_type<B>(
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, String)> {
+    if !accepts_lfs_media_type(req.headers()) {
+        return Err((
+            StatusCode::NOT_ACCEPTABLE,
+            format!("The Accept header should be {}", LFS_MEDIA_TYPE),
+        ));
+    }
+    if req.method() == Method::POST && !has_lfs_content_type(req.headers()) {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Bad Content-Type header, should be {}", LFS_MEDIA_TYPE),
+        ));
+    }
+    Ok(next.run(req).await)
+}
+
+// Without an Accept header, the client accepts any media type
+fn accepts_lfs_media_type(headers: &HeaderMap) -> bool {
+    let mut accepted = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(essence)
+        .peekable();
+    accepted.peek().is_none()
+        || accepted.any(|media_type| {
+            media_type == LFS_MEDIA_TYPE || media_type == "application/*" || media_type == "*/*"
+        })
+}
+
+fn has_lfs_content_type(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| essence(value) == LFS_MEDIA_TYPE)
+}
+
+// Media type without its parameters, such as the charset
+fn essence(media_type: &str) -> String {
+    media_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::any, Router};
+    use tower::ServiceExt;
+
+    fn send(method: Method, headers: &[(&str, &str)]) -> StatusCode {
+        let app = Router::new()
+            .route("/", any(|| async { "ok" }))
+            .route_layer(middleware::from_fn(check_lfs_media_type));
+        let mut request = Request::builder().method(method).uri("/");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = crate::aw!(app.oneshot(request.body(Body::empty()).unwrap())).unwrap();
+        response.status()
+    }
+
+    #[test]
+    fn test_lfs_media_type_accepted() {
+        let headers = [
+            ("Accept", "application/vnd.git-lfs+json; charset=utf-8"),
+            (
+                "Content-Type",
+                "application/vnd.git-lfs+json; charset=utf-8",
+            ),
+        ];
+        assert_eq!(send(Method::POST, &headers), StatusCode::OK);
+        assert_eq!(send(Method::GET, &[]), StatusCode::OK);
+        assert_eq!(
+            send(Method::GET, &[("Accept", "text/html, */*;q=0.8")]),
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn test_other_accept_rejected() {
+        assert_eq!(
+            send(Method::GET, &[("Accept", "text/html, application/json")]),
+            StatusCode::NOT_ACCEPTABLE
+        );
+    }
+
+    #[test]
+    fn test_other_content_type_rejected() {
+        assert_eq!(
+            send(Method::POST, &[("Content-Type", "application/json")]),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(send(Method::POST, &[]), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
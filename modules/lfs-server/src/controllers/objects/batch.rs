@@ -2,6 +2,7 @@ use axum::{
     extract::{Json, Query, State},
     http::{HeaderMap, StatusCode},
 };
+use futures_util::StreamExt;
 use std::sync::Arc;
 
 use crate::{
@@ -10,14 +11,18 @@ use crate::{
         jwt::RepoTokenPayload,
         objects_batch::{
             body::ObjectsBatchRequestPayload,
-            response::{Object, ObjectsBatchSuccessResponse},
+            response::{Object, ObjectAction, ObjectsBatchSuccessResponse, PartAction},
         },
         repo_query::QueryRepo,
     },
+    controllers::objects::chunks::split_in_chunks,
     services::jwt::Jwt,
     traits::{file_storage::FileStorageMetaResult, services::Services},
 };
 
+// Objects of a batch whose actions are signed at the same time
+const BATCH_CONCURRENCY: usize = 16;
+
 // Request the ability to transfer a batch of objects
 // Might be download or upload
 // Implements the Batch api defined at https://github.com/git-lfs/git-lfs/blob/main/docs/api/batch.md
@@ -36,61 +41,213 @@ pub async fn post_objects_batch(
 
     payload.assert_jwt_access_level_higher_than_requested(&jwt_payload)?;
 
+    payload.assert_ref_access(&jwt_payload)?;
+
     query.assert_repo_match_token(&jwt_payload)?;
 
     payload.assert_hash_algo(HashAlgorithm::Sha256)?;
 
-    payload.assert_transfer_accepted(Transfer::Basic)?;
+    payload.assert_objects_count(services.batch_max_objects())?;
 
-    // 7) For each object, verify if it exists on storage server
-    let services = Arc::clone(&services);
+    // The transfers are preferred in this order, the multipart, chunked and tus ones only upload
+    let mut supported = Vec::new();
+    if services.file_storage_multipart_uploader().is_some() {
+        if let Operation::Upload = payload.operation {
+            supported.push(Transfer::Multipart);
+        }
+    }
+    if services.file_storage_chunked_uploader().is_some() {
+        if let Operation::Upload = payload.operation {
+            supported.push(Transfer::Chunked);
+        }
+    }
+    if services.file_storage_resumable_uploader().is_some() {
+        if let Operation::Upload = payload.operation {
+            supported.push(Transfer::Tus);
+        }
+    }
+    supported.push(Transfer::Basic);
+    let transfer = payload.negotiate_transfer(&supported)?;
 
+    // 7) Look up the objects on the storage server, then get their actions a few at a time. The
+    // objects are returned in the order of the request
     let repo = &query.repo;
-    let number_of_objects = payload.objects.len();
-    let mut objects: Vec<Object> = Vec::with_capacity(number_of_objects);
-    for object in payload.objects.iter() {
-        let oid = &object.oid[..];
-        let size = object.size;
-        let result = services
-            .file_storage_meta_requester()
-            .get_meta_result(repo, oid)
-            .await;
-
-        let FileStorageMetaResult { exists, .. } = result;
-        let signer = services.file_storage_link_signer();
-
-        let object = if exists {
-            let download = signer.get_presigned_link(result).await;
-            match download {
-                Ok(download) => Object::download(oid, size, download),
-                Err(error) => Object::error(oid, size, error),
-            }
-        } else if let Operation::Upload = payload.operation {
-            let actions = signer.post_presigned_link(result, size).await;
-            match actions {
-                Ok(actions) => Object::upload(oid, size, actions.0, actions.1),
-                Err(error) => Object::error(oid, size, error),
-            }
-        } else {
-            Object::not_found(oid, size)
-        };
-
-        objects.push(object);
-    }
+    let oids: Vec<&str> = payload.objects.iter().map(|o| o.oid.as_str()).collect();
+    let results = services
+        .file_storage_meta_requester()
+        .get_meta_results(repo, &oids)
+        .await;
+    let objects: Vec<_> = payload
+        .objects
+        .iter()
+        .zip(results)
+        .map(|(object, result)| {
+            get_object(
+                services.as_ref(),
+                &payload.operation,
+                transfer,
+                result,
+                object.size,
+                &jwt_payload.user,
+                payload.ref_.as_ref().map(|r| r.name.as_str()),
+            )
+        })
+        .collect();
+    let objects: Vec<Object> = futures_util::stream::iter(objects)
+        .buffered(BATCH_CONCURRENCY)
+        .collect()
+        .await;
 
     // 8) Return the result
-    let response = ObjectsBatchSuccessResponse::basic_sha256(objects);
+    let response = ObjectsBatchSuccessResponse::sha256(transfer, objects);
 
     Ok(Json(response))
 }
 
+// Get the action of an object, depending on whether it exists on the storage server
+async fn get_object(
+    services: &(dyn Services + Send + Sync),
+    operation: &Operation,
+    transfer: Transfer,
+    result: FileStorageMetaResult<'_>,
+    size: u64,
+    user: &str,
+    ref_name: Option<&str>,
+) -> Object {
+    let oid = result.oid;
+    let signer = services.file_storage_link_signer();
+    if result.exists {
+        match signer.get_presigned_link(result).await {
+            Ok(download) => Object::download(oid, size, download),
+            Err(error) => Object::error(oid, size, error),
+        }
+    } else if let (Operation::Upload, Transfer::Multipart) = (operation, transfer) {
+        multipart_upload(services, result, size, user, ref_name).await
+    } else if let (Operation::Upload, Transfer::Chunked) = (operation, transfer) {
+        chunked_upload(services, result, size, user, ref_name).await
+    } else if let Operation::Upload = operation {
+        match signer
+            .post_presigned_link(result, size, user, ref_name)
+            .await
+        {
+            Ok((upload, verify)) => Object::upload(oid, size, upload, verify),
+            Err(error) => Object::error(oid, size, error),
+        }
+    } else if is_quarantined(services, result.repo, oid).await {
+        Object::gone(oid, size)
+    } else {
+        Object::not_found(oid, size)
+    }
+}
+
+// Whether a missing object was quarantined, so that it is reported as gone rather than missing
+async fn is_quarantined(services: &(dyn Services + Send + Sync), repo: &str, oid: &str) -> bool {
+    let quarantine = match services.file_storage_quarantine() {
+        Some(quarantine) => quarantine,
+        None => return false,
+    };
+    match quarantine.is_quarantined(repo, oid).await {
+        Ok(quarantined) => quarantined,
+        Err(e) => {
+            tracing::error!("Failed to look up the quarantine of {}: {}", oid, e);
+            false
+        }
+    }
+}
+
+// Start the upload of an object in several parts, and return the actions uploading each part
+// and the verify action completing the upload
+async fn multipart_upload(
+    services: &(dyn Services + Send + Sync),
+    result: FileStorageMetaResult<'_>,
+    size: u64,
+    user: &str,
+    ref_name: Option<&str>,
+) -> Object {
+    let (repo, oid) = (result.repo, result.oid);
+    let uploader = match services.file_storage_multipart_uploader() {
+        Some(uploader) => uploader,
+        None => return Object::not_found(oid, size),
+    };
+    let upload = uploader
+        .create_multipart_upload(repo, oid, size)
+        .await
+        .map_err(|error| Object::error(oid, size, error));
+    let upload = match upload {
+        Ok(upload) => upload,
+        Err(object) => return object,
+    };
+
+    let signer = services.file_storage_link_signer();
+    match signer.multipart_verify_action(&result, size, user, ref_name, &upload.upload_id) {
+        Some(verify) => Object::multipart_upload(oid, size, upload.parts, verify),
+        None => {
+            let _ = uploader
+                .abort_multipart_upload(repo, oid, &upload.upload_id)
+                .await;
+            Object::error(
+                oid,
+                size,
+                Box::new(std::io::Error::other("Multipart upload not available")),
+            )
+        }
+    }
+}
+
+// Return the actions uploading each chunk of an object through the server, sharing the token of
+// its upload link, and the verify action reassembling them
+async fn chunked_upload(
+    services: &(dyn Services + Send + Sync),
+    result: FileStorageMetaResult<'_>,
+    size: u64,
+    user: &str,
+    ref_name: Option<&str>,
+) -> Object {
+    let oid = result.oid;
+    let signer = services.file_storage_link_signer();
+    let (upload, verify) = match signer
+        .post_presigned_link(result, size, user, ref_name)
+        .await
+    {
+        Ok((upload, Some(verify))) => (upload, verify),
+        Ok((_, None)) => {
+            return Object::error(
+                oid,
+                size,
+                Box::new(std::io::Error::other("Chunked upload not available")),
+            )
+        }
+        Err(error) => return Object::error(oid, size, error),
+    };
+
+    // The chunks are under the upload link, before its query if any
+    let (path, query) = match upload.href.split_once('?') {
+        Some((path, query)) => (path, format!("?{}", query)),
+        None => (upload.href.as_str(), String::new()),
+    };
+    let authorization = upload.header.as_ref().map(|h| h.authorization.as_str());
+    let parts = (0..)
+        .zip(split_in_chunks(size))
+        .map(|(index, (pos, size))| PartAction {
+            action: ObjectAction::new(
+                format!("{}/chunks/{}{}", path, index, query),
+                authorization,
+                upload.expires_in,
+            ),
+            pos,
+            size,
+        })
+        .collect();
+    Object::multipart_upload(oid, size, parts, verify)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
         api::{
-            enums::Operation,
+            enums::{Operation, Transfer},
             objects_batch::{
-                body::{ObjectIdentity, ObjectsBatchRequestPayload},
+                body::{ObjectIdentity, ObjectsBatchRequestPayload, Ref},
                 response::ObjectsBatchSuccessResponse,
             },
             repo_query::QueryRepo,
@@ -107,7 +264,10 @@ mod tests {
         http::{HeaderMap, StatusCode},
         Json,
     };
-    use std::sync::Arc;
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
 
     fn post(
         headers: HeaderMap,
@@ -219,7 +379,33 @@ mod tests {
 
         assert_eq!(
             serde_json::to_string(&res).unwrap(),
-            "{\"transfer\":\"basic\",\"objects\":[{\"oid\":\"not-found-oid\",\"size\":5,\"error\":{\"message\":\"Not found\"}}],\"hash_algo\":\"sha256\"}"
+            "{\"transfer\":\"basic\",\"objects\":[{\"oid\":\"not-found-oid\",\"size\":5,\"error\":{\"code\":404,\"message\":\"Not found\"}}],\"hash_algo\":\"sha256\"}"
+        );
+    }
+
+    #[test]
+    fn test_post_objects_batch_quarantined_object() {
+        let services = get_mock(MockConfig {
+            found: false,
+            quarantine_success: Some(true),
+            quarantined: true,
+            ..MockConfig::default()
+        });
+
+        let Json(res) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            ObjectsBatchRequestPayload::new_download_default(vec![ObjectIdentity::new(
+                "quarantined-oid",
+                5,
+            )]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            "{\"transfer\":\"basic\",\"objects\":[{\"oid\":\"quarantined-oid\",\"size\":5,\"error\":{\"code\":410,\"message\":\"Gone\"}}],\"hash_algo\":\"sha256\"}"
         );
     }
 
@@ -247,6 +433,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_post_objects_batch_signs_concurrently_in_order() {
+        let services = get_mock(MockConfig {
+            signing_delay: Duration::from_millis(100),
+            ..MockConfig::default()
+        });
+        let oids: Vec<String> = (0..32).map(|i| format!("oid{}", i)).collect();
+        let objects = oids.iter().map(|oid| ObjectIdentity::new(oid, 1)).collect();
+
+        let start = Instant::now();
+        let Json(res) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            ObjectsBatchRequestPayload::new_download_default(objects),
+        )
+        .unwrap();
+
+        // 32 links signed one at a time would take 3.2 seconds
+        assert!(start.elapsed() < Duration::from_millis(1500));
+        let json = serde_json::to_value(&res).unwrap();
+        let returned: Vec<&str> = json["objects"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|object| object["oid"].as_str().unwrap())
+            .collect();
+        assert_eq!(returned, oids);
+    }
+
     #[test]
     fn test_post_upload_bad_authorization_missing_write_auth() {
         let services = get_mock(MockConfig {
@@ -278,6 +494,7 @@ mod tests {
             decoded: Some(DecodedTokenMock {
                 operation: Operation::Upload,
                 repo: String::from("a/b/c"),
+                refs: None,
             }),
             ..MockConfig::default()
         });
@@ -299,6 +516,64 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_post_objects_batch_too_many_objects() {
+        let services = get_mock(MockConfig {
+            batch_max_objects: Some(1),
+            ..MockConfig::default()
+        });
+        let (status_code, _) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            ObjectsBatchRequestPayload::new_download_default(vec![
+                ObjectIdentity::new("oid1", 1),
+                ObjectIdentity::new("oid2", 2),
+            ]),
+        )
+        .unwrap_err();
+        assert_eq!(status_code, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    fn upload_to_ref(refs: &str, ref_name: Option<&str>) -> Result<(), (StatusCode, String)> {
+        let services = get_mock(MockConfig {
+            decoded: Some(DecodedTokenMock {
+                operation: Operation::Upload,
+                repo: String::from("a/b/c"),
+                refs: Some(String::from(refs)),
+            }),
+            ..MockConfig::default()
+        });
+        let mut payload =
+            ObjectsBatchRequestPayload::new_upload_default(vec![ObjectIdentity::new("oid", 5)]);
+        payload.ref_ = ref_name.map(|name| Ref {
+            name: String::from(name),
+        });
+        post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            payload,
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn test_post_upload_objects_batch_allowed_ref() {
+        assert!(upload_to_ref("refs/heads/feature/*", Some("refs/heads/feature/a")).is_ok());
+    }
+
+    #[test]
+    fn test_post_upload_objects_batch_protected_ref() {
+        let (status_code, message) =
+            upload_to_ref("refs/heads/feature/*", Some("refs/heads/main")).unwrap_err();
+        assert_eq!(status_code, StatusCode::FORBIDDEN);
+        assert_eq!(message, "You can not upload to refs/heads/main");
+
+        let (status_code, _) = upload_to_ref("refs/heads/feature/*", None).unwrap_err();
+        assert_eq!(status_code, StatusCode::FORBIDDEN);
+    }
+
     #[test]
     fn test_post_upload_objects_batch_not_found_object() {
         let services = get_mock(MockConfig {
@@ -308,6 +583,7 @@ mod tests {
             decoded: Some(DecodedTokenMock {
                 operation: Operation::Upload,
                 repo: String::from("a/b/c"),
+                refs: None,
             }),
             ..MockConfig::default()
         });
@@ -328,4 +604,221 @@ mod tests {
             "{\"transfer\":\"basic\",\"objects\":[{\"oid\":\"found-oid\",\"size\":5,\"actions\":{\"upload\":{\"href\":\"https://example.com/upload/a/b/c/found-oid?size=5\",\"header\":{\"Authorization\":\"token\"},\"expires_in\":60}}}],\"hash_algo\":\"sha256\"}",
         );
     }
+
+    #[test]
+    fn test_post_upload_objects_batch_object_larger_than_4_gib() {
+        let services = get_mock(MockConfig {
+            found: false,
+            with_verify: false,
+            decoded: Some(DecodedTokenMock {
+                operation: Operation::Upload,
+                repo: String::from("a/b/c"),
+                refs: None,
+            }),
+            ..MockConfig::default()
+        });
+
+        let Json(res) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            ObjectsBatchRequestPayload::new_upload_default(vec![ObjectIdentity::new(
+                "oid",
+                4_294_967_297,
+            )]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            "{\"transfer\":\"basic\",\"objects\":[{\"oid\":\"oid\",\"size\":4294967297,\"actions\":{\"upload\":{\"href\":\"https://example.com/upload/a/b/c/oid?size=4294967297\",\"header\":{\"Authorization\":\"token\"},\"expires_in\":60}}}],\"hash_algo\":\"sha256\"}",
+        );
+    }
+
+    #[test]
+    fn test_post_upload_objects_batch_multipart() {
+        let services = get_mock(MockConfig {
+            found: false,
+            multipart_uploaded_size: Some(0),
+            decoded: Some(DecodedTokenMock {
+                operation: Operation::Upload,
+                repo: String::from("a/b/c"),
+                refs: None,
+            }),
+            ..MockConfig::default()
+        });
+        let mut payload =
+            ObjectsBatchRequestPayload::new_upload_default(vec![ObjectIdentity::new("oid", 5)]);
+        payload.transfers = Some(vec![Transfer::Basic, Transfer::Multipart]);
+
+        let Json(res) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            payload,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            "{\"transfer\":\"multipart-basic\",\"objects\":[{\"oid\":\"oid\",\"size\":5,\"actions\":{\"parts\":[{\"href\":\"https://example.com/part/a/b/c/oid?pos=0\",\"expires_in\":60,\"pos\":0,\"size\":2},{\"href\":\"https://example.com/part/a/b/c/oid?pos=2\",\"expires_in\":60,\"pos\":2,\"size\":3}],\"verify\":{\"href\":\"https://example.com/verify/a/b/c/oid?size=5&upload_id=upload-id\",\"header\":{\"Authorization\":\"token\"},\"expires_in\":60}}}],\"hash_algo\":\"sha256\"}",
+        );
+    }
+
+    #[test]
+    fn test_post_objects_batch_multipart_not_available() {
+        let services = get_mock(MockConfig {
+            ..MockConfig::default()
+        });
+        let mut payload = ObjectsBatchRequestPayload::new_download_default(vec![]);
+        payload.transfers = Some(vec![Transfer::Multipart]);
+
+        let (status_code, message) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            payload,
+        )
+        .unwrap_err();
+
+        assert_eq!(status_code, StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(message, "None of the requested transfers is supported");
+    }
+
+    #[test]
+    fn test_post_objects_batch_multipart_download() {
+        let services = get_mock(MockConfig {
+            multipart_uploaded_size: Some(0),
+            ..MockConfig::default()
+        });
+        let mut payload =
+            ObjectsBatchRequestPayload::new_download_default(vec![ObjectIdentity::new("oid", 5)]);
+        payload.transfers = Some(vec![Transfer::Multipart, Transfer::Basic]);
+
+        let Json(res) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            payload,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            "{\"transfer\":\"basic\",\"objects\":[{\"oid\":\"oid\",\"size\":5,\"actions\":{\"download\":{\"href\":\"https://example.com/download/a/b/c/oid?size=50\",\"header\":{\"Authorization\":\"token\"},\"expires_in\":60}}}],\"hash_algo\":\"sha256\"}",
+        );
+    }
+
+    #[test]
+    fn test_post_upload_objects_batch_tus() {
+        let services = get_mock(MockConfig {
+            found: false,
+            with_verify: false,
+            resumable_received: Some(vec![]),
+            decoded: Some(DecodedTokenMock {
+                operation: Operation::Upload,
+                repo: String::from("a/b/c"),
+                refs: None,
+            }),
+            ..MockConfig::default()
+        });
+        let mut payload =
+            ObjectsBatchRequestPayload::new_upload_default(vec![ObjectIdentity::new("oid", 5)]);
+        payload.transfers = Some(vec![Transfer::Basic, Transfer::Tus]);
+
+        let Json(res) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            payload,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            "{\"transfer\":\"tus\",\"objects\":[{\"oid\":\"oid\",\"size\":5,\"actions\":{\"upload\":{\"href\":\"https://example.com/upload/a/b/c/oid?size=5\",\"header\":{\"Authorization\":\"token\"},\"expires_in\":60}}}],\"hash_algo\":\"sha256\"}",
+        );
+    }
+
+    #[test]
+    fn test_post_objects_batch_tus_download() {
+        let services = get_mock(MockConfig {
+            resumable_received: Some(vec![]),
+            ..MockConfig::default()
+        });
+        let mut payload = ObjectsBatchRequestPayload::new_download_default(vec![]);
+        payload.transfers = Some(vec![Transfer::Tus, Transfer::Basic]);
+
+        let Json(res) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            payload,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            "{\"transfer\":\"basic\",\"objects\":[],\"hash_algo\":\"sha256\"}",
+        );
+    }
+
+    #[test]
+    fn test_post_upload_objects_batch_chunked() {
+        let services = get_mock(MockConfig {
+            found: false,
+            chunks_received: Some(vec![]),
+            decoded: Some(DecodedTokenMock {
+                operation: Operation::Upload,
+                repo: String::from("a/b/c"),
+                refs: None,
+            }),
+            ..MockConfig::default()
+        });
+        let mut payload =
+            ObjectsBatchRequestPayload::new_upload_default(vec![ObjectIdentity::new("oid", 5)]);
+        payload.transfers = Some(vec![Transfer::Basic, Transfer::Tus, Transfer::Chunked]);
+
+        let Json(res) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            payload,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            "{\"transfer\":\"chunked\",\"objects\":[{\"oid\":\"oid\",\"size\":5,\"actions\":{\"parts\":[{\"href\":\"https://example.com/upload/a/b/c/oid/chunks/0?size=5\",\"header\":{\"Authorization\":\"token\"},\"expires_in\":60,\"pos\":0,\"size\":5}],\"verify\":{\"href\":\"https://example.com/verify/a/b/c/oid?size=5\",\"header\":{\"Authorization\":\"token\"},\"expires_in\":60}}}],\"hash_algo\":\"sha256\"}",
+        );
+    }
+
+    #[test]
+    fn test_post_upload_objects_batch_chunked_without_verify() {
+        let services = get_mock(MockConfig {
+            found: false,
+            with_verify: false,
+            chunks_received: Some(vec![]),
+            decoded: Some(DecodedTokenMock {
+                operation: Operation::Upload,
+                repo: String::from("a/b/c"),
+                refs: None,
+            }),
+            ..MockConfig::default()
+        });
+        let mut payload =
+            ObjectsBatchRequestPayload::new_upload_default(vec![ObjectIdentity::new("oid", 5)]);
+        payload.transfers = Some(vec![Transfer::Chunked]);
+
+        let Json(res) = post(
+            test_auth_headers("Bearer token"),
+            "a/b/c",
+            services,
+            payload,
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(&res).unwrap();
+        assert_eq!(json["objects"][0]["error"]["code"], 500);
+    }
 }
@@ -0,0 +1,344 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, RawBody, State},
+    http::{HeaderMap, StatusCode},
+};
+use futures_util::TryStreamExt;
+use tokio::io::AsyncReadExt;
+use tokio_util::io::StreamReader;
+
+use crate::{
+    api::{enums::Operation, repo_query::QueryRepo},
+    services::verifying_reader::VerifyingReader,
+    traits::services::Services,
+};
+
+// Minimum size of the chunks of the chunked uploads
+const CHUNK_MIN_SIZE: u64 = 16 * 1024 * 1024;
+
+// Maximum number of chunks of an object, the chunks get larger for larger objects
+const CHUNK_MAX_COUNT: u64 = 1000;
+
+// Split an object in the chunks of its chunked upload, as (position, size) pairs. An empty
+// object still gets one chunk, so that its upload goes through the same steps
+pub fn split_in_chunks(size: u64) -> Vec<(u64, u64)> {
+    let chunk_size = CHUNK_MIN_SIZE.max(size.div_ceil(CHUNK_MAX_COUNT));
+    let count = size.div_ceil(chunk_size).max(1);
+    (0..count)
+        .map(|i| {
+            let pos = i * chunk_size;
+            (pos, chunk_size.min(size - pos))
+        })
+        .collect()
+}
+
+// Upload a chunk of an object, several chunks being sent at the same time
+// Expect the signed upload link token in the header
+// Available at PUT /objects/access/<oid>/chunks/<index>?repo=a/b/c
+pub async fn put_chunk(
+    headers: HeaderMap,
+    query: Query<QueryRepo>,
+    State(services): State<Arc<dyn Services + Send + Sync + 'static>>,
+    Path((oid, index)): Path<(String, u64)>,
+    RawBody(body): RawBody,
+) -> Result<(), (StatusCode, String)> {
+    // 1) Extract and validate
+    let signer = services.file_storage_link_signer();
+    let is_link_ok = signer
+        .check_link(&query.repo, &oid, Some(&headers), Operation::Upload)
+        .await;
+    if !is_link_ok {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            String::from("Link verification failed"),
+        ));
+    }
+    // The oid names the directory of the chunks
+    if oid.len() != 64 || !oid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            String::from("The oid should be a SHA-256"),
+        ));
+    }
+    let uploader = services.file_storage_chunked_uploader().ok_or((
+        StatusCode::NOT_IMPLEMENTED,
+        String::from("Chunked uploads are not available"),
+    ))?;
+    let size = signer.signed_size(&headers).ok_or((
+        StatusCode::UNPROCESSABLE_ENTITY,
+        String::from("The link does not declare the size of the object"),
+    ))?;
+    let (_, chunk_size) = usize::try_from(index)
+        .ok()
+        .and_then(|index| split_in_chunks(size).get(index).copied())
+        .ok_or((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("The object has no chunk {}", index),
+        ))?;
+
+    // 2) Store the chunk, reading at most one byte more than expected to detect a larger one
+    let data = StreamReader::new(body.map_err(std::io::Error::other)).take(chunk_size + 1);
+    let received = uploader
+        .put_chunk(&query.repo, &oid, index, Box::pin(data))
+        .await
+        .map_err(|e| chunk_error(e.to_string()))?;
+    if received != chunk_size {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Received {} bytes, expected {}", received, chunk_size),
+        ));
+    }
+    Ok(())
+}
+
+// Reassemble the chunks of an object uploaded through the server, once they are all uploaded.
+// The content is checked on the way against the oid and the size declared in the batch request,
+// and rejected content is dropped so that the upload starts over. Return false when the object
+// was not uploaded in chunks
+pub async fn reassemble_chunks(
+    services: &(dyn Services + Send + Sync),
+    repo: &str,
+    oid: &str,
+    size: u64,
+    headers: &HeaderMap,
+) -> Result<bool, (StatusCode, String)> {
+    let uploader = match services.file_storage_chunked_uploader() {
+        Some(uploader) => uploader,
+        None => return Ok(false),
+    };
+    let uploaded = uploader
+        .get_chunk_sizes(repo, oid)
+        .await
+        .map_err(|e| chunk_error(e.to_string()))?;
+    if uploaded.is_empty() {
+        return Ok(false);
+    }
+
+    // 1) Each chunk must be uploaded, with its expected size. Missing chunks can still be sent
+    let chunks = split_in_chunks(size);
+    for (index, (_, chunk_size)) in (0..).zip(&chunks) {
+        match uploaded.get(&index) {
+            Some(received) if received == chunk_size => (),
+            Some(received) => {
+                return Err((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!(
+                        "Chunk {} has {} bytes, expected {}",
+                        index, received, chunk_size
+                    ),
+                ))
+            }
+            None => {
+                return Err((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("Chunk {} is missing", index),
+                ))
+            }
+        }
+    }
+
+    // 2) Store the chunks in order as the object
+    let proxy = services.file_storage_proxy().ok_or((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        String::from("No proxy implementation"),
+    ))?;
+    let content = uploader
+        .get_chunks(repo, oid, chunks.len() as u64)
+        .await
+        .map_err(|e| chunk_error(e.to_string()))?;
+    let data = VerifyingReader::new(Box::pin(StreamReader::new(content)), oid, Some(size));
+    let verification = data.outcome();
+    let user = services.file_storage_link_signer().signed_user(headers);
+    let stored = proxy
+        .post(
+            repo,
+            oid,
+            Box::pin(data),
+            "application/octet-stream",
+            user.as_deref(),
+        )
+        .await
+        .map_err(|e| e.to_string());
+    let rejection = match stored {
+        Ok(()) => None,
+        Err(error) => match verification.rejection() {
+            Some(rejection) => Some(rejection),
+            None => return Err(chunk_error(error)),
+        },
+    };
+
+    // 3) Stored or rejected, the chunks are not needed anymore
+    if let Err(e) = uploader.discard_chunks(repo, oid).await {
+        tracing::error!("Failed to discard the chunks of {}: {}", oid, e);
+    }
+    match rejection {
+        Some(rejection) => Err((StatusCode::UNPROCESSABLE_ENTITY, rejection)),
+        None => Ok(true),
+    }
+}
+
+fn chunk_error(error: String) -> (StatusCode, String) {
+    tracing::error!("Chunked upload error: {}", error);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        String::from("Chunked upload error"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::mocks::{get_mock, MockConfig};
+    use axum::body::Body;
+
+    // sha256 of [1, 2, 3]
+    const OID: &str = "039058c6f2c0cb492c533b0a4d14ef77cc0f78abccced5287d84a1a2011cfb81";
+
+    type TestServices = Arc<dyn Services + Send + Sync + 'static>;
+
+    fn get_services(chunks: Option<Vec<Vec<u8>>>, signed_size: u64) -> TestServices {
+        Arc::new(get_mock(MockConfig {
+            found: false,
+            proxy_enabled: true,
+            signed_size: Some(signed_size),
+            chunks_received: chunks,
+            ..MockConfig::default()
+        }))
+    }
+
+    fn put(
+        services: &TestServices,
+        oid: &str,
+        index: u64,
+        body: Vec<u8>,
+    ) -> Result<(), (StatusCode, String)> {
+        crate::aw!(put_chunk(
+            HeaderMap::new(),
+            Query(QueryRepo::new(String::from("a/b/c"))),
+            State(services.clone()),
+            Path((String::from(oid), index)),
+            RawBody(Body::from(body)),
+        ))
+    }
+
+    fn reassemble(services: &TestServices, size: u64) -> Result<bool, (StatusCode, String)> {
+        crate::aw!(reassemble_chunks(
+            services.as_ref(),
+            "a/b/c",
+            OID,
+            size,
+            &HeaderMap::new(),
+        ))
+    }
+
+    fn chunk_sizes(services: &TestServices) -> Vec<(u64, u64)> {
+        let uploader = services.file_storage_chunked_uploader().unwrap();
+        crate::aw!(uploader.get_chunk_sizes("a/b/c", OID))
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_split_in_chunks() {
+        assert_eq!(split_in_chunks(0), vec![(0, 0)]);
+        assert_eq!(split_in_chunks(3), vec![(0, 3)]);
+        assert_eq!(
+            split_in_chunks(CHUNK_MIN_SIZE + 1),
+            vec![(0, CHUNK_MIN_SIZE), (CHUNK_MIN_SIZE, 1)]
+        );
+        // Larger objects get larger chunks
+        let chunks = split_in_chunks(CHUNK_MIN_SIZE * CHUNK_MAX_COUNT * 3);
+        assert_eq!(chunks.len() as u64, CHUNK_MAX_COUNT);
+        assert_eq!(chunks[1], (CHUNK_MIN_SIZE * 3, CHUNK_MIN_SIZE * 3));
+    }
+
+    #[test]
+    fn test_put_chunk_ok() {
+        let services = get_services(Some(vec![]), 3);
+
+        put(&services, OID, 0, vec![1, 2, 3]).unwrap();
+
+        assert_eq!(chunk_sizes(&services), vec![(0, 3)]);
+    }
+
+    #[test]
+    fn test_put_chunk_size_mismatch() {
+        let services = get_services(Some(vec![]), 3);
+
+        let (status_code, message) = put(&services, OID, 0, vec![1, 2, 3, 4]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message, "Received 4 bytes, expected 3");
+    }
+
+    #[test]
+    fn test_put_chunk_out_of_range() {
+        let services = get_services(Some(vec![]), 3);
+
+        let (status_code, message) = put(&services, OID, 1, vec![1]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message, "The object has no chunk 1");
+    }
+
+    #[test]
+    fn test_put_chunk_invalid_oid() {
+        let services = get_services(Some(vec![]), 3);
+
+        let (status_code, message) = put(&services, "../oid", 0, vec![1]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message, "The oid should be a SHA-256");
+    }
+
+    #[test]
+    fn test_put_chunk_not_available() {
+        let services = get_services(None, 3);
+
+        let (status_code, _) = put(&services, OID, 0, vec![1, 2, 3]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[test]
+    fn test_reassemble_chunks() {
+        let services = get_services(Some(vec![vec![1, 2, 3]]), 3);
+
+        assert!(reassemble(&services, 3).unwrap());
+
+        // The object is stored, the chunks are dropped
+        assert!(chunk_sizes(&services).is_empty());
+    }
+
+    #[test]
+    fn test_reassemble_without_chunks() {
+        let services = get_services(Some(vec![]), 3);
+
+        assert!(!reassemble(&services, 3).unwrap());
+    }
+
+    #[test]
+    fn test_reassemble_missing_chunk() {
+        let services = get_services(Some(vec![vec![0; CHUNK_MIN_SIZE as usize]]), 3);
+
+        let (status_code, message) = reassemble(&services, CHUNK_MIN_SIZE + 1).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message, "Chunk 1 is missing");
+        // The uploaded chunks are kept, the missing one can still be sent
+        assert_eq!(chunk_sizes(&services), vec![(0, CHUNK_MIN_SIZE)]);
+    }
+
+    #[test]
+    fn test_reassemble_rejected_content() {
+        let services = get_services(Some(vec![vec![1, 2, 4]]), 3);
+
+        let (status_code, message) = reassemble(&services, 3).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message, "Content hash does not match the oid");
+        assert!(chunk_sizes(&services).is_empty());
+    }
+}
@@ -1,13 +1,18 @@
 use std::sync::Arc;
 
 use axum::{
+    body::StreamBody,
     extract::{Path, Query, State},
-    http::{header, HeaderMap, StatusCode},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
 };
 
 use crate::{
-    api::{enums::Operation, repo_query::QueryRepo},
-    traits::services::Services,
+    api::{
+        enums::Operation,
+        range::{ByteRange, RangeNotSatisfiable},
+        repo_query::QueryRepo,
+    },
+    traits::{file_storage::ObjectStream, services::Services},
 };
 
 pub async fn download_object(
@@ -15,7 +20,7 @@ pub async fn download_object(
     query: Query<QueryRepo>,
     State(services): State<Arc<dyn Services + Send + Sync + 'static>>,
     Path(oid): Path<String>,
-) -> Result<(HeaderMap, Vec<u8>), (StatusCode, String)> {
+) -> Result<(StatusCode, HeaderMap, StreamBody<ObjectStream>), (StatusCode, String)> {
     // 1) Extract and validate
     let is_link_ok = services
         .file_storage_link_signer()
@@ -32,32 +37,80 @@ pub async fn download_object(
             String::from("Link verification failed"),
         ));
     }
+    let proxy = services.file_storage_proxy().ok_or((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        String::from("No proxy implementation"),
+    ))?;
 
-    // 2) Download from proxy
-    let (data, content_type) = services
-        .file_storage_proxy()
-        .ok_or((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            String::from("No proxy implementation"),
-        ))?
-        .get(&query.repo, &oid)
-        .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                String::from("Download error"),
-            )
-        })?;
-
-    // 3) Return, with content type
+    // 2) Resolve the requested range, if any. Objects are content addressed, so the oid is a
+    // strong validator for If-Range
+    let etag = format!("\"{}\"", oid);
     let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Ok(value) = etag.parse() {
+        response_headers.insert(header::ETAG, value);
+    }
+
+    let mut range = None;
+    if headers.contains_key(header::RANGE) {
+        let meta = services
+            .file_storage_meta_requester()
+            .get_meta_result(&query.repo, &oid)
+            .await;
+        if meta.exists {
+            match ByteRange::from_headers(&headers, &etag, meta.size) {
+                Ok(requested) => range = requested.map(|r| (r, meta.size)),
+                Err(RangeNotSatisfiable) => {
+                    response_headers.insert(
+                        header::CONTENT_RANGE,
+                        header_value(format!("bytes */{}", meta.size)),
+                    );
+                    let empty: ObjectStream = Box::pin(futures_util::stream::empty());
+                    return Ok((
+                        StatusCode::RANGE_NOT_SATISFIABLE,
+                        response_headers,
+                        StreamBody::new(empty),
+                    ));
+                }
+            }
+        }
+    }
+
+    // 3) Download from proxy
+    let download = match range {
+        Some((range, _)) => proxy.get_range(&query.repo, &oid, range).await,
+        None => proxy.get(&query.repo, &oid).await,
+    };
+    let (data, content_type) = download.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            String::from("Download error"),
+        )
+    })?;
+
+    // 4) Return, with content type. The body is streamed from the storage as it is sent
     response_headers.insert(
         header::CONTENT_TYPE,
         content_type
             .parse()
             .unwrap_or(header::HeaderValue::from_static("application/octet-stream")),
     );
-    Ok((response_headers, data))
+    let status = match range {
+        Some((range, size)) => {
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                header_value(range.content_range(size)),
+            );
+            response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(range.length()));
+            StatusCode::PARTIAL_CONTENT
+        }
+        None => StatusCode::OK,
+    };
+    Ok((status, response_headers, StreamBody::new(data)))
+}
+
+fn header_value(value: String) -> HeaderValue {
+    HeaderValue::from_str(&value).expect("range headers are always valid ascii")
 }
 
 #[cfg(test)]
@@ -69,6 +122,7 @@ mod tests {
         test_utils::mocks::{get_mock, MockConfig},
     };
     use axum::{
+        body::HttpBody,
         extract::{Path, Query, State},
         http::{header, HeaderMap, StatusCode},
     };
@@ -79,13 +133,24 @@ mod tests {
         repo: &str,
         services: InjectedServices,
         path: &str,
-    ) -> Result<(HeaderMap, Vec<u8>), (StatusCode, String)> {
-        crate::aw!(download_object(
+    ) -> Result<(StatusCode, HeaderMap, Vec<u8>), (StatusCode, String)> {
+        let (status, headers, mut body) = crate::aw!(download_object(
             headers,
             Query(QueryRepo::new(String::from(repo))),
             State(Arc::new(services)),
             Path(String::from(path)),
-        ))
+        ))?;
+        let mut bytes = Vec::new();
+        while let Some(chunk) = crate::aw!(body.data()) {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        Ok((status, headers, bytes))
+    }
+
+    fn range_headers(range: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, range.parse().unwrap());
+        headers
     }
 
     #[test]
@@ -137,13 +202,86 @@ mod tests {
             ..MockConfig::default()
         });
 
-        let (headers, bytes) = download(HeaderMap::new(), "a/b/c", services, "/").unwrap();
+        let (status, headers, bytes) =
+            download(HeaderMap::new(), "a/b/c", services, "oid").unwrap();
 
-        assert_eq!(headers.len(), 1);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.len(), 3);
         assert_eq!(
             headers.get(header::CONTENT_TYPE).unwrap(),
             "application/octet-stream"
         );
+        assert_eq!(headers.get(header::ACCEPT_RANGES).unwrap(), "bytes");
+        assert_eq!(headers.get(header::ETAG).unwrap(), "\"oid\"");
         assert_eq!(bytes, vec![1, 2, 3]);
     }
+
+    #[test]
+    fn test_download_range() {
+        let services = get_mock(MockConfig {
+            proxy_enabled: true,
+            ..MockConfig::default()
+        });
+
+        let (status, headers, bytes) =
+            download(range_headers("bytes=10-14"), "a/b/c", services, "oid").unwrap();
+
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            headers.get(header::CONTENT_RANGE).unwrap(),
+            "bytes 10-14/50"
+        );
+        assert_eq!(headers.get(header::CONTENT_LENGTH).unwrap(), "5");
+        assert_eq!(bytes, vec![10, 11, 12, 13, 14]);
+    }
+
+    #[test]
+    fn test_download_range_if_range_match() {
+        let services = get_mock(MockConfig {
+            proxy_enabled: true,
+            ..MockConfig::default()
+        });
+        let mut headers = range_headers("bytes=-2");
+        headers.insert(header::IF_RANGE, "\"oid\"".parse().unwrap());
+
+        let (status, headers, bytes) = download(headers, "a/b/c", services, "oid").unwrap();
+
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            headers.get(header::CONTENT_RANGE).unwrap(),
+            "bytes 48-49/50"
+        );
+        assert_eq!(bytes, vec![48, 49]);
+    }
+
+    #[test]
+    fn test_download_range_if_range_mismatch() {
+        let services = get_mock(MockConfig {
+            proxy_enabled: true,
+            ..MockConfig::default()
+        });
+        let mut headers = range_headers("bytes=10-14");
+        headers.insert(header::IF_RANGE, "\"other\"".parse().unwrap());
+
+        let (status, headers, bytes) = download(headers, "a/b/c", services, "oid").unwrap();
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get(header::CONTENT_RANGE).is_none());
+        assert_eq!(bytes, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_download_range_not_satisfiable() {
+        let services = get_mock(MockConfig {
+            proxy_enabled: true,
+            ..MockConfig::default()
+        });
+
+        let (status, headers, bytes) =
+            download(range_headers("bytes=50-"), "a/b/c", services, "oid").unwrap();
+
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(headers.get(header::CONTENT_RANGE).unwrap(), "bytes */50");
+        assert!(bytes.is_empty());
+    }
 }
@@ -0,0 +1,667 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, RawBody, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use futures_util::TryStreamExt;
+use tokio::io::AsyncReadExt;
+use tokio_util::io::StreamReader;
+
+use crate::{
+    api::{enums::Operation, repo_query::QueryRepo},
+    services::verifying_reader::VerifyingReader,
+    traits::{file_storage::FileStorageResumableUploader, services::Services},
+};
+
+// Version of the tus protocol, required in the requests and sent back in the responses
+const TUS_VERSION: &str = "1.0.0";
+
+// Media type of the content sent by the PATCH requests
+const TUS_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+const TUS_RESUMABLE: &str = "tus-resumable";
+const UPLOAD_OFFSET: &str = "upload-offset";
+const UPLOAD_LENGTH: &str = "upload-length";
+
+// Upload of an object, as signed in its link
+struct TusUpload<'a> {
+    uploader: &'a (dyn FileStorageResumableUploader + 'static),
+    repo: &'a str,
+    oid: &'a str,
+    size: u64,
+}
+
+// Create the upload of an object, dropping what was received by a previous attempt
+// Implements the creation extension defined at https://tus.io/protocols/resumable-upload#creation
+// Available at POST /objects/access/<oid>?repo=a/b/c
+pub async fn create_upload(
+    headers: HeaderMap,
+    query: Query<QueryRepo>,
+    State(services): State<Arc<dyn Services + Send + Sync + 'static>>,
+    Path(oid): Path<String>,
+    uri: Uri,
+) -> Result<(StatusCode, HeaderMap), (StatusCode, String)> {
+    // 1) Extract and validate
+    check_tus_version(&headers)?;
+    let upload = get_upload(&headers, &query.repo, &oid, services.as_ref()).await?;
+    let length = parse_header(&headers, UPLOAD_LENGTH)?;
+    if length != upload.size {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "Upload length {} does not match the declared size {}",
+                length, upload.size
+            ),
+        ));
+    }
+
+    // 2) Start over
+    upload
+        .uploader
+        .discard(upload.repo, upload.oid)
+        .await
+        .map_err(|e| upload_error(e.to_string()))?;
+
+    // 3) The upload is at the same location as the object
+    let mut response_headers = tus_headers(0);
+    response_headers.insert(header::LOCATION, header_value(uri.to_string()));
+    Ok((StatusCode::CREATED, response_headers))
+}
+
+// Get the offset of an upload, or the headers of a download of the object when the request is
+// not a tus one
+// Implements the HEAD request defined at https://tus.io/protocols/resumable-upload#head
+// Available at HEAD /objects/access/<oid>?repo=a/b/c
+pub async fn head_object(
+    headers: HeaderMap,
+    query: Query<QueryRepo>,
+    State(services): State<Arc<dyn Services + Send + Sync + 'static>>,
+    Path(oid): Path<String>,
+) -> Response {
+    if !headers.contains_key(TUS_RESUMABLE) {
+        return get_object_headers(&headers, &query.repo, &oid, services.as_ref())
+            .await
+            .into_response();
+    }
+    get_upload_offset(&headers, &query.repo, &oid, services.as_ref())
+        .await
+        .into_response()
+}
+
+// Headers a download of the object would have, answered from its meta data without reading it
+async fn get_object_headers(
+    headers: &HeaderMap,
+    repo: &str,
+    oid: &str,
+    services: &(dyn Services + Send + Sync),
+) -> Result<(StatusCode, HeaderMap), (StatusCode, String)> {
+    let is_link_ok = services
+        .file_storage_link_signer()
+        .check_link(repo, oid, Some(headers), Operation::Download)
+        .await;
+    if !is_link_ok {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            String::from("Link verification failed"),
+        ));
+    }
+    let meta = services
+        .file_storage_meta_requester()
+        .get_meta_result(repo, oid)
+        .await;
+    if !meta.exists {
+        return Err((StatusCode::NOT_FOUND, String::from("Object not found")));
+    }
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response_headers.insert(header::ETAG, header_value(format!("\"{}\"", oid)));
+    response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(meta.size));
+    Ok((StatusCode::OK, response_headers))
+}
+
+async fn get_upload_offset(
+    headers: &HeaderMap,
+    repo: &str,
+    oid: &str,
+    services: &(dyn Services + Send + Sync),
+) -> Result<(StatusCode, HeaderMap), (StatusCode, String)> {
+    check_tus_version(headers)?;
+    let upload = get_upload(headers, repo, oid, services).await?;
+    let (offset, _) = get_offset(services, &upload).await?;
+
+    let mut response_headers = tus_headers(offset);
+    response_headers.insert(UPLOAD_LENGTH, HeaderValue::from(upload.size));
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok((StatusCode::OK, response_headers))
+}
+
+// Append to an upload the content following its offset. Once all of it is received, the object
+// is checked against its oid and stored
+// Implements the PATCH request defined at https://tus.io/protocols/resumable-upload#patch
+// Available at PATCH /objects/access/<oid>?repo=a/b/c
+pub async fn patch_object(
+    headers: HeaderMap,
+    query: Query<QueryRepo>,
+    State(services): State<Arc<dyn Services + Send + Sync + 'static>>,
+    Path(oid): Path<String>,
+    RawBody(body): RawBody,
+) -> Result<(StatusCode, HeaderMap), (StatusCode, String)> {
+    // 1) Extract and validate
+    check_tus_version(&headers)?;
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    if content_type != Some(TUS_CONTENT_TYPE) {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Bad Content-Type header, should be {}", TUS_CONTENT_TYPE),
+        ));
+    }
+    let requested_offset = parse_header(&headers, UPLOAD_OFFSET)?;
+    let upload = get_upload(&headers, &query.repo, &oid, services.as_ref()).await?;
+
+    // 2) The content must follow what was already received
+    let (offset, stored) = get_offset(services.as_ref(), &upload).await?;
+    if requested_offset != offset {
+        return Err((
+            StatusCode::CONFLICT,
+            format!(
+                "Upload offset {} does not match the received size {}",
+                requested_offset, offset
+            ),
+        ));
+    }
+    if stored {
+        return Ok((StatusCode::NO_CONTENT, tus_headers(offset)));
+    }
+
+    // 3) Append, keeping what was received when the connection is lost. The uploader checks the
+    // offset again, as another request may have appended meanwhile
+    let remaining = upload.size.saturating_sub(offset);
+    let data = StreamReader::new(body.map_err(std::io::Error::other)).take(remaining);
+    let offset = upload
+        .uploader
+        .append(upload.repo, upload.oid, offset, Box::pin(data))
+        .await
+        .map_err(append_error)?;
+
+    // 4) Store the object once complete. A previous completion may have failed, an empty PATCH
+    // at the end of the upload tries it again
+    if offset == upload.size {
+        complete_upload(services.as_ref(), &upload, &headers).await?;
+    }
+    Ok((StatusCode::NO_CONTENT, tus_headers(offset)))
+}
+
+// Store the received content as the object, checked on the way against the oid and the size
+// declared in the batch request. Rejected content is dropped, so that the upload starts over
+async fn complete_upload(
+    services: &(dyn Services + Send + Sync),
+    upload: &TusUpload<'_>,
+    headers: &HeaderMap,
+) -> Result<(), (StatusCode, String)> {
+    let proxy = services.file_storage_proxy().ok_or((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        String::from("No proxy implementation"),
+    ))?;
+    let content = upload
+        .uploader
+        .get_content(upload.repo, upload.oid)
+        .await
+        .map_err(|e| upload_error(e.to_string()))?;
+    let data = VerifyingReader::new(
+        Box::pin(StreamReader::new(content)),
+        upload.oid,
+        Some(upload.size),
+    );
+    let verification = data.outcome();
+    let uploader = services.file_storage_link_signer().signed_user(headers);
+    let stored = proxy
+        .post(
+            upload.repo,
+            upload.oid,
+            Box::pin(data),
+            "application/octet-stream",
+            uploader.as_deref(),
+        )
+        .await
+        .map_err(|e| e.to_string());
+    let rejection = match stored {
+        Ok(()) => None,
+        Err(error) => match verification.rejection() {
+            Some(rejection) => Some(rejection),
+            None => return Err(upload_error(error)),
+        },
+    };
+
+    // Stored or rejected, the received content is not needed anymore
+    if let Err(e) = upload.uploader.discard(upload.repo, upload.oid).await {
+        tracing::error!("Failed to discard the upload of {}: {}", upload.oid, e);
+    }
+    match rejection {
+        Some(rejection) => Err((StatusCode::UNPROCESSABLE_ENTITY, rejection)),
+        None => Ok(()),
+    }
+}
+
+// Check the link of the upload, and get the size signed in it
+async fn get_upload<'a>(
+    headers: &HeaderMap,
+    repo: &'a str,
+    oid: &'a str,
+    services: &'a (dyn Services + Send + Sync),
+) -> Result<TusUpload<'a>, (StatusCode, String)> {
+    let is_link_ok = services
+        .file_storage_link_signer()
+        .check_link(repo, oid, Some(headers), Operation::Upload)
+        .await;
+    if !is_link_ok {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            String::from("Link verification failed"),
+        ));
+    }
+    // The oid names the file of the upload
+    if oid.len() != 64 || !oid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            String::from("The oid should be a SHA-256"),
+        ));
+    }
+    let uploader = services.file_storage_resumable_uploader().ok_or((
+        StatusCode::NOT_IMPLEMENTED,
+        String::from("Resumable uploads are not available"),
+    ))?;
+    let size = services
+        .file_storage_link_signer()
+        .signed_size(headers)
+        .ok_or((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            String::from("The link does not declare the size of the object"),
+        ))?;
+    Ok(TusUpload {
+        uploader,
+        repo,
+        oid,
+        size,
+    })
+}
+
+// Size received so far, which is the whole object once it is stored, and whether it is stored
+async fn get_offset(
+    services: &(dyn Services + Send + Sync),
+    upload: &TusUpload<'_>,
+) -> Result<(u64, bool), (StatusCode, String)> {
+    let stored = services
+        .file_storage_meta_requester()
+        .get_meta_result(upload.repo, upload.oid)
+        .await
+        .exists;
+    if stored {
+        return Ok((upload.size, true));
+    }
+    let offset = upload
+        .uploader
+        .get_offset(upload.repo, upload.oid)
+        .await
+        .map_err(|e| upload_error(e.to_string()))?;
+    Ok((offset, false))
+}
+
+fn check_tus_version(headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    match headers.get(TUS_RESUMABLE) {
+        Some(version) if version == TUS_VERSION => Ok(()),
+        _ => Err((
+            StatusCode::PRECONDITION_FAILED,
+            format!("The Tus-Resumable header should be {}", TUS_VERSION),
+        )),
+    }
+}
+
+fn parse_header(headers: &HeaderMap, name: &str) -> Result<u64, (StatusCode, String)> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .ok_or((StatusCode::BAD_REQUEST, format!("Invalid {} header", name)))
+}
+
+fn tus_headers(offset: u64) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    headers.insert(UPLOAD_OFFSET, HeaderValue::from(offset));
+    headers
+}
+
+fn header_value(value: String) -> HeaderValue {
+    HeaderValue::from_str(&value).expect("uris are always valid ascii")
+}
+
+// The loser of concurrent appends to an upload gets a conflict, like a client late on the offset
+fn append_error(error: Box<dyn std::error::Error>) -> (StatusCode, String) {
+    match error.downcast_ref::<std::io::Error>() {
+        Some(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            (StatusCode::CONFLICT, e.to_string())
+        }
+        _ => upload_error(error.to_string()),
+    }
+}
+
+fn upload_error(error: String) -> (StatusCode, String) {
+    tracing::error!("Upload error: {}", error);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        String::from("Upload error"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::mocks::{get_mock, MockConfig};
+    use axum::body::Body;
+
+    // sha256 of [1, 2, 3]
+    const OID: &str = "039058c6f2c0cb492c533b0a4d14ef77cc0f78abccced5287d84a1a2011cfb81";
+
+    type TestServices = Arc<dyn Services + Send + Sync + 'static>;
+
+    fn get_services(received: Option<Vec<u8>>) -> TestServices {
+        Arc::new(get_mock(MockConfig {
+            found: false,
+            proxy_enabled: true,
+            signed_size: Some(3),
+            resumable_received: received,
+            ..MockConfig::default()
+        }))
+    }
+
+    fn tus_request_headers(offset: Option<u64>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(TUS_CONTENT_TYPE),
+        );
+        if let Some(offset) = offset {
+            headers.insert(UPLOAD_OFFSET, HeaderValue::from(offset));
+        }
+        headers
+    }
+
+    fn query() -> Query<QueryRepo> {
+        Query(QueryRepo::new(String::from("a/b/c")))
+    }
+
+    fn head(headers: HeaderMap, services: &TestServices) -> Response {
+        crate::aw!(head_object(
+            headers,
+            query(),
+            State(services.clone()),
+            Path(String::from(OID)),
+        ))
+    }
+
+    fn patch(
+        headers: HeaderMap,
+        services: &TestServices,
+        oid: &str,
+        body: Vec<u8>,
+    ) -> Result<(StatusCode, HeaderMap), (StatusCode, String)> {
+        crate::aw!(patch_object(
+            headers,
+            query(),
+            State(services.clone()),
+            Path(String::from(oid)),
+            RawBody(Body::from(body)),
+        ))
+    }
+
+    fn received_size(services: &TestServices) -> u64 {
+        let uploader = services.file_storage_resumable_uploader().unwrap();
+        crate::aw!(uploader.get_offset("a/b/c", OID)).unwrap()
+    }
+
+    #[test]
+    fn test_head_upload_offset() {
+        let services = get_services(Some(vec![1]));
+
+        let response = head(tus_request_headers(None), &services);
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[UPLOAD_OFFSET], "1");
+        assert_eq!(response.headers()[UPLOAD_LENGTH], "3");
+        assert_eq!(response.headers()[TUS_RESUMABLE], TUS_VERSION);
+    }
+
+    #[test]
+    fn test_head_stored_object() {
+        let services: TestServices = Arc::new(get_mock(MockConfig {
+            proxy_enabled: true,
+            signed_size: Some(3),
+            resumable_received: Some(vec![]),
+            ..MockConfig::default()
+        }));
+
+        let response = head(tus_request_headers(None), &services);
+
+        assert_eq!(response.headers()[UPLOAD_OFFSET], "3");
+    }
+
+    #[test]
+    fn test_head_without_tus_downloads() {
+        let services: TestServices = Arc::new(get_mock(MockConfig {
+            size: 3,
+            proxy_enabled: true,
+            proxy_get_success: false,
+            ..MockConfig::default()
+        }));
+
+        // Answered without reading the object, which would fail
+        let response = head(HeaderMap::new(), &services);
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(UPLOAD_OFFSET));
+        assert_eq!(response.headers()[header::ETAG], format!("\"{}\"", OID));
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "3");
+    }
+
+    #[test]
+    fn test_head_without_tus_missing_object() {
+        let services = get_services(Some(vec![1]));
+
+        let response = head(HeaderMap::new(), &services);
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_patch_missing_tus_version() {
+        let services = get_services(Some(vec![]));
+        let mut headers = tus_request_headers(Some(0));
+        headers.remove(TUS_RESUMABLE);
+
+        let (status_code, _) = patch(headers, &services, OID, vec![1, 2, 3]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::PRECONDITION_FAILED);
+    }
+
+    #[test]
+    fn test_patch_bad_content_type() {
+        let services = get_services(Some(vec![]));
+        let mut headers = tus_request_headers(Some(0));
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        );
+
+        let (status_code, message) = patch(headers, &services, OID, vec![1, 2, 3]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            message,
+            "Bad Content-Type header, should be application/offset+octet-stream"
+        );
+    }
+
+    #[test]
+    fn test_patch_invalid_oid() {
+        let services = get_services(Some(vec![]));
+
+        let (status_code, message) =
+            patch(tus_request_headers(Some(0)), &services, "../oid", vec![1]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message, "The oid should be a SHA-256");
+    }
+
+    #[test]
+    fn test_patch_not_available() {
+        let services = get_services(None);
+
+        let (status_code, _) =
+            patch(tus_request_headers(Some(0)), &services, OID, vec![1]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[test]
+    fn test_patch_offset_conflict() {
+        let services = get_services(Some(vec![1]));
+
+        let (status_code, message) =
+            patch(tus_request_headers(Some(0)), &services, OID, vec![1, 2, 3]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::CONFLICT);
+        assert_eq!(
+            message,
+            "Upload offset 0 does not match the received size 1"
+        );
+        assert_eq!(received_size(&services), 1);
+    }
+
+    #[test]
+    fn test_patch_concurrent_appends() {
+        let services = get_services(Some(vec![1]));
+        let (mut sender, body) = Body::channel();
+
+        let (first, second) = crate::aw!(async {
+            // The first request is still receiving its content when the second one appends
+            let first = patch_object(
+                tus_request_headers(Some(1)),
+                query(),
+                State(services.clone()),
+                Path(String::from(OID)),
+                RawBody(body),
+            );
+            let second = async {
+                let second = patch_object(
+                    tus_request_headers(Some(1)),
+                    query(),
+                    State(services.clone()),
+                    Path(String::from(OID)),
+                    RawBody(Body::from(vec![2])),
+                )
+                .await;
+                sender.send_data(vec![2].into()).await.unwrap();
+                drop(sender);
+                second
+            };
+            tokio::join!(first, second)
+        });
+
+        assert_eq!(second.unwrap().1[UPLOAD_OFFSET], "2");
+        let (status_code, message) = first.unwrap_err();
+        assert_eq!(status_code, StatusCode::CONFLICT);
+        assert_eq!(
+            message,
+            "Upload offset 1 does not match the received size 2"
+        );
+        assert_eq!(received_size(&services), 2);
+    }
+
+    #[test]
+    fn test_patch_partial() {
+        let services = get_services(Some(vec![1]));
+
+        let (status_code, headers) =
+            patch(tus_request_headers(Some(1)), &services, OID, vec![2]).unwrap();
+
+        assert_eq!(status_code, StatusCode::NO_CONTENT);
+        assert_eq!(headers[UPLOAD_OFFSET], "2");
+        assert_eq!(received_size(&services), 2);
+    }
+
+    #[test]
+    fn test_patch_completes_upload() {
+        let services = get_services(Some(vec![1]));
+
+        let (status_code, headers) =
+            patch(tus_request_headers(Some(1)), &services, OID, vec![2, 3, 4]).unwrap();
+
+        assert_eq!(status_code, StatusCode::NO_CONTENT);
+        assert_eq!(headers[UPLOAD_OFFSET], "3");
+        // The object is stored, the received content is dropped
+        assert_eq!(received_size(&services), 0);
+    }
+
+    #[test]
+    fn test_patch_rejected_content() {
+        let services = get_services(Some(vec![1]));
+
+        let (status_code, message) =
+            patch(tus_request_headers(Some(1)), &services, OID, vec![2, 4]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message, "Content hash does not match the oid");
+        assert_eq!(received_size(&services), 0);
+    }
+
+    #[test]
+    fn test_create_upload() {
+        let services = get_services(Some(vec![1]));
+        let mut headers = tus_request_headers(None);
+        headers.insert(UPLOAD_LENGTH, HeaderValue::from(3));
+        let uri: Uri = format!("/objects/access/{}?repo=a/b/c", OID)
+            .parse()
+            .unwrap();
+
+        let (status_code, response_headers) = crate::aw!(create_upload(
+            headers,
+            query(),
+            State(services.clone()),
+            Path(String::from(OID)),
+            uri.clone(),
+        ))
+        .unwrap();
+
+        assert_eq!(status_code, StatusCode::CREATED);
+        assert_eq!(response_headers[header::LOCATION], uri.to_string());
+        assert_eq!(received_size(&services), 0);
+    }
+
+    #[test]
+    fn test_create_upload_length_mismatch() {
+        let services = get_services(Some(vec![]));
+        let mut headers = tus_request_headers(None);
+        headers.insert(UPLOAD_LENGTH, HeaderValue::from(4));
+
+        let (status_code, message) = crate::aw!(create_upload(
+            headers,
+            query(),
+            State(services),
+            Path(String::from(OID)),
+            Uri::from_static("/"),
+        ))
+        .unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            message,
+            "Upload length 4 does not match the declared size 3"
+        );
+    }
+}
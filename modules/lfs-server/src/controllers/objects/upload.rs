@@ -1,13 +1,15 @@
 use std::sync::Arc;
 
 use axum::{
-    body::Bytes,
-    extract::{Path, Query, State},
-    http::{HeaderMap, StatusCode},
+    extract::{Path, Query, RawBody, State},
+    http::{header, HeaderMap, StatusCode},
 };
+use futures_util::TryStreamExt;
+use tokio_util::io::StreamReader;
 
 use crate::{
     api::{enums::Operation, repo_query::QueryRepo},
+    services::verifying_reader::VerifyingReader,
     traits::services::Services,
 };
 
@@ -16,7 +18,7 @@ pub async fn upload_object(
     query: Query<QueryRepo>,
     State(services): State<Arc<dyn Services + Send + Sync + 'static>>,
     Path(oid): Path<String>,
-    body: Bytes,
+    RawBody(body): RawBody,
 ) -> Result<(), (StatusCode, String)> {
     // 1) Extract and validate
     let is_link_ok = services
@@ -30,7 +32,25 @@ pub async fn upload_object(
         ));
     }
 
-    // 2) Get content type
+    // 2) Reject a body of another size than the one signed in the link before receiving it
+    let declared_size = services.file_storage_link_signer().signed_size(&headers);
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let (Some(declared_size), Some(content_length)) = (declared_size, content_length) {
+        if declared_size != content_length {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "Content length {} does not match the declared size {}",
+                    content_length, declared_size
+                ),
+            ));
+        }
+    }
+
+    // 3) Get content type
     let content_type = headers
         .get("content-type")
         .map(|v| {
@@ -43,16 +63,30 @@ pub async fn upload_object(
         })
         .unwrap_or(Ok("application/octet-stream"))?;
 
-    // 3) Upload
+    // 4) Upload, streaming the body to the storage as it is received. It is checked on the way
+    // against the oid and the size declared in the batch request
+    let uploader = services.file_storage_link_signer().signed_user(&headers);
+    let data = StreamReader::new(body.map_err(std::io::Error::other));
+    let data = VerifyingReader::new(Box::pin(data), &oid, declared_size);
+    let verification = data.outcome();
     services
         .file_storage_proxy()
         .ok_or((
             StatusCode::INTERNAL_SERVER_ERROR,
             String::from("No proxy implementation"),
         ))?
-        .post(&query.repo, oid.as_str(), body.to_vec(), content_type)
+        .post(
+            &query.repo,
+            oid.as_str(),
+            Box::pin(data),
+            content_type,
+            uploader.as_deref(),
+        )
         .await
         .map_err(|e| {
+            if let Some(rejection) = verification.rejection() {
+                return (StatusCode::UNPROCESSABLE_ENTITY, rejection);
+            }
             tracing::error!("Upload error: {:?}", e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -70,12 +104,15 @@ mod tests {
         test_utils::mocks::{get_mock, MockConfig},
     };
     use axum::{
-        body::Bytes,
-        extract::{Path, Query, State},
+        body::Body,
+        extract::{Path, Query, RawBody, State},
         http::{header, HeaderMap, StatusCode},
     };
     use std::sync::Arc;
 
+    // sha256 of [1, 2, 3]
+    const OID: &str = "039058c6f2c0cb492c533b0a4d14ef77cc0f78abccced5287d84a1a2011cfb81";
+
     fn upload(
         headers: HeaderMap,
         repo: &str,
@@ -88,7 +125,7 @@ mod tests {
             Query(QueryRepo::new(String::from(repo))),
             State(Arc::new(services)),
             Path(String::from(path)),
-            Bytes::from(body),
+            RawBody(Body::from(body)),
         ))
     }
 
@@ -134,19 +171,71 @@ mod tests {
         });
 
         let (status_code, message) =
-            upload(HeaderMap::new(), "a/b/c", services, "/", vec![1, 2, 3]).unwrap_err();
+            upload(HeaderMap::new(), "a/b/c", services, OID, vec![1, 2, 3]).unwrap_err();
 
         assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
         assert_eq!(message, "Upload error");
     }
 
+    #[test]
+    fn test_upload_hash_mismatch() {
+        let services = get_mock(MockConfig {
+            proxy_enabled: true,
+            ..MockConfig::default()
+        });
+
+        let (status_code, message) =
+            upload(HeaderMap::new(), "a/b/c", services, OID, vec![1, 2, 4]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message, "Content hash does not match the oid");
+    }
+
+    #[test]
+    fn test_upload_size_mismatch() {
+        let services = get_mock(MockConfig {
+            proxy_enabled: true,
+            signed_size: Some(4),
+            ..MockConfig::default()
+        });
+
+        let (status_code, message) =
+            upload(HeaderMap::new(), "a/b/c", services, OID, vec![1, 2, 3]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message, "Received 3 bytes, expected 4");
+    }
+
+    #[test]
+    fn test_upload_content_length_mismatch() {
+        let services = get_mock(MockConfig {
+            proxy_enabled: true,
+            signed_size: Some(3),
+            ..MockConfig::default()
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_LENGTH, "4".parse().unwrap());
+        let (status_code, message) =
+            upload(headers, "a/b/c", services, OID, vec![1, 2, 3, 4]).unwrap_err();
+
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            message,
+            "Content length 4 does not match the declared size 3"
+        );
+    }
+
     #[test]
     fn test_upload_ok() {
         let services = get_mock(MockConfig {
             proxy_enabled: true,
+            signed_size: Some(3),
             ..MockConfig::default()
         });
 
-        upload(HeaderMap::new(), "a/b/c", services, "/", vec![1, 2, 3]).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_LENGTH, "3".parse().unwrap());
+        upload(headers, "a/b/c", services, OID, vec![1, 2, 3]).unwrap();
     }
 }
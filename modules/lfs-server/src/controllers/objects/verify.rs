@@ -0,0 +1,400 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Json, Query, State},
+    http::{HeaderMap, StatusCode},
+};
+
+use crate::{
+    api::{objects_batch::body::ObjectIdentity, repo_query::QueryRepo},
+    controllers::objects::chunks::reassemble_chunks,
+    traits::services::Services,
+};
+
+// Verify that an object has been uploaded successfully
+// Implements the verify action defined at https://github.com/git-lfs/git-lfs/blob/main/docs/api/basic-transfers.md
+// Expect the signed verify link token in the header
+// Available at /objects/verify?repo=a/b/c
+pub async fn verify_object(
+    headers: HeaderMap,
+    query: Query<QueryRepo>,
+    State(services): State<Arc<dyn Services + Send + Sync + 'static>>,
+    Json(payload): Json<ObjectIdentity>,
+) -> Result<(), (StatusCode, String)> {
+    // 1) Extract and validate
+    let signer = services.file_storage_link_signer();
+    let is_link_ok = signer
+        .check_verify_link(&query.repo, &payload.oid, Some(&headers))
+        .await;
+    if !is_link_ok {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            String::from("Link verification failed"),
+        ));
+    }
+    let size = payload.size;
+    if signer.signed_size(&headers).is_some_and(|s| s != size) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            String::from("Size does not match the batch request"),
+        ));
+    }
+
+    // 2) An upload made in several parts is completed once they are all uploaded, and
+    // discarded otherwise
+    if let Some(upload_id) = signer.signed_upload_id(&headers) {
+        complete_multipart_upload(
+            services.as_ref(),
+            &query.repo,
+            &payload.oid,
+            &upload_id,
+            size,
+        )
+        .await?;
+    }
+
+    // 3) The object must exist, with the expected size. An object uploaded in chunks through the
+    // server is stored once they are reassembled
+    let meta_requester = services.file_storage_meta_requester();
+    let mut result = meta_requester
+        .get_meta_result(&query.repo, &payload.oid)
+        .await;
+    if !result.exists
+        && reassemble_chunks(services.as_ref(), &query.repo, &payload.oid, size, &headers).await?
+    {
+        result = meta_requester
+            .get_meta_result(&query.repo, &payload.oid)
+            .await;
+    }
+    if !result.exists {
+        return Err((StatusCode::NOT_FOUND, String::from("Not found")));
+    }
+    if result.size != size {
+        let message = format!("Expected {} bytes, found {}", size, result.size);
+        return Err(reject_object(services.as_ref(), &query.repo, &payload.oid, message).await);
+    }
+
+    // 4) The content is already checked while it is received in proxy mode. Otherwise, check its
+    // hash if the storage is able to compute it, and the object is small enough to be read back
+    let read_back = services
+        .verify_content_max_size()
+        .is_some_and(|max_size| size <= max_size);
+    if services.file_storage_proxy().is_none() && read_back {
+        let hash = meta_requester
+            .get_content_sha256(&query.repo, &payload.oid)
+            .await
+            .map_err(|e| verify_error(e.to_string()))?;
+        if hash.is_some_and(|h| !h.eq_ignore_ascii_case(&payload.oid)) {
+            let message = String::from("Content hash does not match the oid");
+            return Err(reject_object(services.as_ref(), &query.repo, &payload.oid, message).await);
+        }
+    }
+
+    Ok(())
+}
+
+// Move aside an object failing the verification, so that the next batch reports it as missing
+// and the client uploads it again
+async fn reject_object(
+    services: &(dyn Services + Send + Sync),
+    repo: &str,
+    oid: &str,
+    message: String,
+) -> (StatusCode, String) {
+    if let Some(quarantine) = services.file_storage_quarantine() {
+        if let Err(e) = quarantine.quarantine(repo, oid).await {
+            return verify_error(e.to_string());
+        }
+        tracing::warn!("Quarantined {}/{}: {}", repo, oid, message);
+    }
+    (StatusCode::UNPROCESSABLE_ENTITY, message)
+}
+
+fn verify_error(error: String) -> (StatusCode, String) {
+    tracing::error!("Verify error: {}", error);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        String::from("Verify error"),
+    )
+}
+
+async fn complete_multipart_upload(
+    services: &(dyn Services + Send + Sync),
+    repo: &str,
+    oid: &str,
+    upload_id: &str,
+    size: u64,
+) -> Result<(), (StatusCode, String)> {
+    let uploader = services.file_storage_multipart_uploader().ok_or((
+        StatusCode::NOT_IMPLEMENTED,
+        String::from("Multipart upload not available"),
+    ))?;
+    let multipart_error = |e: Box<dyn std::error::Error>| {
+        tracing::error!("Multipart upload error: {:?}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            String::from("Multipart upload error"),
+        )
+    };
+
+    let uploaded = uploader
+        .get_uploaded_size(repo, oid, upload_id)
+        .await
+        .map_err(multipart_error)?;
+    if uploaded != size {
+        let aborted = uploader.abort_multipart_upload(repo, oid, upload_id).await;
+        aborted.map_err(multipart_error)?;
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Expected {} bytes, uploaded {}", size, uploaded),
+        ));
+    }
+    uploader
+        .complete_multipart_upload(repo, oid, upload_id)
+        .await
+        .map_err(multipart_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify_object;
+    use crate::{
+        api::{objects_batch::body::ObjectIdentity, repo_query::QueryRepo},
+        services::injected_services::InjectedServices,
+        test_utils::{
+            helpers::assert_http_error,
+            mocks::{get_mock, MockConfig},
+        },
+    };
+    use axum::{
+        extract::{Json, Query, State},
+        http::{HeaderMap, StatusCode},
+    };
+    use std::sync::Arc;
+
+    fn verify(
+        services: InjectedServices,
+        oid: &str,
+        size: u64,
+    ) -> Result<(), (StatusCode, String)> {
+        crate::aw!(verify_object(
+            HeaderMap::new(),
+            Query(QueryRepo::new(String::from("a/b/c"))),
+            State(Arc::new(services)),
+            Json(ObjectIdentity::new(oid, size)),
+        ))
+    }
+
+    #[test]
+    fn test_verify_bad_signature() {
+        let services = get_mock(MockConfig {
+            check_link_succeed: false,
+            ..MockConfig::default()
+        });
+
+        assert_http_error(
+            verify(services, "oid", 50),
+            StatusCode::UNAUTHORIZED,
+            "Link verification failed",
+        );
+    }
+
+    #[test]
+    fn test_verify_not_found() {
+        let services = get_mock(MockConfig {
+            found: false,
+            ..MockConfig::default()
+        });
+
+        assert_http_error(
+            verify(services, "oid", 50),
+            StatusCode::NOT_FOUND,
+            "Not found",
+        );
+    }
+
+    #[test]
+    fn test_verify_size_mismatch() {
+        let services = get_mock(MockConfig {
+            ..MockConfig::default()
+        });
+
+        assert_http_error(
+            verify(services, "oid", 51),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Expected 51 bytes, found 50",
+        );
+    }
+
+    #[test]
+    fn test_verify_signed_size_mismatch() {
+        let services = get_mock(MockConfig {
+            signed_size: Some(51),
+            ..MockConfig::default()
+        });
+
+        assert_http_error(
+            verify(services, "oid", 50),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Size does not match the batch request",
+        );
+    }
+
+    #[test]
+    fn test_verify_hash_mismatch() {
+        let services = get_mock(MockConfig {
+            content_sha256: Some(String::from("other")),
+            ..MockConfig::default()
+        });
+
+        assert_http_error(
+            verify(services, "oid", 50),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Content hash does not match the oid",
+        );
+    }
+
+    #[test]
+    fn test_verify_hash_mismatch_quarantined() {
+        let services = get_mock(MockConfig {
+            content_sha256: Some(String::from("other")),
+            quarantine_success: Some(true),
+            ..MockConfig::default()
+        });
+
+        assert_http_error(
+            verify(services, "oid", 50),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Content hash does not match the oid",
+        );
+    }
+
+    #[test]
+    fn test_verify_size_mismatch_quarantine_failure() {
+        let services = get_mock(MockConfig {
+            quarantine_success: Some(false),
+            ..MockConfig::default()
+        });
+
+        // The object could not be moved aside, the verification can be retried
+        assert_http_error(
+            verify(services, "oid", 51),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Verify error",
+        );
+    }
+
+    #[test]
+    fn test_verify_hash_not_checked_above_max_size() {
+        let services = get_mock(MockConfig {
+            content_sha256: Some(String::from("other")),
+            verify_content_max_size: Some(49),
+            ..MockConfig::default()
+        });
+
+        verify(services, "oid", 50).unwrap();
+    }
+
+    #[test]
+    fn test_verify_hash_not_checked_by_default() {
+        let services = get_mock(MockConfig {
+            content_sha256: Some(String::from("other")),
+            verify_content_max_size: None,
+            ..MockConfig::default()
+        });
+
+        verify(services, "oid", 50).unwrap();
+    }
+
+    #[test]
+    fn test_verify_hash_not_checked_in_proxy_mode() {
+        let services = get_mock(MockConfig {
+            proxy_enabled: true,
+            content_sha256: Some(String::from("other")),
+            ..MockConfig::default()
+        });
+
+        verify(services, "oid", 50).unwrap();
+    }
+
+    #[test]
+    fn test_verify_multipart_upload() {
+        let services = get_mock(MockConfig {
+            signed_upload_id: Some(String::from("upload-id")),
+            multipart_uploaded_size: Some(50),
+            ..MockConfig::default()
+        });
+
+        verify(services, "oid", 50).unwrap();
+    }
+
+    #[test]
+    fn test_verify_incomplete_multipart_upload() {
+        let services = get_mock(MockConfig {
+            signed_upload_id: Some(String::from("upload-id")),
+            multipart_uploaded_size: Some(30),
+            ..MockConfig::default()
+        });
+
+        assert_http_error(
+            verify(services, "oid", 50),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Expected 50 bytes, uploaded 30",
+        );
+    }
+
+    #[test]
+    fn test_verify_multipart_upload_completion_failure() {
+        let services = get_mock(MockConfig {
+            signed_upload_id: Some(String::from("upload-id")),
+            multipart_uploaded_size: Some(50),
+            multipart_complete_success: false,
+            ..MockConfig::default()
+        });
+
+        assert_http_error(
+            verify(services, "oid", 50),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Multipart upload error",
+        );
+    }
+
+    #[test]
+    fn test_verify_incomplete_chunked_upload() {
+        let services = get_mock(MockConfig {
+            found: false,
+            proxy_enabled: true,
+            chunks_received: Some(vec![vec![1]]),
+            ..MockConfig::default()
+        });
+
+        assert_http_error(
+            verify(services, "oid", 50),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Chunk 0 has 1 bytes, expected 50",
+        );
+    }
+
+    #[test]
+    fn test_verify_object_larger_than_4_gib() {
+        let services = get_mock(MockConfig {
+            size: 4_294_967_297,
+            signed_size: Some(4_294_967_297),
+            content_sha256: Some(String::from("OID")),
+            ..MockConfig::default()
+        });
+
+        verify(services, "oid", 4_294_967_297).unwrap();
+    }
+
+    #[test]
+    fn test_verify_ok() {
+        let services = get_mock(MockConfig {
+            signed_size: Some(50),
+            content_sha256: Some(String::from("OID")),
+            ..MockConfig::default()
+        });
+
+        verify(services, "oid", 50).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 pub mod api {
+    pub mod range;
     pub mod repo_query;
     pub mod objects_batch {
         pub mod body;
@@ -14,10 +15,14 @@ pub mod api {
 
 pub mod controllers {
     pub mod errors;
+    pub mod media_type;
     pub mod objects {
         pub mod batch;
+        pub mod chunks;
         pub mod download;
+        pub mod tus;
         pub mod upload;
+        pub mod verify;
     }
     pub mod locks;
 }
@@ -27,17 +32,35 @@ pub mod services {
         pub mod local_file_storage;
     }
     pub mod minio {
+        pub mod errors;
+        pub mod multiple_bucket_storage;
         pub mod single_bucket_storage;
     }
     pub mod postgres {
+        pub mod postgres_local_file_storage;
         pub mod postgres_lock_row;
         pub mod postgres_locks_provider;
+        pub mod postgres_pool;
         pub mod sql_query_builder;
     }
+    pub mod sftp {
+        pub mod sftp_file_storage;
+    }
+    pub mod cached_storage;
+    pub mod compressed_storage;
+    pub mod content_addressed_storage;
     pub mod custom_link_signer;
+    pub mod encrypted_storage;
+    pub mod existence_cache;
     pub mod injected_services;
     pub mod jwt;
     pub mod jwt_token_encoder_decoder;
+    pub mod mirrored_storage;
+    pub mod scrubber;
+    pub mod storage_migration;
+    pub mod stored_entry_cache;
+    pub mod verify_link_signer;
+    pub mod verifying_reader;
 }
 
 pub mod server {
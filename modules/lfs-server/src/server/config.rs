@@ -1,17 +1,34 @@
 use crate::services::{
-    custom_link_signer::CustomLinkSignerConfig, fs::local_file_storage::LocalFileStorageConfig,
+    cached_storage::CachedStorageConfig,
+    compressed_storage::CompressedStorageConfig,
+    content_addressed_storage::{ContentAddressedStorage, ContentAddressedStorageConfig},
+    custom_link_signer::CustomLinkSignerConfig,
+    encrypted_storage::EncryptedStorageConfig,
+    existence_cache::ExistenceCacheConfig,
+    fs::local_file_storage::LocalFileStorageConfig,
     jwt_token_encoder_decoder::JwtTokenEncoderDecoderConfig,
-    minio::single_bucket_storage::MinioSingleBucketStorageConfig,
-    postgres::postgres_locks_provider::PostgresLocksProviderConfig,
+    minio::{
+        multiple_bucket_storage::MinioMultipleBucketStorageConfig,
+        single_bucket_storage::MinioSingleBucketStorageConfig,
+    },
+    mirrored_storage::MirroredStorageConfig,
+    postgres::{
+        postgres_local_file_storage::PostgresLocalFileStorageConfig,
+        postgres_locks_provider::PostgresLocksProviderConfig, postgres_pool::PostgresPoolConfig,
+    },
+    sftp::sftp_file_storage::SftpFileStorageConfig,
 };
 use s3::{creds::Credentials, Region};
-use std::str::FromStr;
+use std::{path::Path, str::FromStr, time::Duration};
 
 #[derive(Default)]
 pub enum FileStorageImplementation {
     #[default]
     MinioSingleBucketStorage,
+    MinioMultipleBucketStorage,
     LocalFileStorage,
+    PostgresLocalFileStorage,
+    SftpFileStorage,
 }
 
 #[derive(Default)]
@@ -22,6 +39,7 @@ pub enum LocksImplementation {
 }
 
 const FS_ROOT_PATH_KEY: &str = "FS_ROOT_PATH";
+const FS_SHARD_DEPTH_KEY: &str = "FS_SHARD_DEPTH";
 const DATABASE_HOST_KEY: &str = "DATABASE_HOST";
 const DATABASE_NAME_KEY: &str = "DATABASE_NAME";
 const DATABASE_USER_KEY: &str = "DATABASE_USER";
@@ -33,12 +51,45 @@ const SBS_REGION_KEY: &str = "SBS_REGION";
 const SBS_HOST_KEY: &str = "SBS_HOST";
 const SBS_PUBLIC_REGION_KEY: &str = "SBS_PUBLIC_REGION";
 const SBS_PUBLIC_HOST_KEY: &str = "SBS_PUBLIC_HOST";
+const MBS_BUCKET_NAME_TEMPLATE_KEY: &str = "MBS_BUCKET_NAME_TEMPLATE";
+const SFTP_HOST_KEY: &str = "SFTP_HOST";
+const SFTP_PORT_KEY: &str = "SFTP_PORT";
+const SFTP_USER_KEY: &str = "SFTP_USER";
+const SFTP_IDENTITY_PATH_KEY: &str = "SFTP_IDENTITY_PATH";
+const SFTP_KNOWN_HOSTS_PATH_KEY: &str = "SFTP_KNOWN_HOSTS_PATH";
+const SFTP_ROOT_PATH_KEY: &str = "SFTP_ROOT_PATH";
+const SFTP_POOL_SIZE_KEY: &str = "SFTP_POOL_SIZE";
+const MIRROR_FILE_STORAGE_KEY: &str = "MIRROR_FILE_STORAGE";
+const MIRROR_WRITE_QUORUM_KEY: &str = "MIRROR_WRITE_QUORUM";
+// Prefix of the environment variables configuring the storage of the mirror
+const MIRROR_PREFIX: &str = "MIRROR_";
+const ENCRYPTION_MASTER_KEYS_FILE_KEY: &str = "ENCRYPTION_MASTER_KEYS_FILE";
+const COMPRESSION_KEY: &str = "COMPRESSION";
+const COMPRESSION_LEVEL_KEY: &str = "COMPRESSION_LEVEL";
+const CACHE_ROOT_PATH_KEY: &str = "CACHE_ROOT_PATH";
+const CACHE_MAX_SIZE_KEY: &str = "CACHE_MAX_SIZE";
+const GLOBAL_OBJECTS_REPO_KEY: &str = "GLOBAL_OBJECTS_REPO";
+const EXISTENCE_CACHE_MAX_ENTRIES_KEY: &str = "EXISTENCE_CACHE_MAX_ENTRIES";
+const EXISTENCE_CACHE_POSITIVE_TTL_KEY: &str = "EXISTENCE_CACHE_POSITIVE_TTL";
+const EXISTENCE_CACHE_NEGATIVE_TTL_KEY: &str = "EXISTENCE_CACHE_NEGATIVE_TTL";
+const BATCH_MAX_OBJECTS_KEY: &str = "BATCH_MAX_OBJECTS";
+const MAX_BODY_SIZE_KEY: &str = "MAX_BODY_SIZE";
+const VERIFY_CONTENT_MAX_SIZE_KEY: &str = "VERIFY_CONTENT_MAX_SIZE";
 const CUSTOM_SIGNER_HOST_KEY: &str = "CUSTOM_SIGNER_HOST";
 const JWT_SECRET_FILE_KEY: &str = "JWT_SECRET_FILE";
 const JWT_EXPIRES_IN_KEY: &str = "JWT_EXPIRES_IN";
 const CUSTOM_SIGNER_SECRET_FILE_KEY: &str = "CUSTOM_SIGNER_SECRET_FILE";
 const CUSTOM_SIGNER_EXPIRES_IN_KEY: &str = "CUSTOM_SIGNER_EXPIRES_IN";
 
+/**
+ * Limits of the requests to the batch and locks routes, so that an oversized request can't tie
+ * up the server.
+ */
+pub struct RequestLimitsConfig {
+    pub batch_max_objects: usize,
+    pub max_body_size: usize,
+}
+
 #[derive(Default)]
 pub struct ServerConfig {
     pub with_locks: bool,
@@ -50,6 +101,7 @@ pub struct ServerConfig {
 
     // LocalFileStorageConfig
     pub fs_root_path: Option<String>,
+    pub fs_shard_depth: Option<usize>,
 
     // Minio config
     pub sbs_bucket_name: Option<String>,
@@ -60,6 +112,49 @@ pub struct ServerConfig {
     pub sbs_public_region: Option<String>,
     pub sbs_public_host: Option<String>,
 
+    // Minio multiple bucket config, the connection is shared with the single bucket config
+    pub mbs_bucket_name_template: Option<String>,
+
+    // SftpFileStorageConfig
+    pub sftp_host: Option<String>,
+    pub sftp_port: Option<u16>,
+    pub sftp_user: Option<String>,
+    pub sftp_identity_path: Option<String>,
+    pub sftp_known_hosts_path: Option<String>,
+    pub sftp_root_path: Option<String>,
+    pub sftp_pool_size: Option<usize>,
+
+    // Mirror of the file storage, configured by the variables of its own storage
+    pub mirror_file_storage_implementation: Option<FileStorageImplementation>,
+    pub mirror_storage: Option<Box<ServerConfig>>,
+    pub mirror_write_quorum: Option<usize>,
+
+    // Encryption of the objects, wrapping the file storage
+    pub encryption_master_keys: Option<String>,
+
+    // Compression of the objects, wrapping the file storage
+    pub compression: Option<String>,
+    pub compression_level: Option<i32>,
+
+    // Disk cache, wrapping the file storage
+    pub cache_root_path: Option<String>,
+    pub cache_max_size: Option<u64>,
+
+    // Content addressed layout, wrapping the file storage
+    pub global_objects_repo: Option<String>,
+
+    // Cache of the lookups of the objects, wrapping the file storage
+    pub existence_cache_max_entries: Option<usize>,
+    pub existence_cache_positive_ttl: Option<u64>,
+    pub existence_cache_negative_ttl: Option<u64>,
+
+    // Limits of the requests to the batch and locks routes
+    pub batch_max_objects: Option<usize>,
+    pub max_body_size: Option<usize>,
+
+    // Objects read back at verify in signer mode, up to this size
+    pub verify_content_max_size: Option<u64>,
+
     // Jwt
     pub jwt_secret: Option<String>,
     pub jwt_expires_in: Option<u64>,
@@ -82,8 +177,8 @@ impl ServerConfig {
      *
      * The CLI expect 0, 2 or 4 arguments:
      *   - If no arguments is provided, "proxy fs" is assumed
-     *   - If 2 arguments are provided, it is expected to be "<signer|proxy> <fs|sbs>"
-     *   - If 4 arguments are provided, it is expected to be "<signer|proxy> <fs|sbs> locks pg"
+     *   - If 2 arguments are provided, it is expected to be "<signer|proxy> <fs|sbs|mbs|sftp|lsdb>"
+     *   - If 4 arguments are provided, it is expected to be "<signer|proxy> <fs|sbs|mbs|sftp|lsdb> locks pg"
      */
     pub fn parse_args(self, args: Vec<String>) -> Self {
         self.cli_parse_proxy(&args)
@@ -96,10 +191,256 @@ impl ServerConfig {
      *
      * The following environment variables are required:
      *   - FS_ROOT_PATH
+     *
+     * The following environment variables are accepted:
+     *   - FS_SHARD_DEPTH (default 0)
      */
     pub fn get_local_file_storage_config(&self) -> LocalFileStorageConfig {
         LocalFileStorageConfig {
             root_path: Self::unwrap_config_value(FS_ROOT_PATH_KEY, &self.fs_root_path),
+            shard_depth: self.fs_shard_depth.unwrap_or(0),
+        }
+    }
+
+    /**
+     * Get the config for a local file storage with its metadata in a postgres database.
+     *
+     * The following environment variables are required:
+     *   - FS_ROOT_PATH
+     *   - DATABASE_HOST
+     *   - DATABASE_NAME
+     *   - DATABASE_USER
+     *   - DATABASE_PASSWORD_FILE
+     *
+     * The following environment variables are accepted:
+     *   - FS_SHARD_DEPTH (default 0)
+     */
+    pub fn get_postgres_local_file_storage_config(&self) -> PostgresLocalFileStorageConfig {
+        PostgresLocalFileStorageConfig {
+            files: self.get_local_file_storage_config(),
+            database: self.get_postgres_pool_config(),
+        }
+    }
+
+    /**
+     * Get the config for a sftp file storage.
+     *
+     * The following environment variables are required:
+     *   - SFTP_HOST
+     *   - SFTP_USER
+     *   - SFTP_ROOT_PATH
+     *
+     * The following environment variables are accepted:
+     *   - SFTP_PORT (default 22)
+     *   - SFTP_IDENTITY_PATH
+     *   - SFTP_KNOWN_HOSTS_PATH
+     *   - SFTP_POOL_SIZE (default 4)
+     */
+    pub fn get_sftp_file_storage_config(&self) -> SftpFileStorageConfig {
+        SftpFileStorageConfig {
+            host: Self::unwrap_config_value(SFTP_HOST_KEY, &self.sftp_host),
+            port: self.sftp_port.unwrap_or(22),
+            user: Self::unwrap_config_value(SFTP_USER_KEY, &self.sftp_user),
+            identity_path: self.sftp_identity_path.clone(),
+            known_hosts_path: self.sftp_known_hosts_path.clone(),
+            root_path: Self::unwrap_config_value(SFTP_ROOT_PATH_KEY, &self.sftp_root_path),
+            pool_size: self.sftp_pool_size.unwrap_or(4),
+        }
+    }
+
+    /**
+     * Get the config for the mirroring of the file storage, if enabled, along with the config and
+     * implementation of the storage of the mirror.
+     *
+     * The following environment variables are accepted:
+     *   - MIRROR_FILE_STORAGE (fs|sbs|mbs|sftp|lsdb)
+     *   - MIRROR_WRITE_QUORUM (default 2, both storages)
+     *
+     * The storage of the mirror is configured with the same environment variables as the main
+     * storage, prefixed by MIRROR_, e.g. MIRROR_FS_ROOT_PATH. It must not be the main storage.
+     */
+    pub fn get_mirrored_storage_config(
+        &self,
+    ) -> Option<(
+        MirroredStorageConfig,
+        &ServerConfig,
+        &FileStorageImplementation,
+    )> {
+        let implementation = self.mirror_file_storage_implementation.as_ref()?;
+        let mirror_storage = match &self.mirror_storage {
+            Some(mirror_storage) => mirror_storage,
+            None => panic!("Missing the configuration of the mirror"),
+        };
+        let location = self.get_storage_location(&self.file_storage_implementation);
+        if location == mirror_storage.get_storage_location(implementation) {
+            panic!(
+                "Invalid {}: the mirror is stored in the main storage, at {}",
+                MIRROR_FILE_STORAGE_KEY, location
+            );
+        }
+        let mirror_config = MirroredStorageConfig {
+            write_quorum: self.mirror_write_quorum.unwrap_or(2),
+        };
+        Some((mirror_config, mirror_storage, implementation))
+    }
+
+    /**
+     * Where a file storage implementation keeps the objects, so that two configs storing them at
+     * the same place can be told apart. The local storages share their layout, with or without
+     * their metadata in postgres.
+     */
+    fn get_storage_location(&self, implementation: &FileStorageImplementation) -> String {
+        match implementation {
+            FileStorageImplementation::LocalFileStorage
+            | FileStorageImplementation::PostgresLocalFileStorage => {
+                let root_path = self.get_local_file_storage_config().root_path;
+                let root_path: std::path::PathBuf = Path::new(&root_path).components().collect();
+                format!("fs:{}", root_path.display())
+            }
+            FileStorageImplementation::MinioSingleBucketStorage => {
+                let config = self.get_minio_single_bucket_storage_config();
+                format!(
+                    "s3:{}/{}",
+                    config.direct_access_region.endpoint(),
+                    config.bucket_name
+                )
+            }
+            FileStorageImplementation::MinioMultipleBucketStorage => {
+                let config = self.get_minio_multiple_bucket_storage_config();
+                format!(
+                    "s3:{}/{}",
+                    config.direct_access_region.endpoint(),
+                    config.bucket_name_template
+                )
+            }
+            FileStorageImplementation::SftpFileStorage => {
+                let config = self.get_sftp_file_storage_config();
+                let root_path: std::path::PathBuf =
+                    Path::new(&config.root_path).components().collect();
+                format!(
+                    "sftp:{}@{}:{}{}",
+                    config.user,
+                    config.host,
+                    config.port,
+                    root_path.display()
+                )
+            }
+        }
+    }
+
+    /**
+     * Get the config for the encryption of the objects, if enabled.
+     *
+     * The following environment variables are accepted:
+     *   - ENCRYPTION_MASTER_KEYS_FILE (hex encoded keys, one per line, the current one first)
+     */
+    pub fn get_encrypted_storage_config(&self) -> Option<EncryptedStorageConfig> {
+        self.encryption_master_keys
+            .as_ref()
+            .map(|master_keys| EncryptedStorageConfig {
+                master_keys: master_keys.clone(),
+            })
+    }
+
+    /**
+     * Get the config for the compression of the objects, if enabled.
+     *
+     * The following environment variables are accepted:
+     *   - COMPRESSION (zstd)
+     *   - COMPRESSION_LEVEL (default 3)
+     */
+    pub fn get_compressed_storage_config(&self) -> Option<CompressedStorageConfig> {
+        match self.compression.as_deref() {
+            Some("zstd") => Some(CompressedStorageConfig {
+                level: self.compression_level.unwrap_or(3),
+            }),
+            Some(codec) => panic!("Invalid {}: {}", COMPRESSION_KEY, codec),
+            None => None,
+        }
+    }
+
+    /**
+     * Get the config for the disk cache, if enabled.
+     *
+     * The following environment variables are accepted:
+     *   - CACHE_ROOT_PATH
+     *
+     * If it is set, the following environment variables are required:
+     *   - CACHE_MAX_SIZE (in bytes)
+     */
+    pub fn get_cached_storage_config(&self) -> Option<CachedStorageConfig> {
+        self.cache_root_path
+            .clone()
+            .map(|root_path| CachedStorageConfig {
+                root_path,
+                max_size: Self::unwrap_config_value(CACHE_MAX_SIZE_KEY, &self.cache_max_size),
+            })
+    }
+
+    /**
+     * Get the config for the content addressed layout, if enabled.
+     *
+     * The following environment variables are accepted:
+     *   - GLOBAL_OBJECTS_REPO (a top level name starting with a dot, for instance .global)
+     *
+     * The references are recorded when the uploads go through the server, so the layout is
+     * refused in signer mode. The content of every repository is stored in the global area, so
+     * it would be encrypted with the data key of the area rather than with the one of each
+     * repository: the layout is refused with the encryption.
+     */
+    pub fn get_content_addressed_storage_config(&self) -> Option<ContentAddressedStorageConfig> {
+        let global_repo = self.global_objects_repo.clone()?;
+        if !self.with_proxy {
+            panic!(
+                "{} is only available in proxy mode",
+                GLOBAL_OBJECTS_REPO_KEY
+            );
+        }
+        if self.encryption_master_keys.is_some() {
+            panic!(
+                "{} is not available with {}",
+                GLOBAL_OBJECTS_REPO_KEY, ENCRYPTION_MASTER_KEYS_FILE_KEY
+            );
+        }
+        if !ContentAddressedStorage::is_reserved_name(&global_repo) {
+            panic!(
+                "Invalid {}: {}, expected a top level name starting with a dot",
+                GLOBAL_OBJECTS_REPO_KEY, global_repo
+            );
+        }
+        Some(ContentAddressedStorageConfig { global_repo })
+    }
+
+    /**
+     * Get the config for the cache of the lookups of the objects, if enabled.
+     *
+     * The following environment variables are accepted:
+     *   - EXISTENCE_CACHE_MAX_ENTRIES
+     *   - EXISTENCE_CACHE_POSITIVE_TTL (in seconds, default 3600)
+     *   - EXISTENCE_CACHE_NEGATIVE_TTL (in seconds, default 10)
+     */
+    pub fn get_existence_cache_config(&self) -> Option<ExistenceCacheConfig> {
+        self.existence_cache_max_entries
+            .map(|max_entries| ExistenceCacheConfig {
+                max_entries,
+                positive_ttl: Duration::from_secs(
+                    self.existence_cache_positive_ttl.unwrap_or(3600),
+                ),
+                negative_ttl: Duration::from_secs(self.existence_cache_negative_ttl.unwrap_or(10)),
+            })
+    }
+
+    /**
+     * Get the limits of the requests to the batch and locks routes.
+     *
+     * The following environment variables are accepted:
+     *   - BATCH_MAX_OBJECTS (default 1000)
+     *   - MAX_BODY_SIZE (in bytes, default 2 MiB)
+     */
+    pub fn get_request_limits_config(&self) -> RequestLimitsConfig {
+        RequestLimitsConfig {
+            batch_max_objects: self.batch_max_objects.unwrap_or(1000),
+            max_body_size: self.max_body_size.unwrap_or(2 * 1024 * 1024),
         }
     }
 
@@ -164,6 +505,46 @@ impl ServerConfig {
      */
     pub fn get_minio_single_bucket_storage_config(&self) -> MinioSingleBucketStorageConfig {
         let sbs_bucket_name = Self::unwrap_config_value(SBS_BUCKET_NAME_KEY, &self.sbs_bucket_name);
+        let (credentials, direct_access_region, public_access_region) = self.get_minio_access();
+
+        MinioSingleBucketStorageConfig {
+            bucket_name: sbs_bucket_name,
+            credentials,
+            direct_access_region,
+            public_access_region,
+        }
+    }
+
+    /**
+     * Get the config for a minio multiple bucket storage.
+     *
+     * The following environment variables are required:
+     *   - MBS_BUCKET_NAME_TEMPLATE
+     *   - SBS_ACCESS_KEY_FILE
+     *   - SBS_SECRET_KEY_FILE
+     *   - SBS_REGION or SBS_HOST
+     *
+     * In signer mode, the following environment variables are accepted.
+     * If not provided, it will fallback on the SBS_REGION and SBS_HOST values:
+     *   - SBS_PUBLIC_REGION or SBS_PUBLIC_HOST
+     */
+    pub fn get_minio_multiple_bucket_storage_config(&self) -> MinioMultipleBucketStorageConfig {
+        let mbs_bucket_name_template =
+            Self::unwrap_config_value(MBS_BUCKET_NAME_TEMPLATE_KEY, &self.mbs_bucket_name_template);
+        let (credentials, direct_access_region, public_access_region) = self.get_minio_access();
+
+        MinioMultipleBucketStorageConfig {
+            bucket_name_template: mbs_bucket_name_template,
+            credentials,
+            direct_access_region,
+            public_access_region,
+        }
+    }
+
+    /**
+     * Get the credentials, and the direct and public access regions of the minio server.
+     */
+    fn get_minio_access(&self) -> (Credentials, Region, Option<Region>) {
         let sbs_access_key =
             Self::unwrap_config_value(SBS_ACCESS_KEY_FILE_KEY, &self.sbs_access_key);
         let sbs_secret_key =
@@ -178,31 +559,26 @@ impl ServerConfig {
             ),
         };
 
-        MinioSingleBucketStorageConfig {
-            bucket_name: sbs_bucket_name,
-            credentials: Credentials::new(
-                Some(&sbs_access_key),
-                Some(&sbs_secret_key),
-                None,
-                None,
-                None,
+        let credentials = Credentials::new(
+            Some(&sbs_access_key),
+            Some(&sbs_secret_key),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let public_access_region = if !self.with_proxy {
+            Some(
+                match Self::get_region(self.sbs_public_region.clone(), self.sbs_public_host.clone())
+                {
+                    Some(region) => region,
+                    None => region.clone(),
+                },
             )
-            .unwrap(),
-            direct_access_region: region.clone(),
-            public_access_region: if !self.with_proxy {
-                Some(
-                    match Self::get_region(
-                        self.sbs_public_region.clone(),
-                        self.sbs_public_host.clone(),
-                    ) {
-                        Some(region) => region,
-                        None => region,
-                    },
-                )
-            } else {
-                None
-            },
-        }
+        } else {
+            None
+        };
+        (credentials, region, public_access_region)
     }
 
     /**
@@ -215,7 +591,15 @@ impl ServerConfig {
      *   - DATABASE_PASSWORD_FILE
      */
     pub fn get_postgres_locks_provider_config(&self) -> PostgresLocksProviderConfig {
-        PostgresLocksProviderConfig {
+        self.get_postgres_pool_config()
+    }
+
+    /**
+     * Get the connection to the postgres database, shared by the locks provider and the
+     * metadata of the local file storage.
+     */
+    fn get_postgres_pool_config(&self) -> PostgresPoolConfig {
+        PostgresPoolConfig {
             host: Self::unwrap_config_value(DATABASE_HOST_KEY, &self.database_host),
             dbname: Self::unwrap_config_value(DATABASE_NAME_KEY, &self.database_name),
             username: Self::unwrap_config_value(DATABASE_USER_KEY, &self.database_user),
@@ -251,28 +635,80 @@ impl ServerConfig {
      * This do not verify that the values exists, but if they exist
      * they should be valid. (files shall exists, u64 shall be parsable, etc.)
      */
-    pub fn parse_env(mut self) -> Self {
-        self.fs_root_path = std::env::var(FS_ROOT_PATH_KEY).ok();
-        self.database_host = std::env::var(DATABASE_HOST_KEY).ok();
-        self.database_name = std::env::var(DATABASE_NAME_KEY).ok();
-        self.database_user = std::env::var(DATABASE_USER_KEY).ok();
-        self.database_password = Self::read_env_file(DATABASE_PASSWORD_FILE_KEY);
-        self.sbs_bucket_name = std::env::var(SBS_BUCKET_NAME_KEY).ok();
-        self.sbs_access_key = Self::read_env_file(SBS_ACCESS_KEY_FILE_KEY);
-        self.sbs_secret_key = Self::read_env_file(SBS_SECRET_KEY_FILE_KEY);
-        self.sbs_region = std::env::var(SBS_REGION_KEY).ok();
-        self.sbs_host = std::env::var(SBS_HOST_KEY).ok();
-        self.sbs_public_region = std::env::var(SBS_PUBLIC_REGION_KEY).ok();
-        self.sbs_public_host = std::env::var(SBS_PUBLIC_HOST_KEY).ok();
-        self.jwt_secret = Self::read_env_file(JWT_SECRET_FILE_KEY);
-        self.jwt_expires_in = std::env::var(JWT_EXPIRES_IN_KEY)
-            .ok()
-            .map(|v| v.parse::<u64>().unwrap());
-        self.custom_signer_host = std::env::var(CUSTOM_SIGNER_HOST_KEY).ok();
-        self.custom_signer_secret = Self::read_env_file(CUSTOM_SIGNER_SECRET_FILE_KEY);
-        self.custom_signer_expires_in = std::env::var(CUSTOM_SIGNER_EXPIRES_IN_KEY)
-            .ok()
-            .map(|v| v.parse::<u64>().unwrap());
+    pub fn parse_env(self) -> Self {
+        self.parse_vars(|key| std::env::var(key).ok())
+    }
+
+    /**
+     * Set the config values from variables looked up by name, as the environment variables are
+     * by `parse_env`. It allows to read several configs from the environment, each one with its
+     * own prefix.
+     */
+    pub fn parse_vars(self, var: impl Fn(&str) -> Option<String>) -> Self {
+        self.parse_var_lookup(&var)
+    }
+
+    fn parse_var_lookup(mut self, var: &dyn Fn(&str) -> Option<String>) -> Self {
+        self.fs_root_path = var(FS_ROOT_PATH_KEY);
+        self.fs_shard_depth = var(FS_SHARD_DEPTH_KEY).map(|v| v.parse::<usize>().unwrap());
+        self.database_host = var(DATABASE_HOST_KEY);
+        self.database_name = var(DATABASE_NAME_KEY);
+        self.database_user = var(DATABASE_USER_KEY);
+        self.database_password = Self::read_env_file(var, DATABASE_PASSWORD_FILE_KEY);
+        self.sbs_bucket_name = var(SBS_BUCKET_NAME_KEY);
+        self.sbs_access_key = Self::read_env_file(var, SBS_ACCESS_KEY_FILE_KEY);
+        self.sbs_secret_key = Self::read_env_file(var, SBS_SECRET_KEY_FILE_KEY);
+        self.sbs_region = var(SBS_REGION_KEY);
+        self.sbs_host = var(SBS_HOST_KEY);
+        self.sbs_public_region = var(SBS_PUBLIC_REGION_KEY);
+        self.sbs_public_host = var(SBS_PUBLIC_HOST_KEY);
+        self.mbs_bucket_name_template = var(MBS_BUCKET_NAME_TEMPLATE_KEY);
+        self.sftp_host = var(SFTP_HOST_KEY);
+        self.sftp_port = var(SFTP_PORT_KEY).map(|v| v.parse::<u16>().unwrap());
+        self.sftp_user = var(SFTP_USER_KEY);
+        self.sftp_identity_path = var(SFTP_IDENTITY_PATH_KEY);
+        self.sftp_known_hosts_path = var(SFTP_KNOWN_HOSTS_PATH_KEY);
+        self.sftp_root_path = var(SFTP_ROOT_PATH_KEY);
+        self.sftp_pool_size = var(SFTP_POOL_SIZE_KEY).map(|v| v.parse::<usize>().unwrap());
+        self.mirror_file_storage_implementation = var(MIRROR_FILE_STORAGE_KEY).map(|v| {
+            match Self::parse_file_storage_implementation(&v) {
+                Some(implementation) => implementation,
+                None => panic!("Invalid {}: {}", MIRROR_FILE_STORAGE_KEY, v),
+            }
+        });
+        self.mirror_storage = self.mirror_file_storage_implementation.as_ref().map(|_| {
+            // The mirroring requires the proxy mode
+            let mirror_var = |key: &str| var(&format!("{}{}", MIRROR_PREFIX, key));
+            let mirror_storage = ServerConfig {
+                with_proxy: true,
+                ..ServerConfig::default()
+            };
+            Box::new(mirror_storage.parse_var_lookup(&mirror_var))
+        });
+        self.mirror_write_quorum =
+            var(MIRROR_WRITE_QUORUM_KEY).map(|v| v.parse::<usize>().unwrap());
+        self.encryption_master_keys = Self::read_env_file(var, ENCRYPTION_MASTER_KEYS_FILE_KEY);
+        self.compression = var(COMPRESSION_KEY);
+        self.compression_level = var(COMPRESSION_LEVEL_KEY).map(|v| v.parse::<i32>().unwrap());
+        self.cache_root_path = var(CACHE_ROOT_PATH_KEY);
+        self.cache_max_size = var(CACHE_MAX_SIZE_KEY).map(|v| v.parse::<u64>().unwrap());
+        self.global_objects_repo = var(GLOBAL_OBJECTS_REPO_KEY);
+        self.existence_cache_max_entries =
+            var(EXISTENCE_CACHE_MAX_ENTRIES_KEY).map(|v| v.parse::<usize>().unwrap());
+        self.existence_cache_positive_ttl =
+            var(EXISTENCE_CACHE_POSITIVE_TTL_KEY).map(|v| v.parse::<u64>().unwrap());
+        self.existence_cache_negative_ttl =
+            var(EXISTENCE_CACHE_NEGATIVE_TTL_KEY).map(|v| v.parse::<u64>().unwrap());
+        self.batch_max_objects = var(BATCH_MAX_OBJECTS_KEY).map(|v| v.parse::<usize>().unwrap());
+        self.max_body_size = var(MAX_BODY_SIZE_KEY).map(|v| v.parse::<usize>().unwrap());
+        self.verify_content_max_size =
+            var(VERIFY_CONTENT_MAX_SIZE_KEY).map(|v| v.parse::<u64>().unwrap());
+        self.jwt_secret = Self::read_env_file(var, JWT_SECRET_FILE_KEY);
+        self.jwt_expires_in = var(JWT_EXPIRES_IN_KEY).map(|v| v.parse::<u64>().unwrap());
+        self.custom_signer_host = var(CUSTOM_SIGNER_HOST_KEY);
+        self.custom_signer_secret = Self::read_env_file(var, CUSTOM_SIGNER_SECRET_FILE_KEY);
+        self.custom_signer_expires_in =
+            var(CUSTOM_SIGNER_EXPIRES_IN_KEY).map(|v| v.parse::<u64>().unwrap());
         self
     }
 
@@ -292,20 +728,20 @@ impl ServerConfig {
      * If the variable is set, read the file and return its content.
      * If the file cannot be read, panic giving the key name.
      */
-    fn read_env_file(key: &str) -> Option<String> {
-        match std::env::var(key) {
-            Ok(path) => match std::fs::read_to_string(path) {
+    fn read_env_file(var: &dyn Fn(&str) -> Option<String>, key: &str) -> Option<String> {
+        match var(key) {
+            Some(path) => match std::fs::read_to_string(path) {
                 Ok(content) => Some(content),
                 Err(_) => panic!("Failed to read file described by env variable {}", key),
             },
-            Err(_) => None,
+            None => None,
         }
     }
 
     /**
      * Parse the proxy/signer CLI argument.
      */
-    fn cli_parse_proxy(mut self, args: &Vec<String>) -> Self {
+    fn cli_parse_proxy(mut self, args: &[String]) -> Self {
         if args.is_empty() || args[0] == "proxy" {
             self.with_proxy = true;
         } else if args[0] == "signer" {
@@ -319,21 +755,39 @@ impl ServerConfig {
     /**
      * Parse the fs implementation CLI argument.
      */
-    fn cli_parse_fs_impl(mut self, args: &Vec<String>) -> Self {
-        if args.is_empty() || args.len() > 1 && args[1] == "fs" {
+    fn cli_parse_fs_impl(mut self, args: &[String]) -> Self {
+        if args.is_empty() {
             self.file_storage_implementation = FileStorageImplementation::LocalFileStorage;
-        } else if args.len() > 1 && args[1] == "sbs" {
-            self.file_storage_implementation = FileStorageImplementation::MinioSingleBucketStorage;
-        } else {
-            panic!("Invalid arguments: {}", args.join(", "));
+            return self;
+        }
+        match args
+            .get(1)
+            .and_then(|name| Self::parse_file_storage_implementation(name))
+        {
+            Some(implementation) => self.file_storage_implementation = implementation,
+            None => panic!("Invalid arguments: {}", args.join(", ")),
         }
         self
     }
 
+    /**
+     * Parse the name of a file storage implementation.
+     */
+    pub fn parse_file_storage_implementation(name: &str) -> Option<FileStorageImplementation> {
+        match name {
+            "fs" => Some(FileStorageImplementation::LocalFileStorage),
+            "sbs" => Some(FileStorageImplementation::MinioSingleBucketStorage),
+            "mbs" => Some(FileStorageImplementation::MinioMultipleBucketStorage),
+            "lsdb" => Some(FileStorageImplementation::PostgresLocalFileStorage),
+            "sftp" => Some(FileStorageImplementation::SftpFileStorage),
+            _ => None,
+        }
+    }
+
     /**
      * Parse the locks CLI arguments.
      */
-    fn cli_parse_locks_impl(mut self, args: &Vec<String>) -> Self {
+    fn cli_parse_locks_impl(mut self, args: &[String]) -> Self {
         if args.len() <= 2 {
             self.with_locks = false;
             self.locks_implementation = LocksImplementation::None;
@@ -346,3 +800,78 @@ impl ServerConfig {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse(vars: &[(&str, &str)]) -> ServerConfig {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        ServerConfig::default()
+            .parse_args(vec![String::from("proxy"), String::from("fs")])
+            .parse_vars(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn test_mirror_has_its_own_variables() {
+        let config = parse(&[
+            ("FS_ROOT_PATH", "/data/lfs"),
+            ("MIRROR_FILE_STORAGE", "fs"),
+            ("MIRROR_FS_ROOT_PATH", "/mirror/lfs"),
+            ("MIRROR_WRITE_QUORUM", "1"),
+        ]);
+        let (mirror_config, mirror_storage, _) = config.get_mirrored_storage_config().unwrap();
+        assert_eq!(mirror_config.write_quorum, 1);
+        assert_eq!(
+            mirror_storage.get_local_file_storage_config().root_path,
+            "/mirror/lfs"
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "GLOBAL_OBJECTS_REPO is not available with ENCRYPTION_MASTER_KEYS_FILE"
+    )]
+    fn test_content_addressed_layout_with_encryption_is_refused() {
+        let mut config = parse(&[("GLOBAL_OBJECTS_REPO", ".global")]);
+        assert!(config.get_content_addressed_storage_config().is_some());
+        config.encryption_master_keys = Some(String::from("00"));
+        config.get_content_addressed_storage_config();
+    }
+
+    #[test]
+    #[should_panic(expected = "the mirror is stored in the main storage")]
+    fn test_mirror_in_the_same_root_is_refused() {
+        let config = parse(&[
+            ("FS_ROOT_PATH", "/data/lfs"),
+            ("MIRROR_FILE_STORAGE", "lsdb"),
+            ("MIRROR_FS_ROOT_PATH", "/data/lfs/"),
+        ]);
+        config.get_mirrored_storage_config();
+    }
+
+    #[test]
+    #[should_panic(expected = "the mirror is stored in the main storage")]
+    fn test_mirror_in_the_same_bucket_is_refused() {
+        let minio = [
+            ("BUCKET_NAME", "lfs"),
+            ("ACCESS_KEY_FILE", "/dev/null"),
+            ("SECRET_KEY_FILE", "/dev/null"),
+            ("HOST", "http://localhost:9000"),
+        ];
+        let mut vars = vec![(String::from("MIRROR_FILE_STORAGE"), String::from("sbs"))];
+        for prefix in ["SBS_", "MIRROR_SBS_"] {
+            for (key, value) in minio {
+                vars.push((format!("{}{}", prefix, key), value.to_string()));
+            }
+        }
+        let vars: Vec<_> = vars.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        let mut config = parse(&vars);
+        config.file_storage_implementation = FileStorageImplementation::MinioSingleBucketStorage;
+        config.get_mirrored_storage_config();
+    }
+}
@@ -1,17 +1,39 @@
 use crate::{
     server::config::{FileStorageImplementation, LocksImplementation, ServerConfig},
     services::{
-        custom_link_signer::CustomLinkSigner, fs::local_file_storage::LocalFileStorage,
-        injected_services::InjectedServices, jwt_token_encoder_decoder::JwtTokenEncoderDecoder,
-        minio::single_bucket_storage::MinioSingleBucketStorage,
-        postgres::postgres_locks_provider::PostgresLocksProvider,
+        cached_storage::CachedStorage,
+        compressed_storage::CompressedStorage,
+        content_addressed_storage::ContentAddressedStorage,
+        custom_link_signer::CustomLinkSigner,
+        encrypted_storage::EncryptedStorage,
+        existence_cache::ExistenceCache,
+        fs::local_file_storage::LocalFileStorage,
+        injected_services::InjectedServices,
+        jwt_token_encoder_decoder::JwtTokenEncoderDecoder,
+        minio::{
+            multiple_bucket_storage::MinioMultipleBucketStorage,
+            single_bucket_storage::MinioSingleBucketStorage,
+        },
+        mirrored_storage::{MirrorBackend, MirroredStorage},
+        postgres::{
+            postgres_local_file_storage::PostgresLocalFileStorage,
+            postgres_locks_provider::PostgresLocksProvider,
+        },
+        scrubber::ScrubStorage,
+        sftp::sftp_file_storage::SftpFileStorage,
+        storage_migration::MigrationStorage,
+        verify_link_signer::VerifyLinkSigner,
     },
     traits::{
-        file_storage::{FileStorageLinkSigner, FileStorageMetaRequester, FileStorageProxy},
+        file_storage::{
+            FileStorageChunkedUploader, FileStorageLinkSigner, FileStorageLister,
+            FileStorageMetaRequester, FileStorageMultipartUploader, FileStorageProxy,
+            FileStorageQuarantine, FileStorageResumableUploader,
+        },
         locks::LocksProvider,
     },
 };
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 struct FileBackendServices(
     Arc<dyn FileStorageMetaRequester + 'static>,
@@ -31,6 +53,26 @@ fn get_custom_signer_implementation(
     ))
 }
 
+/**
+ * In signer mode, add verify actions to the links signed by the storage, if the server host is
+ * configured. Otherwise, return the storage signer as is.
+ */
+fn with_verify_links(
+    config: &ServerConfig,
+    signer: Arc<dyn FileStorageLinkSigner + 'static>,
+) -> Arc<dyn FileStorageLinkSigner + 'static> {
+    if config.custom_signer_host.is_none() {
+        return signer;
+    }
+    Arc::new(VerifyLinkSigner::new(
+        signer,
+        CustomLinkSigner::from_config(
+            &config.get_custom_signer_config(),
+            JwtTokenEncoderDecoder::from_config(config.get_custom_signer_encoder_decoder_config()),
+        ),
+    ))
+}
+
 /**
  * Get the single bucket storage implementation from the given configuration.
  * Get in order the FileStorageMetaRequester, the FileStorageProxy, FileStorageLinkSigner implementations
@@ -38,7 +80,7 @@ fn get_custom_signer_implementation(
  * In proxy mode, FileStorageMetaRequester and FileStorageProxy are both a reference to an instance of MinioSingleBucketStorage
  *      and the signer is a CustomLinkSigner
  * In signer mode, FileStorageMetaRequester and FileStorageLinkSigner are both a reference to an instance of MinioSingleBucketStorage
- *      and the proxy is unset (not a proxy)
+ *      (with verify actions if CUSTOM_SIGNER_HOST is set) and the proxy is unset (not a proxy)
  */
 fn get_sbs_implementation(config: &ServerConfig) -> FileBackendServices {
     let fs = Arc::new(MinioSingleBucketStorage::from_config(
@@ -49,7 +91,114 @@ fn get_sbs_implementation(config: &ServerConfig) -> FileBackendServices {
         let custom_signer = get_custom_signer_implementation(config);
         FileBackendServices(fs.clone(), Some(fs), custom_signer)
     } else {
-        FileBackendServices(fs.clone(), None, fs)
+        FileBackendServices(fs.clone(), None, with_verify_links(config, fs))
+    }
+}
+
+/**
+ * In signer mode, let the clients upload the objects of a single bucket storage in several
+ * parts. The server completes the uploads when they are verified, so it requires the verify
+ * actions, and thus CUSTOM_SIGNER_HOST.
+ */
+fn get_multipart_uploader(
+    config: &ServerConfig,
+) -> Option<Arc<dyn FileStorageMultipartUploader + 'static>> {
+    match config.file_storage_implementation {
+        FileStorageImplementation::MinioSingleBucketStorage
+            if !config.with_proxy && config.custom_signer_host.is_some() =>
+        {
+            Some(Arc::new(MinioSingleBucketStorage::from_config(
+                config.get_minio_single_bucket_storage_config(),
+            )))
+        }
+        _ => None,
+    }
+}
+
+/**
+ * In signer mode, the objects are uploaded directly to the storage: the ones failing the
+ * verification are quarantined, so that the clients upload them again.
+ */
+fn get_quarantine(config: &ServerConfig) -> Option<Arc<dyn FileStorageQuarantine + 'static>> {
+    if config.with_proxy {
+        return None;
+    }
+    match config.file_storage_implementation {
+        FileStorageImplementation::MinioSingleBucketStorage => Some(Arc::new(
+            MinioSingleBucketStorage::from_config(config.get_minio_single_bucket_storage_config()),
+        )),
+        FileStorageImplementation::MinioMultipleBucketStorage => {
+            Some(Arc::new(MinioMultipleBucketStorage::from_config(
+                config.get_minio_multiple_bucket_storage_config(),
+            )))
+        }
+        _ => None,
+    }
+}
+
+/**
+ * In proxy mode, let the clients resume their uploads with the tus transfer. The content
+ * received so far is kept on the local filesystem of the storage, under the uploads area of the
+ * repository, and goes through the optional layers once complete. It would be kept in plaintext
+ * until then, so the transfer is not offered with the encryption.
+ */
+fn get_resumable_uploader(
+    config: &ServerConfig,
+) -> Option<Arc<dyn FileStorageResumableUploader + 'static>> {
+    if config.get_encrypted_storage_config().is_some() {
+        return None;
+    }
+    match config.file_storage_implementation {
+        FileStorageImplementation::LocalFileStorage
+        | FileStorageImplementation::PostgresLocalFileStorage
+            if config.with_proxy =>
+        {
+            Some(Arc::new(LocalFileStorage::from_config(
+                config.get_local_file_storage_config(),
+            )))
+        }
+        _ => None,
+    }
+}
+
+/**
+ * In proxy mode, let the clients upload the objects as chunks sent several at a time. The chunks
+ * are kept in the storage itself, with the local and single bucket storages, and go through the
+ * optional layers once reassembled. They would be kept in plaintext until then, so the transfer
+ * is not offered with the encryption.
+ */
+fn get_chunked_uploader(
+    config: &ServerConfig,
+) -> Option<Arc<dyn FileStorageChunkedUploader + 'static>> {
+    if !config.with_proxy || config.get_encrypted_storage_config().is_some() {
+        return None;
+    }
+    match config.file_storage_implementation {
+        FileStorageImplementation::LocalFileStorage
+        | FileStorageImplementation::PostgresLocalFileStorage => Some(Arc::new(
+            LocalFileStorage::from_config(config.get_local_file_storage_config()),
+        )),
+        FileStorageImplementation::MinioSingleBucketStorage => Some(Arc::new(
+            MinioSingleBucketStorage::from_config(config.get_minio_single_bucket_storage_config()),
+        )),
+        _ => None,
+    }
+}
+
+/**
+ * Get the multiple bucket storage implementation from the given configuration.
+ * It is wired as the single bucket storage, in proxy as in signer mode.
+ */
+fn get_mbs_implementation(config: &ServerConfig) -> FileBackendServices {
+    let fs = Arc::new(MinioMultipleBucketStorage::from_config(
+        config.get_minio_multiple_bucket_storage_config(),
+    ));
+
+    if config.with_proxy {
+        let custom_signer = get_custom_signer_implementation(config);
+        FileBackendServices(fs.clone(), Some(fs), custom_signer)
+    } else {
+        FileBackendServices(fs.clone(), None, with_verify_links(config, fs))
     }
 }
 
@@ -64,6 +213,329 @@ fn get_fs_implementation(config: &ServerConfig) -> FileBackendServices {
     FileBackendServices(fs.clone(), Some(fs), custom_signer)
 }
 
+/**
+ * Get the local file storage with its metadata in postgres from the given configuration.
+ * As for the local file storage, the server always acts as a proxy.
+ */
+fn get_lsdb_implementation(config: &ServerConfig) -> FileBackendServices {
+    let fs = Arc::new(PostgresLocalFileStorage::from_config(
+        config.get_postgres_local_file_storage_config(),
+    ));
+    let custom_signer = get_custom_signer_implementation(config);
+    FileBackendServices(fs.clone(), Some(fs), custom_signer)
+}
+
+/**
+ * Get the sftp file storage implementation from the given configuration.
+ * As for the local file storage, the server always acts as a proxy.
+ */
+fn get_sftp_implementation(config: &ServerConfig) -> FileBackendServices {
+    let fs = Arc::new(SftpFileStorage::from_config(
+        config.get_sftp_file_storage_config(),
+    ));
+    let custom_signer = get_custom_signer_implementation(config);
+    FileBackendServices(fs.clone(), Some(fs), custom_signer)
+}
+
+/**
+ * Get the services of a file storage implementation.
+ */
+fn get_file_backend_services(
+    config: &ServerConfig,
+    implementation: &FileStorageImplementation,
+) -> FileBackendServices {
+    match implementation {
+        FileStorageImplementation::MinioSingleBucketStorage => get_sbs_implementation(config),
+        FileStorageImplementation::MinioMultipleBucketStorage => get_mbs_implementation(config),
+        FileStorageImplementation::LocalFileStorage => get_fs_implementation(config),
+        FileStorageImplementation::PostgresLocalFileStorage => get_lsdb_implementation(config),
+        FileStorageImplementation::SftpFileStorage => get_sftp_implementation(config),
+    }
+}
+
+/**
+ * The services of a file storage implementation used by the tools maintaining the stored
+ * objects, which do not need the server to run.
+ */
+struct MaintenanceServices(
+    Arc<dyn FileStorageMetaRequester + 'static>,
+    Arc<dyn FileStorageProxy + 'static>,
+    Arc<dyn FileStorageLister + 'static>,
+    Arc<dyn FileStorageQuarantine + 'static>,
+);
+
+/**
+ * Get the maintenance services of a file storage implementation, without the optional layers.
+ */
+fn get_maintenance_services(
+    config: &ServerConfig,
+    implementation: &FileStorageImplementation,
+) -> MaintenanceServices {
+    fn services<T>(fs: Arc<T>) -> MaintenanceServices
+    where
+        T: FileStorageMetaRequester
+            + FileStorageProxy
+            + FileStorageLister
+            + FileStorageQuarantine
+            + 'static,
+    {
+        MaintenanceServices(fs.clone(), fs.clone(), fs.clone(), fs)
+    }
+
+    match implementation {
+        FileStorageImplementation::MinioSingleBucketStorage => services(Arc::new(
+            MinioSingleBucketStorage::from_config(config.get_minio_single_bucket_storage_config()),
+        )),
+        FileStorageImplementation::MinioMultipleBucketStorage => {
+            services(Arc::new(MinioMultipleBucketStorage::from_config(
+                config.get_minio_multiple_bucket_storage_config(),
+            )))
+        }
+        FileStorageImplementation::LocalFileStorage => services(Arc::new(
+            LocalFileStorage::from_config(config.get_local_file_storage_config()),
+        )),
+        FileStorageImplementation::PostgresLocalFileStorage => services(Arc::new(
+            PostgresLocalFileStorage::from_config(config.get_postgres_local_file_storage_config()),
+        )),
+        FileStorageImplementation::SftpFileStorage => services(Arc::new(
+            SftpFileStorage::from_config(config.get_sftp_file_storage_config()),
+        )),
+    }
+}
+
+/**
+ * Get a file storage implementation to migrate objects from or to, without the optional layers,
+ * so that the objects are copied as they are stored.
+ */
+pub fn get_migration_storage(
+    config: &ServerConfig,
+    implementation: &FileStorageImplementation,
+) -> MigrationStorage {
+    let MaintenanceServices(meta, proxy, lister, _) =
+        get_maintenance_services(config, implementation);
+    MigrationStorage {
+        meta,
+        proxy,
+        lister,
+    }
+}
+
+/**
+ * Get a file storage implementation to scrub. The objects are read through the configured
+ * encryption and compression, which change their stored content.
+ */
+pub fn get_scrub_storage(
+    config: &ServerConfig,
+    implementation: &FileStorageImplementation,
+) -> ScrubStorage {
+    let MaintenanceServices(meta, proxy, lister, quarantine) =
+        get_maintenance_services(config, implementation);
+    let (meta, proxy): (Arc<dyn FileStorageMetaRequester>, Arc<dyn FileStorageProxy>) =
+        match config.get_encrypted_storage_config() {
+            Some(encryption_config) => {
+                let fs = Arc::new(EncryptedStorage::from_config(
+                    encryption_config,
+                    meta,
+                    proxy,
+                ));
+                (fs.clone(), fs)
+            }
+            None => (meta, proxy),
+        };
+    let (meta, proxy): (Arc<dyn FileStorageMetaRequester>, Arc<dyn FileStorageProxy>) =
+        match config.get_compressed_storage_config() {
+            Some(compression_config) => {
+                let fs = Arc::new(CompressedStorage::from_config(
+                    compression_config,
+                    meta,
+                    proxy,
+                ));
+                (fs.clone(), fs)
+            }
+            None => (meta, proxy),
+        };
+    ScrubStorage {
+        meta,
+        proxy,
+        lister,
+        quarantine,
+    }
+}
+
+/**
+ * Get the content addressed layout of the storage, to grant repositories access to the objects
+ * of its global area. The references are stored through the same layers as by the server, except
+ * for the disk cache, which belongs to the server.
+ */
+pub fn get_content_addressed_storage(config: &ServerConfig) -> ContentAddressedStorage {
+    let layout_config = match config.get_content_addressed_storage_config() {
+        Some(layout_config) => layout_config,
+        None => panic!("The content addressed layout is not configured"),
+    };
+    let services = get_file_backend_services(config, &config.file_storage_implementation);
+    let services = with_mirror(config, services);
+    let services = with_encryption(config, services);
+    let services = with_compression(config, services);
+    let FileBackendServices(meta, proxy, _) = services;
+    let proxy = match proxy {
+        Some(proxy) => proxy,
+        None => panic!("The content addressed layout is only available in proxy mode"),
+    };
+    ContentAddressedStorage::from_config(layout_config, meta, proxy)
+}
+
+/**
+ * Mirror the storage to a second storage, if it is configured. The server writes to both, so the
+ * mirroring requires the proxy mode. The mirror is only read and written by the server, so it
+ * needs no signer.
+ */
+fn with_mirror(config: &ServerConfig, services: FileBackendServices) -> FileBackendServices {
+    let (mirror_config, mirror_storage, mirror_implementation) =
+        match config.get_mirrored_storage_config() {
+            Some(mirror) => mirror,
+            None => return services,
+        };
+    let FileBackendServices(meta, proxy, signer) = services;
+    let proxy = match proxy {
+        Some(proxy) => proxy,
+        None => panic!("The mirroring of the file storage is only available in proxy mode"),
+    };
+    let MaintenanceServices(mirror_meta, mirror_proxy, _, _) =
+        get_maintenance_services(mirror_storage, mirror_implementation);
+    let fs = Arc::new(MirroredStorage::from_config(
+        mirror_config,
+        vec![
+            MirrorBackend { meta, proxy },
+            MirrorBackend {
+                meta: mirror_meta,
+                proxy: mirror_proxy,
+            },
+        ],
+    ));
+    FileBackendServices(fs.clone(), Some(fs), signer)
+}
+
+/**
+ * Encrypt the objects before storing them, if it is configured. The server must see the content
+ * of the objects, so the encryption requires the proxy mode.
+ */
+fn with_encryption(config: &ServerConfig, services: FileBackendServices) -> FileBackendServices {
+    let encryption_config = match config.get_encrypted_storage_config() {
+        Some(encryption_config) => encryption_config,
+        None => return services,
+    };
+    let FileBackendServices(meta, proxy, signer) = services;
+    let proxy = match proxy {
+        Some(proxy) => proxy,
+        None => panic!("The encryption of the objects is only available in proxy mode"),
+    };
+    let fs = Arc::new(EncryptedStorage::from_config(
+        encryption_config,
+        meta,
+        proxy,
+    ));
+    FileBackendServices(fs.clone(), Some(fs), signer)
+}
+
+/**
+ * Compress the objects before storing them, if it is configured. The server must see the content
+ * of the objects, so the compression requires the proxy mode.
+ */
+fn with_compression(config: &ServerConfig, services: FileBackendServices) -> FileBackendServices {
+    let compression_config = match config.get_compressed_storage_config() {
+        Some(compression_config) => compression_config,
+        None => return services,
+    };
+    let FileBackendServices(meta, proxy, signer) = services;
+    let proxy = match proxy {
+        Some(proxy) => proxy,
+        None => panic!("The compression of the objects is only available in proxy mode"),
+    };
+    let fs = Arc::new(CompressedStorage::from_config(
+        compression_config,
+        meta,
+        proxy,
+    ));
+    FileBackendServices(fs.clone(), Some(fs), signer)
+}
+
+/**
+ * Put a disk cache in front of the storage, if it is configured. Only the downloads going through
+ * the server can be cached, so the cache requires the proxy mode. It is put below the encryption
+ * and the compression, so that it keeps the objects as they are stored.
+ */
+fn with_cache(config: &ServerConfig, services: FileBackendServices) -> FileBackendServices {
+    let cache_config = match config.get_cached_storage_config() {
+        Some(cache_config) => cache_config,
+        None => return services,
+    };
+    let FileBackendServices(meta, proxy, signer) = services;
+    let proxy = match proxy {
+        Some(proxy) => proxy,
+        None => panic!("The disk cache is only available in proxy mode"),
+    };
+    let fs = Arc::new(CachedStorage::from_config(cache_config, meta, proxy));
+    FileBackendServices(fs.clone(), Some(fs), signer)
+}
+
+/**
+ * Wrap the storage in the content addressed layout, if it is configured. The references can only
+ * be recorded when the uploads go through the server, so the layout requires the proxy mode.
+ */
+fn with_content_addressed_layout(
+    config: &ServerConfig,
+    services: FileBackendServices,
+) -> FileBackendServices {
+    let layout_config = match config.get_content_addressed_storage_config() {
+        Some(layout_config) => layout_config,
+        None => return services,
+    };
+    let FileBackendServices(meta, proxy, signer) = services;
+    let proxy = match proxy {
+        Some(proxy) => proxy,
+        None => panic!("The content addressed layout is only available in proxy mode"),
+    };
+    let fs = Arc::new(ContentAddressedStorage::from_config(
+        layout_config,
+        meta,
+        proxy,
+    ));
+    FileBackendServices(fs.clone(), Some(fs), signer)
+}
+
+/**
+ * Cache the lookups of the objects, if it is configured. In signer mode, the uploads do not go
+ * through the server, which would keep reporting an uploaded object as missing, up to the
+ * verification of the upload: only found objects are cached. The objects quarantined at verify
+ * go through the cache, which forgets them.
+ */
+fn with_existence_cache(
+    config: &ServerConfig,
+    services: FileBackendServices,
+    quarantine: Option<Arc<dyn FileStorageQuarantine + 'static>>,
+) -> (
+    FileBackendServices,
+    Option<Arc<dyn FileStorageQuarantine + 'static>>,
+) {
+    let mut cache_config = match config.get_existence_cache_config() {
+        Some(cache_config) => cache_config,
+        None => return (services, quarantine),
+    };
+    let FileBackendServices(meta, proxy, signer) = services;
+    if proxy.is_none() {
+        cache_config.negative_ttl = Duration::ZERO;
+    }
+    let fs = Arc::new(ExistenceCache::from_config(
+        cache_config,
+        meta,
+        proxy.clone(),
+        quarantine.clone(),
+    ));
+    let proxy = proxy.map(|_| fs.clone() as Arc<dyn FileStorageProxy>);
+    let quarantine = quarantine.map(|_| fs.clone() as Arc<dyn FileStorageQuarantine>);
+    (FileBackendServices(fs, proxy, signer), quarantine)
+}
+
 /**
  * Create the services from the given configuration. Might panic when some environment variables
  * are missing.
@@ -75,14 +547,32 @@ pub fn from_server_config(config: &ServerConfig) -> InjectedServices {
     ));
 
     // Get the file storage, file proxy and link signer implementations
+    let services = get_file_backend_services(config, &config.file_storage_implementation);
+
+    // Wrap the storage in the optional layers, from the closest to the storage
+    let services = with_mirror(config, services);
+    let services = with_cache(config, services);
+    let services = with_encryption(config, services);
+    let services = with_compression(config, services);
+    let services = with_content_addressed_layout(config, services);
+    let (services, file_storage_quarantine) =
+        with_existence_cache(config, services, get_quarantine(config));
     let FileBackendServices(
         file_storage_meta_requester,
         file_storage_proxy,
         file_storage_link_signer,
-    ) = match &config.file_storage_implementation {
-        FileStorageImplementation::MinioSingleBucketStorage => get_sbs_implementation(config),
-        FileStorageImplementation::LocalFileStorage => get_fs_implementation(config),
-    };
+    ) = services;
+
+    // Get the multipart uploader, only available for some storages in signer mode
+    let file_storage_multipart_uploader = get_multipart_uploader(config);
+
+    // Get the resumable uploader, only available for the local storages in proxy mode, without
+    // the encryption
+    let file_storage_resumable_uploader = get_resumable_uploader(config);
+
+    // Get the chunked uploader, only available for some storages in proxy mode, without the
+    // encryption
+    let file_storage_chunked_uploader = get_chunked_uploader(config);
 
     // Get the locks provider implementation
     let locks_provider: Option<Arc<dyn LocksProvider>> = match config.locks_implementation {
@@ -99,5 +589,11 @@ pub fn from_server_config(config: &ServerConfig) -> InjectedServices {
         file_storage_link_signer,
         token_encoder_decoder,
         locks_provider,
+        file_storage_multipart_uploader,
+        file_storage_resumable_uploader,
+        file_storage_chunked_uploader,
+        file_storage_quarantine,
+        batch_max_objects: Some(config.get_request_limits_config().batch_max_objects),
+        verify_content_max_size: config.verify_content_max_size,
     }
 }
@@ -2,15 +2,24 @@ use crate::{
     controllers::{
         errors::handle_and_filter_error_details,
         locks::{list_locks, list_locks_for_verification, post_lock, unlock},
-        objects::{batch::post_objects_batch, download::download_object, upload::upload_object},
+        media_type::check_lfs_media_type,
+        objects::{
+            batch::post_objects_batch,
+            chunks::put_chunk,
+            download::download_object,
+            tus::{create_upload, head_object, patch_object},
+            upload::upload_object,
+            verify::verify_object,
+        },
     },
     server::config::ServerConfig,
     traits::services::Services,
 };
 use axum::{
     body::HttpBody,
+    extract::DefaultBodyLimit,
     middleware,
-    routing::{get, post, put, MethodRouter},
+    routing::{get, head, patch, post, put, MethodRouter},
     Router,
 };
 use std::sync::Arc;
@@ -57,16 +66,26 @@ pub fn run_server(
 
     // Objects module
     //   - `POST /objects/batch?repo=a/b/c`
+    //   - `POST /objects/verify?repo=a/b/c`
     tracing::info!("Objects module enabled");
-    let app = app.directory_route("/objects/batch", post(post_objects_batch));
+    let lfs_api = Router::new().directory_route("/objects/batch", post(post_objects_batch));
+    let app = app.directory_route("/objects/verify", post(verify_object));
 
     // Proxy module
     //   - `PUT /objects/access/<oid>?repo=a/b/c`
     //   - `GET /objects/access/<oid>?repo=a/b/c`
+    //   - `POST /objects/access/<oid>?repo=a/b/c` (tus)
+    //   - `HEAD /objects/access/<oid>?repo=a/b/c` (tus, or download)
+    //   - `PATCH /objects/access/<oid>?repo=a/b/c` (tus)
+    //   - `PUT /objects/access/<oid>/chunks/<index>?repo=a/b/c` (chunked)
     let app = if config.with_proxy {
         tracing::info!("Proxy module enabled");
         app.directory_route("/objects/access/:oid", put(upload_object))
             .directory_route("/objects/access/:oid", get(download_object))
+            .directory_route("/objects/access/:oid", post(create_upload))
+            .directory_route("/objects/access/:oid", head(head_object))
+            .directory_route("/objects/access/:oid", patch(patch_object))
+            .directory_route("/objects/access/:oid/chunks/:index", put(put_chunk))
     } else {
         app
     };
@@ -76,16 +95,24 @@ pub fn run_server(
     //   - `GET /locks?repo=abc`
     //   - `POST /locks/:id/unlock?repo=abc`
     //   - `POST /locks/verify?repo=abc`
-    let app = if config.with_locks {
+    let lfs_api = if config.with_locks {
         tracing::info!("Locks module enabled");
-        app.directory_route("/locks", post(post_lock))
+        lfs_api
+            .directory_route("/locks", post(post_lock))
             .directory_route("/locks", get(list_locks))
             .directory_route("/locks/:id/unlock", post(unlock))
             .directory_route("/locks/verify", post(list_locks_for_verification))
     } else {
-        app
+        lfs_api
     };
 
+    // The batch and locks routes only speak the LFS media type, with bounded payloads
+    let limits = config.get_request_limits_config();
+    let lfs_api = lfs_api
+        .route_layer(middleware::from_fn(check_lfs_media_type))
+        .layer(DefaultBodyLimit::max(limits.max_body_size));
+    let app = app.merge(lfs_api);
+
     // Error handling and services injection
     app.layer(middleware::from_fn(handle_and_filter_error_details))
         .with_state(services)
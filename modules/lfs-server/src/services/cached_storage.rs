@@ -0,0 +1,887 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::SeekFrom,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::TryStreamExt;
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::watch,
+};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    api::range::ByteRange,
+    traits::file_storage::{
+        FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy, ObjectReader,
+        ObjectStream,
+    },
+};
+
+pub struct CachedStorageConfig {
+    pub root_path: String,
+    pub max_size: u64,
+}
+
+/**
+ * Read-through cache, on the local disk, in front of another storage. Objects are immutable by
+ * oid, so a cached object is only forgotten when it is stored again, which the layers above only
+ * do with their own objects, such as the data keys of the encryption. When the cache exceeds its
+ * maximum size, the least recently used objects are evicted.
+ *
+ * The cache keeps the objects as they are stored: it is put below the encryption, so that it
+ * never holds the content of an encrypted object in plaintext.
+ *
+ * A download missing the cache fetches the object from the storage into the cache, and is served
+ * from the file being written as the object is received. Concurrent downloads of the same object
+ * share this fetch. Range downloads are served from the cache when possible, but do not populate
+ * it. Uploads go directly to the storage.
+ *
+ * The cache only lives as long as the server: its objects are cleared on startup.
+ */
+pub struct CachedStorage {
+    meta: Arc<dyn FileStorageMetaRequester>,
+    proxy: Arc<dyn FileStorageProxy>,
+    cache: Arc<DiskCache>,
+}
+
+// Progress of a fetch, followed by the downloads it serves
+type Fetch = watch::Receiver<FetchState>;
+
+#[derive(Clone)]
+enum FetchState {
+    // The object is requested from the storage
+    Starting,
+    // The object is received in its temporary file, of which the first bytes are written
+    Receiving { content_type: String, written: u64 },
+    // The object is complete, and moved into the cache
+    Done { size: u64 },
+    Failed(String),
+}
+
+// Size of the reads of a file being fetched
+const FOLLOW_BUFFER_SIZE: usize = 64 * 1024;
+
+struct DiskCache {
+    root_path: String,
+    max_size: u64,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    // Keys of the entries by last use, the least recently used first
+    uses: BTreeMap<u64, String>,
+    last_use: u64,
+    size: u64,
+    fetches: HashMap<String, Fetch>,
+}
+
+#[derive(Clone)]
+struct CacheEntry {
+    size: u64,
+    content_type: String,
+    last_use: u64,
+}
+
+impl CacheState {
+    fn touch(&mut self, key: &str) -> Option<CacheEntry> {
+        self.last_use += 1;
+        let last_use = self.last_use;
+        let entry = self.entries.get_mut(key)?;
+        self.uses.remove(&entry.last_use);
+        entry.last_use = last_use;
+        self.uses.insert(last_use, key.to_string());
+        Some(entry.clone())
+    }
+
+    /**
+     * Insert an entry, replacing any previous one for the same key, and return the keys of the
+     * entries evicted to make room for it.
+     */
+    fn insert(
+        &mut self,
+        key: String,
+        size: u64,
+        content_type: String,
+        max_size: u64,
+    ) -> Vec<String> {
+        self.last_use += 1;
+        self.size += size;
+        self.uses.insert(self.last_use, key.clone());
+        let entry = CacheEntry {
+            size,
+            content_type,
+            last_use: self.last_use,
+        };
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.uses.remove(&previous.last_use);
+            self.size -= previous.size;
+        }
+
+        let mut evicted = Vec::new();
+        while self.size > max_size {
+            let Some((_, oldest)) = self.uses.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.size -= entry.size;
+            }
+            evicted.push(oldest);
+        }
+        evicted
+    }
+
+    /**
+     * Remove an entry, and tell whether there was one.
+     */
+    fn remove(&mut self, key: &str) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.uses.remove(&entry.last_use);
+                self.size -= entry.size;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl DiskCache {
+    fn get_path(&self, key: &str) -> String {
+        format!("{}/objects/{}", self.root_path, key)
+    }
+
+    fn get_temporary_path(&self, key: &str) -> String {
+        format!("{}/tmp/{}", self.root_path, key)
+    }
+
+    /**
+     * Unique path, outside of the objects directory, to move an evicted object to before it is
+     * removed.
+     */
+    fn get_tombstone_path(&self, key: &str, last_use: u64) -> String {
+        format!("{}/tmp/{}.{}", self.root_path, key, last_use)
+    }
+
+    /**
+     * Download an object from the storage into the cache, reporting its progress to the
+     * downloads following it.
+     *
+     * The files of the objects directory are only moved while holding the lock, along with the
+     * entries: the evicted files are moved aside, and removed once it is released. A file being
+     * removed can then never be the one of a new fetch of the same object. The downloads reading
+     * the temporary file keep reading it once it is moved.
+     */
+    async fn fetch(
+        self: Arc<Self>,
+        proxy: Arc<dyn FileStorageProxy>,
+        key: String,
+        repo: String,
+        oid: String,
+        progress: watch::Sender<FetchState>,
+    ) {
+        let result = self.download(proxy, &key, &repo, &oid, &progress).await;
+        let (tombstones, done) = {
+            let mut state = self.state.lock().unwrap();
+            state.fetches.remove(&key);
+            let committed = result.and_then(|(size, content_type)| {
+                std::fs::rename(self.get_temporary_path(&key), self.get_path(&key))
+                    .map(|_| (size, content_type))
+                    .map_err(|e| {
+                        let _ = std::fs::remove_file(self.get_temporary_path(&key));
+                        e.to_string()
+                    })
+            });
+            match committed {
+                Ok((size, content_type)) => {
+                    let evicted = state.insert(key, size, content_type, self.max_size);
+                    let tombstones = evicted
+                        .into_iter()
+                        .filter_map(|evicted_key| {
+                            let tombstone = self.get_tombstone_path(&evicted_key, state.last_use);
+                            std::fs::rename(self.get_path(&evicted_key), &tombstone).ok()?;
+                            Some(tombstone)
+                        })
+                        .collect::<Vec<_>>();
+                    (tombstones, FetchState::Done { size })
+                }
+                Err(e) => (Vec::new(), FetchState::Failed(e)),
+            }
+        };
+        progress.send_replace(done);
+        for tombstone in tombstones {
+            let _ = tokio::fs::remove_file(tombstone).await;
+        }
+    }
+
+    /**
+     * Download an object into its temporary file, and return its size and content type.
+     */
+    async fn download(
+        &self,
+        proxy: Arc<dyn FileStorageProxy>,
+        key: &str,
+        repo: &str,
+        oid: &str,
+        progress: &watch::Sender<FetchState>,
+    ) -> Result<(u64, String), String> {
+        let (stream, content_type) = proxy.get(repo, oid).await.map_err(|e| e.to_string())?;
+        let temporary_path = self.get_temporary_path(key);
+        match Self::receive(&temporary_path, stream, &content_type, progress).await {
+            Ok(size) => Ok((size, content_type)),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&temporary_path).await;
+                Err(e.to_string())
+            }
+        }
+    }
+
+    /**
+     * Write the content of an object to a file, reporting the size written after each chunk.
+     */
+    async fn receive(
+        path: &str,
+        mut stream: ObjectStream,
+        content_type: &str,
+        progress: &watch::Sender<FetchState>,
+    ) -> Result<u64, std::io::Error> {
+        let mut file = tokio::fs::File::create(path).await?;
+        progress.send_replace(FetchState::Receiving {
+            content_type: content_type.to_string(),
+            written: 0,
+        });
+        let mut size = 0;
+        while let Some(chunk) = stream.try_next().await? {
+            file.write_all(&chunk).await?;
+            // Written to the file before it is reported, for the downloads to read it
+            file.flush().await?;
+            size += chunk.len() as u64;
+            progress.send_modify(|state| {
+                if let FetchState::Receiving { written, .. } = state {
+                    *written = size;
+                }
+            });
+        }
+        Ok(size)
+    }
+
+    /**
+     * Read the temporary file of a fetch as it is written, up to the end of the object.
+     */
+    fn follow(file: tokio::fs::File, progress: Fetch) -> ObjectStream {
+        Box::pin(futures_util::stream::try_unfold(
+            (file, progress, 0),
+            |(mut file, mut progress, position)| async move {
+                loop {
+                    let mut buffer = vec![0; FOLLOW_BUFFER_SIZE];
+                    let read = file.read(&mut buffer).await?;
+                    if read > 0 {
+                        buffer.truncate(read);
+                        let position = position + read as u64;
+                        return Ok(Some((Bytes::from(buffer), (file, progress, position))));
+                    }
+
+                    // Everything written so far was read, wait for more
+                    let state = progress.borrow_and_update().clone();
+                    match state {
+                        FetchState::Receiving { written, .. } if written > position => {}
+                        FetchState::Done { size } if size > position => {}
+                        FetchState::Done { .. } => return Ok(None),
+                        FetchState::Failed(e) => return Err(std::io::Error::other(e)),
+                        _ => {
+                            if progress.changed().await.is_err() {
+                                return Err(std::io::Error::other("Fetch interrupted"));
+                            }
+                        }
+                    }
+                }
+            },
+        ))
+    }
+
+    /**
+     * Forget an object that was replaced in the storage. Its file is moved aside under the lock,
+     * as the evicted ones are.
+     */
+    async fn forget(&self, key: &str) {
+        let tombstone = {
+            let mut state = self.state.lock().unwrap();
+            if !state.remove(key) {
+                return;
+            }
+            state.last_use += 1;
+            let tombstone = self.get_tombstone_path(key, state.last_use);
+            match std::fs::rename(self.get_path(key), &tombstone) {
+                Ok(_) => tombstone,
+                Err(_) => return,
+            }
+        };
+        let _ = tokio::fs::remove_file(tombstone).await;
+    }
+
+    async fn open(&self, key: &str, range: Option<&ByteRange>) -> std::io::Result<ObjectStream> {
+        let mut file = tokio::fs::File::open(self.get_path(key)).await?;
+        let stream: ObjectStream = match range {
+            Some(range) => {
+                file.seek(SeekFrom::Start(range.start)).await?;
+                Box::pin(ReaderStream::new(file.take(range.end - range.start + 1)))
+            }
+            None => Box::pin(ReaderStream::new(file)),
+        };
+        Ok(stream)
+    }
+}
+
+impl CachedStorage {
+    pub fn new(
+        root_path: String,
+        max_size: u64,
+        meta: Arc<dyn FileStorageMetaRequester>,
+        proxy: Arc<dyn FileStorageProxy>,
+    ) -> CachedStorage {
+        // Entries are only known in memory, the files of a previous run are useless
+        for directory in ["objects", "tmp"] {
+            let path = format!("{}/{}", root_path, directory);
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+        }
+
+        CachedStorage {
+            meta,
+            proxy,
+            cache: Arc::new(DiskCache {
+                root_path,
+                max_size,
+                state: Mutex::new(CacheState::default()),
+            }),
+        }
+    }
+
+    pub fn from_config(
+        config: CachedStorageConfig,
+        meta: Arc<dyn FileStorageMetaRequester>,
+        proxy: Arc<dyn FileStorageProxy>,
+    ) -> CachedStorage {
+        CachedStorage::new(config.root_path, config.max_size, meta, proxy)
+    }
+
+    /**
+     * Name of the cached file of an object. The repository and oid are hashed, so that they can
+     * not escape the cache directory.
+     */
+    fn get_key(repo: &str, oid: &str) -> String {
+        hex::encode(Sha256::digest(format!("{}/{}", repo, oid)))
+    }
+
+    /**
+     * Get the fetch of an object into the cache, starting it unless it is already in progress.
+     * The fetch runs in its own task, so that it completes even if the download that started it
+     * is cancelled.
+     */
+    fn get_fetch(&self, key: &str, repo: &str, oid: &str) -> Fetch {
+        let mut state = self.cache.state.lock().unwrap();
+        if let Some(fetch) = state.fetches.get(key) {
+            return fetch.clone();
+        }
+
+        let (progress, fetch) = watch::channel(FetchState::Starting);
+        tokio::spawn(self.cache.clone().fetch(
+            self.proxy.clone(),
+            key.to_string(),
+            repo.to_string(),
+            oid.to_string(),
+            progress,
+        ));
+        state.fetches.insert(key.to_string(), fetch.clone());
+        fetch
+    }
+
+    /**
+     * Serve an object from its fetch into the cache: from its temporary file while it is
+     * received, or from the cache once it is complete.
+     */
+    async fn get_fetched(
+        &self,
+        key: &str,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let mut fetch = self.get_fetch(key, repo, oid);
+        loop {
+            let state = fetch.borrow_and_update().clone();
+            match state {
+                FetchState::Starting => {}
+                FetchState::Receiving { content_type, .. } => {
+                    // The file is missing once moved into the cache, the fetch is then done
+                    let path = self.cache.get_temporary_path(key);
+                    if let Ok(file) = tokio::fs::File::open(path).await {
+                        return Ok((DiskCache::follow(file, fetch), content_type));
+                    }
+                }
+                FetchState::Done { .. } => {
+                    return match self.open_cached(key, None).await {
+                        Some(cached) => Ok(cached),
+                        // Already evicted by other fetches
+                        None => self.proxy.get(repo, oid).await,
+                    };
+                }
+                FetchState::Failed(e) => return Err(Box::new(std::io::Error::other(e))),
+            }
+            if fetch.changed().await.is_err() {
+                return Err(Box::new(std::io::Error::other("Fetch interrupted")));
+            }
+        }
+    }
+
+    fn get_entry(&self, key: &str) -> Option<CacheEntry> {
+        self.cache.state.lock().unwrap().touch(key)
+    }
+
+    /**
+     * Open a cached object. It may have been evicted since its entry was read, in which case
+     * None is returned.
+     */
+    async fn open_cached(
+        &self,
+        key: &str,
+        range: Option<&ByteRange>,
+    ) -> Option<(ObjectStream, String)> {
+        let entry = self.get_entry(key)?;
+        match self.cache.open(key, range).await {
+            Ok(stream) => Some((stream, entry.content_type)),
+            Err(_) => None,
+        }
+    }
+}
+
+#[async_trait]
+impl FileStorageMetaRequester for CachedStorage {
+    async fn get_meta_result<'a>(&self, repo: &'a str, oid: &'a str) -> FileStorageMetaResult<'a> {
+        match self.get_entry(&Self::get_key(repo, oid)) {
+            Some(entry) => FileStorageMetaResult::new(repo, oid, entry.size),
+            None => self.meta.get_meta_result(repo, oid).await,
+        }
+    }
+
+    async fn get_meta_results<'a>(
+        &self,
+        repo: &'a str,
+        oids: &[&'a str],
+    ) -> Vec<FileStorageMetaResult<'a>> {
+        // Only the objects missing the cache are looked up in the storage, all at once
+        let cached: Vec<_> = oids
+            .iter()
+            .map(|oid| self.get_entry(&Self::get_key(repo, oid)))
+            .collect();
+        let missing: Vec<&str> = oids
+            .iter()
+            .zip(&cached)
+            .filter(|(_, entry)| entry.is_none())
+            .map(|(oid, _)| *oid)
+            .collect();
+        let mut looked_up = self.meta.get_meta_results(repo, &missing).await.into_iter();
+        oids.iter()
+            .zip(cached)
+            .map(|(oid, entry)| match entry {
+                Some(entry) => FileStorageMetaResult::new(repo, oid, entry.size),
+                None => looked_up
+                    .next()
+                    .unwrap_or_else(|| FileStorageMetaResult::not_found(repo, oid)),
+            })
+            .collect()
+    }
+
+    async fn get_content_sha256(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        self.meta.get_content_sha256(repo, oid).await
+    }
+}
+
+#[async_trait]
+impl FileStorageProxy for CachedStorage {
+    async fn get(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let key = Self::get_key(repo, oid);
+        if let Some(cached) = self.open_cached(&key, None).await {
+            return Ok(cached);
+        }
+
+        // Objects that would not fit in the cache are not worth fetching into it
+        let result = self.meta.get_meta_result(repo, oid).await;
+        if result.exists && result.size > self.cache.max_size {
+            return self.proxy.get(repo, oid).await;
+        }
+
+        self.get_fetched(&key, repo, oid).await
+    }
+
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let key = Self::get_key(repo, oid);
+        match self.open_cached(&key, Some(&range)).await {
+            Some(cached) => Ok(cached),
+            None => self.proxy.get_range(repo, oid, range).await,
+        }
+    }
+
+    async fn post(
+        &self,
+        repo: &str,
+        oid: &str,
+        data: ObjectReader,
+        content_type: &str,
+        uploader: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.proxy
+            .post(repo, oid, data, content_type, uploader)
+            .await?;
+        // Objects are immutable by oid, except the ones of the layers above, such as data keys
+        self.cache.forget(&Self::get_key(repo, oid)).await;
+        Ok(())
+    }
+
+    async fn post_if_absent(
+        &self,
+        repo: &str,
+        oid: &str,
+        data: ObjectReader,
+        content_type: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        self.proxy
+            .post_if_absent(repo, oid, data, content_type)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fs::local_file_storage::LocalFileStorage;
+    use crate::test_utils::helpers::{read_all, reader};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /**
+     * Local file storage counting the downloads, which are slowed down so that concurrent
+     * downloads overlap.
+     */
+    struct CountingStorage {
+        files: LocalFileStorage,
+        downloads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl FileStorageMetaRequester for CountingStorage {
+        async fn get_meta_result<'a>(
+            &self,
+            repo: &'a str,
+            oid: &'a str,
+        ) -> FileStorageMetaResult<'a> {
+            self.files.get_meta_result(repo, oid).await
+        }
+    }
+
+    #[async_trait]
+    impl FileStorageProxy for CountingStorage {
+        async fn get(
+            &self,
+            repo: &str,
+            oid: &str,
+        ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+            self.downloads.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            self.files.get(repo, oid).await
+        }
+
+        async fn get_range(
+            &self,
+            repo: &str,
+            oid: &str,
+            range: ByteRange,
+        ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+            self.files.get_range(repo, oid, range).await
+        }
+
+        async fn post(
+            &self,
+            repo: &str,
+            oid: &str,
+            data: ObjectReader,
+            content_type: &str,
+            uploader: Option<&str>,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            self.files
+                .post(repo, oid, data, content_type, uploader)
+                .await
+        }
+    }
+
+    fn get_storage(max_size: u64) -> (Arc<CountingStorage>, CachedStorage) {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let backend = Arc::new(CountingStorage {
+            files: LocalFileStorage::new(format!("/tmp/{}/storage", random_dir)),
+            downloads: AtomicUsize::new(0),
+        });
+        let storage = CachedStorage::new(
+            format!("/tmp/{}/cache", random_dir),
+            max_size,
+            backend.clone(),
+            backend.clone(),
+        );
+        (backend, storage)
+    }
+
+    #[tokio::test]
+    async fn test_get_is_cached() {
+        let (backend, storage) = get_storage(100);
+        storage
+            .post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            let (stream, content_type) = storage.get("repo", "oid").await.unwrap();
+            assert_eq!(read_all(stream).await, vec![1, 2, 3]);
+            assert_eq!(content_type, "image/png");
+        }
+        assert_eq!(backend.downloads.load(Ordering::SeqCst), 1);
+
+        let range = ByteRange { start: 1, end: 2 };
+        let (stream, _) = storage.get_range("repo", "oid", range).await.unwrap();
+        assert_eq!(read_all(stream).await, vec![2, 3]);
+        assert_eq!(storage.get_meta_result("repo", "oid").await.size, 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_meta_results_uses_the_cache() {
+        let (backend, storage) = get_storage(100);
+        storage
+            .post("repo", "a", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+        storage
+            .post("repo", "b", reader(vec![1]), "image/png", None)
+            .await
+            .unwrap();
+        let (stream, _) = storage.get("repo", "a").await.unwrap();
+        assert_eq!(read_all(stream).await, vec![1, 2, 3]);
+        // Only known from the cache from now on
+        std::fs::remove_file(backend.files.get_object_path("repo", "a")).unwrap();
+
+        let results = storage.get_meta_results("repo", &["b", "c", "a"]).await;
+        let results: Vec<_> = results
+            .iter()
+            .map(|result| (result.oid, result.exists, result.size))
+            .collect();
+        assert_eq!(
+            results,
+            vec![("b", true, 1), ("c", false, 0), ("a", true, 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_are_coalesced() {
+        let (backend, storage) = get_storage(100);
+        storage
+            .post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+
+        let downloads = (0..5).map(|_| storage.get("repo", "oid"));
+        for result in futures_util::future::join_all(downloads).await {
+            assert_eq!(read_all(result.unwrap().0).await, vec![1, 2, 3]);
+        }
+        assert_eq!(backend.downloads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_is_evicted() {
+        let (backend, storage) = get_storage(5);
+        for oid in ["a", "b", "c"] {
+            storage
+                .post("repo", oid, reader(vec![1, 2]), "image/png", None)
+                .await
+                .unwrap();
+        }
+
+        read_all(storage.get("repo", "a").await.unwrap().0).await;
+        read_all(storage.get("repo", "b").await.unwrap().0).await;
+        read_all(storage.get("repo", "a").await.unwrap().0).await;
+        // Evicts b, the least recently used
+        read_all(storage.get("repo", "c").await.unwrap().0).await;
+        assert_eq!(backend.downloads.load(Ordering::SeqCst), 3);
+
+        read_all(storage.get("repo", "a").await.unwrap().0).await;
+        assert_eq!(backend.downloads.load(Ordering::SeqCst), 3);
+        read_all(storage.get("repo", "b").await.unwrap().0).await;
+        assert_eq!(backend.downloads.load(Ordering::SeqCst), 4);
+    }
+
+    /**
+     * Storage sending the first chunk of every object, and the rest once released.
+     */
+    struct HeldStorage {
+        release: Mutex<Option<tokio::sync::oneshot::Receiver<()>>>,
+    }
+
+    #[async_trait]
+    impl FileStorageMetaRequester for HeldStorage {
+        async fn get_meta_result<'a>(
+            &self,
+            repo: &'a str,
+            oid: &'a str,
+        ) -> FileStorageMetaResult<'a> {
+            FileStorageMetaResult::new(repo, oid, 3)
+        }
+    }
+
+    #[async_trait]
+    impl FileStorageProxy for HeldStorage {
+        async fn get(
+            &self,
+            _repo: &str,
+            _oid: &str,
+        ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+            let release = self.release.lock().unwrap().take().unwrap();
+            let first = futures_util::stream::iter([Ok(Bytes::from(vec![1, 2]))]);
+            let rest = futures_util::stream::once(async move {
+                let _ = release.await;
+                Ok(Bytes::from(vec![3]))
+            });
+            Ok((
+                Box::pin(futures_util::StreamExt::chain(first, rest)),
+                String::from("image/png"),
+            ))
+        }
+
+        async fn get_range(
+            &self,
+            _repo: &str,
+            _oid: &str,
+            _range: ByteRange,
+        ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+            Err(Box::new(std::io::Error::other("Ranges are not held")))
+        }
+
+        async fn post(
+            &self,
+            _repo: &str,
+            _oid: &str,
+            _data: ObjectReader,
+            _content_type: &str,
+            _uploader: Option<&str>,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            Err(Box::new(std::io::Error::other("Read only storage")))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_miss_is_served_while_fetched() {
+        let (release, held) = tokio::sync::oneshot::channel();
+        let backend = Arc::new(HeldStorage {
+            release: Mutex::new(Some(held)),
+        });
+        let root_path = format!("/tmp/{}/cache", uuid::Uuid::new_v4());
+        let storage = CachedStorage::new(root_path, 100, backend.clone(), backend);
+
+        let (mut stream, content_type) = storage.get("repo", "oid").await.unwrap();
+        assert_eq!(content_type, "image/png");
+        let first = tokio::time::timeout(std::time::Duration::from_secs(1), stream.try_next());
+        assert_eq!(first.await.unwrap().unwrap().unwrap(), vec![1, 2]);
+
+        release.send(()).unwrap();
+        assert_eq!(read_all(stream).await, vec![3]);
+        let (stream, _) = storage.get("repo", "oid").await.unwrap();
+        assert_eq!(read_all(stream).await, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_insert_existing_key() {
+        let mut state = CacheState::default();
+        state.insert(String::from("a"), 2, String::from("image/png"), 5);
+        state.insert(String::from("b"), 2, String::from("image/png"), 5);
+        state.insert(String::from("a"), 2, String::from("image/png"), 5);
+        assert_eq!(state.size, 4);
+        assert_eq!(state.uses.len(), 2);
+
+        // b is now the least recently used
+        let evicted = state.insert(String::from("c"), 2, String::from("image/png"), 5);
+        assert_eq!(evicted, vec![String::from("b")]);
+        assert_eq!(state.size, 4);
+    }
+
+    #[tokio::test]
+    async fn test_evicted_files_are_removed() {
+        let (_, storage) = get_storage(3);
+        for oid in ["a", "b"] {
+            storage
+                .post("repo", oid, reader(vec![1, 2]), "image/png", None)
+                .await
+                .unwrap();
+            read_all(storage.get("repo", oid).await.unwrap().0).await;
+        }
+
+        let count = |directory| {
+            let path = format!("{}/{}", storage.cache.root_path, directory);
+            std::fs::read_dir(path).unwrap().count()
+        };
+        assert_eq!(count("objects"), 1);
+        assert_eq!(count("tmp"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_object_larger_than_cache_is_not_cached() {
+        let (backend, storage) = get_storage(2);
+        storage
+            .post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let (stream, _) = storage.get("repo", "oid").await.unwrap();
+            assert_eq!(read_all(stream).await, vec![1, 2, 3]);
+        }
+        assert_eq!(backend.downloads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_object_stored_again_is_forgotten() {
+        let (backend, storage) = get_storage(100);
+        storage
+            .post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+        read_all(storage.get("repo", "oid").await.unwrap().0).await;
+
+        storage
+            .post("repo", "oid", reader(vec![4, 5]), "image/png", None)
+            .await
+            .unwrap();
+        let (stream, _) = storage.get("repo", "oid").await.unwrap();
+        assert_eq!(read_all(stream).await, vec![4, 5]);
+        assert_eq!(storage.get_meta_result("repo", "oid").await.size, 2);
+        assert_eq!(backend.downloads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_missing_object() {
+        let (_, storage) = get_storage(100);
+        assert!(!storage.get_meta_result("repo", "oid").await.exists);
+        assert!(storage.get("repo", "oid").await.is_err());
+    }
+}
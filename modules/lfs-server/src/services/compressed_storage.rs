@@ -0,0 +1,610 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use async_compression::{
+    tokio::bufread::{ZstdDecoder, ZstdEncoder},
+    Level,
+};
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader, ReadBuf};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::{
+    api::range::ByteRange,
+    services::stored_entry_cache::StoredEntryCache,
+    traits::file_storage::{
+        FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy, ObjectReader,
+        ObjectStream,
+    },
+};
+
+pub struct CompressedStorageConfig {
+    pub level: i32,
+}
+
+/**
+ * Storage compressing the objects with zstd before storing them in another storage.
+ *
+ * Each stored object ends with a trailer recording its codec and its size once decompressed, so
+ * that the size of the objects is still the one known by the clients. Objects stored before the
+ * compression was enabled have no trailer, and are served as they are.
+ *
+ * Objects that are already compressed, either recognized by their first bytes or barely
+ * shrinking when a sample is compressed, are stored as they are, with a trailer. Ranges of
+ * those objects are read directly from the storage, while ranges of compressed objects are
+ * decompressed from the beginning of the object.
+ *
+ * The trailers are kept in memory once read or written, so that looking up an object usually
+ * only asks the storage for its stored size.
+ */
+pub struct CompressedStorage {
+    level: i32,
+    meta: Arc<dyn FileStorageMetaRequester>,
+    proxy: Arc<dyn FileStorageProxy>,
+    trailers: Mutex<StoredEntryCache<Option<Trailer>>>,
+}
+
+const TRAILER_MAGIC: &[u8; 8] = b"\x89LFSCDC\n";
+const TRAILER_SIZE: u64 = 17;
+
+// Number of trailers kept in memory, the least recently used ones are evicted beyond it
+const TRAILER_CACHE_MAX_ENTRIES: usize = 100_000;
+
+// Size of the beginning of an object sampled to decide whether to compress it
+const SAMPLE_SIZE: usize = 128 * 1024;
+
+// Below this ratio between compressed and original sizes of the sample, the object is compressed
+const MAX_COMPRESSION_RATIO: f64 = 0.9;
+
+// Bytes found at given offsets in common compressed formats: gzip, zstd, xz, bzip2, zip, 7z,
+// rar, png, jpeg, ogg, flac, webp and mp4
+const COMPRESSED_SIGNATURES: &[&[(usize, &[u8])]] = &[
+    &[(0, b"\x1f\x8b")],
+    &[(0, b"\x28\xb5\x2f\xfd")],
+    &[(0, b"\xfd7zXZ\x00")],
+    &[(0, b"BZh")],
+    &[(0, b"PK\x03\x04")],
+    &[(0, b"7z\xbc\xaf\x27\x1c")],
+    &[(0, b"Rar!\x1a\x07")],
+    &[(0, b"\x89PNG\r\n\x1a\n")],
+    &[(0, b"\xff\xd8\xff")],
+    &[(0, b"OggS")],
+    &[(0, b"fLaC")],
+    &[(0, b"RIFF"), (8, b"WEBP")],
+    &[(4, b"ftyp")],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    None = 0,
+    Zstd = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Trailer {
+    codec: Codec,
+    size: u64,
+}
+
+impl Trailer {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.codec as u8];
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(TRAILER_MAGIC);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Trailer> {
+        if bytes.len() as u64 != TRAILER_SIZE || &bytes[9..] != TRAILER_MAGIC {
+            return None;
+        }
+        let codec = match bytes[0] {
+            0 => Codec::None,
+            1 => Codec::Zstd,
+            _ => return None,
+        };
+        let size = u64::from_be_bytes(bytes[1..9].try_into().unwrap());
+        Some(Trailer { codec, size })
+    }
+}
+
+/**
+ * Reader counting the bytes read from another reader.
+ */
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = (buf.filled().len() - filled) as u64;
+            self.count.fetch_add(read, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+/**
+ * Reader appending the trailer once the stored content is read, when the size of the object is
+ * finally known.
+ */
+struct TrailerReader<R> {
+    inner: R,
+    codec: Codec,
+    count: Arc<AtomicU64>,
+    trailer: Option<std::io::Cursor<Vec<u8>>>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TrailerReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.trailer.is_none() {
+            let filled = buf.filled().len();
+            match Pin::new(&mut self.inner).poll_read(cx, buf) {
+                Poll::Ready(Ok(())) if buf.filled().len() == filled => {
+                    let trailer = Trailer {
+                        codec: self.codec,
+                        size: self.count.load(Ordering::Relaxed),
+                    };
+                    self.trailer = Some(std::io::Cursor::new(trailer.encode()));
+                }
+                poll => return poll,
+            }
+        }
+        let trailer = self.trailer.as_mut().unwrap();
+        Pin::new(trailer).poll_read(cx, buf)
+    }
+}
+
+impl CompressedStorage {
+    pub fn new(
+        level: i32,
+        meta: Arc<dyn FileStorageMetaRequester>,
+        proxy: Arc<dyn FileStorageProxy>,
+    ) -> CompressedStorage {
+        CompressedStorage {
+            level,
+            meta,
+            proxy,
+            trailers: Mutex::new(StoredEntryCache::new(TRAILER_CACHE_MAX_ENTRIES)),
+        }
+    }
+
+    pub fn from_config(
+        config: CompressedStorageConfig,
+        meta: Arc<dyn FileStorageMetaRequester>,
+        proxy: Arc<dyn FileStorageProxy>,
+    ) -> CompressedStorage {
+        CompressedStorage::new(config.level, meta, proxy)
+    }
+
+    /**
+     * Whether compressing an object is worth it, judging from its first bytes.
+     */
+    async fn is_compressible(sample: &[u8]) -> bool {
+        if sample.is_empty()
+            || COMPRESSED_SIGNATURES.iter().any(|signature| {
+                signature.iter().all(|(offset, bytes)| {
+                    sample.get(*offset..).is_some_and(|s| s.starts_with(bytes))
+                })
+            })
+        {
+            return false;
+        }
+        let mut compressed = Vec::new();
+        let mut encoder = ZstdEncoder::with_quality(sample, Level::Fastest);
+        if encoder.read_to_end(&mut compressed).await.is_err() {
+            return false;
+        }
+        (compressed.len() as f64) < (sample.len() as f64) * MAX_COMPRESSION_RATIO
+    }
+
+    async fn read_sample(data: &mut ObjectReader) -> std::io::Result<Vec<u8>> {
+        let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+        data.take(SAMPLE_SIZE as u64)
+            .read_to_end(&mut sample)
+            .await?;
+        Ok(sample)
+    }
+
+    /**
+     * Get the size of the stored object and its trailer, if it has one. An object without
+     * trailer was stored before the compression was enabled. The trailer is only read from the
+     * storage when it is not in the cache.
+     */
+    async fn get_stored(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<(u64, Option<Trailer>)>, Box<dyn std::error::Error>> {
+        let result = self.meta.get_meta_result(repo, oid).await;
+        if !result.exists {
+            return Ok(None);
+        }
+        if result.size < TRAILER_SIZE {
+            return Ok(Some((result.size, None)));
+        }
+        if let Some(trailer) = self.trailers.lock().unwrap().get(repo, oid, result.size) {
+            return Ok(Some((result.size, trailer)));
+        }
+
+        let range = ByteRange {
+            start: result.size - TRAILER_SIZE,
+            end: result.size - 1,
+        };
+        let (stream, _) = self.proxy.get_range(repo, oid, range).await?;
+        let mut bytes = Vec::new();
+        StreamReader::new(stream).read_to_end(&mut bytes).await?;
+        let trailer = Trailer::decode(&bytes);
+        self.trailers
+            .lock()
+            .unwrap()
+            .insert(repo, oid, result.size, trailer);
+        Ok(Some((result.size, trailer)))
+    }
+
+    /**
+     * Read the stored content of an object, without its trailer.
+     */
+    async fn get_content(
+        &self,
+        repo: &str,
+        oid: &str,
+        stored_size: u64,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        if stored_size == TRAILER_SIZE {
+            // The content is empty, only the content type is read
+            let (_, content_type) = self.proxy.get(repo, oid).await?;
+            return Ok((Box::pin(futures_util::stream::empty()), content_type));
+        }
+        let range = ByteRange {
+            start: 0,
+            end: stored_size - TRAILER_SIZE - 1,
+        };
+        self.proxy.get_range(repo, oid, range).await
+    }
+
+    fn decompress(stream: ObjectStream) -> ObjectStream {
+        let reader = StreamReader::new(stream);
+        Box::pin(ReaderStream::new(ZstdDecoder::new(BufReader::new(reader))))
+    }
+
+    fn not_found() -> Box<dyn std::error::Error> {
+        Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Object not found",
+        ))
+    }
+}
+
+#[async_trait]
+impl FileStorageMetaRequester for CompressedStorage {
+    async fn get_meta_result<'a>(&self, repo: &'a str, oid: &'a str) -> FileStorageMetaResult<'a> {
+        match self.get_stored(repo, oid).await {
+            Ok(Some((_, Some(trailer)))) => FileStorageMetaResult::new(repo, oid, trailer.size),
+            Ok(Some((size, None))) => FileStorageMetaResult::new(repo, oid, size),
+            Ok(None) => FileStorageMetaResult::not_found(repo, oid),
+            Err(e) => {
+                tracing::error!("Failed to read the trailer of {}: {}", oid, e);
+                FileStorageMetaResult::not_found(repo, oid)
+            }
+        }
+    }
+
+    async fn get_content_sha256(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let stored = self.get_stored(repo, oid).await?;
+        match stored {
+            // The storage only knows the stored content, trailer included
+            Some((_, Some(_))) => Ok(None),
+            Some((_, None)) => self.meta.get_content_sha256(repo, oid).await,
+            None => Err(Self::not_found()),
+        }
+    }
+}
+
+#[async_trait]
+impl FileStorageProxy for CompressedStorage {
+    async fn get(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let stored = self.get_stored(repo, oid).await?;
+        match stored {
+            Some((stored_size, Some(trailer))) => {
+                let (stream, content_type) = self.get_content(repo, oid, stored_size).await?;
+                match trailer.codec {
+                    Codec::None => Ok((stream, content_type)),
+                    Codec::Zstd => Ok((Self::decompress(stream), content_type)),
+                }
+            }
+            Some((_, None)) => self.proxy.get(repo, oid).await,
+            None => Err(Self::not_found()),
+        }
+    }
+
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let stored = self.get_stored(repo, oid).await?;
+        match stored {
+            Some((stored_size, Some(trailer))) if trailer.codec == Codec::Zstd => {
+                let (stream, content_type) = self.get_content(repo, oid, stored_size).await?;
+                let mut reader = StreamReader::new(Self::decompress(stream));
+                tokio::io::copy(&mut (&mut reader).take(range.start), &mut tokio::io::sink())
+                    .await?;
+                let range_reader = reader.take(range.end - range.start + 1);
+                Ok((Box::pin(ReaderStream::new(range_reader)), content_type))
+            }
+            // The content is stored as is, before the trailer
+            Some(_) => self.proxy.get_range(repo, oid, range).await,
+            None => Err(Self::not_found()),
+        }
+    }
+
+    async fn post(
+        &self,
+        repo: &str,
+        oid: &str,
+        mut data: ObjectReader,
+        content_type: &str,
+        uploader: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let sample = Self::read_sample(&mut data).await?;
+        let compressible = Self::is_compressible(&sample).await;
+
+        let count = Arc::new(AtomicU64::new(0));
+        let content = CountingReader {
+            inner: std::io::Cursor::new(sample).chain(data),
+            count: count.clone(),
+        };
+        let codec = if compressible {
+            Codec::Zstd
+        } else {
+            Codec::None
+        };
+        let stored: ObjectReader = match codec {
+            Codec::Zstd => {
+                let encoder =
+                    ZstdEncoder::with_quality(BufReader::new(content), Level::Precise(self.level));
+                Box::pin(TrailerReader {
+                    inner: encoder,
+                    codec,
+                    count: count.clone(),
+                    trailer: None,
+                })
+            }
+            Codec::None => Box::pin(TrailerReader {
+                inner: content,
+                codec,
+                count: count.clone(),
+                trailer: None,
+            }),
+        };
+        let stored_count = Arc::new(AtomicU64::new(0));
+        let stored = Box::pin(CountingReader {
+            inner: stored,
+            count: stored_count.clone(),
+        });
+        self.proxy
+            .post(repo, oid, stored, content_type, uploader)
+            .await?;
+
+        // The trailer of the object is known, there is no need to read it back
+        let trailer = Trailer {
+            codec,
+            size: count.load(Ordering::Relaxed),
+        };
+        self.trailers.lock().unwrap().insert(
+            repo,
+            oid,
+            stored_count.load(Ordering::Relaxed),
+            Some(trailer),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fs::local_file_storage::LocalFileStorage;
+    use crate::{
+        aw,
+        test_utils::helpers::{read_all, reader},
+    };
+
+    fn get_storage() -> (Arc<LocalFileStorage>, CompressedStorage) {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let files = Arc::new(LocalFileStorage::new(format!("/tmp/{}", random_dir)));
+        let storage = CompressedStorage::new(3, files.clone(), files.clone());
+        (files, storage)
+    }
+
+    fn compressible_data() -> Vec<u8> {
+        (0..300_000).map(|i| ((i / 100) % 7) as u8).collect()
+    }
+
+    fn random_data() -> Vec<u8> {
+        (0..300_000).map(|_| rand::random::<u8>()).collect()
+    }
+
+    #[test]
+    fn test_trailer() {
+        let trailer = Trailer {
+            codec: Codec::Zstd,
+            size: 12345,
+        };
+        let bytes = trailer.encode();
+        assert_eq!(bytes.len() as u64, TRAILER_SIZE);
+        assert_eq!(Trailer::decode(&bytes), Some(trailer));
+        assert_eq!(Trailer::decode(&bytes[1..]), None);
+        assert_eq!(Trailer::decode(&[0; 17]), None);
+    }
+
+    #[test]
+    fn test_compressible_object_is_compressed() {
+        let (files, storage) = get_storage();
+        let data = compressible_data();
+        aw!(storage.post("repo", "oid", reader(data.clone()), "text/csv", None)).unwrap();
+
+        let stored = aw!(files.get_meta_result("repo", "oid"));
+        assert!(stored.size < data.len() as u64 / 10);
+        let result = aw!(storage.get_meta_result("repo", "oid"));
+        assert!(result.exists);
+        assert_eq!(result.size, data.len() as u64);
+
+        let (stream, content_type) = aw!(storage.get("repo", "oid")).unwrap();
+        assert_eq!(aw!(read_all(stream)), data);
+        assert_eq!(content_type, "text/csv");
+
+        let range = ByteRange {
+            start: 1000,
+            end: 250_000,
+        };
+        let (stream, _) = aw!(storage.get_range("repo", "oid", range)).unwrap();
+        assert_eq!(aw!(read_all(stream)), data[1000..=250_000].to_vec());
+    }
+
+    #[test]
+    fn test_incompressible_object_is_stored_as_is() {
+        let (files, storage) = get_storage();
+        let data = random_data();
+        aw!(storage.post("repo", "oid", reader(data.clone()), "image/png", None)).unwrap();
+
+        let stored = aw!(files.get_meta_result("repo", "oid"));
+        assert_eq!(stored.size, data.len() as u64 + TRAILER_SIZE);
+        assert_eq!(
+            aw!(storage.get_meta_result("repo", "oid")).size,
+            data.len() as u64
+        );
+
+        let (stream, _) = aw!(storage.get("repo", "oid")).unwrap();
+        assert_eq!(aw!(read_all(stream)), data);
+
+        let range = ByteRange { start: 10, end: 20 };
+        let (stream, _) = aw!(storage.get_range("repo", "oid", range)).unwrap();
+        assert_eq!(aw!(read_all(stream)), data[10..=20].to_vec());
+    }
+
+    #[test]
+    fn test_compressed_format_is_stored_as_is() {
+        let (files, storage) = get_storage();
+        let mut data = b"PK\x03\x04".to_vec();
+        data.extend(compressible_data());
+        aw!(storage.post("repo", "oid", reader(data.clone()), "application/zip", None)).unwrap();
+
+        let stored = aw!(files.get_meta_result("repo", "oid"));
+        assert_eq!(stored.size, data.len() as u64 + TRAILER_SIZE);
+    }
+
+    #[test]
+    fn test_compressed_format_at_offset_is_stored_as_is() {
+        let (files, storage) = get_storage();
+        let signatures = [
+            b"RIFF\x00\x00\x00\x00WEBP".as_slice(),
+            b"\x00\x00\x00\x18ftyp",
+        ];
+        for (i, signature) in signatures.into_iter().enumerate() {
+            let oid = format!("oid{}", i);
+            let mut data = signature.to_vec();
+            data.extend(compressible_data());
+            aw!(storage.post("repo", &oid, reader(data.clone()), "image/webp", None)).unwrap();
+
+            let stored = aw!(files.get_meta_result("repo", &oid));
+            assert_eq!(stored.size, data.len() as u64 + TRAILER_SIZE);
+        }
+
+        // A RIFF container of another format is compressed
+        let mut data = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        data.extend(compressible_data());
+        aw!(storage.post("repo", "wav", reader(data.clone()), "audio/wav", None)).unwrap();
+        let stored = aw!(files.get_meta_result("repo", "wav"));
+        assert!(stored.size < data.len() as u64);
+    }
+
+    #[test]
+    fn test_empty_object() {
+        let (_, storage) = get_storage();
+        aw!(storage.post("repo", "oid", reader(vec![]), "text/plain", None)).unwrap();
+
+        let result = aw!(storage.get_meta_result("repo", "oid"));
+        assert!(result.exists);
+        assert_eq!(result.size, 0);
+        let (stream, content_type) = aw!(storage.get("repo", "oid")).unwrap();
+        assert!(aw!(read_all(stream)).is_empty());
+        assert_eq!(content_type, "text/plain");
+    }
+
+    #[test]
+    fn test_object_stored_before_compression_is_served() {
+        let (files, storage) = get_storage();
+        aw!(files.post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+
+        assert_eq!(aw!(storage.get_meta_result("repo", "oid")).size, 3);
+        let (stream, _) = aw!(storage.get("repo", "oid")).unwrap();
+        assert_eq!(aw!(read_all(stream)), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_post_failure_is_not_visible() {
+        let (_, storage) = get_storage();
+        let failing: ObjectReader = Box::pin(
+            tokio_test::io::Builder::new()
+                .read(&compressible_data())
+                .read_error(std::io::Error::other("rejected"))
+                .build(),
+        );
+        assert!(aw!(storage.post("repo", "oid", failing, "text/csv", None)).is_err());
+        assert!(!aw!(storage.get_meta_result("repo", "oid")).exists);
+    }
+
+    #[test]
+    fn test_trailer_is_not_read_back() {
+        let (files, storage) = get_storage();
+        let data = compressible_data();
+        aw!(storage.post("repo", "oid", reader(data.clone()), "text/csv", None)).unwrap();
+
+        // A trailer read from the storage would now be invalid
+        let path = files.get_object_path("repo", "oid");
+        let mut stored = std::fs::read(&path).unwrap();
+        let stored_size = stored.len();
+        stored[stored_size - 1] = 0;
+        std::fs::write(&path, stored).unwrap();
+
+        let result = aw!(storage.get_meta_result("repo", "oid"));
+        assert!(result.exists);
+        assert_eq!(result.size, data.len() as u64);
+    }
+
+    #[test]
+    fn test_missing_object() {
+        let (_, storage) = get_storage();
+        assert!(!aw!(storage.get_meta_result("repo", "oid")).exists);
+        assert!(aw!(storage.get("repo", "oid")).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::{
+    api::range::ByteRange,
+    traits::file_storage::{
+        FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy, ObjectReader,
+        ObjectStream,
+    },
+};
+
+// SHA-256 of the empty content, the only oid whose content is as empty as a reference
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+pub struct ContentAddressedStorageConfig {
+    pub global_repo: String,
+}
+
+/**
+ * Storage keeping the content of each oid once, whatever the number of repositories it is
+ * pushed to. It wraps another storage, and uses two areas of it:
+ *   - the global area, a reserved repository holding the content of every object
+ *   - per repository references, which are empty objects stored where the content of the object
+ *     would be without this layout
+ *
+ * A repository only sees the objects it has a reference to, so that knowing an oid is not
+ * enough to download it: a repository gets a reference by uploading the content, even if it is
+ * already in the global area, or by being granted it explicitly.
+ *
+ * Objects stored before this layout was enabled are still served from their repository, as
+ * their reference holds their content.
+ */
+pub struct ContentAddressedStorage {
+    global_repo: String,
+    meta: Arc<dyn FileStorageMetaRequester>,
+    proxy: Arc<dyn FileStorageProxy>,
+}
+
+impl ContentAddressedStorage {
+    pub fn new(
+        global_repo: String,
+        meta: Arc<dyn FileStorageMetaRequester>,
+        proxy: Arc<dyn FileStorageProxy>,
+    ) -> ContentAddressedStorage {
+        ContentAddressedStorage {
+            global_repo,
+            meta,
+            proxy,
+        }
+    }
+
+    pub fn from_config(
+        config: ContentAddressedStorageConfig,
+        meta: Arc<dyn FileStorageMetaRequester>,
+        proxy: Arc<dyn FileStorageProxy>,
+    ) -> ContentAddressedStorage {
+        ContentAddressedStorage::new(config.global_repo, meta, proxy)
+    }
+
+    /**
+     * Whether a name can hold the global area. Git hosting services do not allow a namespace
+     * starting with a dot, so a top level name starting with one can never collide with the
+     * path of a real repository.
+     */
+    pub fn is_reserved_name(name: &str) -> bool {
+        name.len() > 1 && name.starts_with('.') && name != ".." && !name.contains('/')
+    }
+
+    /**
+     * Grant a repository access to an object already in the global area, without uploading it.
+     */
+    pub async fn grant(&self, repo: &str, oid: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.assert_not_global(repo)?;
+        if !self
+            .meta
+            .get_meta_result(&self.global_repo, oid)
+            .await
+            .exists
+        {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Object not in the global area",
+            )));
+        }
+        self.add_reference(repo, oid, "application/octet-stream", None)
+            .await
+    }
+
+    async fn add_reference(
+        &self,
+        repo: &str,
+        oid: &str,
+        content_type: &str,
+        uploader: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.proxy
+            .post(
+                repo,
+                oid,
+                Box::pin(tokio::io::empty()),
+                content_type,
+                uploader,
+            )
+            .await
+    }
+
+    /**
+     * Repository in which the content of a referenced object is: the global area, or the
+     * repository itself for the objects stored before this layout.
+     */
+    async fn get_content_repo<'a>(
+        &'a self,
+        repo: &'a str,
+        oid: &str,
+    ) -> Result<&'a str, Box<dyn std::error::Error>> {
+        self.assert_not_global(repo)?;
+        if !self.meta.get_meta_result(repo, oid).await.exists {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "No reference to the object",
+            )));
+        }
+        if self
+            .meta
+            .get_meta_result(&self.global_repo, oid)
+            .await
+            .exists
+        {
+            Ok(&self.global_repo)
+        } else {
+            Ok(repo)
+        }
+    }
+
+    /**
+     * The global area is not a repository, it must not be reachable as one.
+     */
+    fn assert_not_global(&self, repo: &str) -> Result<(), std::io::Error> {
+        if repo == self.global_repo {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Reserved repository",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FileStorageMetaRequester for ContentAddressedStorage {
+    async fn get_meta_result<'a>(&self, repo: &'a str, oid: &'a str) -> FileStorageMetaResult<'a> {
+        if self.assert_not_global(repo).is_err() {
+            return FileStorageMetaResult::not_found(repo, oid);
+        }
+        let reference = self.meta.get_meta_result(repo, oid).await;
+        if !reference.exists {
+            return reference;
+        }
+        let content = self.meta.get_meta_result(&self.global_repo, oid).await;
+        if content.exists {
+            FileStorageMetaResult::new(repo, oid, content.size)
+        } else if reference.size == 0 && oid != EMPTY_SHA256 {
+            // A reference whose content was removed from the global area, e.g. quarantined. It
+            // is reported as missing so that the object is uploaded again
+            FileStorageMetaResult::not_found(repo, oid)
+        } else {
+            reference
+        }
+    }
+
+    async fn get_content_sha256(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let content_repo = self.get_content_repo(repo, oid).await?;
+        self.meta.get_content_sha256(content_repo, oid).await
+    }
+}
+
+#[async_trait]
+impl FileStorageProxy for ContentAddressedStorage {
+    async fn get(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let content_repo = self.get_content_repo(repo, oid).await?;
+        self.proxy.get(content_repo, oid).await
+    }
+
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let content_repo = self.get_content_repo(repo, oid).await?;
+        self.proxy.get_range(content_repo, oid, range).await
+    }
+
+    async fn post(
+        &self,
+        repo: &str,
+        oid: &str,
+        mut data: ObjectReader,
+        content_type: &str,
+        uploader: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.assert_not_global(repo)?;
+        if self
+            .meta
+            .get_meta_result(&self.global_repo, oid)
+            .await
+            .exists
+        {
+            // The content is still read to the end, so that it is verified against the oid
+            // before the repository gets a reference to it
+            tokio::io::copy(&mut data, &mut tokio::io::sink()).await?;
+        } else {
+            self.proxy
+                .post(&self.global_repo, oid, data, content_type, uploader)
+                .await?;
+        }
+        self.add_reference(repo, oid, content_type, uploader).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fs::local_file_storage::LocalFileStorage;
+    use crate::{
+        aw,
+        test_utils::helpers::{read_all, reader},
+    };
+
+    fn get_storage() -> (String, ContentAddressedStorage) {
+        let root_path = format!("/tmp/{}", uuid::Uuid::new_v4());
+        let files = Arc::new(LocalFileStorage::new(root_path.clone()));
+        let storage = ContentAddressedStorage::new(String::from(".global"), files.clone(), files);
+        (root_path, storage)
+    }
+
+    #[test]
+    fn test_is_reserved_name() {
+        assert!(ContentAddressedStorage::is_reserved_name(".global"));
+        for name in ["_global", "global", ".", "..", ".a/b", "a/.global", ""] {
+            assert!(!ContentAddressedStorage::is_reserved_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_post_stores_content_once() {
+        let (root_path, storage) = get_storage();
+        aw!(storage.post("a", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+        aw!(storage.post("b", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+
+        let global = std::fs::metadata(format!("{}/.global/objects/oid", root_path)).unwrap();
+        assert_eq!(global.len(), 3);
+        for repo in ["a", "b"] {
+            let reference =
+                std::fs::metadata(format!("{}/{}/objects/oid", root_path, repo)).unwrap();
+            assert_eq!(reference.len(), 0);
+
+            let result = aw!(storage.get_meta_result(repo, "oid"));
+            assert!(result.exists);
+            assert_eq!(result.size, 3);
+
+            let (stream, content_type) = aw!(storage.get(repo, "oid")).unwrap();
+            assert_eq!(aw!(read_all(stream)), vec![1, 2, 3]);
+            assert_eq!(content_type, "image/png");
+        }
+
+        let range = ByteRange { start: 1, end: 2 };
+        let (stream, _) = aw!(storage.get_range("b", "oid", range)).unwrap();
+        assert_eq!(aw!(read_all(stream)), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_repo_without_reference_does_not_see_object() {
+        let (_, storage) = get_storage();
+        aw!(storage.post("a", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+
+        assert!(!aw!(storage.get_meta_result("b", "oid")).exists);
+        assert!(aw!(storage.get("b", "oid")).is_err());
+    }
+
+    #[test]
+    fn test_global_repo_is_not_reachable() {
+        let (_, storage) = get_storage();
+        aw!(storage.post("a", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+
+        assert!(!aw!(storage.get_meta_result(".global", "oid")).exists);
+        assert!(aw!(storage.get(".global", "oid")).is_err());
+        assert!(aw!(storage.post(".global", "oid2", reader(vec![1]), "image/png", None)).is_err());
+    }
+
+    #[test]
+    fn test_failed_upload_of_existing_content_adds_no_reference() {
+        let (_, storage) = get_storage();
+        aw!(storage.post("a", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+
+        let failing: ObjectReader = Box::pin(
+            tokio_test::io::Builder::new()
+                .read(&[1, 2, 3])
+                .read_error(std::io::Error::other("rejected"))
+                .build(),
+        );
+        assert!(aw!(storage.post("b", "oid", failing, "image/png", None)).is_err());
+        assert!(!aw!(storage.get_meta_result("b", "oid")).exists);
+    }
+
+    #[test]
+    fn test_grant() {
+        let (_, storage) = get_storage();
+        assert!(aw!(storage.grant("b", "oid")).is_err());
+
+        aw!(storage.post("a", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+        aw!(storage.grant("b", "oid")).unwrap();
+
+        let result = aw!(storage.get_meta_result("b", "oid"));
+        assert!(result.exists);
+        assert_eq!(result.size, 3);
+    }
+
+    #[test]
+    fn test_object_stored_before_layout_is_served() {
+        let (root_path, storage) = get_storage();
+        let files = LocalFileStorage::new(root_path);
+        aw!(files.post("a", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+
+        let result = aw!(storage.get_meta_result("a", "oid"));
+        assert!(result.exists);
+        assert_eq!(result.size, 3);
+        let (stream, _) = aw!(storage.get("a", "oid")).unwrap();
+        assert_eq!(aw!(read_all(stream)), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_reference_without_content_is_missing() {
+        let (root_path, storage) = get_storage();
+        aw!(storage.post("a", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+        std::fs::remove_file(format!("{}/.global/objects/oid", root_path)).unwrap();
+
+        assert!(!aw!(storage.get_meta_result("a", "oid")).exists);
+        aw!(storage.post("a", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+        assert_eq!(aw!(storage.get_meta_result("a", "oid")).size, 3);
+    }
+}
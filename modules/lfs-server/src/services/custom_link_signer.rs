@@ -13,43 +13,139 @@ use crate::{
     },
 };
 
+// Action an upload link is signed for. Both links carry the same claims otherwise, so that
+// without it, the verify link would be accepted to upload the object, and the other way round
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+enum LinkAction {
+    Upload,
+    Verify,
+}
+
+impl LinkAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LinkAction::Upload => "upload",
+            LinkAction::Verify => "verify",
+        }
+    }
+
+    fn parse(action: &str) -> Option<LinkAction> {
+        match action {
+            "upload" => Some(LinkAction::Upload),
+            "verify" => Some(LinkAction::Verify),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct LinkSignature {
     operation: Operation,
+    action: Option<LinkAction>,
     oid: String,
     repo: String,
+    size: Option<u64>,
+    user: Option<String>,
+    ref_name: Option<String>,
+    upload_id: Option<String>,
 }
 
 impl LinkSignature {
     pub fn new(operation: Operation, oid: String, repo: String) -> LinkSignature {
         LinkSignature {
             operation,
+            action: None,
             oid,
             repo,
+            size: None,
+            user: None,
+            ref_name: None,
+            upload_id: None,
         }
     }
 
-    pub fn from_headers(headers: &HeaderMap, signer: &impl TokenEncoderDecoder) -> LinkSignature {
-        let jwt = Jwt::from_headers(headers, signer).unwrap();
-        let operation = match jwt.get_claim("operation").unwrap().as_str() {
+    pub fn with_action(mut self, action: LinkAction) -> LinkSignature {
+        self.action = Some(action);
+        self
+    }
+
+    pub fn with_size(mut self, size: u64) -> LinkSignature {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn with_user(mut self, user: &str) -> LinkSignature {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    pub fn with_ref(mut self, ref_name: Option<&str>) -> LinkSignature {
+        self.ref_name = ref_name.map(|r| r.to_string());
+        self
+    }
+
+    pub fn with_upload_id(mut self, upload_id: &str) -> LinkSignature {
+        self.upload_id = Some(upload_id.to_string());
+        self
+    }
+
+    /**
+     * Read the signature of a link from the headers of a request. A missing, invalid or expired
+     * token, or one signed for something else than a link, gives None.
+     */
+    pub fn from_headers(
+        headers: &HeaderMap,
+        signer: &impl TokenEncoderDecoder,
+    ) -> Option<LinkSignature> {
+        let jwt = Jwt::from_headers(headers, signer).ok()?;
+        let operation = match jwt.get_claim("operation").ok()?.as_str() {
             "download" => Operation::Download,
             "upload" => Operation::Upload,
-            _ => panic!(),
+            _ => return None,
         };
-        let oid = jwt.get_claim("oid").unwrap();
-        let repo = jwt.get_claim("repo").unwrap();
-        LinkSignature {
+        let action = jwt
+            .get_claim("action")
+            .ok()
+            .and_then(|a| LinkAction::parse(&a));
+        let oid = jwt.get_claim("oid").ok()?;
+        let repo = jwt.get_claim("repo").ok()?;
+        let size = jwt.get_claim("size").ok().and_then(|s| s.parse().ok());
+        let user = jwt.get_claim("user").ok();
+        let ref_name = jwt.get_claim("ref").ok();
+        let upload_id = jwt.get_claim("upload_id").ok();
+        Some(LinkSignature {
             operation,
+            action,
             oid,
             repo,
-        }
+            size,
+            user,
+            ref_name,
+            upload_id,
+        })
     }
 
     pub fn sign(&self, signer: &impl TokenEncoderDecoder) -> String {
         let mut claims = BTreeMap::new();
         claims.insert("operation", self.operation.to_string());
+        if let Some(action) = self.action {
+            claims.insert("action", action.as_str().to_string());
+        }
         claims.insert("oid", self.oid.clone());
         claims.insert("repo", self.repo.clone());
+        if let Some(size) = self.size {
+            claims.insert("size", size.to_string());
+        }
+        if let Some(user) = &self.user {
+            claims.insert("user", user.clone());
+        }
+        if let Some(ref_name) = &self.ref_name {
+            claims.insert("ref", ref_name.clone());
+        }
+        if let Some(upload_id) = &self.upload_id {
+            claims.insert("upload_id", upload_id.clone());
+        }
         signer.encode_token(&mut claims).unwrap()
     }
 }
@@ -85,6 +181,77 @@ impl<TTokenEncoderDecoder: TokenEncoderDecoder> CustomLinkSigner<TTokenEncoderDe
         let host = std::env::var(host_key).unwrap();
         CustomLinkSigner::new(host, signer)
     }
+
+    /**
+     * Build the action to verify an upload through the `/objects/verify` endpoint. It is signed
+     * the same way as the upload link, but for the verify action, so that neither link is
+     * accepted in place of the other.
+     */
+    pub fn verify_action(
+        &self,
+        result: &FileStorageMetaResult,
+        size: u64,
+        user: &str,
+        ref_name: Option<&str>,
+    ) -> ObjectAction {
+        let signature =
+            Self::upload_signature(result, size, user, ref_name).with_action(LinkAction::Verify);
+        self.sign_verify_action(result, signature)
+    }
+
+    /**
+     * Whether the headers carry a link signed for an object and an action of it.
+     */
+    fn check_signature(
+        &self,
+        repo: &str,
+        oid: &str,
+        headers: Option<&HeaderMap>,
+        operation: Operation,
+        action: Option<LinkAction>,
+    ) -> bool {
+        let headers = match headers {
+            None => return false,
+            Some(s) => s,
+        };
+        let signature_payload = match LinkSignature::from_headers(headers, &self.signer) {
+            None => return false,
+            Some(s) => s,
+        };
+        signature_payload.oid == oid
+            && signature_payload.repo == repo
+            && signature_payload.operation == operation
+            && signature_payload.action == action
+    }
+
+    fn upload_signature(
+        result: &FileStorageMetaResult,
+        size: u64,
+        user: &str,
+        ref_name: Option<&str>,
+    ) -> LinkSignature {
+        LinkSignature::new(
+            Operation::Upload,
+            result.oid.to_string(),
+            result.repo.to_string(),
+        )
+        .with_size(size)
+        .with_user(user)
+        .with_ref(ref_name)
+    }
+
+    fn sign_verify_action(
+        &self,
+        result: &FileStorageMetaResult,
+        signature: LinkSignature,
+    ) -> ObjectAction {
+        let link = format!("{}/{}/objects/verify", self.host, result.repo);
+        ObjectAction::new(
+            link,
+            Some(&format!("Bearer {}", signature.sign(&self.signer))),
+            3600,
+        )
+    }
 }
 
 #[async_trait]
@@ -114,24 +281,23 @@ impl<TTokenEncoderDecoder: TokenEncoderDecoder + Sync + Send> FileStorageLinkSig
     async fn post_presigned_link<'a>(
         &self,
         result: FileStorageMetaResult<'a>,
-        _size: u32,
+        size: u64,
+        user: &str,
+        ref_name: Option<&str>,
     ) -> Result<(ObjectAction, Option<ObjectAction>), Box<dyn std::error::Error>> {
         let link = format!(
             "{}/{}/objects/access/{}",
             self.host, result.repo, result.oid
         );
-        let signature = LinkSignature::new(
-            Operation::Upload,
-            result.oid.to_string(),
-            result.repo.to_string(),
-        );
+        let signature =
+            Self::upload_signature(&result, size, user, ref_name).with_action(LinkAction::Upload);
         return Ok((
             ObjectAction::new(
                 link,
                 Some(&format!("Bearer {}", signature.sign(&self.signer))),
                 3600,
             ),
-            None,
+            Some(self.verify_action(&result, size, user, ref_name)),
         ));
     }
 
@@ -142,14 +308,59 @@ impl<TTokenEncoderDecoder: TokenEncoderDecoder + Sync + Send> FileStorageLinkSig
         headers: Option<&HeaderMap>,
         operation: Operation,
     ) -> bool {
-        let headers = match headers {
-            None => return false,
-            Some(s) => s,
+        // Only the upload links are accepted to upload, not the verify links
+        let action = match operation {
+            Operation::Download => None,
+            Operation::Upload => Some(LinkAction::Upload),
         };
-        let signature_payload = LinkSignature::from_headers(headers, &self.signer);
-        return signature_payload.oid == oid
-            && signature_payload.repo == repo
-            && signature_payload.operation == operation;
+        self.check_signature(repo, oid, headers, operation, action)
+    }
+
+    async fn check_verify_link(&self, repo: &str, oid: &str, headers: Option<&HeaderMap>) -> bool {
+        self.check_signature(
+            repo,
+            oid,
+            headers,
+            Operation::Upload,
+            Some(LinkAction::Verify),
+        )
+    }
+
+    fn signed_size(&self, headers: &HeaderMap) -> Option<u64> {
+        Jwt::from_headers(headers, &self.signer)
+            .ok()?
+            .get_claim("size")
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    fn signed_user(&self, headers: &HeaderMap) -> Option<String> {
+        Jwt::from_headers(headers, &self.signer)
+            .ok()?
+            .get_claim("user")
+            .ok()
+    }
+
+    fn multipart_verify_action(
+        &self,
+        result: &FileStorageMetaResult,
+        size: u64,
+        user: &str,
+        ref_name: Option<&str>,
+        upload_id: &str,
+    ) -> Option<ObjectAction> {
+        let signature = Self::upload_signature(result, size, user, ref_name)
+            .with_action(LinkAction::Verify)
+            .with_upload_id(upload_id);
+        Some(self.sign_verify_action(result, signature))
+    }
+
+    fn signed_upload_id(&self, headers: &HeaderMap) -> Option<String> {
+        Jwt::from_headers(headers, &self.signer)
+            .ok()?
+            .get_claim("upload_id")
+            .ok()
     }
 }
 
@@ -195,21 +406,176 @@ mod tests {
     #[test]
     fn test_post_presigned_link() {
         // Build link
-        let (post_link, verify_link) =
-            aw!(get_signer()
-                .post_presigned_link(FileStorageMetaResult::new("repo", "oid", 100), 100))
-            .unwrap();
-        assert!(verify_link.is_none());
+        let (post_link, verify_link) = aw!(get_signer().post_presigned_link(
+            FileStorageMetaResult::new("repo", "oid", 100),
+            100,
+            "user",
+            Some("refs/heads/main")
+        ))
+        .unwrap();
         assert_eq!(
             post_link.href,
             "http://localhost:8080/repo/objects/access/oid"
         );
+        let verify_link = verify_link.unwrap();
+        assert_eq!(
+            verify_link.href,
+            "http://localhost:8080/repo/objects/verify"
+        );
 
         // Check headers
         let jwt = parse_header_helper(post_link);
         assert_eq!(jwt.get("oid").unwrap(), "oid");
         assert_eq!(jwt.get("repo").unwrap(), "repo");
         assert_eq!(jwt.get("operation").unwrap(), "upload");
+        assert_eq!(jwt.get("action").unwrap(), "upload");
+        assert_eq!(jwt.get("size").unwrap(), "100");
+        assert_eq!(jwt.get("user").unwrap(), "user");
+        assert_eq!(jwt.get("ref").unwrap(), "refs/heads/main");
+        let jwt = parse_header_helper(verify_link);
+        assert_eq!(jwt.get("oid").unwrap(), "oid");
+        assert_eq!(jwt.get("operation").unwrap(), "upload");
+        assert_eq!(jwt.get("action").unwrap(), "verify");
+        assert_eq!(jwt.get("size").unwrap(), "100");
+        assert_eq!(jwt.get("ref").unwrap(), "refs/heads/main");
+    }
+
+    fn authorization_headers(link: ObjectAction) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            link.header.unwrap().authorization.parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_upload_and_verify_links_are_not_interchangeable() {
+        let signer = get_signer();
+        let (post_link, verify_link) = aw!(signer.post_presigned_link(
+            FileStorageMetaResult::new("repo", "oid", 100),
+            100,
+            "user",
+            None
+        ))
+        .unwrap();
+        let upload = authorization_headers(post_link);
+        let verify = authorization_headers(verify_link.unwrap());
+
+        assert!(aw!(signer.check_link(
+            "repo",
+            "oid",
+            Some(&upload),
+            Operation::Upload
+        )));
+        assert!(!aw!(signer.check_verify_link("repo", "oid", Some(&upload))));
+        assert!(aw!(signer.check_verify_link("repo", "oid", Some(&verify))));
+        assert!(!aw!(signer.check_link(
+            "repo",
+            "oid",
+            Some(&verify),
+            Operation::Upload
+        )));
+        assert!(!aw!(signer.check_verify_link(
+            "repo",
+            "bad-oid",
+            Some(&verify)
+        )));
+    }
+
+    #[test]
+    fn test_verify_link_malformed_token() {
+        let signer = get_signer();
+        assert!(!aw!(signer.check_verify_link("repo", "oid", None)));
+        for authorization in ["Bearer", "Bearer not-a-token", "Basic dXNlcjpwYXNz"] {
+            let mut headers = HeaderMap::new();
+            headers.insert("Authorization", authorization.parse().unwrap());
+            assert!(!aw!(signer.check_verify_link(
+                "repo",
+                "oid",
+                Some(&headers)
+            )));
+        }
+
+        // A token signed for something else than a link, without oid
+        let mut claims = BTreeMap::from([("operation", String::from("upload"))]);
+        let token = JwtTokenEncoderDecoder::new("secret".to_string(), 3600)
+            .encode_token(&mut claims)
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        assert!(!aw!(signer.check_verify_link(
+            "repo",
+            "oid",
+            Some(&headers)
+        )));
+
+        // An expired link
+        let expired = CustomLinkSigner::new(
+            "http://localhost:8080".to_string(),
+            JwtTokenEncoderDecoder::new("secret".to_string(), 0),
+        );
+        let verify = expired.verify_action(
+            &FileStorageMetaResult::new("repo", "oid", 100),
+            100,
+            "user",
+            None,
+        );
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        let headers = authorization_headers(verify);
+        assert!(!aw!(signer.check_verify_link(
+            "repo",
+            "oid",
+            Some(&headers)
+        )));
+    }
+
+    #[test]
+    fn test_signed_size_and_user() {
+        let signer = get_signer();
+        let (post_link, _) = aw!(signer.post_presigned_link(
+            FileStorageMetaResult::new("repo", "oid", 100),
+            100,
+            "user",
+            None
+        ))
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            post_link.header.unwrap().authorization.parse().unwrap(),
+        );
+        assert_eq!(signer.signed_size(&headers), Some(100));
+        assert_eq!(signer.signed_user(&headers), Some(String::from("user")));
+        assert_eq!(
+            signer.signed_size(&get_test_headers(Operation::Upload)),
+            None
+        );
+        assert_eq!(
+            signer.signed_user(&get_test_headers(Operation::Upload)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_signed_size_larger_than_4_gib() {
+        let signer = get_signer();
+        let (post_link, _) = aw!(signer.post_presigned_link(
+            FileStorageMetaResult::new("repo", "oid", 0),
+            5_000_000_000,
+            "user",
+            None
+        ))
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            post_link.header.unwrap().authorization.parse().unwrap(),
+        );
+        assert_eq!(signer.signed_size(&headers), Some(5_000_000_000));
     }
 
     fn get_test_headers(operation: Operation) -> HeaderMap {
@@ -0,0 +1,963 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use openssl::{
+    md::Md,
+    pkey::Id,
+    pkey_ctx::PkeyCtx,
+    symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::io::StreamReader;
+
+use crate::{
+    api::range::ByteRange,
+    services::stored_entry_cache::StoredEntryCache,
+    traits::file_storage::{
+        FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy, ObjectReader,
+        ObjectStream,
+    },
+};
+
+pub struct EncryptedStorageConfig {
+    pub master_keys: String,
+}
+
+/**
+ * Storage encrypting the objects with AES-256-GCM before storing them in another storage.
+ *
+ * Each repository has its own data key, created on its first upload and stored in the
+ * repository, wrapped by the master key along with the name of the repository. The data key is
+ * only stored if the repository has none yet, and read back, so that servers creating one at the
+ * same time all use the one kept. Rotating the master key only rewraps the data keys: the master
+ * keys are given one per line, the first one being used to wrap the data keys, and the others
+ * only to unwrap the data keys they wrapped before the rotation. These are rewrapped with the
+ * first master key when they are loaded.
+ *
+ * Each object is encrypted with its own key, derived from the data key with HKDF-SHA256 and a
+ * random salt stored in its header, so that no two objects share a key and nonce. Objects are
+ * encrypted in segments, each one with its own authentication tag, so that they can be decrypted
+ * as they are downloaded, and ranges can be read without decrypting the whole object. Objects
+ * stored before the encryption was enabled are served as they are.
+ *
+ * The headers are kept in memory once read or written, so that looking up an object usually only
+ * asks the storage for its stored size.
+ */
+pub struct EncryptedStorage {
+    master_keys: Vec<MasterKey>,
+    meta: Arc<dyn FileStorageMetaRequester>,
+    proxy: Arc<dyn FileStorageProxy>,
+    data_keys: Mutex<HashMap<String, Arc<DataKey>>>,
+    headers: Mutex<StoredEntryCache<Option<Header>>>,
+    // Held while a data key is created, so that this server does not create two of them
+    data_key_creation: tokio::sync::Mutex<()>,
+}
+
+// Name under which the wrapped data key of a repository is stored. It can not be an oid.
+const DATA_KEY_OID: &str = "lfs-data-key";
+
+const HEADER_MAGIC: &[u8; 8] = b"\x89LFSENC\n";
+const HEADER_SIZE: u64 = 49;
+const SEGMENT_SIZE: u64 = 64 * 1024;
+const TAG_SIZE: u64 = 16;
+const SALT_SIZE: usize = 32;
+
+// Number of headers kept in memory, the least recently used ones are evicted beyond it
+const HEADER_CACHE_MAX_ENTRIES: usize = 100_000;
+
+// Context of the derivation of the keys of the objects from the data keys
+const OBJECT_KEY_INFO: &[u8] = b"lfs-object-key";
+
+const KEY_SIZE: usize = 32;
+const KEY_ID_SIZE: usize = 8;
+const WRAPPED_KEY_SIZE: usize = KEY_ID_SIZE + 12 + KEY_SIZE + TAG_SIZE as usize;
+
+struct MasterKey {
+    id: [u8; KEY_ID_SIZE],
+    key: [u8; KEY_SIZE],
+}
+
+struct DataKey {
+    id: [u8; KEY_ID_SIZE],
+    key: [u8; KEY_SIZE],
+}
+
+fn key_id(key: &[u8]) -> [u8; KEY_ID_SIZE] {
+    let digest = Sha256::digest(key);
+    digest[..KEY_ID_SIZE].try_into().unwrap()
+}
+
+/**
+ * Additional data authenticated along with the data key of a repository, so that a data key
+ * copied to another repository fails authentication there.
+ */
+fn data_key_aad(repo: &str) -> Vec<u8> {
+    format!("{}:{}", DATA_KEY_OID, repo).into_bytes()
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+/**
+ * Header of an encrypted object: the id of the data key, and the salt deriving the key of the
+ * object from it.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    key_id: [u8; KEY_ID_SIZE],
+    salt: [u8; SALT_SIZE],
+}
+
+impl Header {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = HEADER_MAGIC.to_vec();
+        bytes.push(1);
+        bytes.extend_from_slice(&self.key_id);
+        bytes.extend_from_slice(&self.salt);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Header> {
+        if bytes.len() as u64 != HEADER_SIZE || &bytes[..8] != HEADER_MAGIC || bytes[8] != 1 {
+            return None;
+        }
+        Some(Header {
+            key_id: bytes[9..17].try_into().unwrap(),
+            salt: bytes[17..].try_into().unwrap(),
+        })
+    }
+
+    /**
+     * Derive the key of the object from the data key. As it is only used for this object, the
+     * nonces of the segments only need to be unique within the object.
+     */
+    fn object_key(&self, data_key: &DataKey) -> std::io::Result<[u8; KEY_SIZE]> {
+        let mut ctx = PkeyCtx::new_id(Id::HKDF)?;
+        ctx.derive_init()?;
+        ctx.set_hkdf_md(Md::sha256())?;
+        ctx.set_hkdf_key(&data_key.key)?;
+        ctx.set_hkdf_salt(&self.salt)?;
+        ctx.add_hkdf_info(OBJECT_KEY_INFO)?;
+        let mut key = [0; KEY_SIZE];
+        ctx.derive(Some(&mut key))?;
+        Ok(key)
+    }
+
+    fn nonce(segment: u64, last: bool) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[7..11].copy_from_slice(&(segment as u32).to_be_bytes());
+        nonce[11] = last as u8;
+        nonce
+    }
+}
+
+/**
+ * Size of the content of an encrypted object, from its stored size. The last segment is never
+ * full, it is empty when the size of the content is a multiple of the segment size.
+ */
+fn content_size(stored_size: u64) -> u64 {
+    let body = stored_size.saturating_sub(HEADER_SIZE);
+    let segments = body.div_ceil(SEGMENT_SIZE + TAG_SIZE);
+    body.saturating_sub(segments * TAG_SIZE)
+}
+
+/**
+ * Encrypt or decrypt the segments of an object, one after the other.
+ */
+struct Segments {
+    reader: ObjectReader,
+    key: [u8; KEY_SIZE],
+    aad: Vec<u8>,
+    segment: u64,
+    done: bool,
+}
+
+impl Segments {
+    fn new(
+        reader: ObjectReader,
+        key: [u8; KEY_SIZE],
+        header: &Header,
+        oid: &str,
+        first: u64,
+    ) -> Self {
+        let mut aad = header.encode();
+        aad.extend_from_slice(oid.as_bytes());
+        Segments {
+            reader,
+            key,
+            aad,
+            segment: first,
+            done: false,
+        }
+    }
+
+    async fn read(&mut self, size: u64) -> std::io::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(size as usize);
+        (&mut self.reader)
+            .take(size)
+            .read_to_end(&mut buffer)
+            .await?;
+        Ok(buffer)
+    }
+
+    async fn encrypt_next(&mut self) -> std::io::Result<Option<Bytes>> {
+        if self.done {
+            return Ok(None);
+        }
+        let plaintext = self.read(SEGMENT_SIZE).await?;
+        let last = (plaintext.len() as u64) < SEGMENT_SIZE;
+        let nonce = Header::nonce(self.segment, last);
+        let mut tag = [0; TAG_SIZE as usize];
+        let mut segment = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(&nonce),
+            &self.aad,
+            &plaintext,
+            &mut tag,
+        )
+        .map_err(std::io::Error::other)?;
+        segment.extend_from_slice(&tag);
+        self.segment += 1;
+        self.done = last;
+        Ok(Some(Bytes::from(segment)))
+    }
+
+    async fn decrypt_next(&mut self) -> std::io::Result<Option<Bytes>> {
+        if self.done {
+            return Ok(None);
+        }
+        let segment = self.read(SEGMENT_SIZE + TAG_SIZE).await?;
+        if (segment.len() as u64) < TAG_SIZE {
+            return Err(invalid_data("Truncated object"));
+        }
+        let last = (segment.len() as u64) < SEGMENT_SIZE + TAG_SIZE;
+        let (ciphertext, tag) = segment.split_at(segment.len() - TAG_SIZE as usize);
+        let nonce = Header::nonce(self.segment, last);
+        let plaintext = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(&nonce),
+            &self.aad,
+            ciphertext,
+            tag,
+        )
+        .map_err(|_| invalid_data("Object failed authentication"))?;
+        self.segment += 1;
+        self.done = last;
+        Ok(Some(Bytes::from(plaintext)))
+    }
+
+    /**
+     * Encrypt the segments as they are read, adding their size to a count.
+     */
+    fn encrypt(self, count: Arc<AtomicU64>) -> ObjectReader {
+        let stream = futures_util::stream::unfold(self, move |mut segments| {
+            let count = count.clone();
+            async move {
+                match segments.encrypt_next().await {
+                    Ok(Some(segment)) => {
+                        count.fetch_add(segment.len() as u64, Ordering::Relaxed);
+                        Some((Ok(segment), segments))
+                    }
+                    Ok(None) => None,
+                    Err(e) => {
+                        segments.done = true;
+                        Some((Err(e), segments))
+                    }
+                }
+            }
+        });
+        Box::pin(StreamReader::new(stream))
+    }
+
+    fn decrypt(self) -> ObjectStream {
+        Box::pin(futures_util::stream::unfold(
+            self,
+            |mut segments| async move {
+                match segments.decrypt_next().await {
+                    Ok(Some(segment)) => Some((Ok(segment), segments)),
+                    Ok(None) => None,
+                    Err(e) => {
+                        segments.done = true;
+                        Some((Err(e), segments))
+                    }
+                }
+            },
+        ))
+    }
+}
+
+impl EncryptedStorage {
+    pub fn new(
+        master_keys: String,
+        meta: Arc<dyn FileStorageMetaRequester>,
+        proxy: Arc<dyn FileStorageProxy>,
+    ) -> EncryptedStorage {
+        let master_keys: Vec<MasterKey> = master_keys
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let key: [u8; KEY_SIZE] = hex::decode(line)
+                    .ok()
+                    .and_then(|key| key.try_into().ok())
+                    .expect("Master keys must be 32 bytes, hex encoded");
+                MasterKey {
+                    id: key_id(&key),
+                    key,
+                }
+            })
+            .collect();
+        if master_keys.is_empty() {
+            panic!("No master key");
+        }
+
+        EncryptedStorage {
+            master_keys,
+            meta,
+            proxy,
+            data_keys: Mutex::new(HashMap::new()),
+            headers: Mutex::new(StoredEntryCache::new(HEADER_CACHE_MAX_ENTRIES)),
+            data_key_creation: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn from_config(
+        config: EncryptedStorageConfig,
+        meta: Arc<dyn FileStorageMetaRequester>,
+        proxy: Arc<dyn FileStorageProxy>,
+    ) -> EncryptedStorage {
+        EncryptedStorage::new(config.master_keys, meta, proxy)
+    }
+
+    /**
+     * Wrap the data key of a repository with the current master key: the id of the master key,
+     * the nonce, and the encrypted data key with its tag.
+     */
+    fn wrap(&self, repo: &str, data_key: &[u8; KEY_SIZE]) -> std::io::Result<Vec<u8>> {
+        let master_key = &self.master_keys[0];
+        let mut nonce = [0; 12];
+        openssl::rand::rand_bytes(&mut nonce).map_err(std::io::Error::other)?;
+        let mut tag = [0; TAG_SIZE as usize];
+        let wrapped = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &master_key.key,
+            Some(&nonce),
+            &data_key_aad(repo),
+            data_key,
+            &mut tag,
+        )
+        .map_err(std::io::Error::other)?;
+
+        let mut bytes = master_key.id.to_vec();
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&wrapped);
+        bytes.extend_from_slice(&tag);
+        Ok(bytes)
+    }
+
+    /**
+     * Unwrap the data key of a repository, and tell whether it is wrapped by the current master
+     * key. The data keys wrapped by a previous master key are still unwrapped, to be rewrapped.
+     */
+    fn unwrap(&self, repo: &str, bytes: &[u8]) -> std::io::Result<([u8; KEY_SIZE], bool)> {
+        if bytes.len() != WRAPPED_KEY_SIZE {
+            return Err(invalid_data("Invalid data key"));
+        }
+        let (master_key_id, rest) = bytes.split_at(KEY_ID_SIZE);
+        let (nonce, rest) = rest.split_at(12);
+        let (wrapped, tag) = rest.split_at(KEY_SIZE);
+        let position = self
+            .master_keys
+            .iter()
+            .position(|master_key| master_key.id == master_key_id)
+            .ok_or_else(|| invalid_data("Data key wrapped by an unknown master key"))?;
+        let data_key = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.master_keys[position].key,
+            Some(nonce),
+            &data_key_aad(repo),
+            wrapped,
+            tag,
+        )
+        .map_err(|_| invalid_data("Data key failed authentication"))?;
+        Ok((data_key.try_into().unwrap(), position == 0))
+    }
+
+    async fn store_data_key(
+        &self,
+        repo: &str,
+        data_key: &[u8; KEY_SIZE],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let wrapped = self.wrap(repo, data_key)?;
+        self.proxy
+            .post(
+                repo,
+                DATA_KEY_OID,
+                Box::pin(std::io::Cursor::new(wrapped)),
+                "application/octet-stream",
+                None,
+            )
+            .await
+    }
+
+    /**
+     * Load the data key of a repository from the storage. Data keys wrapped by a previous master
+     * key are rewrapped with the current one.
+     */
+    async fn load_data_key(
+        &self,
+        repo: &str,
+    ) -> Result<Option<[u8; KEY_SIZE]>, Box<dyn std::error::Error>> {
+        if !self.meta.get_meta_result(repo, DATA_KEY_OID).await.exists {
+            return Ok(None);
+        }
+        let (stream, _) = self.proxy.get(repo, DATA_KEY_OID).await?;
+        let mut bytes = Vec::new();
+        StreamReader::new(stream).read_to_end(&mut bytes).await?;
+        let (data_key, current) = self.unwrap(repo, &bytes)?;
+        if !current {
+            self.store_data_key(repo, &data_key).await?;
+            tracing::info!("Rewrapped the data key of {}", repo);
+        }
+        Ok(Some(data_key))
+    }
+
+    /**
+     * Get the data key of a repository, creating it if requested and missing. Another server may
+     * create one at the same time: the data key is only stored if there is none yet, and read
+     * back, so that the objects are only encrypted with the data key kept.
+     */
+    async fn get_data_key(
+        &self,
+        repo: &str,
+        create: bool,
+    ) -> Result<Option<Arc<DataKey>>, Box<dyn std::error::Error>> {
+        if let Some(data_key) = self.data_keys.lock().unwrap().get(repo) {
+            return Ok(Some(data_key.clone()));
+        }
+
+        let _creation = self.data_key_creation.lock().await;
+        let loaded = self.load_data_key(repo).await?;
+        let key = match loaded {
+            Some(key) => key,
+            None if create => {
+                let mut key = [0; KEY_SIZE];
+                openssl::rand::rand_bytes(&mut key)?;
+                let wrapped = Box::pin(std::io::Cursor::new(self.wrap(repo, &key)?));
+                let stored = self
+                    .proxy
+                    .post_if_absent(repo, DATA_KEY_OID, wrapped, "application/octet-stream")
+                    .await?;
+                if !stored {
+                    tracing::info!("Another server created the data key of {}", repo);
+                }
+                match self.load_data_key(repo).await? {
+                    Some(key) => key,
+                    None => return Err(Box::new(invalid_data("Missing data key"))),
+                }
+            }
+            None => return Ok(None),
+        };
+        let data_key = Arc::new(DataKey {
+            id: key_id(&key),
+            key,
+        });
+        self.data_keys
+            .lock()
+            .unwrap()
+            .insert(repo.to_string(), data_key.clone());
+        Ok(Some(data_key))
+    }
+
+    /**
+     * Key of an encrypted object, derived from the data key given by its header.
+     */
+    async fn get_object_key(
+        &self,
+        repo: &str,
+        header: &Header,
+    ) -> Result<[u8; KEY_SIZE], Box<dyn std::error::Error>> {
+        match self.get_data_key(repo, false).await? {
+            Some(data_key) if data_key.id == header.key_id => Ok(header.object_key(&data_key)?),
+            _ => Err(Box::new(invalid_data("Missing data key"))),
+        }
+    }
+
+    /**
+     * Get the size of the stored object and its header, if it has one. An object without header
+     * was stored before the encryption was enabled. The header is only read from the storage
+     * when it is not in the cache.
+     */
+    async fn get_stored(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<(u64, Option<Header>)>, Box<dyn std::error::Error>> {
+        let result = self.meta.get_meta_result(repo, oid).await;
+        if !result.exists {
+            return Ok(None);
+        }
+        if result.size < HEADER_SIZE {
+            return Ok(Some((result.size, None)));
+        }
+        if let Some(header) = self.headers.lock().unwrap().get(repo, oid, result.size) {
+            return Ok(Some((result.size, header)));
+        }
+
+        let range = ByteRange {
+            start: 0,
+            end: HEADER_SIZE - 1,
+        };
+        let (stream, _) = self.proxy.get_range(repo, oid, range).await?;
+        let mut bytes = Vec::new();
+        StreamReader::new(stream).read_to_end(&mut bytes).await?;
+        let header = Header::decode(&bytes);
+        self.headers
+            .lock()
+            .unwrap()
+            .insert(repo, oid, result.size, header);
+        Ok(Some((result.size, header)))
+    }
+
+    fn assert_not_data_key(oid: &str) -> Result<(), std::io::Error> {
+        if oid == DATA_KEY_OID {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Reserved oid",
+            ));
+        }
+        Ok(())
+    }
+
+    fn not_found() -> Box<dyn std::error::Error> {
+        Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Object not found",
+        ))
+    }
+}
+
+/**
+ * Prepend bytes already read to the rest of a reader.
+ */
+fn prepend(bytes: Vec<u8>, reader: impl AsyncRead + Send + 'static) -> ObjectReader {
+    Box::pin(std::io::Cursor::new(bytes).chain(reader))
+}
+
+#[async_trait]
+impl FileStorageMetaRequester for EncryptedStorage {
+    async fn get_meta_result<'a>(&self, repo: &'a str, oid: &'a str) -> FileStorageMetaResult<'a> {
+        if Self::assert_not_data_key(oid).is_err() {
+            return FileStorageMetaResult::not_found(repo, oid);
+        }
+        match self.get_stored(repo, oid).await {
+            Ok(Some((size, Some(_)))) => FileStorageMetaResult::new(repo, oid, content_size(size)),
+            Ok(Some((size, None))) => FileStorageMetaResult::new(repo, oid, size),
+            Ok(None) => FileStorageMetaResult::not_found(repo, oid),
+            Err(e) => {
+                tracing::error!("Failed to read the header of {}: {}", oid, e);
+                FileStorageMetaResult::not_found(repo, oid)
+            }
+        }
+    }
+
+    async fn get_content_sha256(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        Self::assert_not_data_key(oid)?;
+        let stored = self.get_stored(repo, oid).await?;
+        match stored {
+            // The storage only knows the encrypted content
+            Some((_, Some(_))) => Ok(None),
+            Some((_, None)) => self.meta.get_content_sha256(repo, oid).await,
+            None => Err(Self::not_found()),
+        }
+    }
+}
+
+#[async_trait]
+impl FileStorageProxy for EncryptedStorage {
+    async fn get(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        Self::assert_not_data_key(oid)?;
+        let (stream, content_type) = self.proxy.get(repo, oid).await?;
+        let mut reader = StreamReader::new(stream);
+        let mut bytes = Vec::new();
+        (&mut reader)
+            .take(HEADER_SIZE)
+            .read_to_end(&mut bytes)
+            .await?;
+
+        let header = match Header::decode(&bytes) {
+            Some(header) => header,
+            None => {
+                let reader = prepend(bytes, reader);
+                let stream = tokio_util::io::ReaderStream::new(reader);
+                return Ok((Box::pin(stream), content_type));
+            }
+        };
+        let key = self.get_object_key(repo, &header).await?;
+        let segments = Segments::new(Box::pin(reader), key, &header, oid, 0);
+        Ok((segments.decrypt(), content_type))
+    }
+
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        Self::assert_not_data_key(oid)?;
+        let stored = self.get_stored(repo, oid).await?;
+        let (stored_size, header) = match stored {
+            Some((stored_size, Some(header))) => (stored_size, header),
+            Some((_, None)) => return self.proxy.get_range(repo, oid, range).await,
+            None => return Err(Self::not_found()),
+        };
+        let key = self.get_object_key(repo, &header).await?;
+
+        // Only the segments containing the range are read
+        let first = range.start / SEGMENT_SIZE;
+        let last = range.end / SEGMENT_SIZE;
+        let stored_range = ByteRange {
+            start: HEADER_SIZE + first * (SEGMENT_SIZE + TAG_SIZE),
+            end: std::cmp::min(
+                HEADER_SIZE + (last + 1) * (SEGMENT_SIZE + TAG_SIZE),
+                stored_size,
+            ) - 1,
+        };
+        let (stream, content_type) = self.proxy.get_range(repo, oid, stored_range).await?;
+        let reader = Box::pin(StreamReader::new(stream));
+        let segments = Segments::new(reader, key, &header, oid, first);
+
+        let mut reader = StreamReader::new(segments.decrypt());
+        let skipped = range.start - first * SEGMENT_SIZE;
+        tokio::io::copy(&mut (&mut reader).take(skipped), &mut tokio::io::sink()).await?;
+        let range_reader = reader.take(range.end - range.start + 1);
+        Ok((
+            Box::pin(tokio_util::io::ReaderStream::new(range_reader)),
+            content_type,
+        ))
+    }
+
+    async fn post(
+        &self,
+        repo: &str,
+        oid: &str,
+        data: ObjectReader,
+        content_type: &str,
+        uploader: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Self::assert_not_data_key(oid)?;
+        let key = match self.get_data_key(repo, true).await? {
+            Some(key) => key,
+            None => return Err(Box::new(invalid_data("Missing data key"))),
+        };
+
+        let mut salt = [0; SALT_SIZE];
+        openssl::rand::rand_bytes(&mut salt)?;
+        let header = Header {
+            key_id: key.id,
+            salt,
+        };
+        let segments = Segments::new(data, header.object_key(&key)?, &header, oid, 0);
+        let stored_size = Arc::new(AtomicU64::new(HEADER_SIZE));
+        let stored = prepend(header.encode(), segments.encrypt(stored_size.clone()));
+        self.proxy
+            .post(repo, oid, stored, content_type, uploader)
+            .await?;
+
+        // The header of the object is known, and replaces the one of a previous upload
+        self.headers.lock().unwrap().insert(
+            repo,
+            oid,
+            stored_size.load(Ordering::Relaxed),
+            Some(header),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fs::local_file_storage::LocalFileStorage;
+    use crate::{
+        aw,
+        test_utils::helpers::{read_all, reader, try_read_all},
+    };
+
+    const MASTER_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const NEW_MASTER_KEY: &str = "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    fn get_storage(master_keys: &str) -> (Arc<LocalFileStorage>, EncryptedStorage) {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let files = Arc::new(LocalFileStorage::new(format!("/tmp/{}", random_dir)));
+        let storage = EncryptedStorage::new(master_keys.to_string(), files.clone(), files.clone());
+        (files, storage)
+    }
+
+    fn test_data(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_content_size() {
+        for size in [0, 1, 65535, 65536, 65537, 200_000, 3 * 65536] {
+            let segments = size as u64 / SEGMENT_SIZE + 1;
+            let stored_size = HEADER_SIZE + size as u64 + segments * TAG_SIZE;
+            assert_eq!(content_size(stored_size), size as u64);
+        }
+    }
+
+    #[test]
+    fn test_header_is_read_once() {
+        let (files, storage) = get_storage(MASTER_KEY);
+        let data = test_data(100);
+        aw!(storage.post("repo", "oid", reader(data.clone()), "image/png", None)).unwrap();
+        assert_eq!(aw!(storage.get_meta_result("repo", "oid")).size, 100);
+
+        // A header read from the storage would now be invalid
+        let path = files.get_object_path("repo", "oid");
+        let mut stored = std::fs::read(&path).unwrap();
+        stored[0] = 0;
+        std::fs::write(&path, stored).unwrap();
+
+        let result = aw!(storage.get_meta_result("repo", "oid"));
+        assert!(result.exists);
+        assert_eq!(result.size, 100);
+    }
+
+    #[test]
+    fn test_header_of_new_upload_is_kept() {
+        let (_, storage) = get_storage(MASTER_KEY);
+        let data = test_data(100);
+        aw!(storage.post("repo", "oid", reader(data.clone()), "image/png", None)).unwrap();
+        assert_eq!(aw!(storage.get_meta_result("repo", "oid")).size, 100);
+
+        // The same object uploaded again has the same stored size, with another salt
+        aw!(storage.post("repo", "oid", reader(data.clone()), "image/png", None)).unwrap();
+        let range = ByteRange { start: 10, end: 19 };
+        let (stream, _) = aw!(storage.get_range("repo", "oid", range)).unwrap();
+        assert_eq!(aw!(read_all(stream)), data[10..20].to_vec());
+    }
+
+    #[test]
+    fn test_post_and_retrieve() {
+        let (files, storage) = get_storage(MASTER_KEY);
+        for size in [0, 3, 65536, 200_000] {
+            let data = test_data(size);
+            let oid = format!("oid{}", size);
+            aw!(storage.post("repo", &oid, reader(data.clone()), "image/png", None)).unwrap();
+
+            let (stream, _) = aw!(files.get("repo", &oid)).unwrap();
+            let stored = aw!(read_all(stream));
+            assert!(stored.starts_with(HEADER_MAGIC));
+            if size > 0 {
+                assert!(!stored
+                    .windows(size.min(64))
+                    .any(|w| w == &data[..size.min(64)]));
+            }
+
+            let result = aw!(storage.get_meta_result("repo", &oid));
+            assert!(result.exists);
+            assert_eq!(result.size, size as u64);
+            let (stream, content_type) = aw!(storage.get("repo", &oid)).unwrap();
+            assert_eq!(aw!(read_all(stream)), data);
+            assert_eq!(content_type, "image/png");
+        }
+    }
+
+    #[test]
+    fn test_retrieve_range() {
+        let (_, storage) = get_storage(MASTER_KEY);
+        let data = test_data(200_000);
+        aw!(storage.post("repo", "oid", reader(data.clone()), "image/png", None)).unwrap();
+
+        for (start, end) in [(0, 0), (10, 20), (65530, 65540), (70_000, 199_999)] {
+            let range = ByteRange { start, end };
+            let (stream, _) = aw!(storage.get_range("repo", "oid", range)).unwrap();
+            let expected = data[start as usize..=end as usize].to_vec();
+            assert_eq!(aw!(read_all(stream)), expected);
+        }
+    }
+
+    #[test]
+    fn test_tampered_object_fails() {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let files = Arc::new(LocalFileStorage::new(format!("/tmp/{}", random_dir)));
+        let storage = EncryptedStorage::new(MASTER_KEY.to_string(), files.clone(), files.clone());
+        aw!(storage.post("repo", "oid", reader(test_data(100)), "image/png", None)).unwrap();
+
+        let path = files.get_object_path("repo", "oid");
+        let mut stored = std::fs::read(&path).unwrap();
+        stored[30] ^= 1;
+        std::fs::write(&path, stored).unwrap();
+
+        let (stream, _) = aw!(storage.get("repo", "oid")).unwrap();
+        assert!(aw!(try_read_all(stream)).is_err());
+    }
+
+    #[test]
+    fn test_object_keys_are_per_object() {
+        let (files, storage) = get_storage(MASTER_KEY);
+        let data = test_data(1000);
+        for oid in ["oid1", "oid2"] {
+            aw!(storage.post("repo", oid, reader(data.clone()), "image/png", None)).unwrap();
+        }
+
+        let (stream, _) = aw!(files.get("repo", "oid1")).unwrap();
+        let stored_1 = aw!(read_all(stream));
+        let (stream, _) = aw!(files.get("repo", "oid2")).unwrap();
+        let stored_2 = aw!(read_all(stream));
+        let header_1 = Header::decode(&stored_1[..HEADER_SIZE as usize]).unwrap();
+        let header_2 = Header::decode(&stored_2[..HEADER_SIZE as usize]).unwrap();
+        assert_eq!(header_1.key_id, header_2.key_id);
+        assert_ne!(header_1.salt, header_2.salt);
+        // The same content under the same data key gives different ciphertexts
+        assert_ne!(
+            stored_1[HEADER_SIZE as usize..],
+            stored_2[HEADER_SIZE as usize..]
+        );
+    }
+
+    #[test]
+    fn test_data_keys_are_per_repo() {
+        let (files, storage) = get_storage(MASTER_KEY);
+        aw!(storage.post("a", "oid", reader(test_data(100)), "image/png", None)).unwrap();
+        aw!(storage.post("b", "oid", reader(test_data(100)), "image/png", None)).unwrap();
+
+        let (stream, _) = aw!(files.get("a", DATA_KEY_OID)).unwrap();
+        let key_a = aw!(read_all(stream));
+        let (stream, _) = aw!(files.get("b", DATA_KEY_OID)).unwrap();
+        let key_b = aw!(read_all(stream));
+        assert_ne!(
+            storage.unwrap("a", &key_a).unwrap().0,
+            storage.unwrap("b", &key_b).unwrap().0
+        );
+
+        // The data key is not reachable as an object
+        assert!(!aw!(storage.get_meta_result("a", DATA_KEY_OID)).exists);
+        assert!(aw!(storage.get("a", DATA_KEY_OID)).is_err());
+    }
+
+    #[test]
+    fn test_concurrent_data_key_creation() {
+        let (files, _) = get_storage(MASTER_KEY);
+        for i in 0..20 {
+            // Two servers sharing the storage upload the first objects of a repository
+            let repo = format!("repo{}", i);
+            let first = EncryptedStorage::new(MASTER_KEY.to_string(), files.clone(), files.clone());
+            let second =
+                EncryptedStorage::new(MASTER_KEY.to_string(), files.clone(), files.clone());
+            let (a, b) = aw!(async {
+                futures_util::join!(
+                    first.post(&repo, "a", reader(test_data(100)), "image/png", None),
+                    second.post(&repo, "b", reader(test_data(200)), "image/png", None),
+                )
+            });
+            a.unwrap();
+            b.unwrap();
+
+            // Both objects are encrypted with the data key kept
+            let reader =
+                EncryptedStorage::new(MASTER_KEY.to_string(), files.clone(), files.clone());
+            let (stream, _) = aw!(reader.get(&repo, "a")).unwrap();
+            assert_eq!(aw!(read_all(stream)), test_data(100));
+            let (stream, _) = aw!(reader.get(&repo, "b")).unwrap();
+            assert_eq!(aw!(read_all(stream)), test_data(200));
+        }
+    }
+
+    #[test]
+    fn test_data_key_is_not_replaced() {
+        let (files, storage) = get_storage(MASTER_KEY);
+        aw!(storage.post("repo", "oid", reader(test_data(100)), "image/png", None)).unwrap();
+        let (stream, _) = aw!(files.get("repo", DATA_KEY_OID)).unwrap();
+        let stored = aw!(read_all(stream));
+
+        let other = storage.wrap("repo", &[7; KEY_SIZE]).unwrap();
+        let created = aw!(files.post_if_absent("repo", DATA_KEY_OID, reader(other), "a/b"));
+        assert!(!created.unwrap());
+        let (stream, _) = aw!(files.get("repo", DATA_KEY_OID)).unwrap();
+        assert_eq!(aw!(read_all(stream)), stored);
+    }
+
+    #[test]
+    fn test_data_key_copied_to_another_repo_fails() {
+        let (files, storage) = get_storage(MASTER_KEY);
+        aw!(storage.post("a", "oid", reader(test_data(100)), "image/png", None)).unwrap();
+        let (stream, _) = aw!(files.get("a", DATA_KEY_OID)).unwrap();
+        let key_a = aw!(read_all(stream));
+
+        assert!(storage.unwrap("b", &key_a).is_err());
+        aw!(files.post("b", DATA_KEY_OID, reader(key_a), "a/b", None)).unwrap();
+        assert!(aw!(storage.post("b", "oid", reader(test_data(100)), "image/png", None)).is_err());
+    }
+
+    #[test]
+    fn test_master_key_rotation() {
+        let (files, storage) = get_storage(MASTER_KEY);
+        let data = test_data(1000);
+        aw!(storage.post("repo", "oid", reader(data.clone()), "image/png", None)).unwrap();
+
+        // The new master key comes first, the previous one is still known
+        let master_keys = format!("{}\n{}\n", NEW_MASTER_KEY, MASTER_KEY);
+        let rotated = EncryptedStorage::new(master_keys, files.clone(), files.clone());
+        let (stream, _) = aw!(rotated.get("repo", "oid")).unwrap();
+        assert_eq!(aw!(read_all(stream)), data);
+
+        // The data key was rewrapped, the previous master key is no longer needed
+        let only_new = EncryptedStorage::new(NEW_MASTER_KEY.to_string(), files.clone(), files);
+        let (stream, _) = aw!(only_new.get("repo", "oid")).unwrap();
+        assert_eq!(aw!(read_all(stream)), data);
+    }
+
+    #[test]
+    fn test_unknown_master_key_fails() {
+        let (files, storage) = get_storage(MASTER_KEY);
+        aw!(storage.post("repo", "oid", reader(test_data(10)), "image/png", None)).unwrap();
+
+        let other = EncryptedStorage::new(NEW_MASTER_KEY.to_string(), files.clone(), files);
+        assert!(aw!(other.get("repo", "oid")).is_err());
+    }
+
+    #[test]
+    fn test_object_stored_before_encryption_is_served() {
+        let (files, storage) = get_storage(MASTER_KEY);
+        aw!(files.post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+
+        assert_eq!(aw!(storage.get_meta_result("repo", "oid")).size, 3);
+        let (stream, _) = aw!(storage.get("repo", "oid")).unwrap();
+        assert_eq!(aw!(read_all(stream)), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_post_failure_is_not_visible() {
+        let (_, storage) = get_storage(MASTER_KEY);
+        let failing: ObjectReader = Box::pin(
+            tokio_test::io::Builder::new()
+                .read(&test_data(100_000))
+                .read_error(std::io::Error::other("rejected"))
+                .build(),
+        );
+        assert!(aw!(storage.post("repo", "oid", failing, "image/png", None)).is_err());
+        assert!(!aw!(storage.get_meta_result("repo", "oid")).exists);
+    }
+}
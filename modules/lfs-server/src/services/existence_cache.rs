@@ -0,0 +1,520 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::{
+    api::range::ByteRange,
+    traits::file_storage::{
+        FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy, FileStorageQuarantine,
+        ObjectReader, ObjectStream,
+    },
+};
+
+pub struct ExistenceCacheConfig {
+    pub max_entries: usize,
+    pub positive_ttl: Duration,
+    pub negative_ttl: Duration,
+}
+
+/**
+ * In-memory cache of the lookups of the objects, in front of another storage. Objects are
+ * immutable by oid, so an object found once keeps its size: found objects stay in the cache for
+ * a long time, unless it holds too many entries and the least recently used ones are evicted.
+ * They still expire, as objects may be removed behind the back of the cache, by the scrub
+ * command for instance. Missing objects may be uploaded at any time, so they are only cached for
+ * a short time, if at all.
+ *
+ * Uploads going through the cache record the object as found once they succeed, and objects
+ * quarantined through it are forgotten.
+ */
+pub struct ExistenceCache {
+    meta: Arc<dyn FileStorageMetaRequester>,
+    proxy: Option<Arc<dyn FileStorageProxy>>,
+    quarantine: Option<Arc<dyn FileStorageQuarantine>>,
+    max_entries: usize,
+    positive_ttl: Duration,
+    negative_ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+type Key = (String, String);
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<Key, CacheEntry>,
+    // Keys of the entries by last use, the least recently used first
+    uses: BTreeMap<u64, Key>,
+    last_use: u64,
+}
+
+#[derive(Clone, Copy)]
+struct CacheEntry {
+    // None for a missing object
+    size: Option<u64>,
+    expires_at: Option<Instant>,
+    last_use: u64,
+}
+
+impl CacheState {
+    /**
+     * Get the size of an object if it is found, None if it is missing, or nothing if the object
+     * is not in the cache.
+     */
+    fn touch(&mut self, key: &Key) -> Option<Option<u64>> {
+        let entry = *self.entries.get(key)?;
+        self.uses.remove(&entry.last_use);
+        if entry
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now())
+        {
+            self.entries.remove(key);
+            return None;
+        }
+        self.last_use += 1;
+        self.uses.insert(self.last_use, key.clone());
+        self.entries.get_mut(key).unwrap().last_use = self.last_use;
+        Some(entry.size)
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.uses.remove(&entry.last_use);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: Key,
+        size: Option<u64>,
+        expires_at: Option<Instant>,
+        max_entries: usize,
+    ) {
+        self.last_use += 1;
+        self.uses.insert(self.last_use, key.clone());
+        let entry = CacheEntry {
+            size,
+            expires_at,
+            last_use: self.last_use,
+        };
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.uses.remove(&previous.last_use);
+        }
+
+        while self.entries.len() > max_entries {
+            let Some((_, oldest)) = self.uses.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+impl ExistenceCache {
+    pub fn new(
+        max_entries: usize,
+        positive_ttl: Duration,
+        negative_ttl: Duration,
+        meta: Arc<dyn FileStorageMetaRequester>,
+        proxy: Option<Arc<dyn FileStorageProxy>>,
+        quarantine: Option<Arc<dyn FileStorageQuarantine>>,
+    ) -> ExistenceCache {
+        ExistenceCache {
+            meta,
+            proxy,
+            quarantine,
+            max_entries,
+            positive_ttl,
+            negative_ttl,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn from_config(
+        config: ExistenceCacheConfig,
+        meta: Arc<dyn FileStorageMetaRequester>,
+        proxy: Option<Arc<dyn FileStorageProxy>>,
+        quarantine: Option<Arc<dyn FileStorageQuarantine>>,
+    ) -> ExistenceCache {
+        ExistenceCache::new(
+            config.max_entries,
+            config.positive_ttl,
+            config.negative_ttl,
+            meta,
+            proxy,
+            quarantine,
+        )
+    }
+
+    fn get_key(repo: &str, oid: &str) -> Key {
+        (repo.to_string(), oid.to_string())
+    }
+
+    fn get_cached<'a>(&self, repo: &'a str, oid: &'a str) -> Option<FileStorageMetaResult<'a>> {
+        let size = self
+            .state
+            .lock()
+            .unwrap()
+            .touch(&Self::get_key(repo, oid))?;
+        Some(match size {
+            Some(size) => FileStorageMetaResult::new(repo, oid, size),
+            None => FileStorageMetaResult::not_found(repo, oid),
+        })
+    }
+
+    fn record(&self, result: &FileStorageMetaResult) {
+        let (size, expires_at) = match result.exists {
+            true => (Some(result.size), Some(Instant::now() + self.positive_ttl)),
+            false if self.negative_ttl.is_zero() => return,
+            false => (None, Some(Instant::now() + self.negative_ttl)),
+        };
+        self.state.lock().unwrap().insert(
+            Self::get_key(result.repo, result.oid),
+            size,
+            expires_at,
+            self.max_entries,
+        );
+    }
+
+    fn get_proxy(&self) -> Result<&Arc<dyn FileStorageProxy>, Box<dyn std::error::Error>> {
+        self.proxy
+            .as_ref()
+            .ok_or_else(|| Box::new(std::io::Error::other("No proxy implementation")).into())
+    }
+}
+
+#[async_trait]
+impl FileStorageMetaRequester for ExistenceCache {
+    async fn get_meta_result<'a>(&self, repo: &'a str, oid: &'a str) -> FileStorageMetaResult<'a> {
+        if let Some(cached) = self.get_cached(repo, oid) {
+            return cached;
+        }
+        let result = self.meta.get_meta_result(repo, oid).await;
+        self.record(&result);
+        result
+    }
+
+    async fn get_meta_results<'a>(
+        &self,
+        repo: &'a str,
+        oids: &[&'a str],
+    ) -> Vec<FileStorageMetaResult<'a>> {
+        // Only the objects missing the cache are looked up in the storage, all at once
+        let cached: Vec<_> = oids.iter().map(|oid| self.get_cached(repo, oid)).collect();
+        let missing: Vec<&str> = oids
+            .iter()
+            .zip(&cached)
+            .filter(|(_, result)| result.is_none())
+            .map(|(oid, _)| *oid)
+            .collect();
+        let mut looked_up = self.meta.get_meta_results(repo, &missing).await.into_iter();
+        oids.iter()
+            .zip(cached)
+            .map(|(oid, cached)| match cached {
+                Some(cached) => cached,
+                None => {
+                    let result = looked_up
+                        .next()
+                        .unwrap_or_else(|| FileStorageMetaResult::not_found(repo, oid));
+                    self.record(&result);
+                    result
+                }
+            })
+            .collect()
+    }
+
+    async fn get_content_sha256(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        self.meta.get_content_sha256(repo, oid).await
+    }
+}
+
+#[async_trait]
+impl FileStorageQuarantine for ExistenceCache {
+    async fn quarantine(&self, repo: &str, oid: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let quarantine = self
+            .quarantine
+            .as_ref()
+            .ok_or_else(|| Box::new(std::io::Error::other("No quarantine implementation")))?;
+        let quarantined = quarantine.quarantine(repo, oid).await;
+        self.state.lock().unwrap().remove(&Self::get_key(repo, oid));
+        quarantined
+    }
+
+    async fn is_quarantined(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        match &self.quarantine {
+            Some(quarantine) => quarantine.is_quarantined(repo, oid).await,
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl FileStorageProxy for ExistenceCache {
+    async fn get(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let proxy = self.get_proxy()?;
+        proxy.get(repo, oid).await
+    }
+
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let proxy = self.get_proxy()?;
+        proxy.get_range(repo, oid, range).await
+    }
+
+    async fn post(
+        &self,
+        repo: &str,
+        oid: &str,
+        data: ObjectReader,
+        content_type: &str,
+        uploader: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let proxy = self.get_proxy()?;
+        let received = Arc::new(AtomicU64::new(0));
+        let data = Box::pin(CountingReader {
+            inner: data,
+            received: received.clone(),
+        });
+        proxy.post(repo, oid, data, content_type, uploader).await?;
+        let size = received.load(Ordering::SeqCst);
+        self.record(&FileStorageMetaResult::new(repo, oid, size));
+        Ok(())
+    }
+}
+
+/**
+ * Reader counting the bytes read from another one.
+ */
+struct CountingReader {
+    inner: ObjectReader,
+    received: Arc<AtomicU64>,
+}
+
+impl AsyncRead for CountingReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = self.inner.as_mut().poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = (buf.filled().len() - filled) as u64;
+            self.received.fetch_add(read, Ordering::SeqCst);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fs::local_file_storage::LocalFileStorage;
+    use crate::test_utils::helpers::reader;
+    use std::sync::atomic::AtomicUsize;
+
+    /**
+     * Local file storage counting the objects it looks up.
+     */
+    struct CountingStorage {
+        files: LocalFileStorage,
+        lookups: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl FileStorageMetaRequester for CountingStorage {
+        async fn get_meta_result<'a>(
+            &self,
+            repo: &'a str,
+            oid: &'a str,
+        ) -> FileStorageMetaResult<'a> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            self.files.get_meta_result(repo, oid).await
+        }
+    }
+
+    fn get_cache(
+        max_entries: usize,
+        positive_ttl: Duration,
+        negative_ttl: Duration,
+    ) -> (Arc<LocalFileStorage>, Arc<CountingStorage>, ExistenceCache) {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let files = Arc::new(LocalFileStorage::new(format!("/tmp/{}", random_dir)));
+        let backend = Arc::new(CountingStorage {
+            files: LocalFileStorage::new(format!("/tmp/{}", random_dir)),
+            lookups: AtomicUsize::new(0),
+        });
+        let cache = ExistenceCache::new(
+            max_entries,
+            positive_ttl,
+            negative_ttl,
+            backend.clone(),
+            Some(files.clone()),
+            Some(files.clone()),
+        );
+        (files, backend, cache)
+    }
+
+    #[tokio::test]
+    async fn test_found_objects_are_cached() {
+        let (files, backend, cache) = get_cache(10, Duration::from_secs(3600), Duration::ZERO);
+        files
+            .post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            let result = cache.get_meta_result("repo", "oid").await;
+            assert!(result.exists);
+            assert_eq!(result.size, 3);
+        }
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_missing_objects_expire() {
+        let (files, backend, cache) =
+            get_cache(10, Duration::from_secs(3600), Duration::from_millis(100));
+        assert!(!cache.get_meta_result("repo", "oid").await.exists);
+
+        // Uploaded without going through the cache
+        files
+            .post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+        assert!(!cache.get_meta_result("repo", "oid").await.exists);
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(cache.get_meta_result("repo", "oid").await.exists);
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_found_objects_expire() {
+        let (files, backend, cache) = get_cache(10, Duration::from_millis(100), Duration::ZERO);
+        files
+            .post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+        assert!(cache.get_meta_result("repo", "oid").await.exists);
+
+        // Removed without going through the cache
+        std::fs::remove_file(files.get_object_path("repo", "oid")).unwrap();
+        assert!(cache.get_meta_result("repo", "oid").await.exists);
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(!cache.get_meta_result("repo", "oid").await.exists);
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_missing_objects_are_not_cached_without_ttl() {
+        let (_, backend, cache) = get_cache(10, Duration::from_secs(3600), Duration::ZERO);
+        assert!(!cache.get_meta_result("repo", "oid").await.exists);
+        assert!(!cache.get_meta_result("repo", "oid").await.exists);
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_uploads_are_recorded() {
+        let (_, backend, cache) = get_cache(10, Duration::from_secs(3600), Duration::from_secs(60));
+        assert!(!cache.get_meta_result("repo", "oid").await.exists);
+
+        cache
+            .post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+        let result = cache.get_meta_result("repo", "oid").await;
+        assert!(result.exists);
+        assert_eq!(result.size, 3);
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_quarantined_objects_are_forgotten() {
+        let (_, backend, cache) = get_cache(10, Duration::from_secs(3600), Duration::ZERO);
+        cache
+            .post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+        assert!(cache.get_meta_result("repo", "oid").await.exists);
+
+        cache.quarantine("repo", "oid").await.unwrap();
+        assert!(!cache.get_meta_result("repo", "oid").await.exists);
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_are_evicted() {
+        let (files, backend, cache) = get_cache(2, Duration::from_secs(3600), Duration::ZERO);
+        for oid in ["a", "b", "c"] {
+            files
+                .post("repo", oid, reader(vec![1]), "image/png", None)
+                .await
+                .unwrap();
+        }
+
+        cache.get_meta_result("repo", "a").await;
+        cache.get_meta_result("repo", "b").await;
+        cache.get_meta_result("repo", "a").await;
+        cache.get_meta_result("repo", "c").await;
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 3);
+
+        // b was evicted to make room for c
+        cache.get_meta_result("repo", "a").await;
+        cache.get_meta_result("repo", "b").await;
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_get_meta_results() {
+        let (files, backend, cache) = get_cache(10, Duration::from_secs(3600), Duration::ZERO);
+        files
+            .post("repo", "a", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+        files
+            .post("repo", "b", reader(vec![1]), "image/png", None)
+            .await
+            .unwrap();
+        cache.get_meta_result("repo", "a").await;
+
+        let results = cache.get_meta_results("repo", &["b", "c", "a"]).await;
+        let results: Vec<_> = results
+            .iter()
+            .map(|result| (result.oid, result.exists, result.size))
+            .collect();
+        assert_eq!(
+            results,
+            vec![("b", true, 1), ("c", false, 0), ("a", true, 3)]
+        );
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,36 +1,412 @@
-use crate::traits::file_storage::{
-    FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy,
+use crate::{
+    api::range::ByteRange,
+    traits::file_storage::{
+        FileStorageChunkedUploader, FileStorageLister, FileStorageMetaRequester,
+        FileStorageMetaResult, FileStorageProxy, FileStorageQuarantine,
+        FileStorageResumableUploader, ObjectReader, ObjectStream, StoredObject, StoredObjectStream,
+    },
 };
 use async_trait::async_trait;
+use futures_util::{Stream, StreamExt, TryStreamExt};
 use regex::Regex;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
 
 pub struct LocalFileStorageConfig {
     pub root_path: String,
+    pub shard_depth: usize,
 }
 
+/**
+ * Storage of the objects in the local filesystem, under `<root>/<repo>/objects`, with their
+ * content type in a sidecar file under `<root>/<repo>/mime-types`. The content of the resumable
+ * uploads is received under `<root>/<repo>/uploads`, and the chunks of the chunked uploads under
+ * `<root>/<repo>/chunks/<oid>`, until they complete.
+ *
+ * With a shard depth of 0, all the objects of a repository are in the same directory. Otherwise,
+ * they are spread in nested directories named after the successive pairs of characters of their
+ * oid, e.g. `objects/ab/cd/abcdef...` with a depth of 2, like git lfs does.
+ */
+#[derive(Clone)]
 pub struct LocalFileStorage {
     root_path: String,
+    shard_depth: usize,
+    // Paths of the uploads being appended to, so that only one request appends to each of them
+    appending: Arc<Mutex<HashSet<String>>>,
+}
+
+// Mark of an upload being appended to, removed once the append is over
+struct AppendGuard<'a> {
+    appending: &'a Mutex<HashSet<String>>,
+    path: String,
+}
+
+impl Drop for AppendGuard<'_> {
+    fn drop(&mut self) {
+        self.appending.lock().unwrap().remove(&self.path);
+    }
 }
 
+// Areas of a repository where the files are named after the oid of their object
+const SHARDED_AREAS: [&str; 2] = ["objects", "mime-types"];
+
+// Areas of a repository which are not nested repositories
+const REPO_AREAS: [&str; 6] = [
+    "objects",
+    "mime-types",
+    "tmp",
+    "quarantine",
+    "uploads",
+    "chunks",
+];
+
 impl LocalFileStorage {
     pub fn new(root_path: String) -> LocalFileStorage {
-        LocalFileStorage { root_path }
+        LocalFileStorage::new_sharded(root_path, 0)
+    }
+
+    pub fn new_sharded(root_path: String, shard_depth: usize) -> LocalFileStorage {
+        LocalFileStorage {
+            root_path,
+            shard_depth,
+            appending: Arc::new(Mutex::new(HashSet::new())),
+        }
     }
 
     pub fn from_config(config: LocalFileStorageConfig) -> LocalFileStorage {
-        LocalFileStorage::new(config.root_path)
+        LocalFileStorage::new_sharded(config.root_path, config.shard_depth)
+    }
+
+    /**
+     * Directory of an area of a repository in which the file of an object is.
+     */
+    fn get_shard_path(&self, repo: &str, area: &str, oid: &str) -> String {
+        let mut path = format!("{}/{}/{}", &self.root_path, repo, area);
+        for level in 0..self.shard_depth {
+            path.push('/');
+            // Oids too short to be fully sharded still get a directory at each level
+            path.push_str(oid.get(2 * level..2 * level + 2).unwrap_or("_"));
+        }
+        path
     }
 
     pub fn get_object_path(&self, repo: &str, oid: &str) -> String {
-        format!("{}/{}/objects/{}", &self.root_path, repo, oid)
+        format!("{}/{}", self.get_shard_path(repo, "objects", oid), oid)
     }
 
     pub fn get_mime_type_object_path(&self, repo: &str, oid: &str) -> String {
-        format!("{}/{}/mime-types/{}.mime", &self.root_path, repo, oid)
+        format!(
+            "{}/{}.mime",
+            self.get_shard_path(repo, "mime-types", oid),
+            oid
+        )
+    }
+
+    pub fn get_upload_path(&self, repo: &str, oid: &str) -> String {
+        format!("{}/{}/uploads/{}", &self.root_path, repo, oid)
+    }
+
+    /**
+     * Mark an upload as being appended to, unless another request already does.
+     */
+    fn start_append(&self, path: String) -> Result<AppendGuard<'_>, std::io::Error> {
+        if !self.appending.lock().unwrap().insert(path.clone()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "The upload is being appended to by another request",
+            ));
+        }
+        Ok(AppendGuard {
+            appending: &self.appending,
+            path,
+        })
+    }
+
+    pub fn get_chunks_path(&self, repo: &str, oid: &str) -> String {
+        format!("{}/{}/chunks/{}", &self.root_path, repo, oid)
+    }
+
+    /**
+     * Move the files of every repository to where they are in the layout of this storage,
+     * whatever the layout they were stored with. The server must not be running meanwhile. As
+     * files already in place are left as they are, an interrupted migration can be run again.
+     * Return the number of files moved.
+     */
+    pub fn migrate_layout(&self) -> Result<u64, std::io::Error> {
+        let mut moved = 0;
+        for repo in self.find_repos()? {
+            for area in SHARDED_AREAS {
+                let path = format!("{}/{}/{}", &self.root_path, repo, area);
+                if Path::new(&path).is_dir() {
+                    moved += self.migrate_area(&path, &repo, area)?;
+                }
+            }
+        }
+        Ok(moved)
+    }
+
+    /**
+     * Move the files found anywhere under an area of a repository, and remove the directories
+     * left empty.
+     */
+    fn migrate_area(&self, path: &str, repo: &str, area: &str) -> Result<u64, std::io::Error> {
+        let (files, directories) = Self::walk(Path::new(path))?;
+
+        let mut moved = 0;
+        for file in files {
+            let name = file.file_name().unwrap().to_string_lossy().to_string();
+            let oid = match area {
+                "mime-types" => name.strip_suffix(".mime").unwrap_or(&name),
+                _ => &name,
+            };
+            let target = format!("{}/{}", self.get_shard_path(repo, area, oid), name);
+            if file == Path::new(&target) {
+                continue;
+            }
+            std::fs::create_dir_all(self.get_shard_path(repo, area, oid))?;
+            std::fs::rename(&file, &target)?;
+            moved += 1;
+        }
+
+        // The deepest directories come last, they are removed before their parents
+        for directory in directories.iter().rev() {
+            if std::fs::read_dir(directory)?.next().is_none() {
+                std::fs::remove_dir(directory)?;
+            }
+        }
+        Ok(moved)
+    }
+
+    /**
+     * Find the objects of every repository.
+     */
+    fn find_repo_objects(&self, repo: &str) -> Result<Vec<StoredObject>, std::io::Error> {
+        let objects = self
+            .find_area_files(repo, "objects")?
+            .into_iter()
+            .map(|file| StoredObject {
+                repo: repo.to_string(),
+                oid: file.file_name().unwrap().to_string_lossy().to_string(),
+            })
+            .collect();
+        Ok(objects)
+    }
+
+    /**
+     * Find the content types kept for objects which are not stored.
+     */
+    fn find_orphaned_content_types(&self) -> Result<Vec<StoredObject>, std::io::Error> {
+        let mut orphans = Vec::new();
+        for repo in self.find_repos()? {
+            for file in self.find_area_files(&repo, "mime-types")? {
+                let name = file.file_name().unwrap().to_string_lossy().to_string();
+                let oid = name.strip_suffix(".mime").unwrap_or(&name);
+                if !Path::new(&self.get_object_path(&repo, oid)).exists() {
+                    orphans.push(StoredObject {
+                        repo: repo.clone(),
+                        oid: oid.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(orphans)
+    }
+
+    fn find_area_files(&self, repo: &str, area: &str) -> Result<Vec<PathBuf>, std::io::Error> {
+        let path = format!("{}/{}/{}", &self.root_path, repo, area);
+        if !Path::new(&path).is_dir() {
+            return Ok(Vec::new());
+        }
+        let (files, _) = Self::walk(Path::new(&path))?;
+        Ok(files)
+    }
+
+    /**
+     * Find the repositories, which are the directories with objects or content types.
+     * Repositories can be nested, as their names can contain slashes.
+     */
+    fn find_repos(&self) -> Result<Vec<String>, std::io::Error> {
+        let mut repos = Vec::new();
+        let mut pending = vec![String::new()];
+        while let Some(repo) = pending.pop() {
+            let path = format!("{}/{}", &self.root_path, repo);
+            if !Path::new(&path).is_dir() {
+                continue;
+            }
+            let mut is_repo = false;
+            for entry in std::fs::read_dir(&path)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_dir() {
+                    continue;
+                }
+                let name = entry.file_name().to_string_lossy().to_string();
+                if SHARDED_AREAS.contains(&name.as_str()) {
+                    is_repo = !repo.is_empty();
+                } else if !REPO_AREAS.contains(&name.as_str()) {
+                    pending.push(match repo.as_str() {
+                        "" => name,
+                        _ => format!("{}/{}", repo, name),
+                    });
+                }
+            }
+            if is_repo {
+                repos.push(repo);
+            }
+        }
+        Ok(repos)
+    }
+
+    /**
+     * List the files and the directories under a directory, parents before their children.
+     */
+    fn walk(path: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>), std::io::Error> {
+        let mut files = Vec::new();
+        let mut directories = Vec::new();
+        let mut pending = vec![path.to_path_buf()];
+        while let Some(directory) = pending.pop() {
+            for entry in std::fs::read_dir(&directory)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    pending.push(entry.path());
+                    directories.push(entry.path());
+                } else {
+                    files.push(entry.path());
+                }
+            }
+        }
+        Ok((files, directories))
+    }
+
+    /**
+     * Unique path, outside of the objects directory, to receive an object being uploaded.
+     */
+    fn get_temporary_object_path(&self, repo: &str, oid: &str) -> String {
+        static UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+        let upload = UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed);
+        format!(
+            "{}/{}/tmp/{}.{}.{}",
+            &self.root_path,
+            repo,
+            oid,
+            std::process::id(),
+            upload
+        )
+    }
+
+    async fn receive(path: &str, data: &mut ObjectReader) -> Result<u64, std::io::Error> {
+        let mut file = tokio::fs::File::create(path).await?;
+        let size = tokio::io::copy(data, &mut file).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+        Ok(size)
+    }
+
+    /**
+     * Receive an object in a temporary file, so that a failed or rejected upload is never
+     * visible under the oid. Return the path of the temporary file and the size of the object.
+     */
+    pub(crate) async fn receive_object(
+        &self,
+        repo: &str,
+        oid: &str,
+        data: &mut ObjectReader,
+    ) -> Result<(String, u64), std::io::Error> {
+        self.create_if_missing(&self.get_shard_path(repo, "objects", oid))
+            .await?;
+        self.create_if_missing(&format!("{}/{}/tmp", &self.root_path, repo))
+            .await?;
+
+        let temporary_path = self.get_temporary_object_path(repo, oid);
+        match Self::receive(&temporary_path, data).await {
+            Ok(size) => Ok((temporary_path, size)),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&temporary_path).await;
+                Err(e)
+            }
+        }
+    }
+
+    /**
+     * Move a received object into place at once.
+     */
+    pub(crate) async fn commit_object(
+        &self,
+        temporary_path: &str,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(), std::io::Error> {
+        tokio::fs::rename(temporary_path, self.get_object_path(repo, oid)).await
+    }
+
+    /**
+     * Move a received object into place, unless an object is already there. The object is
+     * linked to its path, which fails if the path exists, rather than renamed over it. Tell
+     * whether the object was moved into place.
+     */
+    pub(crate) async fn commit_new_object(
+        &self,
+        temporary_path: &str,
+        repo: &str,
+        oid: &str,
+    ) -> Result<bool, std::io::Error> {
+        let linked = tokio::fs::hard_link(temporary_path, self.get_object_path(repo, oid)).await;
+        tokio::fs::remove_file(temporary_path).await?;
+        match linked {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /**
+     * Open an object, or a range of it, to be read as the response is sent.
+     */
+    pub(crate) async fn open_object(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, std::io::Error> {
+        let mut file = tokio::fs::File::open(self.get_object_path(repo, oid)).await?;
+        match range {
+            None => Ok(Box::pin(ReaderStream::new(file))),
+            Some(range) => {
+                // Seek to the start of the range, and only read up to its end
+                file.seek(SeekFrom::Start(range.start)).await?;
+                Ok(Box::pin(ReaderStream::new(file.take(range.length()))))
+            }
+        }
     }
 
-    async fn create_if_missing(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    async fn read_content_type(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let content_type_file_path = self.get_mime_type_object_path(repo, oid);
+        let mut content_type_file = match tokio::fs::File::open(content_type_file_path).await {
+            Ok(file) => file,
+            // An object stored only if absent is in place before its content type is written
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(String::from("application/octet-stream"))
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+        let mut content_type = String::new();
+        content_type_file.read_to_string(&mut content_type).await?;
+        Ok(content_type)
+    }
+
+    async fn create_if_missing(&self, path: &str) -> Result<(), std::io::Error> {
         let exists = tokio::fs::try_exists(path).await?;
         if !exists {
             tokio::fs::create_dir_all(path).await?;
@@ -63,61 +439,275 @@ impl FileStorageProxy for LocalFileStorage {
         &self,
         repo: &str,
         oid: &str,
-    ) -> Result<(Vec<u8>, String), Box<dyn std::error::Error>> {
-        // Read the file
-        let path = self.get_object_path(repo, oid);
-        let mut file = tokio::fs::File::open(path).await?;
-        let mut response = Vec::new();
-        file.read_to_end(&mut response).await?;
-
-        // Read the mime type
-        let content_type_file_path = self.get_mime_type_object_path(repo, oid);
-        let mut content_type_file = tokio::fs::File::open(content_type_file_path).await?;
-        let mut content_type = String::new();
-        content_type_file.read_to_string(&mut content_type).await?;
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let stream = self.open_object(repo, oid, None).await?;
+        let content_type = self.read_content_type(repo, oid).await?;
+        Ok((stream, content_type))
+    }
 
-        return Ok((response, content_type));
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let stream = self.open_object(repo, oid, Some(range)).await?;
+        let content_type = self.read_content_type(repo, oid).await?;
+        Ok((stream, content_type))
     }
 
     async fn post(
         &self,
         repo: &str,
         oid: &str,
-        data: Vec<u8>,
+        mut data: ObjectReader,
         _content_type: &str,
+        _uploader: Option<&str>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        self.create_if_missing(&self.root_path).await?;
-        self.create_if_missing(&format!("{}/{}", &self.root_path, repo))
-            .await?;
-        self.create_if_missing(&format!("{}/{}/objects", &self.root_path, repo))
-            .await?;
-        self.create_if_missing(&format!("{}/{}/mime-types", &self.root_path, repo))
+        self.create_if_missing(&self.get_shard_path(repo, "mime-types", oid))
             .await?;
+        let (temporary_path, _) = self.receive_object(repo, oid, &mut data).await?;
 
-        let path = self.get_object_path(repo, oid);
         let mime_type_path = self.get_mime_type_object_path(repo, oid);
-
         let mut mime_type_file = tokio::fs::File::create(mime_type_path).await?;
         mime_type_file.write_all(_content_type.as_bytes()).await?;
 
-        let mut file = tokio::fs::File::create(path).await?;
-        file.write_all(&data).await?;
+        self.commit_object(&temporary_path, repo, oid).await?;
 
         return Ok(());
     }
+
+    async fn post_if_absent(
+        &self,
+        repo: &str,
+        oid: &str,
+        mut data: ObjectReader,
+        content_type: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        self.create_if_missing(&self.get_shard_path(repo, "mime-types", oid))
+            .await?;
+        let (temporary_path, _) = self.receive_object(repo, oid, &mut data).await?;
+
+        // The content type is only written by the upload which stored the object
+        if !self.commit_new_object(&temporary_path, repo, oid).await? {
+            return Ok(false);
+        }
+        let mime_type_path = self.get_mime_type_object_path(repo, oid);
+        tokio::fs::write(mime_type_path, content_type.as_bytes()).await?;
+        Ok(true)
+    }
+}
+
+/**
+ * Stream the items found by a blocking listing, once it is over.
+ */
+fn blocking_listing<T: Send + 'static>(
+    list: impl FnOnce() -> Result<Vec<T>, std::io::Error> + Send + 'static,
+) -> impl Stream<Item = Result<T, std::io::Error>> + Send {
+    futures_util::stream::once(async move {
+        tokio::task::spawn_blocking(list)
+            .await
+            .map_err(std::io::Error::other)?
+    })
+    .map_ok(|items| futures_util::stream::iter(items.into_iter().map(Ok)))
+    .try_flatten()
+}
+
+#[async_trait]
+impl FileStorageLister for LocalFileStorage {
+    fn list_objects(&self) -> StoredObjectStream<'_> {
+        // The repositories are found first, then listed one at a time
+        let files = self.clone();
+        let objects = blocking_listing(move || files.find_repos())
+            .map_ok(move |repo| {
+                let files = self.clone();
+                blocking_listing(move || files.find_repo_objects(&repo))
+            })
+            .try_flatten();
+        Box::pin(objects)
+    }
+
+    async fn list_orphaned_content_types(
+        &self,
+    ) -> Result<Vec<StoredObject>, Box<dyn std::error::Error>> {
+        let files = self.clone();
+        let orphans =
+            tokio::task::spawn_blocking(move || files.find_orphaned_content_types()).await??;
+        Ok(orphans)
+    }
+}
+
+#[async_trait]
+impl FileStorageQuarantine for LocalFileStorage {
+    async fn quarantine(&self, repo: &str, oid: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let quarantine_path = format!("{}/{}/quarantine", &self.root_path, repo);
+        self.create_if_missing(&quarantine_path).await?;
+        tokio::fs::rename(
+            self.get_object_path(repo, oid),
+            format!("{}/{}", quarantine_path, oid),
+        )
+        .await?;
+        // The content type goes along, if there is one
+        let _ = tokio::fs::rename(
+            self.get_mime_type_object_path(repo, oid),
+            format!("{}/{}.mime", quarantine_path, oid),
+        )
+        .await;
+        Ok(())
+    }
+
+    async fn is_quarantined(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let quarantine_path = format!("{}/{}/quarantine/{}", &self.root_path, repo, oid);
+        Ok(tokio::fs::try_exists(quarantine_path).await?)
+    }
+}
+
+#[async_trait]
+impl FileStorageResumableUploader for LocalFileStorage {
+    async fn get_offset(&self, repo: &str, oid: &str) -> Result<u64, Box<dyn std::error::Error>> {
+        match tokio::fs::metadata(self.get_upload_path(repo, oid)).await {
+            Ok(meta) => Ok(meta.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    async fn append(
+        &self,
+        repo: &str,
+        oid: &str,
+        offset: u64,
+        mut data: ObjectReader,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let path = self.get_upload_path(repo, oid);
+        let _guard = self.start_append(path.clone())?;
+        self.create_if_missing(&format!("{}/{}/uploads", &self.root_path, repo))
+            .await?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let received = file.metadata().await?.len();
+        if received != offset {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!(
+                    "Upload offset {} does not match the received size {}",
+                    offset, received
+                ),
+            )));
+        }
+        let copied = tokio::io::copy(&mut data, &mut file).await;
+        // What was written before a failure of the data stays, the upload resumes after it
+        file.flush().await?;
+        file.sync_all().await?;
+        copied?;
+        Ok(file.metadata().await?.len())
+    }
+
+    async fn get_content(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<ObjectStream, Box<dyn std::error::Error>> {
+        let file = tokio::fs::File::open(self.get_upload_path(repo, oid)).await?;
+        Ok(Box::pin(ReaderStream::new(file)))
+    }
+
+    async fn discard(&self, repo: &str, oid: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match tokio::fs::remove_file(self.get_upload_path(repo, oid)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Box::new(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl FileStorageChunkedUploader for LocalFileStorage {
+    async fn put_chunk(
+        &self,
+        repo: &str,
+        oid: &str,
+        index: u64,
+        mut data: ObjectReader,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let chunks_path = self.get_chunks_path(repo, oid);
+        self.create_if_missing(&chunks_path).await?;
+        self.create_if_missing(&format!("{}/{}/tmp", &self.root_path, repo))
+            .await?;
+
+        // The chunk is received apart, so that a failed upload never replaces a complete one
+        let temporary_path = self.get_temporary_object_path(repo, &format!("{}.{}", oid, index));
+        let size = match Self::receive(&temporary_path, &mut data).await {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&temporary_path).await;
+                return Err(Box::new(e));
+            }
+        };
+        tokio::fs::rename(&temporary_path, format!("{}/{}", chunks_path, index)).await?;
+        Ok(size)
+    }
+
+    async fn get_chunk_sizes(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<BTreeMap<u64, u64>, Box<dyn std::error::Error>> {
+        let mut sizes = BTreeMap::new();
+        let mut entries = match tokio::fs::read_dir(self.get_chunks_path(repo, oid)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(sizes),
+            Err(e) => return Err(Box::new(e)),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if let Ok(index) = entry.file_name().to_string_lossy().parse() {
+                sizes.insert(index, entry.metadata().await?.len());
+            }
+        }
+        Ok(sizes)
+    }
+
+    async fn get_chunks(
+        &self,
+        repo: &str,
+        oid: &str,
+        count: u64,
+    ) -> Result<ObjectStream, Box<dyn std::error::Error>> {
+        let chunks_path = self.get_chunks_path(repo, oid);
+        let chunks = futures_util::stream::iter(0..count)
+            .then(move |index| tokio::fs::File::open(format!("{}/{}", chunks_path, index)))
+            .map_ok(ReaderStream::new)
+            .try_flatten();
+        Ok(Box::pin(chunks))
+    }
+
+    async fn discard_chunks(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match tokio::fs::remove_dir_all(self.get_chunks_path(repo, oid)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Box::new(e)),
+            _ => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        aw,
+        test_utils::helpers::{read_all, reader},
+    };
     use uuid;
 
-    macro_rules! aw {
-        ($e:expr) => {
-            tokio_test::block_on($e)
-        };
-    }
-
     #[test]
     fn test_get_object_path() {
         let random_dir = uuid::Uuid::new_v4().to_string();
@@ -126,6 +716,24 @@ mod tests {
         assert_eq!(path, format!("/tmp/{}/repo/objects/oid", random_dir));
     }
 
+    #[test]
+    fn test_get_sharded_object_path() {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let storage = super::LocalFileStorage::new_sharded(format!("/tmp/{}", random_dir), 2);
+        let path = storage.get_object_path("repo", "abcdef");
+        assert_eq!(
+            path,
+            format!("/tmp/{}/repo/objects/ab/cd/abcdef", random_dir)
+        );
+        let path = storage.get_mime_type_object_path("repo", "abcdef");
+        assert_eq!(
+            path,
+            format!("/tmp/{}/repo/mime-types/ab/cd/abcdef.mime", random_dir)
+        );
+        let path = storage.get_object_path("repo", "oid");
+        assert_eq!(path, format!("/tmp/{}/repo/objects/oi/_/oid", random_dir));
+    }
+
     #[test]
     fn test_get_meta_result() {
         let random_dir = uuid::Uuid::new_v4().to_string();
@@ -141,24 +749,104 @@ mod tests {
     fn test_post() {
         let random_dir = uuid::Uuid::new_v4().to_string();
         let storage = super::LocalFileStorage::new(format!("/tmp/{}", random_dir));
-        aw!(storage.post("repo", "oid", vec![1, 2, 3], "application/octet-stream")).unwrap();
+        aw!(storage.post(
+            "repo",
+            "oid",
+            reader(vec![1, 2, 3]),
+            "application/octet-stream",
+            None
+        ))
+        .unwrap();
     }
 
     #[test]
     fn test_post_and_retrieve() {
         let random_dir = uuid::Uuid::new_v4().to_string();
         let storage = super::LocalFileStorage::new(format!("/tmp/{}", random_dir));
-        aw!(storage.post("repo", "oid", vec![1, 2, 3], "application/octet-stream")).unwrap();
+        aw!(storage.post(
+            "repo",
+            "oid",
+            reader(vec![1, 2, 3]),
+            "application/octet-stream",
+            None
+        ))
+        .unwrap();
         let retrieved = aw!(storage.get("repo", "oid"));
         assert!(retrieved.is_ok());
-        assert_eq!(retrieved.unwrap().0, vec![1, 2, 3]);
+        assert_eq!(aw!(read_all(retrieved.unwrap().0)), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_post_and_retrieve_multiple_chunks() {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let storage = super::LocalFileStorage::new(format!("/tmp/{}", random_dir));
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        aw!(storage.post(
+            "repo",
+            "oid",
+            reader(data.clone()),
+            "application/octet-stream",
+            None
+        ))
+        .unwrap();
+        let (stream, _) = aw!(storage.get("repo", "oid")).unwrap();
+        assert_eq!(aw!(read_all(stream)), data);
+    }
+
+    #[test]
+    fn test_post_and_retrieve_range() {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let storage = super::LocalFileStorage::new(format!("/tmp/{}", random_dir));
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        aw!(storage.post(
+            "repo",
+            "oid",
+            reader(data.clone()),
+            "application/octet-stream",
+            None
+        ))
+        .unwrap();
+        let range = ByteRange {
+            start: 1000,
+            end: 80_000,
+        };
+        let (stream, content_type) = aw!(storage.get_range("repo", "oid", range)).unwrap();
+        assert_eq!(content_type, "application/octet-stream");
+        assert_eq!(aw!(read_all(stream)), data[1000..=80_000].to_vec());
+    }
+
+    #[test]
+    fn test_post_failure_is_not_visible() {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let storage = super::LocalFileStorage::new(format!("/tmp/{}", random_dir));
+        let failing: ObjectReader = Box::pin(
+            tokio_test::io::Builder::new()
+                .read(&[1, 2, 3])
+                .read_error(std::io::Error::other("rejected"))
+                .build(),
+        );
+        assert!(
+            aw!(storage.post("repo", "oid", failing, "application/octet-stream", None)).is_err()
+        );
+
+        let result = aw!(storage.get_meta_result("repo", "oid"));
+        assert!(!result.exists);
+        let temporary_files = std::fs::read_dir(format!("/tmp/{}/repo/tmp", random_dir)).unwrap();
+        assert_eq!(temporary_files.count(), 0);
     }
 
     #[test]
     fn test_post_and_get_meta() {
         let random_dir = uuid::Uuid::new_v4().to_string();
         let storage = super::LocalFileStorage::new(format!("/tmp/{}", random_dir));
-        aw!(storage.post("repo", "oid", vec![1, 2, 3], "application/octet-stream")).unwrap();
+        aw!(storage.post(
+            "repo",
+            "oid",
+            reader(vec![1, 2, 3]),
+            "application/octet-stream",
+            None
+        ))
+        .unwrap();
         let result = aw!(storage.get_meta_result("repo", "oid"));
         assert!(result.exists);
         assert_eq!(result.size, 3);
@@ -170,9 +858,173 @@ mod tests {
     fn test_keep_mime_type() {
         let random_dir = uuid::Uuid::new_v4().to_string();
         let storage = super::LocalFileStorage::new(format!("/tmp/{}", random_dir));
-        aw!(storage.post("repo", "oid", vec![1, 2, 3], "image/png")).unwrap();
+        aw!(storage.post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
         let retrieved = aw!(storage.get("repo", "oid"));
         assert!(retrieved.is_ok());
         assert_eq!(retrieved.unwrap().1, "image/png");
     }
+
+    #[test]
+    fn test_sharded_post_and_retrieve() {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let storage = super::LocalFileStorage::new_sharded(format!("/tmp/{}", random_dir), 2);
+        aw!(storage.post("repo", "abcdef", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+
+        let result = aw!(storage.get_meta_result("repo", "abcdef"));
+        assert!(result.exists);
+        assert_eq!(result.size, 3);
+        let (stream, content_type) = aw!(storage.get("repo", "abcdef")).unwrap();
+        assert_eq!(aw!(read_all(stream)), vec![1, 2, 3]);
+        assert_eq!(content_type, "image/png");
+    }
+
+    #[test]
+    fn test_migrate_layout() {
+        let root_path = format!("/tmp/{}", uuid::Uuid::new_v4());
+        let flat = super::LocalFileStorage::new(root_path.clone());
+        for repo in ["repo", "group/repo"] {
+            for oid in ["abcdef", "abcd00", "123456"] {
+                aw!(flat.post(repo, oid, reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+            }
+        }
+
+        let sharded = super::LocalFileStorage::new_sharded(root_path.clone(), 2);
+        assert_eq!(sharded.migrate_layout().unwrap(), 12);
+        for repo in ["repo", "group/repo"] {
+            for oid in ["abcdef", "abcd00", "123456"] {
+                assert!(!aw!(flat.get_meta_result(repo, oid)).exists);
+                let (stream, content_type) = aw!(sharded.get(repo, oid)).unwrap();
+                assert_eq!(aw!(read_all(stream)), vec![1, 2, 3]);
+                assert_eq!(content_type, "image/png");
+            }
+        }
+
+        // Running it again moves nothing, and it can go back to the flat layout
+        assert_eq!(sharded.migrate_layout().unwrap(), 0);
+        assert_eq!(flat.migrate_layout().unwrap(), 12);
+        assert!(aw!(flat.get_meta_result("group/repo", "abcdef")).exists);
+        let objects = std::fs::read_dir(format!("{}/repo/objects", root_path)).unwrap();
+        assert_eq!(objects.count(), 3);
+    }
+
+    #[test]
+    fn test_list_objects() {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let storage = super::LocalFileStorage::new_sharded(format!("/tmp/{}", random_dir), 2);
+        for (repo, oid) in [("a/b", "abcdef"), ("a/b", "123456"), ("a", "abcdef")] {
+            aw!(storage.post(repo, oid, reader(vec![1]), "image/png", None)).unwrap();
+        }
+
+        let mut objects = aw!(storage.list_objects().try_collect::<Vec<_>>()).unwrap();
+        objects.sort_by(|a, b| (&a.repo, &a.oid).cmp(&(&b.repo, &b.oid)));
+        let listed: Vec<_> = objects
+            .iter()
+            .map(|object| (object.repo.as_str(), object.oid.as_str()))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![("a", "abcdef"), ("a/b", "123456"), ("a/b", "abcdef")]
+        );
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let root_path = format!("/tmp/{}", uuid::Uuid::new_v4());
+        let storage = super::LocalFileStorage::new_sharded(root_path.clone(), 2);
+        aw!(storage.post("repo", "abcdef", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+
+        assert!(!aw!(storage.is_quarantined("repo", "abcdef")).unwrap());
+        aw!(storage.quarantine("repo", "abcdef")).unwrap();
+        assert!(!aw!(storage.get_meta_result("repo", "abcdef")).exists);
+        assert!(aw!(storage.is_quarantined("repo", "abcdef")).unwrap());
+        assert!(aw!(storage.list_objects().try_collect::<Vec<_>>())
+            .unwrap()
+            .is_empty());
+        assert!(std::path::Path::new(&format!("{}/repo/quarantine/abcdef", root_path)).exists());
+
+        // The orphaned content type is moved along with the object
+        assert!(aw!(storage.list_orphaned_content_types())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_resumable_upload() {
+        let root_path = format!("/tmp/{}", uuid::Uuid::new_v4());
+        let storage = super::LocalFileStorage::new_sharded(root_path, 2);
+        assert_eq!(aw!(storage.get_offset("repo", "abcdef")).unwrap(), 0);
+
+        assert_eq!(
+            aw!(storage.append("repo", "abcdef", 0, reader(vec![1, 2]))).unwrap(),
+            2
+        );
+        assert_eq!(
+            aw!(storage.append("repo", "abcdef", 2, reader(vec![3]))).unwrap(),
+            3
+        );
+        // Content following another offset than the received size is refused
+        let error = aw!(storage.append("repo", "abcdef", 2, reader(vec![3]))).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::AlreadyExists
+        );
+        // So is an append while another one is running
+        let guard = storage.start_append(storage.get_upload_path("repo", "abcdef"));
+        assert!(aw!(storage.append("repo", "abcdef", 3, reader(vec![4]))).is_err());
+        drop(guard);
+        assert_eq!(aw!(storage.get_offset("repo", "abcdef")).unwrap(), 3);
+        let chunks: Vec<_> = aw!(aw!(storage.get_content("repo", "abcdef"))
+            .unwrap()
+            .try_collect())
+        .unwrap();
+        assert_eq!(chunks.concat(), vec![1, 2, 3]);
+
+        // The upload is neither an object nor a repository
+        assert!(!aw!(storage.get_meta_result("repo", "abcdef")).exists);
+        assert!(aw!(storage.list_objects().try_collect::<Vec<_>>())
+            .unwrap()
+            .is_empty());
+
+        aw!(storage.discard("repo", "abcdef")).unwrap();
+        aw!(storage.discard("repo", "abcdef")).unwrap();
+        assert_eq!(aw!(storage.get_offset("repo", "abcdef")).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_chunked_upload() {
+        let root_path = format!("/tmp/{}", uuid::Uuid::new_v4());
+        let storage = super::LocalFileStorage::new_sharded(root_path, 2);
+        assert!(aw!(storage.get_chunk_sizes("repo", "abcdef"))
+            .unwrap()
+            .is_empty());
+
+        // Chunks are received in any order, and can be sent again
+        aw!(storage.put_chunk("repo", "abcdef", 1, reader(vec![3]))).unwrap();
+        aw!(storage.put_chunk("repo", "abcdef", 0, reader(vec![0]))).unwrap();
+        assert_eq!(
+            aw!(storage.put_chunk("repo", "abcdef", 0, reader(vec![1, 2]))).unwrap(),
+            2
+        );
+        assert_eq!(
+            aw!(storage.get_chunk_sizes("repo", "abcdef")).unwrap(),
+            BTreeMap::from([(0, 2), (1, 1)])
+        );
+        let chunks: Vec<_> = aw!(aw!(storage.get_chunks("repo", "abcdef", 2))
+            .unwrap()
+            .try_collect())
+        .unwrap();
+        assert_eq!(chunks.concat(), vec![1, 2, 3]);
+
+        // The chunks are neither objects nor repositories
+        assert!(!aw!(storage.get_meta_result("repo", "abcdef")).exists);
+        assert!(aw!(storage.list_objects().try_collect::<Vec<_>>())
+            .unwrap()
+            .is_empty());
+
+        aw!(storage.discard_chunks("repo", "abcdef")).unwrap();
+        aw!(storage.discard_chunks("repo", "abcdef")).unwrap();
+        assert!(aw!(storage.get_chunk_sizes("repo", "abcdef"))
+            .unwrap()
+            .is_empty());
+    }
 }
@@ -1,6 +1,10 @@
 use crate::traits::services::Services;
 use crate::traits::{
-    file_storage::{FileStorageLinkSigner, FileStorageMetaRequester, FileStorageProxy},
+    file_storage::{
+        FileStorageChunkedUploader, FileStorageLinkSigner, FileStorageMetaRequester,
+        FileStorageMultipartUploader, FileStorageProxy, FileStorageQuarantine,
+        FileStorageResumableUploader,
+    },
     locks::LocksProvider,
     token_encoder_decoder::TokenEncoderDecoder,
 };
@@ -12,6 +16,12 @@ pub struct InjectedServices {
     pub file_storage_link_signer: Arc<dyn FileStorageLinkSigner + 'static>,
     pub token_encoder_decoder: Arc<dyn TokenEncoderDecoder + 'static>,
     pub locks_provider: Option<Arc<dyn LocksProvider + 'static>>,
+    pub file_storage_multipart_uploader: Option<Arc<dyn FileStorageMultipartUploader + 'static>>,
+    pub file_storage_resumable_uploader: Option<Arc<dyn FileStorageResumableUploader + 'static>>,
+    pub file_storage_chunked_uploader: Option<Arc<dyn FileStorageChunkedUploader + 'static>>,
+    pub file_storage_quarantine: Option<Arc<dyn FileStorageQuarantine + 'static>>,
+    pub batch_max_objects: Option<usize>,
+    pub verify_content_max_size: Option<u64>,
 }
 
 impl Services for InjectedServices {
@@ -34,4 +44,38 @@ impl Services for InjectedServices {
     fn file_storage_proxy(&self) -> Option<&(dyn FileStorageProxy + 'static)> {
         self.file_storage_proxy.as_ref().map(|x| x.as_ref())
     }
+
+    fn file_storage_multipart_uploader(
+        &self,
+    ) -> Option<&(dyn FileStorageMultipartUploader + 'static)> {
+        self.file_storage_multipart_uploader
+            .as_ref()
+            .map(|x| x.as_ref())
+    }
+
+    fn file_storage_resumable_uploader(
+        &self,
+    ) -> Option<&(dyn FileStorageResumableUploader + 'static)> {
+        self.file_storage_resumable_uploader
+            .as_ref()
+            .map(|x| x.as_ref())
+    }
+
+    fn file_storage_chunked_uploader(&self) -> Option<&(dyn FileStorageChunkedUploader + 'static)> {
+        self.file_storage_chunked_uploader
+            .as_ref()
+            .map(|x| x.as_ref())
+    }
+
+    fn file_storage_quarantine(&self) -> Option<&(dyn FileStorageQuarantine + 'static)> {
+        self.file_storage_quarantine.as_ref().map(|x| x.as_ref())
+    }
+
+    fn batch_max_objects(&self) -> Option<usize> {
+        self.batch_max_objects
+    }
+
+    fn verify_content_max_size(&self) -> Option<u64> {
+        self.verify_content_max_size
+    }
 }
@@ -111,10 +111,12 @@ mod tests {
             claims.insert("operation".to_string(), self.resolves_operation.clone());
             let exp = match self.expires_in.signum() {
                 -1 => {
-                    SystemTime::now() - std::time::Duration::from_secs(self.expires_in.abs() as u64)
+                    SystemTime::now()
+                        - std::time::Duration::from_secs(self.expires_in.unsigned_abs())
                 }
                 _ => {
-                    SystemTime::now() + std::time::Duration::from_secs(self.expires_in.abs() as u64)
+                    SystemTime::now()
+                        + std::time::Duration::from_secs(self.expires_in.unsigned_abs())
                 }
             };
             claims.insert(
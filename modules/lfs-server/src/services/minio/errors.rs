@@ -0,0 +1,44 @@
+use s3::error::S3Error;
+use std::io::ErrorKind;
+
+use crate::traits::file_storage::ObjectGone;
+
+/**
+ * Convert an error of the S3 client to an io error, of the kind matching its HTTP status, so
+ * that the callers of the MinIO storages can tell the errors apart without knowing about S3.
+ * The S3 error is kept as the source of the io error, or of the ObjectGone error of a 410.
+ */
+pub fn to_io_error(error: S3Error) -> std::io::Error {
+    let kind = match error {
+        S3Error::Http(410, _) => return ObjectGone::io_error(error),
+        S3Error::Http(404, _) => ErrorKind::NotFound,
+        S3Error::Http(409, _) => ErrorKind::AlreadyExists,
+        S3Error::Http(411 | 413 | 422, _) => ErrorKind::InvalidInput,
+        S3Error::Http(507, _) => ErrorKind::StorageFull,
+        _ => ErrorKind::Other,
+    };
+    std::io::Error::new(kind, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_io_error() {
+        let kind = |status| to_io_error(S3Error::Http(status, String::new())).kind();
+        assert_eq!(kind(404), ErrorKind::NotFound);
+        assert_eq!(kind(410), ErrorKind::NotFound);
+        assert_eq!(kind(409), ErrorKind::AlreadyExists);
+        assert_eq!(kind(413), ErrorKind::InvalidInput);
+        assert_eq!(kind(507), ErrorKind::StorageFull);
+        assert_eq!(kind(503), ErrorKind::Other);
+        assert_eq!(to_io_error(S3Error::HttpFail).kind(), ErrorKind::Other);
+
+        let error = to_io_error(S3Error::Http(410, String::from("Gone")));
+        assert!(error.get_ref().unwrap().is::<ObjectGone>());
+
+        let error = to_io_error(S3Error::Http(507, String::from("Full")));
+        assert!(error.get_ref().unwrap().is::<S3Error>());
+    }
+}
@@ -0,0 +1,556 @@
+use std::{
+    collections::HashMap,
+    future::ready,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use axum::http::{header::HOST, HeaderMap};
+use futures_util::{stream, TryStreamExt};
+use s3::{creds::Credentials, error::S3Error, Bucket, BucketConfiguration, Region};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    api::{enums::Operation, objects_batch::response::ObjectAction, range::ByteRange},
+    services::minio::{
+        errors::to_io_error,
+        single_bucket_storage::{presign, MinioSingleBucketStorage},
+    },
+    traits::file_storage::{
+        FileStorageLinkSigner, FileStorageLister, FileStorageMetaRequester, FileStorageMetaResult,
+        FileStorageProxy, FileStorageQuarantine, ObjectReader, ObjectStream, StoredObjectStream,
+    },
+};
+
+/* -------------------------------------------------------------------------- */
+/*                                  Requester                                 */
+/* -------------------------------------------------------------------------- */
+
+/**
+ * Storage of the objects in several buckets. Each repository is mapped to a bucket through a
+ * naming template, in which `{repo}` is replaced by the whole repository path and `{namespace}`
+ * by its first segment. For instance, with `lfs-{namespace}`, the repositories `team/a` and
+ * `team/b` are both stored in the `lfs-team` bucket.
+ *
+ * Inside a bucket, objects are stored as in a single bucket storage. Buckets are created on the
+ * first upload to them.
+ */
+pub struct MinioMultipleBucketStorage {
+    bucket_name_template: String,
+    credentials: Credentials,
+    direct_access_region: Region,
+    public_access_region: Option<Region>,
+    storages: Mutex<HashMap<String, BucketStorage>>,
+    http_client: reqwest::Client,
+}
+
+struct BucketStorage {
+    storage: Arc<MinioSingleBucketStorage>,
+    exists: bool,
+}
+
+pub struct MinioMultipleBucketStorageConfig {
+    pub bucket_name_template: String,
+    pub credentials: Credentials,
+    pub direct_access_region: Region,
+    pub public_access_region: Option<Region>,
+}
+
+impl MinioMultipleBucketStorage {
+    pub fn new(
+        bucket_name_template: String,
+        credentials: Credentials,
+        direct_access_region: Region,
+        public_access_region: Option<Region>,
+    ) -> MinioMultipleBucketStorage {
+        MinioMultipleBucketStorage {
+            bucket_name_template,
+            credentials,
+            direct_access_region,
+            public_access_region,
+            storages: Mutex::new(HashMap::new()),
+            http_client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn from_config(config: MinioMultipleBucketStorageConfig) -> MinioMultipleBucketStorage {
+        Self::new(
+            config.bucket_name_template,
+            config.credentials,
+            config.direct_access_region,
+            config.public_access_region,
+        )
+    }
+
+    /**
+     * Name of the bucket storing the objects of a repository. A name that is not a valid bucket
+     * name is made one: its characters that are not lowercase letters, digits or dashes are
+     * replaced by dashes, it is truncated, and a short hash of the name is appended so that two
+     * repositories mapped to the same characters still get distinct buckets.
+     */
+    pub fn get_bucket_name(&self, repo: &str) -> String {
+        let namespace = repo.split('/').next().unwrap_or(repo);
+        let name = self
+            .bucket_name_template
+            .replace("{repo}", repo)
+            .replace("{namespace}", namespace);
+        if is_valid_bucket_name(&name) {
+            return name;
+        }
+
+        let hash = hex::encode(&Sha256::digest(name.as_bytes())[..4]);
+        let sanitized: String = name
+            .to_lowercase()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | '0'..='9' => c,
+                _ => '-',
+            })
+            .take(BUCKET_NAME_MAX_LEN - hash.len() - 1)
+            .collect();
+        let sanitized = sanitized.trim_matches('-');
+        let bucket_name = match sanitized.is_empty() {
+            true => hash,
+            false => format!("{}-{}", sanitized, hash),
+        };
+        debug_assert!(is_valid_bucket_name(&bucket_name));
+        bucket_name
+    }
+
+    /**
+     * Get the single bucket storage of the bucket of a repository, without checking that the
+     * bucket exists.
+     */
+    fn get_storage(&self, repo: &str) -> Arc<MinioSingleBucketStorage> {
+        self.get_bucket_storage(&self.get_bucket_name(repo))
+    }
+
+    /**
+     * Get the single bucket storage of a bucket, without checking that it exists.
+     */
+    fn get_bucket_storage(&self, bucket_name: &str) -> Arc<MinioSingleBucketStorage> {
+        let mut storages = self.storages.lock().unwrap();
+        let bucket = storages.entry(bucket_name.to_string()).or_insert_with(|| {
+            let storage = MinioSingleBucketStorage::new(
+                bucket_name.to_string(),
+                self.credentials.clone(),
+                self.direct_access_region.clone(),
+                self.public_access_region.clone(),
+            );
+            BucketStorage {
+                storage: Arc::new(storage),
+                exists: false,
+            }
+        });
+        bucket.storage.clone()
+    }
+
+    /**
+     * Get the single bucket storage of the bucket of a repository, creating the bucket if it does
+     * not exist yet.
+     */
+    async fn get_or_create_storage(
+        &self,
+        repo: &str,
+    ) -> Result<Arc<MinioSingleBucketStorage>, S3Error> {
+        let storage = self.get_storage(repo);
+        let bucket_name = self.get_bucket_name(repo);
+        let exists = self
+            .storages
+            .lock()
+            .unwrap()
+            .get(&bucket_name)
+            .is_some_and(|b| b.exists);
+        if exists {
+            return Ok(storage);
+        }
+
+        match Bucket::create_with_path_style(
+            &bucket_name,
+            self.direct_access_region.clone(),
+            self.credentials.clone(),
+            BucketConfiguration::private(),
+        )
+        .await
+        {
+            Ok(_) => tracing::info!("Created bucket {}", bucket_name),
+            // Already created, by us or by a previous run
+            Err(S3Error::Http(409, _)) => {}
+            Err(e) => return Err(e),
+        }
+
+        if let Some(bucket) = self.storages.lock().unwrap().get_mut(&bucket_name) {
+            bucket.exists = true;
+        }
+        Ok(storage)
+    }
+
+    /**
+     * Names of the buckets the credentials can see. The s3 client can not list them, so the
+     * request is signed by hand, and sent through a short-lived presigned link.
+     */
+    async fn list_buckets(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let region = &self.direct_access_region;
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, region.host().parse()?);
+        let url = format!("{}://{}/", region.scheme(), region.host());
+        let link = presign("GET", &url, &headers, region, &self.credentials, &[], 60)?;
+        let response = self.http_client.get(link).send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            return Err(Box::new(S3Error::Http(status.as_u16(), text)));
+        }
+
+        let result: ListAllMyBucketsResult = quick_xml::de::from_str(&text)?;
+        Ok(result.buckets.buckets.into_iter().map(|b| b.name).collect())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListAllMyBucketsResult {
+    #[serde(default)]
+    buckets: ListedBuckets,
+}
+
+#[derive(Deserialize, Default)]
+struct ListedBuckets {
+    #[serde(rename = "Bucket", default)]
+    buckets: Vec<ListedBucket>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedBucket {
+    name: String,
+}
+
+const BUCKET_NAME_MAX_LEN: usize = 63;
+
+/**
+ * Check a bucket name against the naming rules of S3: 3 to 63 lowercase letters, digits, dots
+ * and dashes, starting and ending with a letter or a digit, without two adjacent dots or a dot
+ * next to a dash, and not formatted as an IP address.
+ */
+fn is_valid_bucket_name(name: &str) -> bool {
+    let is_ip_address = name.split('.').count() == 4
+        && name
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+    (3..=BUCKET_NAME_MAX_LEN).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '.' | '-'))
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric())
+        && !name.contains("..")
+        && !name.contains(".-")
+        && !name.contains("-.")
+        && !is_ip_address
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                    Meta                                    */
+/* -------------------------------------------------------------------------- */
+
+#[async_trait]
+impl FileStorageMetaRequester for MinioMultipleBucketStorage {
+    async fn get_meta_result<'a>(&self, repo: &'a str, oid: &'a str) -> FileStorageMetaResult<'a> {
+        // A missing bucket is reported as a missing object
+        self.get_storage(repo).get_meta_result(repo, oid).await
+    }
+
+    async fn get_content_sha256(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        self.get_storage(repo).get_content_sha256(repo, oid).await
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                   Listing                                  */
+/* -------------------------------------------------------------------------- */
+
+#[async_trait]
+impl FileStorageLister for MinioMultipleBucketStorage {
+    fn list_objects(&self) -> StoredObjectStream<'_> {
+        let buckets = stream::once(async move {
+            let names = self
+                .list_buckets()
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            Ok::<_, std::io::Error>(stream::iter(names.into_iter().map(Ok::<_, std::io::Error>)))
+        })
+        .try_flatten();
+
+        // The buckets are listed one after the other. Only the objects of the repositories
+        // mapped to the bucket they are found in are kept, skipping the buckets of the other
+        // applications sharing the server.
+        Box::pin(
+            buckets
+                .map_ok(move |bucket_name: String| {
+                    self.get_bucket_storage(&bucket_name)
+                        .list_bucket()
+                        .try_filter(move |o| ready(self.get_bucket_name(&o.repo) == bucket_name))
+                })
+                .try_flatten(),
+        )
+    }
+}
+
+#[async_trait]
+impl FileStorageQuarantine for MinioMultipleBucketStorage {
+    async fn quarantine(&self, repo: &str, oid: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.get_storage(repo).quarantine(repo, oid).await
+    }
+
+    async fn is_quarantined(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        self.get_storage(repo).is_quarantined(repo, oid).await
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                link signing                                */
+/* -------------------------------------------------------------------------- */
+
+#[async_trait]
+impl FileStorageLinkSigner for MinioMultipleBucketStorage {
+    async fn get_presigned_link<'a>(
+        &self,
+        result: FileStorageMetaResult<'a>,
+    ) -> Result<ObjectAction, Box<dyn std::error::Error>> {
+        self.get_storage(result.repo)
+            .get_presigned_link(result)
+            .await
+    }
+
+    async fn post_presigned_link<'a>(
+        &self,
+        result: FileStorageMetaResult<'a>,
+        size: u64,
+        user: &str,
+        ref_name: Option<&str>,
+    ) -> Result<(ObjectAction, Option<ObjectAction>), Box<dyn std::error::Error>> {
+        // The bucket must exist before the client uploads to it
+        let storage = self
+            .get_or_create_storage(result.repo)
+            .await
+            .map_err(to_io_error)?;
+        storage
+            .post_presigned_link(result, size, user, ref_name)
+            .await
+    }
+
+    async fn check_link(
+        &self,
+        _repo: &str,
+        _oid: &str,
+        _header: Option<&HeaderMap>,
+        _operation: Operation,
+    ) -> bool {
+        // in this strategy, we are not responsible for checking the link, it should be done directly by minio
+        return false;
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                             Upload and download                            */
+/* -------------------------------------------------------------------------- */
+
+#[async_trait]
+impl FileStorageProxy for MinioMultipleBucketStorage {
+    async fn get(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        self.get_storage(repo).get(repo, oid).await
+    }
+
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        self.get_storage(repo).get_range(repo, oid, range).await
+    }
+
+    async fn post(
+        &self,
+        repo: &str,
+        oid: &str,
+        data: ObjectReader,
+        content_type: &str,
+        uploader: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let storage = self.get_or_create_storage(repo).await?;
+        storage.post(repo, oid, data, content_type, uploader).await
+    }
+
+    async fn post_if_absent(
+        &self,
+        repo: &str,
+        oid: &str,
+        data: ObjectReader,
+        content_type: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let storage = self.get_or_create_storage(repo).await?;
+        storage.post_if_absent(repo, oid, data, content_type).await
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                    tests                                   */
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aw,
+        test_utils::helpers::{read_all, reader},
+        traits::file_storage::StoredObject,
+    };
+
+    fn get_storage(template: &str) -> MinioMultipleBucketStorage {
+        let credentials = Credentials::new(
+            Some("minio_access_key"),
+            Some("minio_secret_key"),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        MinioMultipleBucketStorage::new(
+            template.to_string(),
+            credentials,
+            Region::Custom {
+                region: String::from("test"),
+                endpoint: String::from("http://localhost:9000"),
+            },
+            Some(Region::Custom {
+                region: String::from("test"),
+                endpoint: String::from("https://storage"),
+            }),
+        )
+    }
+
+    #[test]
+    fn test_get_bucket_name_repo() {
+        let storage = get_storage("lfs-{repo}");
+        assert_eq!(storage.get_bucket_name("project-1"), "lfs-project-1");
+        assert_eq!(
+            storage.get_bucket_name("team/Project_1"),
+            "lfs-team-project-1-12ab849c"
+        );
+    }
+
+    #[test]
+    fn test_get_bucket_name_distinct_repos() {
+        let storage = get_storage("lfs-{repo}");
+        let a = storage.get_bucket_name("a/b");
+        let b = storage.get_bucket_name("a_b");
+        assert!(a.starts_with("lfs-a-b-"));
+        assert!(b.starts_with("lfs-a-b-"));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_get_bucket_name_valid() {
+        let storage = get_storage("{repo}");
+        let long_repo = format!("group/{}", "a".repeat(100));
+        for repo in ["a", "_", "-a-", "a..b", "a.-b", "192.168.0.1", &long_repo] {
+            let bucket_name = storage.get_bucket_name(repo);
+            assert!(is_valid_bucket_name(&bucket_name), "{}", bucket_name);
+        }
+        assert_eq!(storage.get_bucket_name(&long_repo).len(), 63);
+    }
+
+    #[test]
+    fn test_get_bucket_name_namespace() {
+        let storage = get_storage("lfs-{namespace}");
+        assert_eq!(storage.get_bucket_name("team/a"), "lfs-team");
+        assert_eq!(storage.get_bucket_name("team/b/c"), "lfs-team");
+        assert_eq!(storage.get_bucket_name("single"), "lfs-single");
+    }
+
+    #[test]
+    fn test_parse_bucket_listing() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListAllMyBucketsResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+<Owner><ID>id</ID><DisplayName>minio</DisplayName></Owner>
+<Buckets>
+<Bucket><Name>lfs-team</Name><CreationDate>2024-01-01T00:00:00.000Z</CreationDate></Bucket>
+<Bucket><Name>lfs-other</Name><CreationDate>2024-01-01T00:00:00.000Z</CreationDate></Bucket>
+</Buckets>
+</ListAllMyBucketsResult>"#;
+        let result: ListAllMyBucketsResult = quick_xml::de::from_str(text).unwrap();
+        let names: Vec<_> = result.buckets.buckets.into_iter().map(|b| b.name).collect();
+        assert_eq!(names, vec!["lfs-team", "lfs-other"]);
+
+        let text = "<ListAllMyBucketsResult><Buckets></Buckets></ListAllMyBucketsResult>";
+        let result: ListAllMyBucketsResult = quick_xml::de::from_str(text).unwrap();
+        assert!(result.buckets.buckets.is_empty());
+    }
+
+    #[test]
+    fn test_list_objects_of_repo_buckets() {
+        let namespace = uuid::Uuid::new_v4().to_string();
+        let storage = get_storage("mbs-{namespace}");
+        let repo = format!("{}/a", namespace);
+        aw!(storage.post(&repo, "test.txt", reader(b"hello"), "text/plain", None)).unwrap();
+
+        let objects: Vec<_> = aw!(storage.list_objects().try_collect::<Vec<_>>()).unwrap();
+        assert!(objects.contains(&StoredObject {
+            repo,
+            oid: String::from("test.txt"),
+        }));
+    }
+
+    #[test]
+    fn test_post_presigned_link_uses_repo_bucket() {
+        let namespace = uuid::Uuid::new_v4().to_string();
+        let storage = get_storage("mbs-{namespace}");
+        let repo = format!("{}/repo", namespace);
+        let (upload, _) = aw!(storage.post_presigned_link(
+            FileStorageMetaResult::new(&repo, "oid", 0),
+            30,
+            "user",
+            None
+        ))
+        .unwrap();
+        let expected = format!("https://storage/mbs-{}/{}/objects/oid?", namespace, repo);
+        assert!(upload.href.starts_with(&expected));
+    }
+
+    #[test]
+    fn test_post_creates_bucket_and_retrieve() {
+        let namespace = uuid::Uuid::new_v4().to_string();
+        let storage = get_storage("mbs-{namespace}");
+        let repo_a = format!("{}/a", namespace);
+        let repo_b = format!("{}/b", namespace);
+
+        let result = aw!(storage.get_meta_result(&repo_a, "test.txt"));
+        assert!(!result.exists);
+
+        aw!(storage.post(&repo_a, "test.txt", reader(b"hello"), "text/plain", None)).unwrap();
+        aw!(storage.post(&repo_b, "test.txt", reader(b"hello2"), "text/plain", None)).unwrap();
+
+        let result = aw!(storage.get_meta_result(&repo_a, "test.txt"));
+        assert!(result.exists);
+        assert_eq!(result.size, 5);
+
+        let (stream, content_type) = aw!(storage.get(&repo_b, "test.txt")).unwrap();
+        assert_eq!(aw!(read_all(stream)), b"hello2");
+        assert_eq!(content_type, "text/plain");
+    }
+}
@@ -1,23 +1,81 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
 use async_trait::async_trait;
 
-use axum::http::HeaderMap;
+use axum::http::{
+    header::{CONTENT_LENGTH, HOST},
+    HeaderMap,
+};
+use futures_util::{StreamExt, TryStreamExt};
+use hmac::Mac;
 use regex::Regex;
-use s3::{creds::Credentials, Bucket, Region};
+use s3::{
+    bucket::CHUNK_SIZE, creds::Credentials, error::S3Error, serde_types::Part, signing, Bucket,
+    Region,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
 
 use crate::{
-    api::{enums::Operation, objects_batch::response::ObjectAction},
+    api::{
+        enums::Operation,
+        objects_batch::response::{ObjectAction, PartAction},
+        range::ByteRange,
+    },
+    services::minio::errors::to_io_error,
     traits::file_storage::{
-        FileStorageLinkSigner, FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy,
+        FileStorageChunkedUploader, FileStorageLinkSigner, FileStorageLister,
+        FileStorageMetaRequester, FileStorageMetaResult, FileStorageMultipartUploader,
+        FileStorageProxy, FileStorageQuarantine, MultipartUpload, ObjectReader, ObjectStream,
+        StoredObject, StoredObjectStream,
     },
 };
 
+// Format of the dates of the signed links
+const LONG_DATETIME: &[time::format_description::FormatItem<'static>] =
+    time::macros::format_description!("[year][month][day]T[hour][minute][second]Z");
+
+// Minimum size of the parts of the multipart uploads made by the clients
+const MULTIPART_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+// Maximum number of parts of the multipart uploads made by the clients, so that they can be
+// listed in a single request. The parts get larger for larger objects
+const MULTIPART_MAX_PARTS: u64 = 1000;
+
+/**
+ * A part of a multipart upload, as listed by the ListParts request.
+ */
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct UploadedPart {
+    part_number: u32,
+    #[serde(rename = "ETag")]
+    etag: String,
+    size: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListPartsResult {
+    #[serde(rename = "Part", default)]
+    parts: Vec<UploadedPart>,
+    #[serde(default)]
+    is_truncated: bool,
+}
+
 /* -------------------------------------------------------------------------- */
 /*                                  Requester                                 */
 /* -------------------------------------------------------------------------- */
 
+#[derive(Clone)]
 pub struct MinioSingleBucketStorage {
     bucket_direct_access: Bucket,
     bucket_public_access: Bucket,
+    http_client: reqwest::Client,
 }
 
 pub struct MinioSingleBucketStorageConfig {
@@ -61,6 +119,7 @@ impl MinioSingleBucketStorage {
         MinioSingleBucketStorage {
             bucket_direct_access,
             bucket_public_access,
+            http_client: reqwest::Client::new(),
         }
     }
 
@@ -76,6 +135,415 @@ impl MinioSingleBucketStorage {
     pub fn get_object_path(&self, repo: &str, oid: &str) -> String {
         format!("{}/objects/{}", repo, oid)
     }
+
+    pub fn get_chunks_path(&self, repo: &str, oid: &str) -> String {
+        format!("{}/chunks/{}/", repo, oid)
+    }
+
+    /**
+     * Read the next part of an upload, up to CHUNK_SIZE bytes. A part shorter than CHUNK_SIZE
+     * means that the end of the data was reached.
+     */
+    async fn read_part(data: &mut ObjectReader) -> Result<Vec<u8>, std::io::Error> {
+        let mut part = Vec::with_capacity(CHUNK_SIZE);
+        data.take(CHUNK_SIZE as u64).read_to_end(&mut part).await?;
+        Ok(part)
+    }
+
+    /**
+     * Upload the data to a path of the bucket, and return its size.
+     */
+    async fn put(
+        &self,
+        s3_path: String,
+        mut data: ObjectReader,
+        content_type: &str,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        // Small objects are sent in a single request
+        let first_part = Self::read_part(&mut data).await?;
+        if first_part.len() < CHUNK_SIZE {
+            let size = first_part.len() as u64;
+            self.bucket_direct_access
+                .put_object_with_content_type(s3_path, &first_part, content_type)
+                .await?;
+            return Ok(size);
+        }
+
+        // Larger ones are sent part by part, and the upload is aborted on failure
+        let upload = self
+            .bucket_direct_access
+            .initiate_multipart_upload(&s3_path, content_type)
+            .await?;
+        let result = self
+            .put_parts(
+                &upload.key,
+                &upload.upload_id,
+                first_part,
+                &mut data,
+                content_type,
+            )
+            .await;
+        if result.is_err() {
+            let _ = self
+                .bucket_direct_access
+                .abort_upload(&upload.key, &upload.upload_id)
+                .await;
+        }
+        Ok(result?)
+    }
+
+    /**
+     * Upload the remaining parts of an already initiated multipart upload, one at a time so that
+     * at most one part is held in memory. Return the size of the uploaded data.
+     */
+    async fn put_parts(
+        &self,
+        s3_path: &str,
+        upload_id: &str,
+        first_part: Vec<u8>,
+        data: &mut ObjectReader,
+        content_type: &str,
+    ) -> Result<u64, S3Error> {
+        let mut parts = Vec::new();
+        let mut part = first_part;
+        let mut size = 0;
+        loop {
+            let done = part.len() < CHUNK_SIZE;
+            let part_number = parts.len() as u32 + 1;
+            size += part.len() as u64;
+            parts.push(
+                self.bucket_direct_access
+                    .put_multipart_chunk(part, s3_path, part_number, upload_id, content_type)
+                    .await?,
+            );
+            if done {
+                break;
+            }
+            part = Self::read_part(data).await?;
+        }
+        self.bucket_direct_access
+            .complete_multipart_upload(s3_path, upload_id, parts)
+            .await?;
+        Ok(size)
+    }
+
+    /**
+     * Stream an object, or only a range of it, from the bucket.
+     */
+    async fn fetch(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: Option<ByteRange>,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        self.fetch_path(self.get_object_path(repo, oid), range)
+            .await
+    }
+
+    /**
+     * Stream the content at a path of the bucket, or only a range of it.
+     */
+    async fn fetch_path(
+        &self,
+        s3_path: String,
+        range: Option<ByteRange>,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        // The stream returned by the s3 crate can't be sent across threads, so the object is
+        // fetched through a short-lived presigned link on the direct access bucket instead
+        let link = self.bucket_direct_access.presign_get(s3_path, 60, None)?;
+        let mut request = self.http_client.get(link);
+        if let Some(range) = range {
+            request = request.header(reqwest::header::RANGE, range.header_value());
+        }
+        let response = request.send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(Box::new(S3Error::Http(status.as_u16(), text)));
+        }
+
+        // A full body can't be passed on as the requested range
+        if range.is_some() && status != reqwest::StatusCode::PARTIAL_CONTENT {
+            return Err(Box::new(S3Error::Http(
+                status.as_u16(),
+                String::from("Range request not honored"),
+            )));
+        }
+
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("application/octet-stream")
+            .to_owned();
+        let stream = response.bytes_stream().map_err(std::io::Error::other);
+        Ok((Box::pin(stream), content_type))
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                  Presigning                                */
+/* -------------------------------------------------------------------------- */
+
+impl MinioSingleBucketStorage {
+    /**
+     * Sign an upload link on the public access bucket, bound to the size of the upload through
+     * a signed content length. The s3 crate can neither add query parameters to a presigned PUT,
+     * nor list its signed headers in the order expected by S3, so the link is signed here with
+     * its signing functions.
+     */
+    fn presign_put(
+        &self,
+        s3_path: &str,
+        queries: &[(&str, String)],
+        content_length: u64,
+        expiry_secs: u32,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let bucket = &self.bucket_public_access;
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, bucket.host().parse()?);
+        headers.insert(CONTENT_LENGTH, content_length.into());
+        let credentials = Credentials {
+            access_key: bucket.access_key()?,
+            secret_key: bucket.secret_key()?,
+            security_token: bucket.security_token()?,
+            session_token: bucket.session_token()?,
+            expiration: None,
+        };
+        let url = format!("{}/{}", bucket.url(), signing::uri_encode(s3_path, false));
+        presign(
+            "PUT",
+            &url,
+            &headers,
+            &bucket.region(),
+            &credentials,
+            queries,
+            expiry_secs,
+        )
+    }
+}
+
+/**
+ * Sign a link to a request with the signing functions of the s3 crate, for the requests it can
+ * not presign itself. The url has no query, and the signed headers, including the host, must
+ * be sent along the request.
+ */
+pub(crate) fn presign(
+    method: &str,
+    url: &str,
+    headers: &HeaderMap,
+    region: &Region,
+    credentials: &Credentials,
+    queries: &[(&str, String)],
+    expiry_secs: u32,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let datetime = time::OffsetDateTime::now_utc();
+    let credential = format!(
+        "{}/{}",
+        credentials.access_key.clone().unwrap_or_default(),
+        signing::scope_string(&datetime, region)?
+    );
+    let mut query = vec![
+        ("X-Amz-Algorithm", String::from("AWS4-HMAC-SHA256")),
+        ("X-Amz-Credential", credential),
+        ("X-Amz-Date", datetime.format(LONG_DATETIME)?),
+        ("X-Amz-Expires", expiry_secs.to_string()),
+        (
+            "X-Amz-SignedHeaders",
+            signing::signed_header_string(headers),
+        ),
+    ];
+    let token = credentials
+        .security_token
+        .clone()
+        .or(credentials.session_token.clone());
+    if let Some(token) = token {
+        query.push(("X-Amz-Security-Token", token));
+    }
+    query.extend(queries.iter().cloned());
+    let query: Vec<String> = query
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, signing::uri_encode(value, true)))
+        .collect();
+    let url = reqwest::Url::parse(&format!("{}?{}", url, query.join("&")))?;
+
+    let canonical_request = signing::canonical_request(method, &url, headers, "UNSIGNED-PAYLOAD")?;
+    let string_to_sign = signing::string_to_sign(&datetime, region, &canonical_request)?;
+    let signing_key = signing::signing_key(
+        &datetime,
+        &credentials.secret_key.clone().unwrap_or_default(),
+        region,
+        "s3",
+    )?;
+    let mut hmac = signing::HmacSha256::new_from_slice(&signing_key)?;
+    hmac.update(string_to_sign.as_bytes());
+    Ok(format!(
+        "{}&X-Amz-Signature={}",
+        url,
+        hex::encode(hmac.finalize().into_bytes())
+    ))
+}
+
+/* -------------------------------------------------------------------------- */
+/*                              Multipart uploads                             */
+/* -------------------------------------------------------------------------- */
+
+impl MinioSingleBucketStorage {
+    /**
+     * Split an object in the parts of its multipart upload, as (position, size) pairs. An empty
+     * object still gets one part, as a multipart upload can't be completed without any.
+     */
+    fn split_in_parts(size: u64) -> Vec<(u64, u64)> {
+        let part_size = MULTIPART_PART_SIZE.max(size.div_ceil(MULTIPART_MAX_PARTS));
+        let count = size.div_ceil(part_size).max(1);
+        (0..count)
+            .map(|i| {
+                let pos = i * part_size;
+                (pos, part_size.min(size - pos))
+            })
+            .collect()
+    }
+
+    /**
+     * List the parts uploaded so far. The s3 crate has no ListParts request, so it is sent
+     * through a short-lived presigned link on the direct access bucket.
+     */
+    async fn list_parts(
+        &self,
+        s3_path: &str,
+        upload_id: &str,
+    ) -> Result<Vec<UploadedPart>, Box<dyn std::error::Error>> {
+        let queries = HashMap::from([
+            (String::from("uploadId"), upload_id.to_string()),
+            (String::from("max-parts"), MULTIPART_MAX_PARTS.to_string()),
+        ]);
+        let link = self
+            .bucket_direct_access
+            .presign_get(s3_path, 60, Some(queries))?;
+        let response = self.http_client.get(link).send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            return Err(Box::new(S3Error::Http(status.as_u16(), text)));
+        }
+
+        let result: ListPartsResult = quick_xml::de::from_str(&text)?;
+        if result.is_truncated {
+            return Err(Box::new(std::io::Error::other(
+                "Too many parts in the multipart upload",
+            )));
+        }
+        Ok(result.parts)
+    }
+
+    /**
+     * Id of a multipart upload left open for an object, by a client which did not complete its
+     * push for instance.
+     */
+    async fn find_multipart_upload(&self, s3_path: &str) -> Result<Option<String>, S3Error> {
+        let results = self
+            .bucket_direct_access
+            .list_multiparts_uploads(Some(s3_path), None)
+            .await?;
+        Ok(results
+            .into_iter()
+            .flat_map(|result| result.uploads)
+            .find(|upload| upload.key == s3_path)
+            .map(|upload| upload.id))
+    }
+}
+
+#[async_trait]
+impl FileStorageMultipartUploader for MinioSingleBucketStorage {
+    async fn create_multipart_upload(
+        &self,
+        repo: &str,
+        oid: &str,
+        size: u64,
+    ) -> Result<MultipartUpload, Box<dyn std::error::Error>> {
+        // An upload left open for the object is continued, instead of piling up with the new one
+        let s3_path = self.get_object_path(repo, oid);
+        let upload_id = match self.find_multipart_upload(&s3_path).await {
+            Ok(Some(upload_id)) => upload_id,
+            Ok(None) => {
+                self.bucket_direct_access
+                    .initiate_multipart_upload(&s3_path, "application/octet-stream")
+                    .await
+                    .map_err(to_io_error)?
+                    .upload_id
+            }
+            Err(e) => return Err(Box::new(to_io_error(e))),
+        };
+        let mut parts = Vec::new();
+        for (i, (pos, size)) in Self::split_in_parts(size).into_iter().enumerate() {
+            let queries = [
+                ("partNumber", (i + 1).to_string()),
+                ("uploadId", upload_id.clone()),
+            ];
+            let link = self.presign_put(&s3_path, &queries, size, 3600)?;
+            parts.push(PartAction {
+                action: ObjectAction::new(link, None, 3600),
+                pos,
+                size,
+            });
+        }
+        Ok(MultipartUpload { upload_id, parts })
+    }
+
+    async fn get_uploaded_size(
+        &self,
+        repo: &str,
+        oid: &str,
+        upload_id: &str,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let s3_path = self.get_object_path(repo, oid);
+        let parts = self.list_parts(&s3_path, upload_id).await?;
+        Ok(parts.iter().map(|part| part.size).sum())
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        repo: &str,
+        oid: &str,
+        upload_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let s3_path = self.get_object_path(repo, oid);
+        let parts = self
+            .list_parts(&s3_path, upload_id)
+            .await?
+            .into_iter()
+            .map(|part| Part {
+                part_number: part.part_number,
+                etag: part.etag,
+            })
+            .collect();
+        let response = self
+            .bucket_direct_access
+            .complete_multipart_upload(&s3_path, upload_id, parts)
+            .await?;
+        // The request may fail after the response status was sent, with an error in its body
+        if response.status_code() >= 300 || response.as_str()?.contains("<Error>") {
+            return Err(Box::new(S3Error::Http(
+                response.status_code(),
+                response.as_str()?.to_string(),
+            )));
+        }
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        repo: &str,
+        oid: &str,
+        upload_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let s3_path = self.get_object_path(repo, oid);
+        self.bucket_direct_access
+            .abort_upload(&s3_path, upload_id)
+            .await?;
+        Ok(())
+    }
 }
 
 /* -------------------------------------------------------------------------- */
@@ -95,14 +563,99 @@ impl FileStorageMetaRequester for MinioSingleBucketStorage {
         let s3_path = self.get_object_path(repo, oid);
         let meta = self.bucket_direct_access.head_object(s3_path).await;
         let size = meta
-            .map(|m| {
-                m.0.content_length
-                    .map(|c| u64::try_from(c).map_or(None, Some))
-            })
+            .map(|m| m.0.content_length.map(|c| u64::try_from(c).ok()))
             .unwrap_or(None)
             .flatten();
         return self.match_size(size, repo, oid);
     }
+
+    async fn get_content_sha256(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        // Objects uploaded with a signed link never went through the server, so the whole
+        // object has to be read back to hash it
+        let (mut stream, _) = self.fetch(repo, oid, None).await?;
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = stream.try_next().await? {
+            hasher.update(&chunk);
+        }
+        Ok(Some(hex::encode(hasher.finalize())))
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                   Listing                                  */
+/* -------------------------------------------------------------------------- */
+
+#[async_trait]
+impl FileStorageLister for MinioSingleBucketStorage {
+    fn list_objects(&self) -> StoredObjectStream<'_> {
+        self.list_bucket()
+    }
+}
+
+impl MinioSingleBucketStorage {
+    /**
+     * List the objects of the bucket, a page at a time. The state of the listing is the
+     * continuation token of the next page, if there is one.
+     */
+    pub(crate) fn list_bucket(&self) -> StoredObjectStream<'static> {
+        let bucket = self.bucket_direct_access.clone();
+        let pages = futures_util::stream::try_unfold(Some(None), move |token| {
+            let bucket = bucket.clone();
+            async move {
+                let Some(token) = token else {
+                    return Ok::<_, std::io::Error>(None);
+                };
+                let (page, _) = bucket
+                    .list_page(String::new(), None, token, None, None)
+                    .await
+                    .map_err(to_io_error)?;
+                let next = page.next_continuation_token.map(Some);
+                Ok(Some((page.contents, next)))
+            }
+        });
+        let objects = pages
+            .map_ok(|contents| {
+                let objects = contents.into_iter().filter_map(|object| {
+                    let (repo, oid) = object.key.rsplit_once("/objects/")?;
+                    Some(Ok(StoredObject {
+                        repo: repo.to_string(),
+                        oid: oid.to_string(),
+                    }))
+                });
+                futures_util::stream::iter(objects)
+            })
+            .try_flatten();
+        Box::pin(objects)
+    }
+}
+
+#[async_trait]
+impl FileStorageQuarantine for MinioSingleBucketStorage {
+    async fn quarantine(&self, repo: &str, oid: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let s3_path = self.get_object_path(repo, oid);
+        self.bucket_direct_access
+            .copy_object_internal(&s3_path, format!("{}/quarantine/{}", repo, oid))
+            .await?;
+        self.bucket_direct_access.delete_object(&s3_path).await?;
+        Ok(())
+    }
+
+    async fn is_quarantined(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let quarantine_path = format!("{}/quarantine/{}", repo, oid);
+        match self.bucket_direct_access.head_object(quarantine_path).await {
+            Ok((_, status)) => Ok((200..300).contains(&status)),
+            Err(S3Error::Http(404, _)) => Ok(false),
+            Err(e) => Err(Box::new(to_io_error(e))),
+        }
+    }
 }
 
 /* -------------------------------------------------------------------------- */
@@ -116,17 +669,22 @@ impl FileStorageLinkSigner for MinioSingleBucketStorage {
         _result: FileStorageMetaResult<'a>,
     ) -> Result<ObjectAction, Box<dyn std::error::Error>> {
         let s3_path = self.get_object_path(_result.repo, _result.oid);
-        let link = self.bucket_public_access.presign_get(s3_path, 3600, None)?;
+        let link = self
+            .bucket_public_access
+            .presign_get(s3_path, 3600, None)
+            .map_err(to_io_error)?;
         return Ok(ObjectAction::new(link, None, 3600));
     }
 
     async fn post_presigned_link<'a>(
         &self,
         result: FileStorageMetaResult<'a>,
-        _size: u32,
+        size: u64,
+        _user: &str,
+        _ref_name: Option<&str>,
     ) -> Result<(ObjectAction, Option<ObjectAction>), Box<dyn std::error::Error>> {
         let s3_path = self.get_object_path(result.repo, result.oid);
-        let link = self.bucket_public_access.presign_put(s3_path, 3600, None)?;
+        let link = self.presign_put(&s3_path, &[], size, 3600)?;
         return Ok((ObjectAction::new(link, None, 3600), None));
     }
 
@@ -152,29 +710,127 @@ impl FileStorageProxy for MinioSingleBucketStorage {
         &self,
         repo: &str,
         oid: &str,
-    ) -> Result<(Vec<u8>, String), Box<dyn std::error::Error>> {
-        let s3_path = self.get_object_path(repo, oid);
-        let response = self.bucket_direct_access.get_object(s3_path).await?;
-        let content_type = response
-            .headers()
-            .get("content-type")
-            .unwrap_or(&String::from("application/octet-stream"))
-            .to_owned();
-        return Ok((response.to_vec(), content_type));
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        self.fetch(repo, oid, None).await
+    }
+
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        self.fetch(repo, oid, Some(range)).await
     }
 
     async fn post(
         &self,
         repo: &str,
         oid: &str,
-        data: Vec<u8>,
+        data: ObjectReader,
         content_type: &str,
+        _uploader: Option<&str>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let s3_path = self.get_object_path(repo, oid);
-        self.bucket_direct_access
-            .put_object_with_content_type(s3_path, &data, content_type)
+        self.put(self.get_object_path(repo, oid), data, content_type)
+            .await?;
+        Ok(())
+    }
+
+    async fn post_if_absent(
+        &self,
+        repo: &str,
+        oid: &str,
+        mut data: ObjectReader,
+        content_type: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        // The object, meant to be small, is sent in a single request which S3 rejects if the
+        // path exists
+        let mut content = Vec::new();
+        data.read_to_end(&mut content).await?;
+        let mut bucket = self.bucket_direct_access.clone();
+        bucket.add_header("If-None-Match", "*");
+        let put = bucket
+            .put_object_with_content_type(self.get_object_path(repo, oid), &content, content_type)
+            .await;
+        match put {
+            Ok(_) => Ok(true),
+            Err(S3Error::Http(412, _)) => Ok(false),
+            Err(e) => Err(Box::new(to_io_error(e))),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                               chunked uploads                              */
+/* -------------------------------------------------------------------------- */
+
+#[async_trait]
+impl FileStorageChunkedUploader for MinioSingleBucketStorage {
+    async fn put_chunk(
+        &self,
+        repo: &str,
+        oid: &str,
+        index: u64,
+        data: ObjectReader,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let s3_path = format!("{}{}", self.get_chunks_path(repo, oid), index);
+        self.put(s3_path, data, "application/octet-stream").await
+    }
+
+    async fn get_chunk_sizes(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<BTreeMap<u64, u64>, Box<dyn std::error::Error>> {
+        let prefix = self.get_chunks_path(repo, oid);
+        let pages = self.bucket_direct_access.list(prefix.clone(), None).await?;
+        let sizes = pages
+            .iter()
+            .flat_map(|page| page.contents.iter())
+            .filter_map(|object| {
+                let index = object.key.strip_prefix(&prefix)?.parse().ok()?;
+                Some((index, object.size))
+            })
+            .collect();
+        Ok(sizes)
+    }
+
+    async fn get_chunks(
+        &self,
+        repo: &str,
+        oid: &str,
+        count: u64,
+    ) -> Result<ObjectStream, Box<dyn std::error::Error>> {
+        let storage = Arc::new(self.clone());
+        let prefix = self.get_chunks_path(repo, oid);
+        let chunks = futures_util::stream::iter(0..count)
+            .then(move |index| {
+                let storage = storage.clone();
+                let s3_path = format!("{}{}", prefix, index);
+                async move {
+                    match storage.fetch_path(s3_path, None).await {
+                        Ok((chunk, _)) => Ok(chunk),
+                        Err(e) => Err(std::io::Error::other(e.to_string())),
+                    }
+                }
+            })
+            .try_flatten();
+        Ok(Box::pin(chunks))
+    }
+
+    async fn discard_chunks(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let pages = self
+            .bucket_direct_access
+            .list(self.get_chunks_path(repo, oid), None)
             .await?;
-        return Ok(());
+        for object in pages.iter().flat_map(|page| page.contents.iter()) {
+            self.bucket_direct_access.delete_object(&object.key).await?;
+        }
+        Ok(())
     }
 }
 
@@ -184,6 +840,10 @@ impl FileStorageProxy for MinioSingleBucketStorage {
 
 #[cfg(test)]
 mod tests {
+    use crate::{
+        aw,
+        test_utils::helpers::{read_all, reader},
+    };
     use s3::{creds::Credentials, Bucket, BucketConfiguration, Region};
 
     use crate::traits::file_storage::{
@@ -192,12 +852,6 @@ mod tests {
 
     use super::*;
 
-    macro_rules! aw {
-        ($e:expr) => {
-            tokio_test::block_on($e)
-        };
-    }
-
     async fn init_random_bucket() -> (String, Credentials, Region) {
         let bucket_name = uuid::Uuid::new_v4().to_string();
         let region = Region::Custom {
@@ -293,12 +947,14 @@ mod tests {
             size: 0,
         };
         let (bucket_name, storage) = get_random_initialized_storage();
-        let (upload, verify) = aw!(storage.post_presigned_link(result, 30)).unwrap();
+        let (upload, verify) = aw!(storage.post_presigned_link(result, 30, "user", None)).unwrap();
 
         let expected = format!("https://storage/{}/repo/objects/oid?X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential=minio_access_key", bucket_name);
         assert!(upload.href.starts_with(&expected));
         assert!(upload.href.contains("&X-Amz-Expires=3600"));
-        assert!(upload.href.contains("&X-Amz-SignedHeaders=host"));
+        assert!(upload
+            .href
+            .contains("&X-Amz-SignedHeaders=content-length%3Bhost"));
         assert!(upload.href.contains("&X-Amz-Signature="));
         assert!(upload.href.contains("&X-Amz-Date="));
         assert_eq!(upload.expires_in, 3600);
@@ -331,24 +987,48 @@ mod tests {
     #[test]
     fn test_get_success() {
         let (_, storage) = get_random_initialized_storage();
-        let (data, content_type) = aw!(storage.get("repo", "test.txt")).unwrap();
-        assert_eq!(data, b"hello");
+        let (stream, content_type) = aw!(storage.get("repo", "test.txt")).unwrap();
+        assert_eq!(aw!(read_all(stream)), b"hello");
         assert_eq!(content_type, "text/plain");
     }
 
+    #[test]
+    fn test_get_range_success() {
+        let (_, storage) = get_random_initialized_storage();
+        let range = ByteRange { start: 1, end: 3 };
+        let (stream, content_type) = aw!(storage.get_range("repo", "test.txt", range)).unwrap();
+        assert_eq!(aw!(read_all(stream)), b"ell");
+        assert_eq!(content_type, "text/plain");
+    }
+
+    #[test]
+    fn test_get_content_sha256() {
+        let (_, storage) = get_random_initialized_storage();
+        let hash = aw!(storage.get_content_sha256("repo", "test.txt")).unwrap();
+        assert_eq!(
+            hash.unwrap(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
     #[test]
     fn test_get_not_found() {
         let (_, storage) = get_random_initialized_storage();
         let result = aw!(storage.get("repo", "test_not_found.txt"));
-        let error = result.unwrap_err();
+        let error = result.err().unwrap();
         assert!(error.to_string().starts_with("Got HTTP 404 with content"));
     }
 
     #[test]
     fn test_post_success() {
         let (_, storage) = get_random_initialized_storage();
-        let result =
-            aw!(storage.post("repo", "another-test.txt", b"hello2".to_vec(), "text/plain"));
+        let result = aw!(storage.post(
+            "repo",
+            "another-test.txt",
+            reader(b"hello2"),
+            "text/plain",
+            None
+        ));
         assert!(result.is_ok());
 
         let response = aw!(storage
@@ -361,14 +1041,141 @@ mod tests {
         assert_eq!(content_type, "text/plain");
     }
 
+    #[test]
+    fn test_post_multipart_success() {
+        let (_, storage) = get_random_initialized_storage();
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| (i % 251) as u8).collect();
+        let result = aw!(storage.post("repo", "large-test.bin", reader(&data), "text/plain", None));
+        assert!(result.is_ok());
+
+        let (stream, content_type) = aw!(storage.get("repo", "large-test.bin")).unwrap();
+        assert_eq!(aw!(read_all(stream)), data);
+        assert_eq!(content_type, "text/plain");
+    }
+
     #[test]
     fn test_post_wrong_bucket() {
         let (_, credentials, region) = aw!(init_random_bucket());
         let storage =
             get_test_minio_single_bucket_storage(String::from("other-bucket"), credentials, region);
-        let result =
-            aw!(storage.post("repo", "another-test.txt", b"hello2".to_vec(), "text/plain"));
+        let result = aw!(storage.post(
+            "repo",
+            "another-test.txt",
+            reader(b"hello2"),
+            "text/plain",
+            None
+        ));
         let error = result.unwrap_err();
         assert!(error.to_string().starts_with("Got HTTP 404 with content"));
     }
+
+    #[test]
+    fn test_chunked_upload() {
+        let (_, storage) = get_random_initialized_storage();
+        let oid = "chunked";
+
+        // Chunks are received in any order, and can be sent again
+        aw!(storage.put_chunk("repo", oid, 1, reader(b"lo4"))).unwrap();
+        aw!(storage.put_chunk("repo", oid, 0, reader(b"h"))).unwrap();
+        assert_eq!(
+            aw!(storage.put_chunk("repo", oid, 0, reader(b"hel"))).unwrap(),
+            3
+        );
+        assert_eq!(
+            aw!(storage.get_chunk_sizes("repo", oid)).unwrap(),
+            BTreeMap::from([(0, 3), (1, 3)])
+        );
+        let content = aw!(storage.get_chunks("repo", oid, 2)).unwrap();
+        assert_eq!(aw!(read_all(content)), b"hello4");
+        assert!(!aw!(storage.get_meta_result("repo", oid)).exists);
+
+        aw!(storage.discard_chunks("repo", oid)).unwrap();
+        assert!(aw!(storage.get_chunk_sizes("repo", oid))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_split_in_parts() {
+        let mib = 1024 * 1024;
+        assert_eq!(MinioSingleBucketStorage::split_in_parts(0), vec![(0, 0)]);
+        assert_eq!(MinioSingleBucketStorage::split_in_parts(10), vec![(0, 10)]);
+        assert_eq!(
+            MinioSingleBucketStorage::split_in_parts(130 * mib),
+            vec![(0, 64 * mib), (64 * mib, 64 * mib), (128 * mib, 2 * mib)]
+        );
+
+        // Larger objects get larger parts rather than more of them
+        let parts = MinioSingleBucketStorage::split_in_parts(200_000 * mib);
+        assert_eq!(parts.len(), 1000);
+        assert_eq!(parts[0], (0, 200 * mib));
+        assert_eq!(
+            parts.iter().map(|(_, size)| size).sum::<u64>(),
+            200_000 * mib
+        );
+    }
+
+    fn get_multipart_storage() -> MinioSingleBucketStorage {
+        let (bucket_name, credentials, region) = aw!(init_random_bucket());
+        MinioSingleBucketStorage::new(bucket_name, credentials, region, None)
+    }
+
+    async fn put_part(link: &str, data: Vec<u8>) {
+        let response = reqwest::Client::new()
+            .put(link)
+            .body(data)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+    }
+
+    #[test]
+    fn test_multipart_upload() {
+        let storage = get_multipart_storage();
+        let upload = aw!(storage.create_multipart_upload("repo", "multipart", 6)).unwrap();
+        assert_eq!(upload.parts.len(), 1);
+        let part = &upload.parts[0];
+        assert!(part.action.href.contains("&partNumber=1&uploadId="));
+        assert_eq!((part.pos, part.size), (0, 6));
+
+        aw!(put_part(&part.action.href, b"hello3".to_vec()));
+        let uploaded = aw!(storage.get_uploaded_size("repo", "multipart", &upload.upload_id));
+        assert_eq!(uploaded.unwrap(), 6);
+        aw!(storage.complete_multipart_upload("repo", "multipart", &upload.upload_id)).unwrap();
+
+        let response = aw!(storage
+            .bucket_direct_access
+            .get_object("/repo/objects/multipart"))
+        .unwrap();
+        assert_eq!(response.to_vec(), b"hello3");
+    }
+
+    #[test]
+    fn test_open_multipart_upload_is_continued() {
+        let storage = get_multipart_storage();
+        let upload = aw!(storage.create_multipart_upload("repo", "continued", 6)).unwrap();
+        aw!(put_part(&upload.parts[0].action.href, b"hel".to_vec()));
+        let other = aw!(storage.create_multipart_upload("repo", "continued2", 6)).unwrap();
+        assert_ne!(other.upload_id, upload.upload_id);
+
+        let retried = aw!(storage.create_multipart_upload("repo", "continued", 6)).unwrap();
+        assert_eq!(retried.upload_id, upload.upload_id);
+        aw!(put_part(&retried.parts[0].action.href, b"hello3".to_vec()));
+        aw!(storage.complete_multipart_upload("repo", "continued", &retried.upload_id)).unwrap();
+        assert_eq!(aw!(storage.get_meta_result("repo", "continued")).size, 6);
+    }
+
+    #[test]
+    fn test_abort_multipart_upload() {
+        let storage = get_multipart_storage();
+        let upload = aw!(storage.create_multipart_upload("repo", "aborted", 6)).unwrap();
+        aw!(put_part(&upload.parts[0].action.href, b"hel".to_vec()));
+        let uploaded = aw!(storage.get_uploaded_size("repo", "aborted", &upload.upload_id));
+        assert_eq!(uploaded.unwrap(), 3);
+
+        aw!(storage.abort_multipart_upload("repo", "aborted", &upload.upload_id)).unwrap();
+        assert!(aw!(storage.get_uploaded_size("repo", "aborted", &upload.upload_id)).is_err());
+        assert!(!aw!(storage.get_meta_result("repo", "aborted")).exists);
+    }
 }
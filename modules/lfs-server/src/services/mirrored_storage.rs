@@ -0,0 +1,408 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::{future::join_all, StreamExt};
+use tokio::sync::mpsc;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::{
+    api::range::ByteRange,
+    traits::file_storage::{
+        FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy, ObjectReader,
+        ObjectStream,
+    },
+};
+
+pub struct MirroredStorageConfig {
+    pub write_quorum: usize,
+}
+
+/**
+ * One of the storages of a mirrored storage.
+ */
+pub struct MirrorBackend {
+    pub meta: Arc<dyn FileStorageMetaRequester>,
+    pub proxy: Arc<dyn FileStorageProxy>,
+}
+
+/**
+ * Storage writing every object to several storages, the first one being the primary. An upload
+ * is streamed to all of them at once, and succeeds when at least `write_quorum` of them stored
+ * the object.
+ *
+ * An object only exists when at least `write_quorum` storages hold it: the copies left by an
+ * upload failing below the quorum are not reported, so that the object is uploaded again. Reads
+ * are served by the primary, and fall back to the next storages when it is missing the object,
+ * for instance when its write failed but the quorum was reached.
+ */
+pub struct MirroredStorage {
+    backends: Vec<MirrorBackend>,
+    write_quorum: usize,
+}
+
+// Number of chunks buffered for each storage, the upload goes at the pace of the slowest one
+const MIRROR_BUFFER_SIZE: usize = 16;
+
+impl MirroredStorage {
+    pub fn new(backends: Vec<MirrorBackend>, write_quorum: usize) -> MirroredStorage {
+        if write_quorum == 0 || write_quorum > backends.len() {
+            panic!(
+                "Invalid write quorum: {}, expected between 1 and {}",
+                write_quorum,
+                backends.len()
+            );
+        }
+        MirroredStorage {
+            backends,
+            write_quorum,
+        }
+    }
+
+    pub fn from_config(
+        config: MirroredStorageConfig,
+        backends: Vec<MirrorBackend>,
+    ) -> MirroredStorage {
+        MirroredStorage::new(backends, config.write_quorum)
+    }
+
+    /**
+     * Stream the data to each storage. A storage failing does not stop the others, while an
+     * error reading the data is forwarded to all of them, so that none of them keeps the object.
+     */
+    async fn feed(
+        data: ObjectReader,
+        mut senders: Vec<Option<mpsc::Sender<std::io::Result<Bytes>>>>,
+    ) {
+        let mut chunks = ReaderStream::new(data);
+        while let Some(chunk) = chunks.next().await {
+            let failed = chunk.is_err();
+            for sender in senders.iter_mut() {
+                let Some(tx) = sender else {
+                    continue;
+                };
+                let message = match &chunk {
+                    Ok(bytes) => Ok(bytes.clone()),
+                    Err(e) => Err(std::io::Error::new(e.kind(), e.to_string())),
+                };
+                if tx.send(message).await.is_err() {
+                    *sender = None;
+                }
+            }
+            if failed {
+                return;
+            }
+        }
+    }
+
+    fn receive(rx: mpsc::Receiver<std::io::Result<Bytes>>) -> ObjectReader {
+        let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (chunk, rx))
+        });
+        Box::pin(StreamReader::new(stream))
+    }
+
+    /**
+     * Result of the first storage holding the object, if enough of them hold it.
+     */
+    fn quorum_result<'a>(
+        &self,
+        repo: &'a str,
+        oid: &'a str,
+        results: Vec<FileStorageMetaResult<'a>>,
+    ) -> FileStorageMetaResult<'a> {
+        let holders = results.iter().filter(|result| result.exists).count();
+        match results.into_iter().find(|result| result.exists) {
+            Some(result) if holders >= self.write_quorum => result,
+            _ => FileStorageMetaResult::not_found(repo, oid),
+        }
+    }
+}
+
+#[async_trait]
+impl FileStorageMetaRequester for MirroredStorage {
+    async fn get_meta_result<'a>(&self, repo: &'a str, oid: &'a str) -> FileStorageMetaResult<'a> {
+        let lookups = self
+            .backends
+            .iter()
+            .map(|backend| backend.meta.get_meta_result(repo, oid));
+        let results = join_all(lookups).await;
+        self.quorum_result(repo, oid, results)
+    }
+
+    async fn get_meta_results<'a>(
+        &self,
+        repo: &'a str,
+        oids: &[&'a str],
+    ) -> Vec<FileStorageMetaResult<'a>> {
+        let lookups = self
+            .backends
+            .iter()
+            .map(|backend| backend.meta.get_meta_results(repo, oids));
+        let mut results: Vec<_> = join_all(lookups)
+            .await
+            .into_iter()
+            .map(|results| results.into_iter())
+            .collect();
+        oids.iter()
+            .map(|oid| {
+                let oid_results = results.iter_mut().filter_map(|r| r.next()).collect();
+                self.quorum_result(repo, oid, oid_results)
+            })
+            .collect()
+    }
+
+    async fn get_content_sha256(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        for backend in &self.backends {
+            if backend.meta.get_meta_result(repo, oid).await.exists {
+                return backend.meta.get_content_sha256(repo, oid).await;
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl FileStorageProxy for MirroredStorage {
+    async fn get(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let mut error = String::from("No storage");
+        for backend in &self.backends {
+            match backend.proxy.get(repo, oid).await {
+                Ok(object) => return Ok(object),
+                Err(e) => error = e.to_string(),
+            }
+        }
+        Err(Box::new(std::io::Error::other(error)))
+    }
+
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let mut error = String::from("No storage");
+        for backend in &self.backends {
+            match backend.proxy.get_range(repo, oid, range).await {
+                Ok(object) => return Ok(object),
+                Err(e) => error = e.to_string(),
+            }
+        }
+        Err(Box::new(std::io::Error::other(error)))
+    }
+
+    async fn post(
+        &self,
+        repo: &str,
+        oid: &str,
+        data: ObjectReader,
+        content_type: &str,
+        uploader: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut senders = Vec::new();
+        let mut uploads = Vec::new();
+        for backend in &self.backends {
+            let (tx, rx) = mpsc::channel(MIRROR_BUFFER_SIZE);
+            senders.push(Some(tx));
+            let upload = backend
+                .proxy
+                .post(repo, oid, Self::receive(rx), content_type, uploader);
+            // Errors are not Send, they must not be kept while other uploads are in progress
+            uploads.push(async move { upload.await.map_err(|e| e.to_string()) });
+        }
+
+        let (_, results) = tokio::join!(Self::feed(data, senders), join_all(uploads));
+        let mut stored = 0;
+        for (index, result) in results.iter().enumerate() {
+            match result {
+                Ok(_) => stored += 1,
+                Err(e) => tracing::warn!("Mirror {} failed to store {}: {}", index, oid, e),
+            }
+        }
+        if stored < self.write_quorum {
+            return Err(Box::new(std::io::Error::other(format!(
+                "Object stored by {} storages out of the {} required",
+                stored, self.write_quorum
+            ))));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fs::local_file_storage::LocalFileStorage;
+    use crate::{
+        aw,
+        test_utils::helpers::{read_all, reader},
+    };
+
+    /**
+     * Storage rejecting every upload, as an unreachable storage would.
+     */
+    struct FailingStorage;
+
+    #[async_trait]
+    impl FileStorageProxy for FailingStorage {
+        async fn get(
+            &self,
+            _repo: &str,
+            _oid: &str,
+        ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+            Err(Box::new(std::io::Error::other("unreachable")))
+        }
+
+        async fn get_range(
+            &self,
+            _repo: &str,
+            _oid: &str,
+            _range: ByteRange,
+        ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+            Err(Box::new(std::io::Error::other("unreachable")))
+        }
+
+        async fn post(
+            &self,
+            _repo: &str,
+            _oid: &str,
+            _data: ObjectReader,
+            _content_type: &str,
+            _uploader: Option<&str>,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            Err(Box::new(std::io::Error::other("unreachable")))
+        }
+    }
+
+    fn local_backend() -> (Arc<LocalFileStorage>, MirrorBackend) {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let files = Arc::new(LocalFileStorage::new(format!("/tmp/{}", random_dir)));
+        let backend = MirrorBackend {
+            meta: files.clone(),
+            proxy: files.clone(),
+        };
+        (files, backend)
+    }
+
+    fn failing_backend() -> MirrorBackend {
+        let (files, _) = local_backend();
+        MirrorBackend {
+            meta: files,
+            proxy: Arc::new(FailingStorage),
+        }
+    }
+
+    #[test]
+    fn test_post_writes_all_storages() {
+        let (primary, primary_backend) = local_backend();
+        let (secondary, secondary_backend) = local_backend();
+        let storage = MirroredStorage::new(vec![primary_backend, secondary_backend], 2);
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+
+        aw!(storage.post("repo", "oid", reader(data.clone()), "image/png", None)).unwrap();
+
+        for files in [primary, secondary] {
+            let (stream, content_type) = aw!(files.get("repo", "oid")).unwrap();
+            assert_eq!(aw!(read_all(stream)), data);
+            assert_eq!(content_type, "image/png");
+        }
+    }
+
+    #[test]
+    fn test_post_fails_below_quorum() {
+        let (primary, primary_backend) = local_backend();
+        let storage = MirroredStorage::new(vec![primary_backend, failing_backend()], 2);
+
+        assert!(
+            aw!(storage.post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)).is_err()
+        );
+        // The primary did store it, but the object is missing until the upload is retried
+        assert!(aw!(primary.get_meta_result("repo", "oid")).exists);
+        assert!(!aw!(storage.get_meta_result("repo", "oid")).exists);
+        let results = aw!(storage.get_meta_results("repo", &["oid"]));
+        assert!(!results[0].exists);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid write quorum: 0, expected between 1 and 2")]
+    fn test_zero_write_quorum() {
+        MirroredStorage::new(vec![local_backend().1, local_backend().1], 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid write quorum: 3, expected between 1 and 2")]
+    fn test_write_quorum_above_storages() {
+        MirroredStorage::new(vec![local_backend().1, local_backend().1], 3);
+    }
+
+    #[test]
+    fn test_post_succeeds_with_quorum() {
+        let storage = MirroredStorage::new(vec![failing_backend(), local_backend().1], 1);
+        aw!(storage.post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+    }
+
+    #[test]
+    fn test_read_error_is_forwarded_to_all_storages() {
+        let (primary, primary_backend) = local_backend();
+        let (secondary, secondary_backend) = local_backend();
+        let storage = MirroredStorage::new(vec![primary_backend, secondary_backend], 1);
+        let failing: ObjectReader = Box::pin(
+            tokio_test::io::Builder::new()
+                .read(&[1, 2, 3])
+                .read_error(std::io::Error::other("rejected"))
+                .build(),
+        );
+
+        assert!(aw!(storage.post("repo", "oid", failing, "image/png", None)).is_err());
+        assert!(!aw!(primary.get_meta_result("repo", "oid")).exists);
+        assert!(!aw!(secondary.get_meta_result("repo", "oid")).exists);
+    }
+
+    #[test]
+    fn test_get_meta_results_falls_back_to_secondary() {
+        let (primary, primary_backend) = local_backend();
+        let (secondary, secondary_backend) = local_backend();
+        let storage = MirroredStorage::new(vec![primary_backend, secondary_backend], 1);
+        aw!(primary.post("repo", "a", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+        aw!(secondary.post("repo", "b", reader(vec![1]), "image/png", None)).unwrap();
+
+        let results = aw!(storage.get_meta_results("repo", &["c", "b", "a"]));
+        let results: Vec<_> = results
+            .iter()
+            .map(|result| (result.oid, result.exists, result.size))
+            .collect();
+        assert_eq!(
+            results,
+            vec![("c", false, 0), ("b", true, 1), ("a", true, 3)]
+        );
+    }
+
+    #[test]
+    fn test_read_falls_back_to_secondary() {
+        let (_, primary_backend) = local_backend();
+        let (secondary, secondary_backend) = local_backend();
+        let storage = MirroredStorage::new(vec![primary_backend, secondary_backend], 1);
+        aw!(secondary.post("repo", "oid", reader(vec![1, 2, 3]), "image/png", None)).unwrap();
+
+        let result = aw!(storage.get_meta_result("repo", "oid"));
+        assert!(result.exists);
+        assert_eq!(result.size, 3);
+
+        let (stream, _) = aw!(storage.get("repo", "oid")).unwrap();
+        assert_eq!(aw!(read_all(stream)), vec![1, 2, 3]);
+
+        let range = ByteRange { start: 1, end: 1 };
+        let (stream, _) = aw!(storage.get_range("repo", "oid", range)).unwrap();
+        assert_eq!(aw!(read_all(stream)), vec![2]);
+
+        assert!(aw!(storage.get("repo", "missing")).is_err());
+    }
+}
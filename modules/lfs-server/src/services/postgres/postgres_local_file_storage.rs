@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use deadpool_postgres::Pool;
+use futures_util::{StreamExt, TryStreamExt};
+
+use crate::{
+    api::range::ByteRange,
+    services::fs::local_file_storage::{LocalFileStorage, LocalFileStorageConfig},
+    traits::file_storage::{
+        FileStorageLister, FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy,
+        FileStorageQuarantine, ObjectReader, ObjectStream, StoredObject, StoredObjectStream,
+    },
+};
+
+use super::postgres_pool::{create_pool, PostgresPoolConfig};
+
+pub struct PostgresLocalFileStorageConfig {
+    pub files: LocalFileStorageConfig,
+    pub database: PostgresPoolConfig,
+}
+
+/**
+ * Storage of the objects in the local filesystem, with the same layout as the local file
+ * storage, while their metadata is indexed in a postgres database. Looking up an object is a
+ * single query, and never touches the filesystem.
+ *
+ * The index is expected in an `objects` table:
+ *
+ * ```sql
+ * CREATE TABLE objects (
+ *     repo TEXT NOT NULL,
+ *     oid TEXT NOT NULL,
+ *     size BIGINT NOT NULL,
+ *     content_type TEXT NOT NULL,
+ *     uploader TEXT,
+ *     uploaded_at TIMESTAMP NOT NULL DEFAULT NOW(),
+ *     PRIMARY KEY (repo, oid)
+ * );
+ * ```
+ */
+pub struct PostgresLocalFileStorage {
+    files: LocalFileStorage,
+    pool: Pool,
+}
+
+impl PostgresLocalFileStorage {
+    pub fn new(files: LocalFileStorage, pool: Pool) -> PostgresLocalFileStorage {
+        PostgresLocalFileStorage { files, pool }
+    }
+
+    pub fn from_config(config: PostgresLocalFileStorageConfig) -> PostgresLocalFileStorage {
+        PostgresLocalFileStorage::new(
+            LocalFileStorage::from_config(config.files),
+            create_pool(config.database),
+        )
+    }
+
+    async fn get_size(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt(
+                "SELECT size FROM objects WHERE repo = $1 AND oid = $2",
+                &[&repo, &oid],
+            )
+            .await?;
+        Ok(row.map(|row| row.get::<_, i64>("size") as u64))
+    }
+
+    async fn get_sizes(
+        &self,
+        repo: &str,
+        oids: &[&str],
+    ) -> Result<HashMap<String, u64>, Box<dyn std::error::Error>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT oid, size FROM objects WHERE repo = $1 AND oid = ANY($2)",
+                &[&repo, &oids],
+            )
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get("oid"), row.get::<_, i64>("size") as u64))
+            .collect())
+    }
+
+    async fn get_content_type(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt(
+                "SELECT content_type FROM objects WHERE repo = $1 AND oid = $2",
+                &[&repo, &oid],
+            )
+            .await?;
+        match row {
+            Some(row) => Ok(row.get("content_type")),
+            None => Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Object not indexed",
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl FileStorageMetaRequester for PostgresLocalFileStorage {
+    async fn get_meta_result<'a>(&self, repo: &'a str, oid: &'a str) -> FileStorageMetaResult<'a> {
+        match self.get_size(repo, oid).await {
+            Ok(size) => self.match_size(size, repo, oid),
+            Err(e) => {
+                tracing::error!("Failed to look up object {} in the index: {}", oid, e);
+                FileStorageMetaResult::not_found(repo, oid)
+            }
+        }
+    }
+
+    async fn get_meta_results<'a>(
+        &self,
+        repo: &'a str,
+        oids: &[&'a str],
+    ) -> Vec<FileStorageMetaResult<'a>> {
+        let sizes = self.get_sizes(repo, oids).await.unwrap_or_else(|e| {
+            tracing::error!(
+                "Failed to look up {} objects in the index: {}",
+                oids.len(),
+                e
+            );
+            HashMap::new()
+        });
+        oids.iter()
+            .map(|oid| self.match_size(sizes.get(*oid).copied(), repo, oid))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl FileStorageProxy for PostgresLocalFileStorage {
+    async fn get(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        // Only indexed objects are served
+        let content_type = self.get_content_type(repo, oid).await?;
+        let stream = self.files.open_object(repo, oid, None).await?;
+        Ok((stream, content_type))
+    }
+
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        let content_type = self.get_content_type(repo, oid).await?;
+        let stream = self.files.open_object(repo, oid, Some(range)).await?;
+        Ok((stream, content_type))
+    }
+
+    async fn post(
+        &self,
+        repo: &str,
+        oid: &str,
+        mut data: ObjectReader,
+        content_type: &str,
+        uploader: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (temporary_path, size) = self.files.receive_object(repo, oid, &mut data).await?;
+        self.files.commit_object(&temporary_path, repo, oid).await?;
+
+        // The object is indexed once it is in place. Objects are immutable, so the first upload
+        // is the one kept in the index
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO objects (repo, oid, size, content_type, uploader) \
+                VALUES ($1, $2, $3, $4, $5) ON CONFLICT (repo, oid) DO NOTHING",
+                &[&repo, &oid, &(size as i64), &content_type, &uploader],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn post_if_absent(
+        &self,
+        repo: &str,
+        oid: &str,
+        mut data: ObjectReader,
+        content_type: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let (temporary_path, size) = self.files.receive_object(repo, oid, &mut data).await?;
+        if !self
+            .files
+            .commit_new_object(&temporary_path, repo, oid)
+            .await?
+        {
+            return Ok(false);
+        }
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO objects (repo, oid, size, content_type) \
+                VALUES ($1, $2, $3, $4) ON CONFLICT (repo, oid) DO NOTHING",
+                &[&repo, &oid, &(size as i64), &content_type],
+            )
+            .await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl FileStorageLister for PostgresLocalFileStorage {
+    fn list_objects(&self) -> StoredObjectStream<'_> {
+        // Only indexed objects are listed, as they are the only ones served. The rows are read
+        // as they are streamed, the client being held until the last one
+        let rows = async move {
+            let client = self.pool.get().await.map_err(std::io::Error::other)?;
+            let rows = client
+                .query_raw("SELECT repo, oid FROM objects", std::iter::empty::<&str>())
+                .await
+                .map_err(std::io::Error::other)?;
+            Ok::<_, std::io::Error>(rows.map(move |row| {
+                let _ = &client;
+                let row = row.map_err(std::io::Error::other)?;
+                Ok(StoredObject {
+                    repo: row.get("repo"),
+                    oid: row.get("oid"),
+                })
+            }))
+        };
+        Box::pin(futures_util::stream::once(rows).try_flatten())
+    }
+}
+
+#[async_trait]
+impl FileStorageQuarantine for PostgresLocalFileStorage {
+    async fn quarantine(&self, repo: &str, oid: &str) -> Result<(), Box<dyn std::error::Error>> {
+        // The object is no longer served as soon as it is out of the index
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "DELETE FROM objects WHERE repo = $1 AND oid = $2",
+                &[&repo, &oid],
+            )
+            .await?;
+        self.files.quarantine(repo, oid).await
+    }
+
+    async fn is_quarantined(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        self.files.is_quarantined(repo, oid).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::helpers::{read_all, reader};
+    use tokio_postgres::NoTls;
+
+    async fn connect(dbname: Option<&str>) -> tokio_postgres::Client {
+        let mut params = String::from("host=localhost user=postgres password=1");
+        if let Some(dbname) = dbname {
+            params.push_str(&format!(" dbname={}", dbname));
+        }
+        let (client, connection) = tokio_postgres::connect(&params, NoTls).await.unwrap();
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                eprintln!("connection error: {}", e);
+            }
+        });
+        client
+    }
+
+    async fn init_test_storage() -> (String, PostgresLocalFileStorage) {
+        let dbname = format!("lsdb_{}", uuid::Uuid::new_v4().simple());
+
+        // 1) create database
+        connect(None)
+            .await
+            .execute(&format!("CREATE DATABASE {};", dbname), &[])
+            .await
+            .unwrap();
+
+        // 2) create table
+        connect(Some(&dbname))
+            .await
+            .execute(
+                "CREATE TABLE objects (
+                    repo TEXT NOT NULL,
+                    oid TEXT NOT NULL,
+                    size BIGINT NOT NULL,
+                    content_type TEXT NOT NULL,
+                    uploader TEXT,
+                    uploaded_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                    PRIMARY KEY (repo, oid)
+                )",
+                &[],
+            )
+            .await
+            .unwrap();
+
+        let storage = PostgresLocalFileStorage::from_config(PostgresLocalFileStorageConfig {
+            files: LocalFileStorageConfig {
+                root_path: format!("/tmp/{}", dbname),
+                shard_depth: 2,
+            },
+            database: PostgresPoolConfig {
+                host: String::from("localhost"),
+                dbname: dbname.clone(),
+                username: String::from("postgres"),
+                password: String::from("1"),
+            },
+        });
+        (dbname, storage)
+    }
+
+    async fn cleanup(dbname: String, storage: PostgresLocalFileStorage) {
+        // The pool holds connections to the database
+        drop(storage);
+        connect(None)
+            .await
+            .execute(&format!("DROP DATABASE {} WITH (FORCE);", dbname), &[])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lsdb_get_meta_result_not_found() {
+        let (dbname, storage) = init_test_storage().await;
+
+        let result = storage.get_meta_result("repo", "oid").await;
+        assert!(!result.exists);
+        assert!(storage.get("repo", "oid").await.is_err());
+
+        cleanup(dbname, storage).await;
+    }
+
+    #[tokio::test]
+    async fn test_lsdb_post_and_retrieve() {
+        let (dbname, storage) = init_test_storage().await;
+
+        storage
+            .post(
+                "a/b",
+                "oid",
+                reader(vec![1, 2, 3, 4]),
+                "image/png",
+                Some("user"),
+            )
+            .await
+            .unwrap();
+
+        let result = storage.get_meta_result("a/b", "oid").await;
+        assert!(result.exists);
+        assert_eq!(result.size, 4);
+        assert!(!storage.get_meta_result("a/c", "oid").await.exists);
+
+        let (stream, content_type) = storage.get("a/b", "oid").await.unwrap();
+        assert_eq!(content_type, "image/png");
+        assert_eq!(read_all(stream).await, vec![1, 2, 3, 4]);
+
+        let range = ByteRange { start: 1, end: 2 };
+        let (stream, _) = storage.get_range("a/b", "oid", range).await.unwrap();
+        assert_eq!(read_all(stream).await, vec![2, 3]);
+
+        let row = connect(Some(&dbname))
+            .await
+            .query_one("SELECT uploader FROM objects WHERE oid = 'oid'", &[])
+            .await
+            .unwrap();
+        assert_eq!(row.get::<_, Option<String>>("uploader").unwrap(), "user");
+
+        cleanup(dbname, storage).await;
+    }
+
+    #[tokio::test]
+    async fn test_lsdb_get_meta_results() {
+        let (dbname, storage) = init_test_storage().await;
+
+        storage
+            .post("repo", "a", reader(vec![1, 2, 3]), "image/png", None)
+            .await
+            .unwrap();
+        storage
+            .post("repo", "c", reader(vec![1]), "image/png", None)
+            .await
+            .unwrap();
+
+        let results = storage.get_meta_results("repo", &["c", "b", "a"]).await;
+        let results: Vec<_> = results
+            .iter()
+            .map(|result| (result.oid, result.exists, result.size))
+            .collect();
+        assert_eq!(
+            results,
+            vec![("c", true, 1), ("b", false, 0), ("a", true, 3)]
+        );
+
+        cleanup(dbname, storage).await;
+    }
+
+    #[tokio::test]
+    async fn test_lsdb_post_failure_is_not_indexed() {
+        let (dbname, storage) = init_test_storage().await;
+
+        let failing: ObjectReader = Box::pin(
+            tokio_test::io::Builder::new()
+                .read(&[1, 2, 3])
+                .read_error(std::io::Error::other("rejected"))
+                .build(),
+        );
+        assert!(storage
+            .post("repo", "oid", failing, "text/plain", None)
+            .await
+            .is_err());
+        assert!(!storage.get_meta_result("repo", "oid").await.exists);
+
+        cleanup(dbname, storage).await;
+    }
+
+    #[tokio::test]
+    async fn test_lsdb_list_objects() {
+        let (dbname, storage) = init_test_storage().await;
+        for repo in ["a/b", "c"] {
+            storage
+                .post(repo, "oid", reader(vec![1]), "image/png", None)
+                .await
+                .unwrap();
+        }
+
+        let mut objects: Vec<_> = storage.list_objects().try_collect().await.unwrap();
+        objects.sort_by(|a, b| a.repo.cmp(&b.repo));
+        let repos: Vec<_> = objects.iter().map(|object| object.repo.as_str()).collect();
+        assert_eq!(repos, vec!["a/b", "c"]);
+
+        cleanup(dbname, storage).await;
+    }
+}
@@ -16,11 +16,11 @@ impl Lock {
     }
 
     pub fn from_row(row: &Row) -> Result<Lock, LocksProviderError> {
-        let id: i32 = Self::try_get_from_row(&row, 0)?;
-        let path: String = Self::try_get_from_row(&row, 1)?;
-        let ref_name: String = Self::try_get_from_row(&row, 2)?;
-        let owner: String = Self::try_get_from_row(&row, 3)?;
-        let locked_at: SystemTime = Self::try_get_from_row(&row, 4)?;
+        let id: i32 = Self::try_get_from_row(row, 0)?;
+        let path: String = Self::try_get_from_row(row, 1)?;
+        let ref_name: String = Self::try_get_from_row(row, 2)?;
+        let owner: String = Self::try_get_from_row(row, 3)?;
+        let locked_at: SystemTime = Self::try_get_from_row(row, 4)?;
         Ok(Lock {
             id: id.to_string(),
             path,
@@ -1,43 +1,28 @@
 use std::vec;
 
 use async_trait::async_trait;
-use deadpool_postgres::{
-    Config, GenericClient, ManagerConfig, Object, Pool, RecyclingMethod, Runtime,
-};
+use deadpool_postgres::{GenericClient, Object, Pool};
 use futures_util::{pin_mut, TryStreamExt};
-use tokio_postgres::{types::ToSql, NoTls, Row, RowStream};
+use tokio_postgres::{types::ToSql, Row, RowStream};
 
 use crate::traits::locks::{Lock, LocksProvider, LocksProviderError};
 
-use super::sql_query_builder::SqlQueryBuilder;
+use super::{
+    postgres_pool::{create_pool, PostgresPoolConfig},
+    sql_query_builder::SqlQueryBuilder,
+};
 
 pub struct PostgresLocksProvider {
     pool: Pool,
 }
 
-pub struct PostgresLocksProviderConfig {
-    pub host: String,
-    pub dbname: String,
-    pub username: String,
-    pub password: String,
-}
+pub type PostgresLocksProviderConfig = PostgresPoolConfig;
 
 impl PostgresLocksProvider {
     pub fn from_config(config: PostgresLocksProviderConfig) -> Self {
-        let mut cfg = Config::new();
-        cfg.host = Some(config.host);
-        cfg.dbname = Some(config.dbname);
-        cfg.password = Some(config.password);
-        cfg.user = Some(config.username);
-
-        cfg.manager = Some(ManagerConfig {
-            recycling_method: RecyclingMethod::Fast,
-        });
-
-        tracing::info!("Creating pool to postgres database {:?}", &cfg.dbname);
-        let pool = cfg.create_pool(Some(Runtime::Tokio1), NoTls).unwrap();
-
-        Self { pool }
+        Self {
+            pool: create_pool(config),
+        }
     }
 
     async fn get_client(&self) -> Result<Object, LocksProviderError> {
@@ -241,6 +226,7 @@ mod tests {
 
     // use crate::traits::locks;
     use rand::{self, Rng};
+    use tokio_postgres::NoTls;
 
     use super::*;
 
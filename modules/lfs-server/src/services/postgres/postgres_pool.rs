@@ -0,0 +1,28 @@
+use deadpool_postgres::{Config, ManagerConfig, Pool, RecyclingMethod, Runtime};
+use tokio_postgres::NoTls;
+
+pub struct PostgresPoolConfig {
+    pub host: String,
+    pub dbname: String,
+    pub username: String,
+    pub password: String,
+}
+
+/**
+ * Create a pool of connections to a postgres database. Connections are opened lazily, on first
+ * use.
+ */
+pub fn create_pool(config: PostgresPoolConfig) -> Pool {
+    let mut cfg = Config::new();
+    cfg.host = Some(config.host);
+    cfg.dbname = Some(config.dbname);
+    cfg.password = Some(config.password);
+    cfg.user = Some(config.username);
+
+    cfg.manager = Some(ManagerConfig {
+        recycling_method: RecyclingMethod::Fast,
+    });
+
+    tracing::info!("Creating pool to postgres database {:?}", &cfg.dbname);
+    cfg.create_pool(Some(Runtime::Tokio1), NoTls).unwrap()
+}
@@ -0,0 +1,355 @@
+use std::{future::ready, sync::Arc};
+
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::traits::file_storage::{
+    FileStorageLister, FileStorageMetaRequester, FileStorageProxy, FileStorageQuarantine,
+    StoredObject,
+};
+
+// SHA-256 of the empty content, the only valid oid for an empty object
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/**
+ * Storage to scrub. The objects are listed and quarantined as they are stored, while they are
+ * read through the layers changing their content, such as the compression, to be hashed.
+ */
+pub struct ScrubStorage {
+    pub meta: Arc<dyn FileStorageMetaRequester>,
+    pub proxy: Arc<dyn FileStorageProxy>,
+    pub lister: Arc<dyn FileStorageLister>,
+    pub quarantine: Arc<dyn FileStorageQuarantine>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrubProblem {
+    // The content does not match the oid
+    Corrupt,
+    // The object is empty while its oid is not the one of the empty content
+    ZeroLength,
+    // The object could not be read
+    Unreadable,
+    // A content type is kept for an object which is not stored
+    OrphanedContentType,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScrubFinding {
+    pub repo: String,
+    pub oid: String,
+    pub problem: ScrubProblem,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub quarantined: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ScrubReport {
+    pub checked: u64,
+    pub findings: Vec<ScrubFinding>,
+}
+
+/**
+ * Check of the stored objects against their oid, reading and hashing each of them. Corrupt and
+ * empty objects can be quarantined, so that they are reported as missing to the clients, which
+ * upload them again. Unreadable objects are only reported, the failure may not last.
+ *
+ * Entries which are not named after a SHA-256, such as the data keys of the encryption, are not
+ * objects and are left out.
+ */
+pub struct Scrubber {
+    storage: ScrubStorage,
+    concurrency: usize,
+    quarantine: bool,
+    // With the content addressed layout, the objects of the repositories are empty references
+    global_repo: Option<String>,
+}
+
+impl Scrubber {
+    pub fn new(
+        storage: ScrubStorage,
+        concurrency: usize,
+        quarantine: bool,
+        global_repo: Option<String>,
+    ) -> Scrubber {
+        Scrubber {
+            storage,
+            concurrency,
+            quarantine,
+            global_repo,
+        }
+    }
+
+    /**
+     * Scrub the objects of a repository, or of all of them.
+     */
+    pub async fn run(&self, repo: Option<&str>) -> Result<ScrubReport, Box<dyn std::error::Error>> {
+        let in_scope = |object: &StoredObject| repo.is_none_or(|repo| object.repo == repo);
+        let orphans = self.storage.lister.list_orphaned_content_types().await?;
+
+        // The objects are checked as they are listed
+        let mut report = ScrubReport::default();
+        let mut checks = self
+            .storage
+            .lister
+            .list_objects()
+            .try_filter(|object| ready(in_scope(object) && is_sha256(&object.oid)))
+            .map_ok(|object| async move {
+                let problem = self.check(&object).await;
+                Ok((object, problem))
+            })
+            .try_buffer_unordered(self.concurrency);
+        while let Some((object, problem)) = checks.try_next().await? {
+            report.checked += 1;
+            if let Some((problem, detail)) = problem {
+                let finding = self.handle(object, problem, detail).await;
+                report.findings.push(finding);
+            }
+        }
+
+        for orphan in orphans.into_iter().filter(in_scope) {
+            report.findings.push(ScrubFinding {
+                repo: orphan.repo,
+                oid: orphan.oid,
+                problem: ScrubProblem::OrphanedContentType,
+                detail: None,
+                quarantined: false,
+            });
+        }
+        Ok(report)
+    }
+
+    async fn check(&self, object: &StoredObject) -> Option<(ScrubProblem, Option<String>)> {
+        let (repo, oid) = (object.repo.as_str(), object.oid.as_str());
+        let oid = oid.to_lowercase();
+        let result = self.storage.meta.get_meta_result(repo, &oid).await;
+        // The layers report a failed lookup as a missing object, while it was just listed
+        if !result.exists {
+            let detail = String::from("The object could not be looked up");
+            return Some((ScrubProblem::Unreadable, Some(detail)));
+        }
+        if result.size == 0 {
+            let is_reference = self
+                .global_repo
+                .as_ref()
+                .is_some_and(|global| global != repo);
+            if is_reference || oid == EMPTY_SHA256 {
+                return None;
+            }
+            return Some((ScrubProblem::ZeroLength, None));
+        }
+
+        match self.hash(repo, &oid).await {
+            Ok(sha256) if sha256 == oid => None,
+            Ok(sha256) => Some((
+                ScrubProblem::Corrupt,
+                Some(format!("Content hash {}", sha256)),
+            )),
+            Err(e) => Some((ScrubProblem::Unreadable, Some(e))),
+        }
+    }
+
+    async fn hash(&self, repo: &str, oid: &str) -> Result<String, String> {
+        let (mut stream, _) = self
+            .storage
+            .proxy
+            .get(repo, oid)
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = stream.try_next().await.map_err(|e| e.to_string())? {
+            hasher.update(&chunk);
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    async fn handle(
+        &self,
+        object: StoredObject,
+        problem: ScrubProblem,
+        detail: Option<String>,
+    ) -> ScrubFinding {
+        let quarantined = self.quarantine
+            && problem != ScrubProblem::Unreadable
+            && match self
+                .storage
+                .quarantine
+                .quarantine(&object.repo, &object.oid)
+                .await
+            {
+                Ok(_) => true,
+                Err(e) => {
+                    tracing::error!("Failed to quarantine {}: {}", object.oid, e);
+                    false
+                }
+            };
+        ScrubFinding {
+            repo: object.repo,
+            oid: object.oid,
+            problem,
+            detail,
+            quarantined,
+        }
+    }
+}
+
+fn is_sha256(oid: &str) -> bool {
+    oid.len() == 64 && oid.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fs::local_file_storage::LocalFileStorage;
+
+    fn get_scrubber(quarantine: bool) -> (Arc<LocalFileStorage>, Scrubber) {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let files = Arc::new(LocalFileStorage::new(format!("/tmp/{}", random_dir)));
+        let storage = ScrubStorage {
+            meta: files.clone(),
+            proxy: files.clone(),
+            lister: files.clone(),
+            quarantine: files.clone(),
+        };
+        (files, Scrubber::new(storage, 2, quarantine, None))
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    async fn post(files: &LocalFileStorage, repo: &str, oid: &str, data: Vec<u8>) {
+        files
+            .post(
+                repo,
+                oid,
+                Box::pin(std::io::Cursor::new(data)),
+                "image/png",
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scrub_finds_problems() {
+        let (files, scrubber) = get_scrubber(false);
+        let valid = sha256(&[1, 2, 3]);
+        let corrupt = sha256(&[4, 5, 6]);
+        let truncated = sha256(&[7, 8, 9]);
+        post(&files, "repo", &valid, vec![1, 2, 3]).await;
+        post(&files, "repo", &corrupt, vec![4, 5, 7]).await;
+        post(&files, "repo", &truncated, vec![]).await;
+        post(&files, "repo", EMPTY_SHA256, vec![]).await;
+        post(&files, "repo", "lfs-data-key", vec![1]).await;
+        std::fs::remove_file(files.get_object_path("repo", &valid)).unwrap();
+
+        let report = scrubber.run(None).await.unwrap();
+        assert_eq!(report.checked, 3);
+        let mut problems: Vec<_> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.oid.as_str(), finding.problem))
+            .collect();
+        problems.sort();
+        let mut expected = vec![
+            (corrupt.as_str(), ScrubProblem::Corrupt),
+            (truncated.as_str(), ScrubProblem::ZeroLength),
+            (valid.as_str(), ScrubProblem::OrphanedContentType),
+        ];
+        expected.sort();
+        assert_eq!(problems, expected);
+        assert!(report.findings.iter().all(|finding| !finding.quarantined));
+        assert!(files.get_meta_result("repo", &corrupt).await.exists);
+    }
+
+    #[tokio::test]
+    async fn test_scrub_quarantines_bad_objects() {
+        let (files, scrubber) = get_scrubber(true);
+        let valid = sha256(&[1, 2, 3]);
+        let corrupt = sha256(&[4, 5, 6]);
+        post(&files, "repo", &valid, vec![1, 2, 3]).await;
+        post(&files, "repo", &corrupt, vec![4, 5, 7]).await;
+
+        let report = scrubber.run(None).await.unwrap();
+        assert_eq!(report.findings.len(), 1);
+        assert!(report.findings[0].quarantined);
+        assert!(!files.get_meta_result("repo", &corrupt).await.exists);
+        assert!(files.get_meta_result("repo", &valid).await.exists);
+
+        // The object can be uploaded again
+        post(&files, "repo", &corrupt, vec![4, 5, 6]).await;
+        assert!(scrubber.run(None).await.unwrap().findings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scrub_one_repo() {
+        let (files, scrubber) = get_scrubber(false);
+        let oid = sha256(&[1, 2, 3]);
+        post(&files, "a", &oid, vec![1, 2, 3]).await;
+        post(&files, "b", &oid, vec![0]).await;
+
+        let report = scrubber.run(Some("a")).await.unwrap();
+        assert_eq!(report.checked, 1);
+        assert!(report.findings.is_empty());
+        let report = scrubber.run(Some("b")).await.unwrap();
+        assert_eq!(report.findings[0].problem, ScrubProblem::Corrupt);
+    }
+
+    /**
+     * Storage failing to look up every object.
+     */
+    struct FailingMeta;
+
+    #[async_trait::async_trait]
+    impl FileStorageMetaRequester for FailingMeta {
+        async fn get_meta_result<'a>(
+            &self,
+            repo: &'a str,
+            oid: &'a str,
+        ) -> crate::traits::file_storage::FileStorageMetaResult<'a> {
+            crate::traits::file_storage::FileStorageMetaResult::not_found(repo, oid)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scrub_does_not_quarantine_failed_lookup() {
+        let (files, _) = get_scrubber(true);
+        let storage = ScrubStorage {
+            meta: Arc::new(FailingMeta),
+            proxy: files.clone(),
+            lister: files.clone(),
+            quarantine: files.clone(),
+        };
+        let scrubber = Scrubber::new(storage, 2, true, None);
+        let oid = sha256(&[1, 2, 3]);
+        post(&files, "repo", &oid, vec![1, 2, 3]).await;
+
+        let report = scrubber.run(None).await.unwrap();
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].problem, ScrubProblem::Unreadable);
+        assert!(!report.findings[0].quarantined);
+        assert!(files.get_meta_result("repo", &oid).await.exists);
+    }
+
+    #[test]
+    fn test_report_as_json() {
+        let report = ScrubReport {
+            checked: 2,
+            findings: vec![ScrubFinding {
+                repo: String::from("repo"),
+                oid: String::from("oid"),
+                problem: ScrubProblem::ZeroLength,
+                detail: None,
+                quarantined: true,
+            }],
+        };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"checked":2,"findings":[{"repo":"repo","oid":"oid","problem":"zero_length","quarantined":true}]}"#
+        );
+    }
+}
@@ -0,0 +1,649 @@
+use crate::{
+    api::range::ByteRange,
+    traits::file_storage::{
+        FileStorageLister, FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy,
+        FileStorageQuarantine, ObjectReader, ObjectStream, StoredObject, StoredObjectStream,
+    },
+};
+use async_trait::async_trait;
+use deadpool::managed::{self, Metrics, Pool, RecycleResult};
+use futures_util::TryStreamExt;
+use openssh_sftp_client::{
+    file::TokioCompatFile, fs::Fs, Error, Sftp, SftpAuxiliaryData, SftpOptions,
+};
+use regex::Regex;
+use std::{
+    io::SeekFrom,
+    path::Path,
+    process::Stdio,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct SftpFileStorageConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub identity_path: Option<String>,
+    pub known_hosts_path: Option<String>,
+    pub root_path: String,
+    pub pool_size: usize,
+}
+
+/**
+ * Storage of the objects on a remote filesystem reached over SFTP, with the same layout as the
+ * local file storage.
+ *
+ * Each connection is an sftp subsystem run by the `ssh` client, so authentication and host key
+ * verification follow the usual OpenSSH rules. Connections are pooled, and each of them can
+ * serve several requests at once.
+ */
+pub struct SftpFileStorage {
+    root_path: String,
+    pool: Pool<SftpConnectionManager>,
+}
+
+/**
+ * Open sftp connections by spawning a command speaking the sftp protocol on its standard input
+ * and output, typically `ssh -s sftp`.
+ */
+struct SftpConnectionManager {
+    command: Vec<String>,
+}
+
+#[async_trait]
+impl managed::Manager for SftpConnectionManager {
+    type Type = Sftp;
+    type Error = Error;
+
+    async fn create(&self) -> Result<Sftp, Error> {
+        let mut child = tokio::process::Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        // The process is kept alive as long as the connection is used
+        Sftp::new_with_auxiliary(
+            stdin,
+            stdout,
+            SftpOptions::default(),
+            SftpAuxiliaryData::Boxed(Box::new(child)),
+        )
+        .await
+    }
+
+    async fn recycle(&self, sftp: &mut Sftp, _metrics: &Metrics) -> RecycleResult<Error> {
+        sftp.fs().metadata(".").await?;
+        Ok(())
+    }
+}
+
+impl SftpFileStorage {
+    pub fn new(root_path: String, command: Vec<String>, pool_size: usize) -> SftpFileStorage {
+        let pool = Pool::builder(SftpConnectionManager { command })
+            .max_size(pool_size)
+            .build()
+            .unwrap();
+        SftpFileStorage { root_path, pool }
+    }
+
+    pub fn from_config(config: SftpFileStorageConfig) -> SftpFileStorage {
+        tracing::info!(
+            "Creating pool of sftp connections to {}@{}:{}",
+            &config.user,
+            &config.host,
+            config.port
+        );
+        let command = Self::get_ssh_command(&config);
+        SftpFileStorage::new(config.root_path, command, config.pool_size)
+    }
+
+    /**
+     * Command opening an sftp session on the configured host. Batch mode prevents ssh from
+     * prompting for a password or a host key confirmation.
+     */
+    fn get_ssh_command(config: &SftpFileStorageConfig) -> Vec<String> {
+        let mut command = vec![
+            String::from("ssh"),
+            String::from("-o"),
+            String::from("BatchMode=yes"),
+            String::from("-p"),
+            config.port.to_string(),
+        ];
+        if let Some(identity_path) = &config.identity_path {
+            command.push(String::from("-i"));
+            command.push(identity_path.clone());
+        }
+        if let Some(known_hosts_path) = &config.known_hosts_path {
+            command.push(String::from("-o"));
+            command.push(format!("UserKnownHostsFile={}", known_hosts_path));
+        }
+        command.extend([
+            String::from("-l"),
+            config.user.clone(),
+            config.host.clone(),
+            String::from("-s"),
+            String::from("sftp"),
+        ]);
+        command
+    }
+
+    pub fn get_object_path(&self, repo: &str, oid: &str) -> String {
+        format!("{}/{}/objects/{}", &self.root_path, repo, oid)
+    }
+
+    pub fn get_mime_type_object_path(&self, repo: &str, oid: &str) -> String {
+        format!("{}/{}/mime-types/{}.mime", &self.root_path, repo, oid)
+    }
+
+    /**
+     * Unique path, outside of the objects directory, to receive an object being uploaded.
+     */
+    fn get_temporary_object_path(&self, repo: &str, oid: &str) -> String {
+        static UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+        let upload = UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed);
+        format!(
+            "{}/{}/tmp/{}.{}.{}",
+            &self.root_path,
+            repo,
+            oid,
+            std::process::id(),
+            upload
+        )
+    }
+
+    async fn receive(sftp: &Sftp, path: &str, data: &mut ObjectReader) -> Result<(), Error> {
+        let mut file = sftp.create(path).await?;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = data.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read]).await?;
+        }
+        file.close().await
+    }
+
+    async fn read_content_type(
+        &self,
+        fs: &mut Fs,
+        repo: &str,
+        oid: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let content_type = fs.read(self.get_mime_type_object_path(repo, oid)).await?;
+        Ok(String::from_utf8_lossy(&content_type).into_owned())
+    }
+
+    /**
+     * Move a file over another one. The posix-rename extension replaces the target at once, but
+     * a plain SFTPv3 rename fails when the target exists, so without it the target is removed
+     * first.
+     */
+    async fn replace(sftp: &Sftp, fs: &mut Fs, from: &str, to: &str) -> Result<(), Error> {
+        if !sftp.support_posix_rename() {
+            let _ = fs.remove_file(to).await;
+        }
+        fs.rename(from, to).await
+    }
+
+    /**
+     * Create a directory and its missing parents. A directory created meanwhile by a concurrent
+     * upload is not an error.
+     */
+    async fn create_if_missing(fs: &mut Fs, path: &str) -> Result<(), Error> {
+        let mut missing = Vec::new();
+        for ancestor in Path::new(path).ancestors() {
+            if ancestor.as_os_str().is_empty() || fs.metadata(ancestor).await.is_ok() {
+                break;
+            }
+            missing.push(ancestor);
+        }
+
+        for directory in missing.into_iter().rev() {
+            if let Err(e) = fs.create_dir(directory).await {
+                if fs.metadata(directory).await.is_err() {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Find the files of an area of every repository, named after their object. Repositories are
+     * the directories with objects or content types, they can be nested as their names can
+     * contain slashes.
+     */
+    async fn find_area_files(&self, fs: &mut Fs, area: &str) -> Result<Vec<StoredObject>, Error> {
+        let mut files = Vec::new();
+        let mut pending = vec![String::new()];
+        while let Some(repo) = pending.pop() {
+            files.extend(
+                self.find_repo_area_files(fs, &repo, area, &mut pending)
+                    .await?,
+            );
+        }
+        Ok(files)
+    }
+
+    /**
+     * Find the files of an area of a single repository, and the nested repositories to look
+     * into next.
+     */
+    async fn find_repo_area_files(
+        &self,
+        fs: &mut Fs,
+        repo: &str,
+        area: &str,
+        pending: &mut Vec<String>,
+    ) -> Result<Vec<StoredObject>, Error> {
+        let mut files = Vec::new();
+        let path = format!("{}/{}", &self.root_path, repo);
+        for (name, is_dir) in Self::read_dir(fs, &path).await? {
+            if !is_dir {
+                continue;
+            }
+            if name == area && !repo.is_empty() {
+                let area_path = format!("{}/{}", path, area);
+                for (file, is_dir) in Self::read_dir(fs, &area_path).await? {
+                    if !is_dir {
+                        files.push(StoredObject {
+                            repo: repo.to_string(),
+                            oid: file,
+                        });
+                    }
+                }
+            } else if !["objects", "mime-types", "tmp", "quarantine"].contains(&name.as_str()) {
+                pending.push(match repo {
+                    "" => name,
+                    _ => format!("{}/{}", repo, name),
+                });
+            }
+        }
+        Ok(files)
+    }
+
+    /**
+     * Names of the entries of a directory, with whether they are directories themselves.
+     */
+    async fn read_dir(fs: &mut Fs, path: &str) -> Result<Vec<(String, bool)>, Error> {
+        let entries: Vec<_> = fs.open_dir(path).await?.read_dir().try_collect().await?;
+        Ok(entries
+            .iter()
+            .map(|entry| {
+                let name = entry.filename().to_string_lossy().to_string();
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                (name, is_dir)
+            })
+            .filter(|(name, _)| name != "." && name != "..")
+            .collect())
+    }
+}
+
+#[async_trait]
+impl FileStorageMetaRequester for SftpFileStorage {
+    async fn get_meta_result<'a>(&self, repo: &'a str, oid: &'a str) -> FileStorageMetaResult<'a> {
+        if !Regex::new(r"^([a-zA-Z0-9\-_]*)\.?([a-zA-Z0-9\-_]*)$")
+            .unwrap()
+            .is_match(oid)
+        {
+            return FileStorageMetaResult::not_found(repo, oid);
+        }
+
+        let sftp = match self.pool.get().await {
+            Ok(sftp) => sftp,
+            Err(e) => {
+                tracing::error!("Failed to connect to the sftp server: {}", e);
+                return FileStorageMetaResult::not_found(repo, oid);
+            }
+        };
+        let meta = sftp.fs().metadata(self.get_object_path(repo, oid)).await;
+
+        let size = meta.ok().and_then(|m| m.len());
+        self.match_size(size, repo, oid)
+    }
+}
+
+#[async_trait]
+impl FileStorageProxy for SftpFileStorage {
+    async fn get(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        // Open the file, it will be read as the response is sent
+        let sftp = self.pool.get().await?;
+        let file = sftp.open(self.get_object_path(repo, oid)).await?;
+
+        // Read the mime type
+        let content_type = self.read_content_type(&mut sftp.fs(), repo, oid).await?;
+
+        let reader = TokioCompatFile::new(file);
+        Ok((Box::pin(ReaderStream::new(reader)), content_type))
+    }
+
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+        // Seek to the start of the range, and only read up to its end
+        let sftp = self.pool.get().await?;
+        let mut file = sftp.open(self.get_object_path(repo, oid)).await?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+        let reader = TokioCompatFile::new(file).take(range.length());
+
+        let content_type = self.read_content_type(&mut sftp.fs(), repo, oid).await?;
+
+        Ok((Box::pin(ReaderStream::new(reader)), content_type))
+    }
+
+    async fn post(
+        &self,
+        repo: &str,
+        oid: &str,
+        mut data: ObjectReader,
+        content_type: &str,
+        _uploader: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let sftp = self.pool.get().await?;
+        let mut fs = sftp.fs();
+        for directory in ["objects", "mime-types", "tmp"] {
+            let path = format!("{}/{}/{}", &self.root_path, repo, directory);
+            Self::create_if_missing(&mut fs, &path).await?;
+        }
+
+        // Receive the data in a temporary file, so that a failed or rejected upload is never
+        // visible under the oid
+        let temporary_path = self.get_temporary_object_path(repo, oid);
+        let received = Self::receive(&sftp, &temporary_path, &mut data).await;
+        if let Err(e) = received {
+            let _ = fs.remove_file(&temporary_path).await;
+            return Err(Box::new(e));
+        }
+
+        fs.write(
+            self.get_mime_type_object_path(repo, oid),
+            content_type.as_bytes(),
+        )
+        .await?;
+
+        // Then move it into place at once
+        let object_path = self.get_object_path(repo, oid);
+        Self::replace(&sftp, &mut fs, &temporary_path, &object_path).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FileStorageLister for SftpFileStorage {
+    fn list_objects(&self) -> StoredObjectStream<'_> {
+        // The repositories are read one at a time, each yielding the objects found in it
+        let repos =
+            futures_util::stream::try_unfold(vec![String::new()], move |mut pending| async move {
+                let Some(repo) = pending.pop() else {
+                    return Ok::<_, std::io::Error>(None);
+                };
+                let sftp = self.pool.get().await.map_err(std::io::Error::other)?;
+                let objects = self
+                    .find_repo_area_files(&mut sftp.fs(), &repo, "objects", &mut pending)
+                    .await
+                    .map_err(std::io::Error::other)?;
+                Ok(Some((objects, pending)))
+            });
+        let objects = repos
+            .map_ok(|objects| futures_util::stream::iter(objects.into_iter().map(Ok)))
+            .try_flatten();
+        Box::pin(objects)
+    }
+
+    async fn list_orphaned_content_types(
+        &self,
+    ) -> Result<Vec<StoredObject>, Box<dyn std::error::Error>> {
+        let sftp = self.pool.get().await?;
+        let mut fs = sftp.fs();
+        let mut orphans = Vec::new();
+        for mut object in self.find_area_files(&mut fs, "mime-types").await? {
+            if let Some(oid) = object.oid.strip_suffix(".mime") {
+                object.oid = oid.to_string();
+            }
+            let path = self.get_object_path(&object.repo, &object.oid);
+            if fs.metadata(path).await.is_err() {
+                orphans.push(object);
+            }
+        }
+        Ok(orphans)
+    }
+}
+
+#[async_trait]
+impl FileStorageQuarantine for SftpFileStorage {
+    async fn quarantine(&self, repo: &str, oid: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let sftp = self.pool.get().await?;
+        let mut fs = sftp.fs();
+        let quarantine_path = format!("{}/{}/quarantine", &self.root_path, repo);
+        Self::create_if_missing(&mut fs, &quarantine_path).await?;
+        // An object quarantined before under the same oid is replaced
+        Self::replace(
+            &sftp,
+            &mut fs,
+            &self.get_object_path(repo, oid),
+            &format!("{}/{}", quarantine_path, oid),
+        )
+        .await?;
+        // The content type goes along, if there is one
+        let _ = Self::replace(
+            &sftp,
+            &mut fs,
+            &self.get_mime_type_object_path(repo, oid),
+            &format!("{}/{}.mime", quarantine_path, oid),
+        )
+        .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::helpers::{read_all, reader};
+
+    fn get_config() -> SftpFileStorageConfig {
+        SftpFileStorageConfig {
+            host: String::from("nas"),
+            port: 2222,
+            user: String::from("lfs"),
+            identity_path: None,
+            known_hosts_path: None,
+            root_path: String::from("/srv/lfs"),
+            pool_size: 2,
+        }
+    }
+
+    /**
+     * Storage served by a local OpenSSH sftp-server, without going through ssh. As the
+     * connections run in the runtime that opened them, these tests use a single runtime.
+     * Without an sftp-server binary, the tests using it are skipped.
+     */
+    fn get_storage() -> Option<SftpFileStorage> {
+        let sftp_server = std::env::var("SFTP_SERVER_PATH")
+            .unwrap_or(String::from("/usr/lib/openssh/sftp-server"));
+        if !Path::new(&sftp_server).exists() {
+            eprintln!("{} not found, skipping the test", sftp_server);
+            return None;
+        }
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let root_path = format!("/tmp/{}", random_dir);
+        Some(SftpFileStorage::new(root_path, vec![sftp_server], 2))
+    }
+
+    #[test]
+    fn test_get_ssh_command() {
+        let command = SftpFileStorage::get_ssh_command(&get_config());
+        assert_eq!(
+            command.join(" "),
+            "ssh -o BatchMode=yes -p 2222 -l lfs nas -s sftp"
+        );
+    }
+
+    #[test]
+    fn test_get_ssh_command_with_keys() {
+        let config = SftpFileStorageConfig {
+            identity_path: Some(String::from("/run/secrets/id")),
+            known_hosts_path: Some(String::from("/run/secrets/known_hosts")),
+            ..get_config()
+        };
+        let command = SftpFileStorage::get_ssh_command(&config);
+        assert_eq!(
+            command.join(" "),
+            "ssh -o BatchMode=yes -p 2222 -i /run/secrets/id \
+            -o UserKnownHostsFile=/run/secrets/known_hosts -l lfs nas -s sftp"
+        );
+    }
+
+    #[test]
+    fn test_get_object_path() {
+        let storage = SftpFileStorage::from_config(get_config());
+        let path = storage.get_object_path("repo", "oid");
+        assert_eq!(path, "/srv/lfs/repo/objects/oid");
+    }
+
+    #[tokio::test]
+    async fn test_sftp_get_meta_result() {
+        let Some(storage) = get_storage() else {
+            return;
+        };
+        let result = storage.get_meta_result("repo", "oid").await;
+        assert!(!result.exists);
+        assert_eq!(result.size, 0);
+    }
+
+    #[tokio::test]
+    async fn test_sftp_post_and_retrieve() {
+        let Some(storage) = get_storage() else {
+            return;
+        };
+        let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        storage
+            .post("a/b", "oid", reader(data.clone()), "image/png", None)
+            .await
+            .unwrap();
+
+        let result = storage.get_meta_result("a/b", "oid").await;
+        assert!(result.exists);
+        assert_eq!(result.size, 200_000);
+
+        let (stream, content_type) = storage.get("a/b", "oid").await.unwrap();
+        assert_eq!(content_type, "image/png");
+        assert_eq!(read_all(stream).await, data);
+    }
+
+    #[tokio::test]
+    async fn test_sftp_post_and_retrieve_range() {
+        let Some(storage) = get_storage() else {
+            return;
+        };
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        storage
+            .post("repo", "oid", reader(data.clone()), "text/plain", None)
+            .await
+            .unwrap();
+
+        let range = ByteRange {
+            start: 1000,
+            end: 80_000,
+        };
+        let (stream, _) = storage.get_range("repo", "oid", range).await.unwrap();
+        assert_eq!(read_all(stream).await, data[1000..=80_000].to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_sftp_post_failure_is_not_visible() {
+        let Some(storage) = get_storage() else {
+            return;
+        };
+        let failing: ObjectReader = Box::pin(
+            tokio_test::io::Builder::new()
+                .read(&[1, 2, 3])
+                .read_error(std::io::Error::other("rejected"))
+                .build(),
+        );
+        assert!(storage
+            .post("repo", "oid", failing, "text/plain", None)
+            .await
+            .is_err());
+
+        let result = storage.get_meta_result("repo", "oid").await;
+        assert!(!result.exists);
+        let temporary_files = std::fs::read_dir(format!("{}/repo/tmp", storage.root_path)).unwrap();
+        assert_eq!(temporary_files.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sftp_list_objects() {
+        let Some(storage) = get_storage() else {
+            return;
+        };
+        for (repo, oid) in [("a/b", "oid1"), ("a/b", "oid2"), ("c", "oid1")] {
+            storage
+                .post(repo, oid, reader(vec![1]), "image/png", None)
+                .await
+                .unwrap();
+        }
+
+        let mut objects: Vec<_> = storage.list_objects().try_collect().await.unwrap();
+        objects.sort_by(|a, b| (&a.repo, &a.oid).cmp(&(&b.repo, &b.oid)));
+        let listed: Vec<_> = objects
+            .iter()
+            .map(|object| (object.repo.as_str(), object.oid.as_str()))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![("a/b", "oid1"), ("a/b", "oid2"), ("c", "oid1")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sftp_post_replaces_existing_object() {
+        let Some(storage) = get_storage() else {
+            return;
+        };
+        for data in [vec![1, 2, 3], vec![4, 5]] {
+            storage
+                .post("repo", "oid", reader(data), "text/plain", None)
+                .await
+                .unwrap();
+        }
+
+        let (stream, _) = storage.get("repo", "oid").await.unwrap();
+        assert_eq!(read_all(stream).await, vec![4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_sftp_quarantine_twice() {
+        let Some(storage) = get_storage() else {
+            return;
+        };
+        for data in [vec![1, 2, 3], vec![4, 5]] {
+            storage
+                .post("repo", "oid", reader(data), "text/plain", None)
+                .await
+                .unwrap();
+            storage.quarantine("repo", "oid").await.unwrap();
+        }
+
+        assert!(!storage.get_meta_result("repo", "oid").await.exists);
+        let quarantined = std::fs::read(format!("{}/repo/quarantine/oid", storage.root_path));
+        assert_eq!(quarantined.unwrap(), vec![4, 5]);
+    }
+}
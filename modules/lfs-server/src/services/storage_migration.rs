@@ -0,0 +1,299 @@
+use std::sync::{Arc, Mutex};
+
+use futures_util::TryStreamExt;
+use sha2::{Digest, Sha256};
+use tokio_util::io::StreamReader;
+
+use crate::traits::file_storage::{
+    FileStorageLister, FileStorageMetaRequester, FileStorageProxy, ObjectStream, StoredObject,
+};
+
+/**
+ * One of the storages of a migration.
+ */
+pub struct MigrationStorage {
+    pub meta: Arc<dyn FileStorageMetaRequester>,
+    pub proxy: Arc<dyn FileStorageProxy>,
+    pub lister: Arc<dyn FileStorageLister>,
+}
+
+/**
+ * Outcome of a migration.
+ */
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub copied: u64,
+    pub skipped: u64,
+    pub failed: Vec<StoredObject>,
+}
+
+enum Copy {
+    Copied,
+    Skipped,
+}
+
+/**
+ * Copy of every object of a storage to another one, whatever their implementations. The objects
+ * are copied as they are stored, with their content type, several at a time.
+ *
+ * Objects already in the destination with the same size are skipped, so that an interrupted
+ * migration can be run again. Each copy is checked against the source: the destination must
+ * report the same size, and the same SHA-256 for the stored content.
+ */
+pub struct StorageMigration {
+    source: MigrationStorage,
+    destination: MigrationStorage,
+    concurrency: usize,
+}
+
+impl StorageMigration {
+    pub fn new(
+        source: MigrationStorage,
+        destination: MigrationStorage,
+        concurrency: usize,
+    ) -> StorageMigration {
+        StorageMigration {
+            source,
+            destination,
+            concurrency,
+        }
+    }
+
+    pub async fn run(&self) -> Result<MigrationReport, Box<dyn std::error::Error>> {
+        // The objects are copied as they are listed
+        let mut report = MigrationReport::default();
+        let mut copies = self
+            .source
+            .lister
+            .list_objects()
+            .map_ok(|object| async move {
+                let result = self.copy(&object).await;
+                Ok((object, result))
+            })
+            .try_buffer_unordered(self.concurrency);
+        while let Some((object, result)) = copies.try_next().await? {
+            match result {
+                Ok(Copy::Copied) => report.copied += 1,
+                Ok(Copy::Skipped) => report.skipped += 1,
+                Err(e) => {
+                    tracing::error!("Failed to copy {} of {}: {}", object.oid, object.repo, e);
+                    report.failed.push(object);
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    async fn copy(&self, object: &StoredObject) -> Result<Copy, String> {
+        let (repo, oid) = (object.repo.as_str(), object.oid.as_str());
+        let source = self.source.meta.get_meta_result(repo, oid).await;
+        if !source.exists {
+            return Err(String::from("Missing from the source"));
+        }
+        let destination = self.destination.meta.get_meta_result(repo, oid).await;
+        if destination.exists && destination.size == source.size {
+            return Ok(Copy::Skipped);
+        }
+
+        // The content is hashed as it is sent to the destination
+        let (stream, content_type) = self
+            .source
+            .proxy
+            .get(repo, oid)
+            .await
+            .map_err(|e| e.to_string())?;
+        let hasher = Arc::new(Mutex::new(Sha256::new()));
+        let hashing = hasher.clone();
+        let stream = stream.inspect_ok(move |chunk| hashing.lock().unwrap().update(chunk));
+        self.destination
+            .proxy
+            .post(
+                repo,
+                oid,
+                Box::pin(StreamReader::new(stream)),
+                &content_type,
+                None,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        let source_sha256 = hex::encode(hasher.lock().unwrap().clone().finalize());
+
+        let copied = self.destination.meta.get_meta_result(repo, oid).await;
+        if !copied.exists || copied.size != source.size {
+            return Err(format!(
+                "Copied {} bytes, expected {}",
+                copied.size, source.size
+            ));
+        }
+        if self.get_destination_sha256(repo, oid).await? != source_sha256 {
+            return Err(String::from("Copied content does not match the source"));
+        }
+        Ok(Copy::Copied)
+    }
+
+    /**
+     * SHA-256 of an object in the destination, computed by the storage if it can, or by reading
+     * it back otherwise.
+     */
+    async fn get_destination_sha256(&self, repo: &str, oid: &str) -> Result<String, String> {
+        let computed = self
+            .destination
+            .meta
+            .get_content_sha256(repo, oid)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(sha256) = computed {
+            return Ok(sha256);
+        }
+        let (stream, _) = self
+            .destination
+            .proxy
+            .get(repo, oid)
+            .await
+            .map_err(|e| e.to_string())?;
+        Self::hash(stream).await.map_err(|e| e.to_string())
+    }
+
+    async fn hash(mut stream: ObjectStream) -> Result<String, std::io::Error> {
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = stream.try_next().await? {
+            hasher.update(&chunk);
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fs::local_file_storage::LocalFileStorage;
+    use crate::test_utils::helpers::read_all;
+
+    fn local_storage() -> (Arc<LocalFileStorage>, MigrationStorage) {
+        let random_dir = uuid::Uuid::new_v4().to_string();
+        let files = Arc::new(LocalFileStorage::new(format!("/tmp/{}", random_dir)));
+        let storage = MigrationStorage {
+            meta: files.clone(),
+            proxy: files.clone(),
+            lister: files.clone(),
+        };
+        (files, storage)
+    }
+
+    async fn post(files: &LocalFileStorage, repo: &str, oid: &str, data: Vec<u8>) {
+        files
+            .post(
+                repo,
+                oid,
+                Box::pin(std::io::Cursor::new(data)),
+                "image/png",
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrate_all_objects() {
+        let (source_files, source) = local_storage();
+        let (destination_files, destination) = local_storage();
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        post(&source_files, "repo", "a", data.clone()).await;
+        post(&source_files, "repo", "b", vec![1, 2, 3]).await;
+        post(&source_files, "group/repo", "a", vec![]).await;
+
+        let migration = StorageMigration::new(source, destination, 2);
+        let report = migration.run().await.unwrap();
+        assert_eq!(report.copied, 3);
+        assert!(report.failed.is_empty());
+
+        let (stream, content_type) = destination_files.get("repo", "a").await.unwrap();
+        assert_eq!(read_all(stream).await, data);
+        assert_eq!(content_type, "image/png");
+        assert!(
+            destination_files
+                .get_meta_result("group/repo", "a")
+                .await
+                .exists
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migration_resumes() {
+        let (source_files, source) = local_storage();
+        let (destination_files, destination) = local_storage();
+        post(&source_files, "repo", "a", vec![1, 2, 3]).await;
+        post(&source_files, "repo", "b", vec![4, 5, 6]).await;
+        // Already copied by a previous run
+        post(&destination_files, "repo", "a", vec![1, 2, 3]).await;
+        // Interrupted while being copied
+        post(&destination_files, "repo", "b", vec![4]).await;
+
+        let migration = StorageMigration::new(source, destination, 2);
+        let report = migration.run().await.unwrap();
+        assert_eq!(report.copied, 1);
+        assert_eq!(report.skipped, 1);
+
+        let (stream, _) = destination_files.get("repo", "b").await.unwrap();
+        assert_eq!(read_all(stream).await, vec![4, 5, 6]);
+    }
+
+    /**
+     * Storage losing the end of the objects it receives.
+     */
+    struct TruncatingStorage(Arc<LocalFileStorage>);
+
+    #[async_trait::async_trait]
+    impl FileStorageProxy for TruncatingStorage {
+        async fn get(
+            &self,
+            repo: &str,
+            oid: &str,
+        ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+            self.0.get(repo, oid).await
+        }
+
+        async fn get_range(
+            &self,
+            repo: &str,
+            oid: &str,
+            range: crate::api::range::ByteRange,
+        ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>> {
+            self.0.get_range(repo, oid, range).await
+        }
+
+        async fn post(
+            &self,
+            repo: &str,
+            oid: &str,
+            data: crate::traits::file_storage::ObjectReader,
+            content_type: &str,
+            uploader: Option<&str>,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            use tokio::io::AsyncReadExt;
+            let truncated = Box::pin(data.take(1));
+            self.0
+                .post(repo, oid, truncated, content_type, uploader)
+                .await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_copy_is_reported() {
+        let (source_files, source) = local_storage();
+        let (destination_files, mut destination) = local_storage();
+        destination.proxy = Arc::new(TruncatingStorage(destination_files));
+        post(&source_files, "repo", "a", vec![1, 2, 3]).await;
+
+        let migration = StorageMigration::new(source, destination, 2);
+        let report = migration.run().await.unwrap();
+        assert_eq!(report.copied, 0);
+        assert_eq!(
+            report.failed,
+            vec![StoredObject {
+                repo: String::from("repo"),
+                oid: String::from("a"),
+            }]
+        );
+    }
+}
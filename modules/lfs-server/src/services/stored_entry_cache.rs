@@ -0,0 +1,102 @@
+use std::collections::{BTreeMap, HashMap};
+
+type Key = (String, String);
+
+/**
+ * Entries read from the stored objects, such as the trailers of the compression or the headers
+ * of the encryption, by repository and oid, along with the stored size they were read for.
+ * Objects are immutable by oid, so an entry is valid as long as the stored size is the same.
+ * Beyond the maximum number of entries, the least recently used ones are evicted.
+ */
+pub struct StoredEntryCache<T> {
+    max_entries: usize,
+    entries: HashMap<Key, StoredEntry<T>>,
+    // Keys of the entries by last use, the least recently used first
+    uses: BTreeMap<u64, Key>,
+    last_use: u64,
+}
+
+struct StoredEntry<T> {
+    stored_size: u64,
+    value: T,
+    last_use: u64,
+}
+
+impl<T: Clone> StoredEntryCache<T> {
+    pub fn new(max_entries: usize) -> StoredEntryCache<T> {
+        StoredEntryCache {
+            max_entries,
+            entries: HashMap::new(),
+            uses: BTreeMap::new(),
+            last_use: 0,
+        }
+    }
+
+    /**
+     * Get the entry of an object, or nothing if it is not in the cache for this stored size.
+     */
+    pub fn get(&mut self, repo: &str, oid: &str, stored_size: u64) -> Option<T> {
+        self.last_use += 1;
+        let key = (repo.to_string(), oid.to_string());
+        let entry = self.entries.get_mut(&key)?;
+        if entry.stored_size != stored_size {
+            return None;
+        }
+        self.uses.remove(&entry.last_use);
+        entry.last_use = self.last_use;
+        self.uses.insert(self.last_use, key);
+        Some(entry.value.clone())
+    }
+
+    pub fn insert(&mut self, repo: &str, oid: &str, stored_size: u64, value: T) {
+        self.last_use += 1;
+        let key = (repo.to_string(), oid.to_string());
+        self.uses.insert(self.last_use, key.clone());
+        let entry = StoredEntry {
+            stored_size,
+            value,
+            last_use: self.last_use,
+        };
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.uses.remove(&previous.last_use);
+        }
+
+        while self.entries.len() > self.max_entries {
+            let Some((_, oldest)) = self.uses.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored_size_change() {
+        let mut cache = StoredEntryCache::new(10);
+        assert_eq!(cache.get("repo", "oid", 50), None);
+        cache.insert("repo", "oid", 50, Some(100));
+        assert_eq!(cache.get("repo", "oid", 50), Some(Some(100)));
+        // The stored object changed
+        assert_eq!(cache.get("repo", "oid", 60), None);
+        cache.insert("repo", "oid", 60, None);
+        assert_eq!(cache.get("repo", "oid", 60), Some(None));
+        assert_eq!(cache.uses.len(), 1);
+    }
+
+    #[test]
+    fn test_least_recently_used_evicted() {
+        let mut cache = StoredEntryCache::new(2);
+        cache.insert("repo", "a", 1, 1);
+        cache.insert("repo", "b", 1, 2);
+        assert_eq!(cache.get("repo", "a", 1), Some(1));
+        cache.insert("repo", "c", 1, 3);
+        assert_eq!(cache.get("repo", "b", 1), None);
+        assert_eq!(cache.get("repo", "a", 1), Some(1));
+        assert_eq!(cache.get("repo", "c", 1), Some(3));
+        assert_eq!(cache.uses.len(), 2);
+    }
+}
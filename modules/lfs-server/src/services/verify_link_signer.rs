@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+
+use crate::{
+    api::{enums::Operation, objects_batch::response::ObjectAction},
+    services::custom_link_signer::CustomLinkSigner,
+    traits::{
+        file_storage::{FileStorageLinkSigner, FileStorageMetaResult},
+        token_encoder_decoder::TokenEncoderDecoder,
+    },
+};
+
+/**
+ * Link signer adding a verify action, pointing to this server, to the upload links of another
+ * signer. This is meant for the signer mode, where objects are uploaded directly to the storage
+ * and the server would otherwise never know whether the upload succeeded.
+ */
+pub struct VerifyLinkSigner<TTokenEncoderDecoder: TokenEncoderDecoder> {
+    signer: Arc<dyn FileStorageLinkSigner>,
+    verify_signer: CustomLinkSigner<TTokenEncoderDecoder>,
+}
+
+impl<TTokenEncoderDecoder: TokenEncoderDecoder> VerifyLinkSigner<TTokenEncoderDecoder> {
+    pub fn new(
+        signer: Arc<dyn FileStorageLinkSigner>,
+        verify_signer: CustomLinkSigner<TTokenEncoderDecoder>,
+    ) -> VerifyLinkSigner<TTokenEncoderDecoder> {
+        VerifyLinkSigner {
+            signer,
+            verify_signer,
+        }
+    }
+}
+
+#[async_trait]
+impl<TTokenEncoderDecoder: TokenEncoderDecoder + Sync + Send> FileStorageLinkSigner
+    for VerifyLinkSigner<TTokenEncoderDecoder>
+{
+    async fn get_presigned_link<'a>(
+        &self,
+        result: FileStorageMetaResult<'a>,
+    ) -> Result<ObjectAction, Box<dyn std::error::Error>> {
+        self.signer.get_presigned_link(result).await
+    }
+
+    async fn post_presigned_link<'a>(
+        &self,
+        result: FileStorageMetaResult<'a>,
+        size: u64,
+        user: &str,
+        ref_name: Option<&str>,
+    ) -> Result<(ObjectAction, Option<ObjectAction>), Box<dyn std::error::Error>> {
+        let verify = self
+            .verify_signer
+            .verify_action(&result, size, user, ref_name);
+        let (upload, _) = self
+            .signer
+            .post_presigned_link(result, size, user, ref_name)
+            .await?;
+        Ok((upload, Some(verify)))
+    }
+
+    async fn check_link(
+        &self,
+        repo: &str,
+        oid: &str,
+        headers: Option<&HeaderMap>,
+        operation: Operation,
+    ) -> bool {
+        // Only the verify links are signed by this server
+        self.verify_signer
+            .check_link(repo, oid, headers, operation)
+            .await
+    }
+
+    async fn check_verify_link(&self, repo: &str, oid: &str, headers: Option<&HeaderMap>) -> bool {
+        self.verify_signer
+            .check_verify_link(repo, oid, headers)
+            .await
+    }
+
+    fn signed_size(&self, headers: &HeaderMap) -> Option<u64> {
+        self.verify_signer.signed_size(headers)
+    }
+
+    fn multipart_verify_action(
+        &self,
+        result: &FileStorageMetaResult,
+        size: u64,
+        user: &str,
+        ref_name: Option<&str>,
+        upload_id: &str,
+    ) -> Option<ObjectAction> {
+        self.verify_signer
+            .multipart_verify_action(result, size, user, ref_name, upload_id)
+    }
+
+    fn signed_upload_id(&self, headers: &HeaderMap) -> Option<String> {
+        self.verify_signer.signed_upload_id(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        services::jwt_token_encoder_decoder::JwtTokenEncoderDecoder,
+        test_utils::mocks::MockLinkSigner,
+    };
+
+    fn get_signer() -> VerifyLinkSigner<JwtTokenEncoderDecoder> {
+        let encoder_decoder = JwtTokenEncoderDecoder::new("secret".to_string(), 3600);
+        VerifyLinkSigner::new(
+            Arc::new(MockLinkSigner {
+                with_verify: false,
+                check_link_succeed: false,
+                signed_size: None,
+                signed_upload_id: None,
+                signing_delay: std::time::Duration::ZERO,
+            }),
+            CustomLinkSigner::new("http://localhost:8080".to_string(), encoder_decoder),
+        )
+    }
+
+    #[test]
+    fn test_post_presigned_link() {
+        let (upload, verify) = crate::aw!(get_signer().post_presigned_link(
+            FileStorageMetaResult::new("repo", "oid", 100),
+            100,
+            "user",
+            None
+        ))
+        .unwrap();
+
+        assert_eq!(upload.href, "https://example.com/upload/repo/oid?size=100");
+        let verify = verify.unwrap();
+        assert_eq!(verify.href, "http://localhost:8080/repo/objects/verify");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            verify.header.unwrap().authorization.parse().unwrap(),
+        );
+        let signer = get_signer();
+        assert!(crate::aw!(signer.check_verify_link(
+            "repo",
+            "oid",
+            Some(&headers)
+        )));
+        assert_eq!(signer.signed_size(&headers), Some(100));
+        assert_eq!(signer.signed_upload_id(&headers), None);
+    }
+
+    #[test]
+    fn test_multipart_verify_action() {
+        let signer = get_signer();
+        let result = FileStorageMetaResult::new("repo", "oid", 100);
+        let verify = signer
+            .multipart_verify_action(&result, 100, "user", None, "upload-id")
+            .unwrap();
+        assert_eq!(verify.href, "http://localhost:8080/repo/objects/verify");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            verify.header.unwrap().authorization.parse().unwrap(),
+        );
+        assert!(crate::aw!(signer.check_verify_link(
+            "repo",
+            "oid",
+            Some(&headers)
+        )));
+        assert_eq!(signer.signed_size(&headers), Some(100));
+        assert_eq!(
+            signer.signed_upload_id(&headers),
+            Some(String::from("upload-id"))
+        );
+    }
+
+    #[test]
+    fn test_get_presigned_link() {
+        let download = crate::aw!(
+            get_signer().get_presigned_link(FileStorageMetaResult::new("repo", "oid", 100))
+        )
+        .unwrap();
+
+        assert_eq!(
+            download.href,
+            "https://example.com/download/repo/oid?size=100"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::traits::file_storage::ObjectReader;
+
+/**
+ * Shared record of why a verified upload was rejected, readable once the storage gave up on it.
+ */
+#[derive(Clone, Default)]
+pub struct VerificationOutcome(Arc<Mutex<Option<String>>>);
+
+impl VerificationOutcome {
+    pub fn rejection(&self) -> Option<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn reject(&self, message: String) -> std::io::Error {
+        *self.0.lock().unwrap() = Some(message.clone());
+        std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+    }
+}
+
+/**
+ * Reader computing the SHA-256 and the size of an object while it is uploaded.
+ *
+ * Instead of reporting the end of the data, it fails if the content does not match the oid or
+ * the declared size, so that the storage never commits the object. It also fails as soon as more
+ * data than declared is received.
+ */
+pub struct VerifyingReader {
+    inner: ObjectReader,
+    hasher: Option<Sha256>,
+    received: u64,
+    oid: String,
+    size: Option<u64>,
+    outcome: VerificationOutcome,
+}
+
+impl VerifyingReader {
+    pub fn new(inner: ObjectReader, oid: &str, size: Option<u64>) -> VerifyingReader {
+        VerifyingReader {
+            inner,
+            hasher: Some(Sha256::new()),
+            received: 0,
+            oid: oid.to_lowercase(),
+            size,
+            outcome: VerificationOutcome::default(),
+        }
+    }
+
+    pub fn outcome(&self) -> VerificationOutcome {
+        self.outcome.clone()
+    }
+
+    fn finish(&mut self) -> Result<(), std::io::Error> {
+        let Some(hasher) = self.hasher.take() else {
+            return Ok(());
+        };
+        if let Some(size) = self.size {
+            if self.received != size {
+                return Err(self.outcome.reject(format!(
+                    "Received {} bytes, expected {}",
+                    self.received, size
+                )));
+            }
+        }
+        let hash = hex::encode(hasher.finalize());
+        if hash != self.oid {
+            return Err(self
+                .outcome
+                .reject(String::from("Content hash does not match the oid")));
+        }
+        Ok(())
+    }
+}
+
+impl AsyncRead for VerifyingReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return this.inner.as_mut().poll_read(cx, buf);
+        }
+
+        let before = buf.filled().len();
+        ready!(this.inner.as_mut().poll_read(cx, buf))?;
+        let read = &buf.filled()[before..];
+
+        // Nothing read into a non-empty buffer means the end of the data
+        if read.is_empty() {
+            return Poll::Ready(this.finish());
+        }
+
+        if let Some(hasher) = this.hasher.as_mut() {
+            hasher.update(read);
+        }
+        this.received += read.len() as u64;
+        if let Some(size) = this.size {
+            if this.received > size {
+                // A failed read shall not hand out any data
+                buf.set_filled(before);
+                return Poll::Ready(Err(this
+                    .outcome
+                    .reject(format!("Received more than {} bytes", size))));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    // sha256 of [1, 2, 3]
+    const OID: &str = "039058c6f2c0cb492c533b0a4d14ef77cc0f78abccced5287d84a1a2011cfb81";
+
+    fn read(oid: &str, size: Option<u64>) -> (Result<Vec<u8>, std::io::Error>, Option<String>) {
+        let mut reader =
+            VerifyingReader::new(Box::pin(std::io::Cursor::new(vec![1, 2, 3])), oid, size);
+        let outcome = reader.outcome();
+        let mut data = Vec::new();
+        let result = crate::aw!(reader.read_to_end(&mut data)).map(|_| data);
+        (result, outcome.rejection())
+    }
+
+    #[test]
+    fn test_matching_content() {
+        let (result, rejection) = read(OID, Some(3));
+        assert_eq!(result.unwrap(), vec![1, 2, 3]);
+        assert!(rejection.is_none());
+    }
+
+    #[test]
+    fn test_matching_content_without_size() {
+        let (result, rejection) = read(&OID.to_uppercase(), None);
+        assert_eq!(result.unwrap(), vec![1, 2, 3]);
+        assert!(rejection.is_none());
+    }
+
+    #[test]
+    fn test_hash_mismatch() {
+        let (result, rejection) = read("oid", Some(3));
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(rejection.unwrap(), "Content hash does not match the oid");
+    }
+
+    #[test]
+    fn test_too_short() {
+        let (result, rejection) = read(OID, Some(4));
+        assert!(result.is_err());
+        assert_eq!(rejection.unwrap(), "Received 3 bytes, expected 4");
+    }
+
+    #[test]
+    fn test_too_long() {
+        let (result, rejection) = read(OID, Some(2));
+        assert!(result.is_err());
+        assert_eq!(rejection.unwrap(), "Received more than 2 bytes");
+    }
+}
@@ -2,7 +2,9 @@ use crate::api::enums::{HashAlgorithm, Operation, Transfer};
 use crate::api::locks::body::{CreateLockPayload, Ref};
 use crate::api::objects_batch::body::{ObjectIdentity, ObjectsBatchRequestPayload};
 use crate::api::repo_query::QueryRepo;
+use crate::traits::file_storage::{ObjectReader, ObjectStream};
 use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use futures_util::TryStreamExt;
 
 #[macro_export]
 macro_rules! aw {
@@ -29,7 +31,7 @@ impl CreateLockPayload {
 }
 
 impl ObjectIdentity {
-    pub fn new(oid: &str, size: u32) -> Self {
+    pub fn new(oid: &str, size: u64) -> Self {
         Self {
             oid: oid.to_string(),
             size,
@@ -44,6 +46,7 @@ impl ObjectsBatchRequestPayload {
             transfers: Some(vec![Transfer::Basic]),
             objects,
             hash_algo: HashAlgorithm::Sha256,
+            ref_: None,
         }
     }
 
@@ -53,6 +56,7 @@ impl ObjectsBatchRequestPayload {
             transfers: Some(vec![Transfer::Basic]),
             objects,
             hash_algo: HashAlgorithm::Sha256,
+            ref_: None,
         }
     }
 }
@@ -76,3 +80,25 @@ pub fn assert_http_error<T>(
         }
     }
 }
+
+/**
+ * Reader of the content of an object to store.
+ */
+pub fn reader(data: impl AsRef<[u8]>) -> ObjectReader {
+    Box::pin(std::io::Cursor::new(data.as_ref().to_vec()))
+}
+
+/**
+ * Read the whole content of an object, failing if the stream fails.
+ */
+pub async fn try_read_all(stream: ObjectStream) -> std::io::Result<Vec<u8>> {
+    let chunks: Vec<_> = stream.try_collect().await?;
+    Ok(chunks.concat())
+}
+
+/**
+ * Read the whole content of an object.
+ */
+pub async fn read_all(stream: ObjectStream) -> Vec<u8> {
+    try_read_all(stream).await.unwrap()
+}
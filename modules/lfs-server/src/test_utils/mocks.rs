@@ -1,22 +1,27 @@
 use crate::api::enums::Operation;
 use crate::api::locks::response::LockOwner;
-use crate::api::objects_batch::response::ObjectAction;
+use crate::api::objects_batch::response::{ObjectAction, PartAction};
+use crate::api::range::ByteRange;
 use crate::services::injected_services::InjectedServices;
 use crate::traits::file_storage::{
-    FileStorageLinkSigner, FileStorageMetaRequester, FileStorageMetaResult, FileStorageProxy,
+    FileStorageChunkedUploader, FileStorageLinkSigner, FileStorageMetaRequester,
+    FileStorageMetaResult, FileStorageMultipartUploader, FileStorageProxy, FileStorageQuarantine,
+    FileStorageResumableUploader, MultipartUpload, ObjectReader, ObjectStream,
 };
 use crate::traits::locks::{Lock, LocksProvider, LocksProviderError};
 use crate::traits::token_encoder_decoder::TokenEncoderDecoder;
 use async_trait::async_trait;
-use axum::http::HeaderMap;
+use axum::{body::Bytes, http::HeaderMap};
 use std::collections::BTreeMap;
 use std::error::Error;
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
 
 pub struct MockFileStorageMetaRequester {
     pub size: u64,
     pub found: bool,
+    pub content_sha256: Option<String>,
 }
 
 #[async_trait]
@@ -28,14 +33,25 @@ impl FileStorageMetaRequester for MockFileStorageMetaRequester {
             FileStorageMetaResult::not_found(repo, oid)
         }
     }
+
+    async fn get_content_sha256(
+        &self,
+        _repo: &str,
+        _oid: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self.content_sha256.clone())
+    }
 }
 
 pub struct MockLinkSigner {
     pub with_verify: bool,
     pub check_link_succeed: bool,
+    pub signed_size: Option<u64>,
+    pub signed_upload_id: Option<String>,
+    pub signing_delay: Duration,
 }
 
-fn build_action(verb: String, result: &FileStorageMetaResult, size: u32) -> ObjectAction {
+fn build_action(verb: String, result: &FileStorageMetaResult, size: u64) -> ObjectAction {
     ObjectAction::new(
         format!(
             "https://example.com/{}/{}/{}?size={}",
@@ -52,18 +68,18 @@ impl FileStorageLinkSigner for MockLinkSigner {
         &self,
         result: FileStorageMetaResult<'a>,
     ) -> Result<ObjectAction, Box<dyn std::error::Error>> {
-        Ok(build_action(
-            String::from("download"),
-            &result,
-            u32::try_from(result.size).unwrap(),
-        ))
+        tokio::time::sleep(self.signing_delay).await;
+        Ok(build_action(String::from("download"), &result, result.size))
     }
 
     async fn post_presigned_link<'a>(
         &self,
         result: FileStorageMetaResult<'a>,
-        size: u32,
+        size: u64,
+        _user: &str,
+        _ref_name: Option<&str>,
     ) -> Result<(ObjectAction, Option<ObjectAction>), Box<dyn std::error::Error>> {
+        tokio::time::sleep(self.signing_delay).await;
         Ok((
             build_action(String::from("upload"), &result, size),
             if self.with_verify {
@@ -83,11 +99,127 @@ impl FileStorageLinkSigner for MockLinkSigner {
     ) -> bool {
         return self.check_link_succeed;
     }
+
+    async fn check_verify_link(
+        &self,
+        _repo: &str,
+        _oid: &str,
+        _header: Option<&HeaderMap>,
+    ) -> bool {
+        self.check_link_succeed
+    }
+
+    fn signed_size(&self, _headers: &HeaderMap) -> Option<u64> {
+        self.signed_size
+    }
+
+    fn multipart_verify_action(
+        &self,
+        result: &FileStorageMetaResult,
+        size: u64,
+        _user: &str,
+        _ref_name: Option<&str>,
+        upload_id: &str,
+    ) -> Option<ObjectAction> {
+        let mut action = build_action(String::from("verify"), result, size);
+        action.href = format!("{}&upload_id={}", action.href, upload_id);
+        Some(action)
+    }
+
+    fn signed_upload_id(&self, _headers: &HeaderMap) -> Option<String> {
+        self.signed_upload_id.clone()
+    }
+}
+
+pub struct MockMultipartUploader {
+    pub uploaded_size: u64,
+    pub complete_success: bool,
+}
+
+#[async_trait]
+impl FileStorageMultipartUploader for MockMultipartUploader {
+    /**
+     * The upload is split in two parts, the first one holding the first half of the object.
+     */
+    async fn create_multipart_upload(
+        &self,
+        repo: &str,
+        oid: &str,
+        size: u64,
+    ) -> Result<MultipartUpload, Box<dyn Error>> {
+        let part = |pos: u64, size: u64| PartAction {
+            action: ObjectAction::new(
+                format!("https://example.com/part/{}/{}?pos={}", repo, oid, pos),
+                None,
+                60,
+            ),
+            pos,
+            size,
+        };
+        Ok(MultipartUpload {
+            upload_id: String::from("upload-id"),
+            parts: vec![part(0, size / 2), part(size / 2, size - size / 2)],
+        })
+    }
+
+    async fn get_uploaded_size(
+        &self,
+        _repo: &str,
+        _oid: &str,
+        _upload_id: &str,
+    ) -> Result<u64, Box<dyn Error>> {
+        Ok(self.uploaded_size)
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        _repo: &str,
+        _oid: &str,
+        _upload_id: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.complete_success {
+            Ok(())
+        } else {
+            Err(Box::new(std::io::Error::other(
+                "MockMultipartUploader error",
+            )))
+        }
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        _repo: &str,
+        _oid: &str,
+        _upload_id: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+pub struct MockQuarantine {
+    pub success: bool,
+    pub quarantined: bool,
+}
+
+#[async_trait]
+impl FileStorageQuarantine for MockQuarantine {
+    async fn quarantine(&self, _repo: &str, _oid: &str) -> Result<(), Box<dyn Error>> {
+        if self.success {
+            Ok(())
+        } else {
+            Err(Box::new(std::io::Error::other("MockQuarantine error")))
+        }
+    }
+
+    async fn is_quarantined(&self, _repo: &str, _oid: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.quarantined)
+    }
 }
 
 pub struct DecodedTokenMock {
     pub repo: String,
     pub operation: Operation,
+    pub refs: Option<String>,
 }
 pub struct TokenEncoderDecoderMock {
     pub encoded_token: Option<String>,
@@ -102,8 +234,7 @@ impl TokenEncoderDecoder for TokenEncoderDecoderMock {
     ) -> Result<String, Box<dyn std::error::Error>> {
         match self.encoded_token {
             Some(ref token) => Ok(token.clone()),
-            None => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            None => Err(Box::new(std::io::Error::other(
                 "TokenEncoderDecoderMock error",
             ))),
         }
@@ -128,15 +259,118 @@ impl TokenEncoderDecoder for TokenEncoderDecoderMock {
                 ),
             ]
             .into_iter()
+            .chain(decoded.refs.clone().map(|refs| ("refs".to_string(), refs)))
             .collect()),
-            None => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            None => Err(Box::new(std::io::Error::other(
                 "TokenEncoderDecoderMock error",
             ))),
         }
     }
 }
 
+/**
+ * Resumable uploads of a single object, kept in memory.
+ */
+pub struct MockResumableUploader {
+    pub received: Mutex<Vec<u8>>,
+}
+
+#[async_trait]
+impl FileStorageResumableUploader for MockResumableUploader {
+    async fn get_offset(&self, _repo: &str, _oid: &str) -> Result<u64, Box<dyn Error>> {
+        Ok(self.received.lock().unwrap().len() as u64)
+    }
+
+    async fn append(
+        &self,
+        _repo: &str,
+        _oid: &str,
+        offset: u64,
+        mut data: ObjectReader,
+    ) -> Result<u64, Box<dyn Error>> {
+        let mut appended = Vec::new();
+        data.read_to_end(&mut appended).await?;
+        let mut received = self.received.lock().unwrap();
+        if received.len() as u64 != offset {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!(
+                    "Upload offset {} does not match the received size {}",
+                    offset,
+                    received.len()
+                ),
+            )));
+        }
+        received.extend(appended);
+        Ok(received.len() as u64)
+    }
+
+    async fn get_content(&self, _repo: &str, _oid: &str) -> Result<ObjectStream, Box<dyn Error>> {
+        let received = self.received.lock().unwrap().clone();
+        let chunks: Vec<Result<Bytes, std::io::Error>> = vec![Ok(Bytes::from(received))];
+        Ok(Box::pin(futures_util::stream::iter(chunks)))
+    }
+
+    async fn discard(&self, _repo: &str, _oid: &str) -> Result<(), Box<dyn Error>> {
+        self.received.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+/**
+ * Chunked uploads of a single object, kept in memory.
+ */
+pub struct MockChunkedUploader {
+    pub chunks: Mutex<BTreeMap<u64, Vec<u8>>>,
+}
+
+#[async_trait]
+impl FileStorageChunkedUploader for MockChunkedUploader {
+    async fn put_chunk(
+        &self,
+        _repo: &str,
+        _oid: &str,
+        index: u64,
+        mut data: ObjectReader,
+    ) -> Result<u64, Box<dyn Error>> {
+        let mut chunk = Vec::new();
+        data.read_to_end(&mut chunk).await?;
+        let size = chunk.len() as u64;
+        self.chunks.lock().unwrap().insert(index, chunk);
+        Ok(size)
+    }
+
+    async fn get_chunk_sizes(
+        &self,
+        _repo: &str,
+        _oid: &str,
+    ) -> Result<BTreeMap<u64, u64>, Box<dyn Error>> {
+        let chunks = self.chunks.lock().unwrap();
+        Ok(chunks
+            .iter()
+            .map(|(index, chunk)| (*index, chunk.len() as u64))
+            .collect())
+    }
+
+    async fn get_chunks(
+        &self,
+        _repo: &str,
+        _oid: &str,
+        count: u64,
+    ) -> Result<ObjectStream, Box<dyn Error>> {
+        let chunks = self.chunks.lock().unwrap();
+        let chunks: Vec<Result<Bytes, std::io::Error>> = (0..count)
+            .map(|index| Ok(Bytes::from(chunks[&index].clone())))
+            .collect();
+        Ok(Box::pin(futures_util::stream::iter(chunks)))
+    }
+
+    async fn discard_chunks(&self, _repo: &str, _oid: &str) -> Result<(), Box<dyn Error>> {
+        self.chunks.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
 pub struct MockProxy {
     pub get_success: bool,
     pub post_success: bool,
@@ -144,14 +378,33 @@ pub struct MockProxy {
 
 #[async_trait]
 impl FileStorageProxy for MockProxy {
-    async fn get(&self, _repo: &str, _oid: &str) -> Result<(Vec<u8>, String), Box<dyn Error>> {
+    async fn get(&self, _repo: &str, _oid: &str) -> Result<(ObjectStream, String), Box<dyn Error>> {
         if self.get_success {
-            Ok((vec![1, 2, 3], String::from("application/octet-stream")))
+            let chunks: Vec<Result<Bytes, std::io::Error>> = vec![Ok(Bytes::from(vec![1, 2, 3]))];
+            Ok((
+                Box::pin(futures_util::stream::iter(chunks)),
+                String::from("application/octet-stream"),
+            ))
         } else {
-            Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "MockProxy error",
-            )))
+            Err(Box::new(std::io::Error::other("MockProxy error")))
+        }
+    }
+
+    async fn get_range(
+        &self,
+        _repo: &str,
+        _oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn Error>> {
+        if self.get_success {
+            let data: Vec<u8> = (range.start..=range.end).map(|i| i as u8).collect();
+            let chunks: Vec<Result<Bytes, std::io::Error>> = vec![Ok(Bytes::from(data))];
+            Ok((
+                Box::pin(futures_util::stream::iter(chunks)),
+                String::from("application/octet-stream"),
+            ))
+        } else {
+            Err(Box::new(std::io::Error::other("MockProxy error")))
         }
     }
 
@@ -159,16 +412,16 @@ impl FileStorageProxy for MockProxy {
         &self,
         _repo: &str,
         _oid: &str,
-        _data: Vec<u8>,
+        mut data: ObjectReader,
         _content_type: &str,
+        _uploader: Option<&str>,
     ) -> Result<(), Box<dyn Error>> {
+        let mut received = Vec::new();
+        data.read_to_end(&mut received).await?;
         if self.post_success {
             Ok(())
         } else {
-            Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "MockProxy error",
-            )))
+            Err(Box::new(std::io::Error::other("MockProxy error")))
         }
     }
 }
@@ -238,10 +491,10 @@ impl LocksProvider for MockLocksProvider {
 
         match limit {
             None => Ok((Some(String::from("id4")), vec![l1, l2, l3])),
-            Some(x) if x == 0 => Ok((None, vec![])),
-            Some(x) if x == 1 => Ok((Some(l2.id), vec![l1])),
-            Some(x) if x == 2 => Ok((Some(l3.id), vec![l1, l2])),
-            Some(x) if x == 3 => Ok((Some(l4.id), vec![l1, l2, l3])),
+            Some(0) => Ok((None, vec![])),
+            Some(1) => Ok((Some(l2.id), vec![l1])),
+            Some(2) => Ok((Some(l3.id), vec![l1, l2])),
+            Some(3) => Ok((Some(l4.id), vec![l1, l2, l3])),
             Some(_) => Ok((None, vec![l1, l2, l3, l4])),
         }
     }
@@ -295,6 +548,46 @@ pub struct MockConfig {
      */
     pub decoded: Option<DecodedTokenMock>,
 
+    /**
+     * Hash of the stored content, if the storage can compute it
+     */
+    pub content_sha256: Option<String>,
+
+    /**
+     * Size carried by the signed upload links, if any
+     */
+    pub signed_size: Option<u64>,
+
+    /**
+     * Id of the multipart upload carried by the signed verify links, if any
+     */
+    pub signed_upload_id: Option<String>,
+
+    /**
+     * Size of the parts uploaded by the clients, if the multipart uploads are enabled
+     */
+    pub multipart_uploaded_size: Option<u64>,
+
+    /**
+     * Does the completion of the multipart uploads succeed?
+     */
+    pub multipart_complete_success: bool,
+
+    /**
+     * Content already received by the resumable uploads, if they are enabled
+     */
+    pub resumable_received: Option<Vec<u8>>,
+
+    /**
+     * Chunks already received by the chunked uploads, if they are enabled
+     */
+    pub chunks_received: Option<Vec<Vec<u8>>>,
+
+    /**
+     * Time taken by the LinkSigner to sign a link
+     */
+    pub signing_delay: Duration,
+
     /**
      * Is decoded token expired?
      */
@@ -319,6 +612,26 @@ pub struct MockConfig {
      * Is locks provider enabled
      */
     pub locks_enabled: bool,
+
+    /**
+     * Does the quarantine of the objects succeed, if it is enabled?
+     */
+    pub quarantine_success: Option<bool>,
+
+    /**
+     * Was the missing object quarantined, if the quarantine is enabled?
+     */
+    pub quarantined: bool,
+
+    /**
+     * Maximum number of objects in a batch, if limited
+     */
+    pub batch_max_objects: Option<usize>,
+
+    /**
+     * Maximum size of the objects read back at verify, if they are
+     */
+    pub verify_content_max_size: Option<u64>,
 }
 
 impl Default for MockConfig {
@@ -332,12 +645,25 @@ impl Default for MockConfig {
             decoded: Some(DecodedTokenMock {
                 repo: String::from("a/b/c"),
                 operation: Operation::Download,
+                refs: None,
             }),
+            content_sha256: None,
+            signed_size: None,
+            signed_upload_id: None,
+            multipart_uploaded_size: None,
+            multipart_complete_success: true,
+            resumable_received: None,
+            chunks_received: None,
+            signing_delay: Duration::ZERO,
             expired: false,
             proxy_enabled: false,
             proxy_get_success: true,
             proxy_post_success: true,
             locks_enabled: false,
+            quarantine_success: None,
+            quarantined: false,
+            batch_max_objects: None,
+            verify_content_max_size: Some(u64::MAX),
         }
     }
 }
@@ -347,10 +673,14 @@ pub fn get_mock(config: MockConfig) -> InjectedServices {
         file_storage_meta_requester: Arc::new(MockFileStorageMetaRequester {
             found: config.found,
             size: config.size,
+            content_sha256: config.content_sha256.clone(),
         }),
         file_storage_link_signer: Arc::new(MockLinkSigner {
             with_verify: config.with_verify,
             check_link_succeed: config.check_link_succeed,
+            signed_size: config.signed_size,
+            signed_upload_id: config.signed_upload_id.clone(),
+            signing_delay: config.signing_delay,
         }),
         token_encoder_decoder: Arc::new(TokenEncoderDecoderMock {
             encoded_token: config.encoded_token.clone(),
@@ -360,6 +690,7 @@ pub fn get_mock(config: MockConfig) -> InjectedServices {
                     Operation::Download => Operation::Download,
                     Operation::Upload => Operation::Upload,
                 },
+                refs: s.refs.clone(),
             }),
             expired: config.expired,
         }),
@@ -376,5 +707,29 @@ pub fn get_mock(config: MockConfig) -> InjectedServices {
         } else {
             None
         },
+        file_storage_multipart_uploader: config.multipart_uploaded_size.map(|uploaded_size| {
+            Arc::new(MockMultipartUploader {
+                uploaded_size,
+                complete_success: config.multipart_complete_success,
+            }) as Arc<dyn FileStorageMultipartUploader>
+        }),
+        file_storage_resumable_uploader: config.resumable_received.map(|received| {
+            Arc::new(MockResumableUploader {
+                received: Mutex::new(received),
+            }) as Arc<dyn FileStorageResumableUploader>
+        }),
+        file_storage_chunked_uploader: config.chunks_received.map(|chunks| {
+            Arc::new(MockChunkedUploader {
+                chunks: Mutex::new((0..).zip(chunks).collect()),
+            }) as Arc<dyn FileStorageChunkedUploader>
+        }),
+        file_storage_quarantine: config.quarantine_success.map(|success| {
+            Arc::new(MockQuarantine {
+                success,
+                quarantined: config.quarantined,
+            }) as Arc<dyn FileStorageQuarantine>
+        }),
+        batch_max_objects: config.batch_max_objects,
+        verify_content_max_size: config.verify_content_max_size,
     }
 }
@@ -1,7 +1,17 @@
 use async_trait::async_trait;
-use axum::http::HeaderMap;
+use axum::{body::Bytes, http::HeaderMap};
+use futures_util::{Stream, StreamExt};
+use std::{collections::BTreeMap, pin::Pin};
+use tokio::io::AsyncRead;
 
-use crate::api::{enums::Operation, objects_batch::response::ObjectAction};
+use crate::api::{
+    enums::Operation,
+    objects_batch::response::{ObjectAction, PartAction},
+    range::ByteRange,
+};
+
+// Lookups run at the same time by the default implementation of get_meta_results
+const META_LOOKUP_CONCURRENCY: usize = 16;
 
 #[derive(Debug)]
 pub struct FileStorageMetaResult<'a> {
@@ -35,6 +45,33 @@ impl FileStorageMetaResult<'_> {
 pub trait FileStorageMetaRequester: Sync + Send {
     async fn get_meta_result<'a>(&self, repo: &'a str, oid: &'a str) -> FileStorageMetaResult<'a>;
 
+    /// Results of several objects of a repository, in the order of the oids. Storages able to
+    /// look them up at once, such as a database, implement it with a single query. Otherwise,
+    /// the objects are looked up one by one, a few at the same time.
+    async fn get_meta_results<'a>(
+        &self,
+        repo: &'a str,
+        oids: &[&'a str],
+    ) -> Vec<FileStorageMetaResult<'a>> {
+        let lookups: Vec<_> = oids
+            .iter()
+            .map(|oid| self.get_meta_result(repo, oid))
+            .collect();
+        futures_util::stream::iter(lookups)
+            .buffered(META_LOOKUP_CONCURRENCY)
+            .collect()
+            .await
+    }
+
+    /// SHA-256 of the stored content, as hex, if the storage is able to compute it.
+    async fn get_content_sha256(
+        &self,
+        _repo: &str,
+        _oid: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+
     fn match_size<'a>(
         &self,
         size: Option<u64>,
@@ -61,7 +98,9 @@ pub trait FileStorageLinkSigner: Sync + Send {
     async fn post_presigned_link<'a>(
         &self,
         result: FileStorageMetaResult<'a>,
-        size: u32,
+        size: u64,
+        user: &str,
+        ref_name: Option<&str>,
     ) -> Result<(ObjectAction, Option<ObjectAction>), Box<dyn std::error::Error>>;
     async fn check_link(
         &self,
@@ -70,20 +109,257 @@ pub trait FileStorageLinkSigner: Sync + Send {
         header: Option<&HeaderMap>,
         operation: Operation,
     ) -> bool;
+
+    /// Whether the headers carry a link signed to verify the upload of an object. Signers not
+    /// signing verify links accept none.
+    async fn check_verify_link(
+        &self,
+        _repo: &str,
+        _oid: &str,
+        _header: Option<&HeaderMap>,
+    ) -> bool {
+        false
+    }
+
+    /// Size of the object declared when the upload link was signed, if the link carries it.
+    fn signed_size(&self, _headers: &HeaderMap) -> Option<u64> {
+        None
+    }
+
+    /// User who requested the upload link, if the link carries it.
+    fn signed_user(&self, _headers: &HeaderMap) -> Option<String> {
+        None
+    }
+
+    /// Action to verify an upload made in several parts, carrying the id of the multipart
+    /// upload to complete, if the signer is able to sign one.
+    fn multipart_verify_action(
+        &self,
+        _result: &FileStorageMetaResult,
+        _size: u64,
+        _user: &str,
+        _ref_name: Option<&str>,
+        _upload_id: &str,
+    ) -> Option<ObjectAction> {
+        None
+    }
+
+    /// Id of the multipart upload to complete, if the verify link carries it.
+    fn signed_upload_id(&self, _headers: &HeaderMap) -> Option<String> {
+        None
+    }
+}
+
+/// An upload of an object in several parts, started but not completed yet.
+pub struct MultipartUpload {
+    pub upload_id: String,
+    pub parts: Vec<PartAction>,
+}
+
+#[async_trait]
+pub trait FileStorageMultipartUploader: Sync + Send {
+    /// Start the upload of an object in several parts, or continue the one left open for it, and
+    /// sign the links to upload each of them.
+    async fn create_multipart_upload(
+        &self,
+        repo: &str,
+        oid: &str,
+        size: u64,
+    ) -> Result<MultipartUpload, Box<dyn std::error::Error>>;
+
+    /// Total size of the parts uploaded so far.
+    async fn get_uploaded_size(
+        &self,
+        repo: &str,
+        oid: &str,
+        upload_id: &str,
+    ) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Assemble the uploaded parts into the object.
+    async fn complete_multipart_upload(
+        &self,
+        repo: &str,
+        oid: &str,
+        upload_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Discard the uploaded parts.
+    async fn abort_multipart_upload(
+        &self,
+        repo: &str,
+        oid: &str,
+        upload_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// The uploads of objects received in several requests, each one resuming where the previous
+/// one stopped. The content received so far is kept apart from the objects until it is complete.
+#[async_trait]
+pub trait FileStorageResumableUploader: Sync + Send {
+    /// Size of the content received so far, 0 if the upload has not started.
+    async fn get_offset(&self, repo: &str, oid: &str) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Append data to the content received so far, provided its size is the offset, and return
+    /// its new size. The data read before a failure of the reader is kept, so that the upload
+    /// can resume from there. The appends to an upload are serialized: one starting while
+    /// another is running, or at another offset than the received size, fails with an io error
+    /// of kind AlreadyExists.
+    async fn append(
+        &self,
+        repo: &str,
+        oid: &str,
+        offset: u64,
+        data: ObjectReader,
+    ) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Content received so far.
+    async fn get_content(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<ObjectStream, Box<dyn std::error::Error>>;
+
+    /// Discard the content received so far.
+    async fn discard(&self, repo: &str, oid: &str) -> Result<(), Box<dyn std::error::Error>>;
 }
 
+/// The uploads of objects sent as numbered chunks through the server, several at a time. The
+/// chunks are kept apart from the objects until they are reassembled.
+#[async_trait]
+pub trait FileStorageChunkedUploader: Sync + Send {
+    /// Store a chunk of an object, replacing any previous upload of it, and return its size.
+    async fn put_chunk(
+        &self,
+        repo: &str,
+        oid: &str,
+        index: u64,
+        data: ObjectReader,
+    ) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Size of the chunks stored so far, by index.
+    async fn get_chunk_sizes(
+        &self,
+        repo: &str,
+        oid: &str,
+    ) -> Result<BTreeMap<u64, u64>, Box<dyn std::error::Error>>;
+
+    /// Content of the first chunks of an object, in order, opening one chunk at a time.
+    async fn get_chunks(
+        &self,
+        repo: &str,
+        oid: &str,
+        count: u64,
+    ) -> Result<ObjectStream, Box<dyn std::error::Error>>;
+
+    /// Discard the chunks of an object.
+    async fn discard_chunks(&self, repo: &str, oid: &str)
+        -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// The content of an object being downloaded, streamed chunk by chunk.
+pub type ObjectStream = Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>;
+
+/// The content of an object being uploaded, read as it is received.
+pub type ObjectReader = Pin<Box<dyn AsyncRead + Send>>;
+
 #[async_trait]
 pub trait FileStorageProxy: Sync + Send {
     async fn get(
         &self,
         repo: &str,
         oid: &str,
-    ) -> Result<(Vec<u8>, String), Box<dyn std::error::Error>>;
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>>;
+    async fn get_range(
+        &self,
+        repo: &str,
+        oid: &str,
+        range: ByteRange,
+    ) -> Result<(ObjectStream, String), Box<dyn std::error::Error>>;
     async fn post(
         &self,
         repo: &str,
         oid: &str,
-        data: Vec<u8>,
+        data: ObjectReader,
         content_type: &str,
+        uploader: Option<&str>,
     ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Store an object unless one is already stored under its oid, and tell whether it was
+    /// stored. Storages unable to check it along with the write store the object anyway, the
+    /// last write winning, so the callers read the object back to know which content was kept.
+    async fn post_if_absent(
+        &self,
+        repo: &str,
+        oid: &str,
+        data: ObjectReader,
+        content_type: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        self.post(repo, oid, data, content_type, None).await?;
+        Ok(true)
+    }
+}
+
+/// An object found when listing a storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredObject {
+    pub repo: String,
+    pub oid: String,
+}
+
+/// The objects found when listing a storage, streamed as they are found.
+pub type StoredObjectStream<'a> =
+    Pin<Box<dyn Stream<Item = Result<StoredObject, std::io::Error>> + Send + 'a>>;
+
+#[async_trait]
+pub trait FileStorageLister: Sync + Send {
+    /// Every object of the storage, in no particular order. They are streamed as the storage is
+    /// listed, so that a large storage is never held in memory at once.
+    fn list_objects(&self) -> StoredObjectStream<'_>;
+
+    /// Objects whose content type is kept apart from them, while they are no longer stored.
+    async fn list_orphaned_content_types(
+        &self,
+    ) -> Result<Vec<StoredObject>, Box<dyn std::error::Error>> {
+        Ok(Vec::new())
+    }
+}
+
+#[async_trait]
+pub trait FileStorageQuarantine: Sync + Send {
+    /// Move an object aside, so that it is no longer served but can still be inspected.
+    async fn quarantine(&self, repo: &str, oid: &str) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Whether an object was moved aside, and is no longer served.
+    async fn is_quarantined(
+        &self,
+        _repo: &str,
+        _oid: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(false)
+    }
+}
+
+/// Error of an object that was stored, but is no longer served, such as a quarantined one. The
+/// storages report it as the source of an io error of kind NotFound, so that the callers not
+/// telling it apart see a missing object.
+#[derive(Debug)]
+pub struct ObjectGone(pub Box<dyn std::error::Error + Send + Sync>);
+
+impl std::fmt::Display for ObjectGone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Object gone: {}", self.0)
+    }
+}
+
+impl std::error::Error for ObjectGone {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.0.as_ref())
+    }
+}
+
+impl ObjectGone {
+    /// Io error reporting an object that is no longer served.
+    pub fn io_error(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::NotFound, ObjectGone(error.into()))
+    }
 }
@@ -1,5 +1,9 @@
 use super::{
-    file_storage::{FileStorageLinkSigner, FileStorageMetaRequester, FileStorageProxy},
+    file_storage::{
+        FileStorageChunkedUploader, FileStorageLinkSigner, FileStorageMetaRequester,
+        FileStorageMultipartUploader, FileStorageProxy, FileStorageQuarantine,
+        FileStorageResumableUploader,
+    },
     locks::LocksProvider,
     token_encoder_decoder::TokenEncoderDecoder,
 };
@@ -15,4 +19,32 @@ pub trait Services: Send + Sync {
     fn file_storage_proxy(&self) -> Option<&(dyn FileStorageProxy + 'static)> {
         None
     }
+
+    fn file_storage_multipart_uploader(
+        &self,
+    ) -> Option<&(dyn FileStorageMultipartUploader + 'static)> {
+        None
+    }
+
+    fn file_storage_resumable_uploader(
+        &self,
+    ) -> Option<&(dyn FileStorageResumableUploader + 'static)> {
+        None
+    }
+
+    fn file_storage_chunked_uploader(&self) -> Option<&(dyn FileStorageChunkedUploader + 'static)> {
+        None
+    }
+
+    fn file_storage_quarantine(&self) -> Option<&(dyn FileStorageQuarantine + 'static)> {
+        None
+    }
+
+    fn batch_max_objects(&self) -> Option<usize> {
+        None
+    }
+
+    fn verify_content_max_size(&self) -> Option<u64> {
+        None
+    }
 }
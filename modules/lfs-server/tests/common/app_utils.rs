@@ -1,6 +1,6 @@
 use axum::{
     body::{Body, HttpBody},
-    http::{Method, Request, StatusCode},
+    http::{HeaderMap, Method, Request, StatusCode},
     Router,
 };
 use lfs_info_server::server::{
@@ -21,6 +21,7 @@ fn get_default_server_env(args: Vec<&str>) -> (String, String, ServerConfig) {
         file_storage_implementation: FileStorageImplementation::LocalFileStorage,
         locks_implementation: LocksImplementation::PostgresLocksProvider,
         fs_root_path: Some(format!("/tmp/IT-{}", fs_root)),
+        fs_shard_depth: None,
         sbs_bucket_name: Some(bucket_id.clone()),
         sbs_access_key: Some(String::from("minio_access_key")),
         sbs_secret_key: Some(String::from("minio_secret_key")),
@@ -28,6 +29,29 @@ fn get_default_server_env(args: Vec<&str>) -> (String, String, ServerConfig) {
         sbs_host: Some(String::from("http://localhost:9000")),
         sbs_public_region: Some(String::from("us-east-1")),
         sbs_public_host: Some(String::from("http://localhost:9000")),
+        mbs_bucket_name_template: Some(format!("{}-{{namespace}}", bucket_id)),
+        sftp_host: None,
+        sftp_port: None,
+        sftp_user: None,
+        sftp_identity_path: None,
+        sftp_known_hosts_path: None,
+        sftp_root_path: None,
+        sftp_pool_size: None,
+        mirror_file_storage_implementation: None,
+        mirror_storage: None,
+        mirror_write_quorum: None,
+        encryption_master_keys: None,
+        compression: None,
+        compression_level: None,
+        cache_root_path: None,
+        cache_max_size: None,
+        global_objects_repo: None,
+        existence_cache_max_entries: None,
+        existence_cache_positive_ttl: None,
+        existence_cache_negative_ttl: None,
+        batch_max_objects: None,
+        max_body_size: None,
+        verify_content_max_size: None,
         jwt_secret: Some(String::from("secret")),
         jwt_expires_in: Some(3600),
         custom_signer_host: Some(String::from("https://example.com")),
@@ -65,31 +89,53 @@ impl ClientHelper {
         content_type: &str,
         body: Body,
     ) -> (StatusCode, Option<Vec<u8>>, Option<String>) {
+        let (status, headers, bytes) = self
+            .send_with_headers(method, uri, auth_header_value, content_type, &[], body)
+            .await;
+        let content_type = headers
+            .get("Content-Type")
+            .map(|v| v.to_str().unwrap().to_string());
+
+        (status, bytes, content_type)
+    }
+
+    async fn send_with_headers(
+        &mut self,
+        method: Method,
+        uri: &str,
+        auth_header_value: &str,
+        content_type: &str,
+        extra_headers: &[(&str, &str)],
+        body: Body,
+    ) -> (StatusCode, HeaderMap, Option<Vec<u8>>) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Content-Type", content_type)
+            .header("Authorization", auth_header_value);
+        for (name, value) in extra_headers {
+            request = request.header(*name, *value);
+        }
         let res = self
             .app
             .ready()
             .await
             .unwrap()
-            .call(
-                Request::builder()
-                    .method(method)
-                    .uri(uri)
-                    .header("Content-Type", content_type)
-                    .header("Authorization", auth_header_value)
-                    .body(body)
-                    .unwrap(),
-            )
+            .call(request.body(body).unwrap())
             .await
             .unwrap();
 
         let status = res.status();
-        let headers = res.headers();
-        let content_type = headers
-            .get("Content-Type")
-            .map(|v| v.to_str().unwrap().to_string());
-        let bytes = res.into_body().data().await.map(|b| b.unwrap().to_vec());
+        let headers = res.headers().clone();
+        let mut body = res.into_body();
+        let mut bytes: Option<Vec<u8>> = None;
+        while let Some(chunk) = body.data().await {
+            bytes
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(&chunk.unwrap());
+        }
 
-        (status, bytes, content_type)
+        (status, headers, bytes)
     }
 
     pub async fn send_json(
@@ -98,13 +144,31 @@ impl ClientHelper {
         uri: &str,
         auth_header_value: &str,
         json_body: &str,
+    ) -> (StatusCode, Option<String>) {
+        self.send_typed_json(
+            method,
+            uri,
+            auth_header_value,
+            "application/vnd.git-lfs+json",
+            json_body,
+        )
+        .await
+    }
+
+    pub async fn send_typed_json(
+        &mut self,
+        method: Method,
+        uri: &str,
+        auth_header_value: &str,
+        content_type: &str,
+        json_body: &str,
     ) -> (StatusCode, Option<String>) {
         let (status, body, _) = self
             .send(
                 method,
                 uri,
                 auth_header_value,
-                "application/json",
+                content_type,
                 Body::from(String::from(json_body)),
             )
             .await;
@@ -173,6 +237,65 @@ impl ClientHelper {
         )
     }
 
+    /**
+     * Download a range of an object, and get back the Content-Range header of the response.
+     */
+    pub async fn get_range(
+        &mut self,
+        uri: &str,
+        auth_header_value: &str,
+        range: &str,
+    ) -> (StatusCode, Option<String>) {
+        let (status, headers, _) = self
+            .send_with_headers(
+                Method::GET,
+                uri,
+                auth_header_value,
+                "application/octet-stream",
+                &[("Range", range)],
+                Body::from(vec![]),
+            )
+            .await;
+        let content_range = headers
+            .get("Content-Range")
+            .map(|v| v.to_str().unwrap().to_string());
+
+        (status, content_range)
+    }
+
+    /**
+     * Send a request of the tus protocol, and get back the offset of the upload, if any.
+     */
+    pub async fn send_tus(
+        &mut self,
+        method: Method,
+        uri: &str,
+        auth_header_value: &str,
+        offset: Option<u64>,
+        raw_data: Vec<u8>,
+    ) -> (StatusCode, Option<u64>) {
+        let offset = offset.map(|offset| offset.to_string());
+        let mut extra_headers = vec![("Tus-Resumable", "1.0.0")];
+        if let Some(offset) = &offset {
+            extra_headers.push(("Upload-Offset", offset));
+        }
+        let (status, headers, _) = self
+            .send_with_headers(
+                method,
+                uri,
+                auth_header_value,
+                "application/offset+octet-stream",
+                &extra_headers,
+                Body::from(raw_data),
+            )
+            .await;
+        let offset = headers
+            .get("Upload-Offset")
+            .map(|v| v.to_str().unwrap().parse().unwrap());
+
+        (status, offset)
+    }
+
     pub async fn lock(
         &mut self,
         repo: &str,
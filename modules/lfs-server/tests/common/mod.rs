@@ -115,6 +115,22 @@ pub async fn init_test_database(db_name: &str) {
         .await
         .unwrap();
     client.execute(&stmt, &[]).await.unwrap();
+
+    let stmt = client
+        .prepare(
+            "CREATE TABLE objects (
+                    repo TEXT NOT NULL,
+                    oid TEXT NOT NULL,
+                    size BIGINT NOT NULL,
+                    content_type TEXT NOT NULL,
+                    uploader TEXT,
+                    uploaded_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                    PRIMARY KEY (repo, oid)
+                )",
+        )
+        .await
+        .unwrap();
+    client.execute(&stmt, &[]).await.unwrap();
 }
 
 /**
@@ -2,7 +2,8 @@ use crate::{
     common::{app_utils::ClientHelper, rewrite_url},
     scenario::{
         batch_object_exit_directory_attack::batch_object_proxy_exit_directory_attack,
-        batch_objects_nominal::batch_objects_nominal_proxy,
+        batch_objects_nominal::batch_objects_nominal_proxy, chunked_upload::chunked_upload_proxy,
+        tus_upload::tus_upload_proxy,
     },
 };
 
@@ -36,3 +37,31 @@ async fn test_batch_object_exit_directory_attack() {
     )
     .await;
 }
+
+/**
+ * Integration test for an upload resumed with the tus transfer
+ */
+#[tokio::test]
+async fn test_tus_upload() {
+    let (app, config) = ClientHelper::new(vec!["proxy", "fs"]);
+    let custom_signer_host = config.custom_signer_host.unwrap();
+    tus_upload_proxy(
+        app,
+        Box::new(move |url, repo| rewrite_url(url, repo, &custom_signer_host)),
+    )
+    .await;
+}
+
+/**
+ * Integration test for an upload sent in chunks reassembled by the server
+ */
+#[tokio::test]
+async fn test_chunked_upload() {
+    let (app, config) = ClientHelper::new(vec!["proxy", "fs"]);
+    let custom_signer_host = config.custom_signer_host.unwrap();
+    chunked_upload_proxy(
+        app,
+        Box::new(move |url, repo| rewrite_url(url, repo, &custom_signer_host)),
+    )
+    .await;
+}
@@ -1,13 +1,18 @@
 use crate::common::{
     app_utils::ClientHelper,
     batch_objects::{
-        app_download_object, app_upload_object, batch_download, batch_download_missing,
-        batch_upload, batch_upload_wrong_token, extract_href, extract_href_auth,
+        app_download_object, app_download_object_range_not_satisfiable, app_upload_object,
+        app_verify_object, batch_download, batch_download_missing, batch_upload,
+        batch_upload_wrong_token, batch_wrong_content_type, extract_href, extract_href_auth,
         http_download_object, http_upload_object, UrlRewrite,
     },
+    rewrite_url,
 };
 
 pub async fn batch_objects_nominal_proxy(mut app: ClientHelper, url_rewrite: Box<UrlRewrite>) {
+    // 0) Send the request with a media type other than the LFS one
+    batch_wrong_content_type(&mut app).await;
+
     // 1) Try to download a non existent file
     batch_download_missing(&mut app).await;
 
@@ -17,20 +22,22 @@ pub async fn batch_objects_nominal_proxy(mut app: ClientHelper, url_rewrite: Box
     // 3) Get a link to upload the file
     let json = batch_upload(
         &mut app,
-        r#"\{"transfer":"basic","objects":\[\{"oid":"test2\.txt","size":123,"actions":\{"upload":\{"href":"https://example.com/testing/objects/access/test2.txt","header":\{"Authorization":"Bearer ([a-zA-Z0-9_\.\-]*)"},"expires_in":3600}}}\],"hash_algo":"sha256"}"#
+        r#"\{"transfer":"basic","objects":\[\{"oid":"0b6a8796af24bb19ea57d5ae93a755045d614f5b7e958bdce3cbeef3b1e2f4af","size":39,"actions":\{"upload":\{"href":"https://example.com/testing/objects/access/0b6a8796af24bb19ea57d5ae93a755045d614f5b7e958bdce3cbeef3b1e2f4af","header":\{"Authorization":"Bearer ([a-zA-Z0-9_\.\-]*)"},"expires_in":3600},"verify":\{"href":"https://example.com/testing/objects/verify","header":\{"Authorization":"Bearer ([a-zA-Z0-9_\.\-]*)"},"expires_in":3600}}}\],"hash_algo":"sha256"}"#
     ).await;
 
     // 4) Parse json and get back the link and the token
     let (href, auth) = extract_href_auth(&json, "upload");
     let href = url_rewrite(&href, "testing");
 
-    // 5) Upload the file
+    // 5) Upload the file, and verify the upload
     app_upload_object(&mut app, &href, &auth).await;
+    let (href, auth) = extract_href_auth(&json, "verify");
+    app_verify_object(&mut app, &url_rewrite(&href, "testing"), &auth).await;
 
     // 6) Get a link to download the file
     let json = batch_download(
         &mut app,
-        r#"\{"transfer":"basic","objects":\[\{"oid":"test2\.txt","size":123,"actions":\{"download":\{"href":"https://example.com/testing/objects/access/test2.txt","header":\{"Authorization":"Bearer ([a-zA-Z0-9_\.\-]*)"},"expires_in":3600}}}\],"hash_algo":"sha256"}"#,
+        r#"\{"transfer":"basic","objects":\[\{"oid":"0b6a8796af24bb19ea57d5ae93a755045d614f5b7e958bdce3cbeef3b1e2f4af","size":39,"actions":\{"download":\{"href":"https://example.com/testing/objects/access/0b6a8796af24bb19ea57d5ae93a755045d614f5b7e958bdce3cbeef3b1e2f4af","header":\{"Authorization":"Bearer ([a-zA-Z0-9_\.\-]*)"},"expires_in":3600}}}\],"hash_algo":"sha256"}"#,
     ).await;
 
     // 7) reparse the actions
@@ -39,6 +46,9 @@ pub async fn batch_objects_nominal_proxy(mut app: ClientHelper, url_rewrite: Box
 
     // 8) Download the content of the file
     app_download_object(&mut app, &href, &auth).await;
+
+    // 9) Resume a download that already has every byte of the file
+    app_download_object_range_not_satisfiable(&mut app, &href, &auth).await;
 }
 
 pub async fn batch_objects_nominal_signer(mut app: ClientHelper) {
@@ -51,19 +61,26 @@ pub async fn batch_objects_nominal_signer(mut app: ClientHelper) {
     // 3) Get a link to upload the file
     let json = batch_upload(
         &mut app,
-        r#"\{"transfer":"basic","objects":\[\{"oid":"test2\.txt","size":123,"actions":\{"upload":\{"href":"http://localhost:9000/(.*)","expires_in":3600}}}\],"hash_algo":"sha256"}"#
+        r#"\{"transfer":"basic","objects":\[\{"oid":"0b6a8796af24bb19ea57d5ae93a755045d614f5b7e958bdce3cbeef3b1e2f4af","size":39,"actions":\{"upload":\{"href":"http://localhost:9000/(.*)","expires_in":3600},"verify":\{"href":"https://example.com/testing/objects/verify","header":\{"Authorization":"Bearer ([a-zA-Z0-9_\.\-]*)"},"expires_in":3600}}}\],"hash_algo":"sha256"}"#
     ).await;
 
     // 4) Parse json and get back the link and the token
     let href = extract_href(&json, "upload");
 
-    // 5) Upload the file
+    // 5) Upload the file, and verify the upload through the server
     http_upload_object(&href).await;
+    let (href, auth) = extract_href_auth(&json, "verify");
+    app_verify_object(
+        &mut app,
+        &rewrite_url(&href, "testing", "https://example.com"),
+        &auth,
+    )
+    .await;
 
     // 6) Get a link to download the file
     let json = batch_download(
         &mut app,
-        r#"\{"transfer":"basic","objects":\[\{"oid":"test2\.txt","size":123,"actions":\{"download":\{"href":"http://localhost:9000/(.*)","expires_in":3600}}}\],"hash_algo":"sha256"}"#,
+        r#"\{"transfer":"basic","objects":\[\{"oid":"0b6a8796af24bb19ea57d5ae93a755045d614f5b7e958bdce3cbeef3b1e2f4af","size":39,"actions":\{"download":\{"href":"http://localhost:9000/(.*)","expires_in":3600}}}\],"hash_algo":"sha256"}"#,
     ).await;
 
     // 7) reparse the actions
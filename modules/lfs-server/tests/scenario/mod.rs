@@ -1,3 +1,5 @@
 pub mod batch_object_exit_directory_attack;
 pub mod batch_objects_nominal;
+pub mod chunked_upload;
 pub mod locks_nominal;
+pub mod tus_upload;
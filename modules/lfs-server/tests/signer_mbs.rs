@@ -0,0 +1,28 @@
+use crate::{
+    common::app_utils::ClientHelper,
+    scenario::{
+        batch_object_exit_directory_attack::batch_object_signer_exit_directory_attack,
+        batch_objects_nominal::batch_objects_nominal_signer,
+    },
+};
+
+pub mod common;
+pub mod scenario;
+
+/**
+ * Integration test for the nominal case
+ */
+#[tokio::test]
+async fn test_batch_objects_nominal() {
+    let (app, _) = ClientHelper::new(vec!["signer", "mbs"]);
+    batch_objects_nominal_signer(app).await;
+}
+
+/**
+ * Integration test for an attack attempting to download objects outside of objects directory
+ */
+#[tokio::test]
+async fn test_batch_object_exit_directory_attack() {
+    let (app, _) = ClientHelper::new(vec!["signer", "mbs"]);
+    batch_object_signer_exit_directory_attack(app).await;
+}
@@ -6,3 +6,13 @@ CREATE TABLE locks (
 	owner TEXT NOT NULL,
 	locked_at TIMESTAMP NOT NULL DEFAULT NOW()
 );
+
+CREATE TABLE objects (
+	repo TEXT NOT NULL,
+	oid TEXT NOT NULL,
+	size BIGINT NOT NULL,
+	content_type TEXT NOT NULL,
+	uploader TEXT,
+	uploaded_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	PRIMARY KEY (repo, oid)
+);